@@ -3,9 +3,10 @@ extern crate cc;
 use std::env;
 
 fn main() {
-    match env::var("CARGO_CFG_TARGET_OS").unwrap_or_default().as_str() {
-        "android" => build_android(),
-        _ => {}
+    println!("cargo:rustc-check-cfg=cfg(feature, values(\"dl_iterate_phdr\"))");
+
+    if env::var("CARGO_CFG_TARGET_OS").unwrap_or_default() == "android" {
+        build_android();
     }
 }
 
@@ -0,0 +1,257 @@
+//! Comparing a loaded image against the file it was loaded from.
+//!
+//! A `BackingFile` maps the object file of a shared library, and its
+//! `segments` line up each loaded segment's memory with the bytes of the file
+//! it was loaded from. Comparing the two shows what the loader and the
+//! process changed since the image was mapped, like relocations and patched
+//! code.
+
+use crate::verify::{macho_uuid, u32_at, u64_at, FAT_MAGIC, FAT_MAGIC_64};
+use crate::{Avma, Segment, SharedLibrary, SharedLibraryId};
+
+use memmap2::Mmap;
+
+use std::convert::TryInto;
+use std::fs::File;
+use std::io;
+use std::ops::Range;
+use std::slice;
+
+/// The object file a shared library was loaded from, mapped into memory.
+///
+/// The file is mapped read-only, but it is not copied: if it is written to
+/// while it is mapped, its contents change under the mapping, and if it is
+/// truncated, accessing the missing pages faults. Files of loaded libraries
+/// are normally left alone, since writing to them would break the processes
+/// that have them loaded as well.
+#[derive(Debug)]
+pub struct BackingFile {
+    map: Mmap,
+    image: Range<usize>,
+}
+
+impl BackingFile {
+    /// Map the file that `shlib` was loaded from, at `shlib.code_file()`.
+    ///
+    /// For a universal binary, this finds the architecture slice with the
+    /// UUID of `shlib`. Fails with `io::ErrorKind::InvalidData` if there is no
+    /// such slice, which is the case if the file was replaced since `shlib`
+    /// was loaded.
+    pub fn open<L: SharedLibrary>(shlib: &L) -> io::Result<BackingFile> {
+        let file = File::open(shlib.code_file())?;
+        let map = unsafe { Mmap::map(&file)? };
+        let uuid = match shlib.id() {
+            Some(SharedLibraryId::Uuid(uuid)) => Some(uuid),
+            _ => None,
+        };
+        let image = image_range(&map, uuid).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                "no architecture slice of the file matches the image",
+            )
+        })?;
+        Ok(BackingFile { map, image })
+    }
+
+    /// Get the bytes of the image in the file.
+    ///
+    /// This is the whole file, except for universal binaries, where it is the
+    /// image's architecture slice.
+    pub fn bytes(&self) -> &[u8] {
+        &self.map[self.image.clone()]
+    }
+
+    /// Line up the loaded segments of `shlib` with the bytes of the file
+    /// they were loaded from.
+    ///
+    /// Segments that aren't readable or aren't backed by the file are
+    /// skipped, and views end where the file or the segment does, whichever
+    /// comes first.
+    ///
+    /// # Safety
+    ///
+    /// `shlib` must be loaded in the current process, from this file, and
+    /// stay loaded for as long as the views are alive. Mach-O images of other
+    /// tasks and snapshots of libraries that may have been unloaded since do
+    /// not qualify.
+    pub unsafe fn segments<'a, L: SharedLibrary>(&'a self, shlib: &'a L) -> Vec<SegmentView<'a>> {
+        let bytes = self.bytes();
+        shlib
+            .segments()
+            .filter(|segment| {
+                segment.is_load()
+                    && segment
+                        .permissions()
+                        .map_or(true, |perms| perms.is_readable())
+            })
+            .filter_map(|segment| {
+                let range = segment.file_range()?;
+                let start: usize = range.start.try_into().ok()?;
+                let file_len: usize = (range.end - range.start).try_into().ok()?;
+                let file = bytes.get(start..)?;
+                let len = file_len.min(segment.len()).min(file.len());
+                if len == 0 {
+                    return None;
+                }
+                let address = segment.actual_virtual_memory_address(shlib);
+                Some(SegmentView {
+                    name: segment.name().to_owned(),
+                    file_offset: range.start,
+                    address,
+                    file: &file[..len],
+                    memory: slice::from_raw_parts(address.0 as *const u8, len),
+                })
+            })
+            .collect()
+    }
+}
+
+/// A loaded segment, lined up with the bytes of the file it was loaded from.
+#[derive(Clone, Debug)]
+pub struct SegmentView<'a> {
+    name: String,
+    file_offset: u64,
+    address: Avma,
+    file: &'a [u8],
+    memory: &'a [u8],
+}
+
+impl<'a> SegmentView<'a> {
+    /// Get the name of the segment.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Get the offset of the segment's bytes in the image's file.
+    pub fn file_offset(&self) -> u64 {
+        self.file_offset
+    }
+
+    /// Get the address the segment is loaded at.
+    pub fn actual_virtual_memory_address(&self) -> Avma {
+        self.address
+    }
+
+    /// Get the segment's bytes in the file.
+    pub fn file(&self) -> &'a [u8] {
+        self.file
+    }
+
+    /// Get the segment's bytes in memory, which are as many as in `file()`.
+    pub fn memory(&self) -> &'a [u8] {
+        self.memory
+    }
+
+    /// Returns `true` if the segment's memory is the same as its bytes in the
+    /// file.
+    pub fn is_unmodified(&self) -> bool {
+        self.file == self.memory
+    }
+}
+
+/// Find the image with `uuid` in the `data` of a file, which is all of it
+/// unless the file is a universal binary.
+fn image_range(data: &[u8], uuid: Option<[u8; 16]>) -> Option<Range<usize>> {
+    let magic = u32_at(data, 0, false);
+    if magic != Some(FAT_MAGIC) && magic != Some(FAT_MAGIC_64) {
+        return Some(0..data.len());
+    }
+
+    // Without a UUID, there is no telling the slices apart.
+    let uuid = uuid?;
+    let nfat_arch = u32_at(data, 4, false)?;
+    let entry_size = if magic == Some(FAT_MAGIC_64) { 32 } else { 20 };
+    for idx in 0..nfat_arch as usize {
+        let entry = idx.checked_mul(entry_size)?.checked_add(8)?;
+        let (offset, size) = if magic == Some(FAT_MAGIC_64) {
+            (
+                u64_at(data, entry + 8, false)?,
+                u64_at(data, entry + 16, false)?,
+            )
+        } else {
+            (
+                u64::from(u32_at(data, entry + 8, false)?),
+                u64::from(u32_at(data, entry + 12, false)?),
+            )
+        };
+        let start: usize = offset.try_into().ok()?;
+        let end = start.checked_add(size.try_into().ok()?)?;
+        if let Some(slice) = data.get(start..end) {
+            if macho_uuid(slice) == Some(uuid) {
+                return Some(start..end);
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::verify::thin_image;
+    use crate::TargetSharedLibrary;
+
+    #[test]
+    fn fat_slices() {
+        let (first, second) = (thin_image([1; 16]), thin_image([2; 16]));
+        let mut fat = vec![];
+        for word in [FAT_MAGIC, 2] {
+            fat.extend_from_slice(&word.to_be_bytes());
+        }
+        let slices = [(0x1000, &first), (0x2000, &second)];
+        for (offset, slice) in slices {
+            for word in [0, 0, offset, slice.len() as u32, 12] {
+                fat.extend_from_slice(&word.to_be_bytes());
+            }
+        }
+        for (offset, slice) in slices {
+            fat.resize(offset as usize, 0);
+            fat.extend_from_slice(slice);
+        }
+
+        assert_eq!(macho_uuid(&first), Some([1; 16]));
+        assert_eq!(
+            image_range(&fat, Some([2; 16])),
+            Some(0x2000..0x2000 + second.len())
+        );
+        assert_eq!(image_range(&fat, Some([3; 16])), None);
+        assert_eq!(image_range(&fat, None), None);
+        assert_eq!(image_range(&first, None), Some(0..first.len()));
+    }
+
+    #[test]
+    fn this_image() {
+        let here = Avma(this_image as fn() as usize);
+        let mut found = false;
+        TargetSharedLibrary::each(|shlib| {
+            if !shlib
+                .segments()
+                .any(|segment| segment.is_code() && segment.contains_avma(shlib, here))
+            {
+                return;
+            }
+            found = true;
+
+            let file = BackingFile::open(shlib).unwrap();
+            assert!(!file.bytes().is_empty());
+            let views = unsafe { file.segments(shlib) };
+            let code = views
+                .iter()
+                .find(|view| {
+                    let start = view.actual_virtual_memory_address().0;
+                    (start..start + view.memory().len()).contains(&here.0)
+                })
+                .unwrap();
+            assert_eq!(code.file().len(), code.memory().len());
+            assert_eq!(
+                &file.bytes()[code.file_offset() as usize..][..code.file().len()],
+                code.file()
+            );
+            // Position-independent code isn't relocated on Linux.
+            if cfg!(target_os = "linux") {
+                assert!(code.is_unmodified());
+            }
+        });
+        assert_eq!(found, crate::TARGET_SUPPORTED);
+    }
+}
@@ -0,0 +1,120 @@
+//! Finding all the executable memory of the process.
+//!
+//! Most executable memory belongs to the code segments of shared libraries,
+//! but JIT compilers, trampolines and injected code put some elsewhere.
+//! `executable_regions` reports every executable range along with where it
+//! comes from, which is what is needed to spot JIT code, audit W^X or explain
+//! a frame that's in no known library.
+//!
+//! The rest of the address space is found by the platform:
+//!
+//! * On Linux and Android from `/proc/self/maps`.
+//! * On macOS and iOS from `mach_vm_region`.
+//! * On Windows from `VirtualQuery`.
+
+use crate::{native_mod, Avma, Segment, SharedLibrary, TargetSharedLibrary};
+
+use std::ffi::OsString;
+use std::ops::Range;
+
+/// Where executable memory comes from.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ExecutableSource {
+    /// A code segment of a shared library, with the library's name.
+    Module(OsString),
+    /// A file mapped executable without the loader knowing about it, with the
+    /// file's path.
+    File(OsString),
+    /// A mapping the kernel sets up, like Linux's `[vsyscall]`, with its name.
+    Special(String),
+    /// Anonymous memory, which is usually where JIT compilers put their code.
+    Anonymous,
+}
+
+/// A range of executable memory.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ExecutableRegion {
+    range: Range<Avma>,
+    source: ExecutableSource,
+}
+
+impl ExecutableRegion {
+    pub(crate) fn new(range: Range<Avma>, source: ExecutableSource) -> ExecutableRegion {
+        ExecutableRegion { range, source }
+    }
+
+    /// Get the addresses of the region.
+    pub fn range(&self) -> Range<Avma> {
+        self.range.clone()
+    }
+
+    /// Get where the region comes from.
+    pub fn source(&self) -> &ExecutableSource {
+        &self.source
+    }
+}
+
+/// Find every range of executable memory in the current process, in order of
+/// their addresses.
+///
+/// The code segments of shared libraries are reported as they are stated.
+/// Mappings of the platform that overlap one are taken to belong to the
+/// library, so page-aligned slivers around a segment are not reported on
+/// their own. Where the platform can't be asked about other mappings, only
+/// the code segments are reported. Memory can be mapped and unmapped at any
+/// time, so this is a snapshot at best.
+pub fn executable_regions() -> Vec<ExecutableRegion> {
+    let mut regions = vec![];
+    TargetSharedLibrary::each(|shlib| {
+        for segment in shlib.segments().filter(|segment| segment.is_code()) {
+            regions.push(ExecutableRegion::new(
+                segment.actual_range(shlib),
+                ExecutableSource::Module(shlib.name().to_owned()),
+            ));
+        }
+    });
+    let modules = regions.len();
+
+    for mapping in native_mod::executable_mappings() {
+        let owned = regions[..modules].iter().any(|module| {
+            module.range.start < mapping.range.end && mapping.range.start < module.range.end
+        });
+        if owned {
+            continue;
+        }
+        // The platform may report adjacent pages with the same source
+        // separately.
+        if let Some(last) = regions[modules..].last_mut() {
+            if last.range.end == mapping.range.start && last.source == mapping.source {
+                last.range.end = mapping.range.end;
+                continue;
+            }
+        }
+        regions.push(mapping);
+    }
+
+    regions.sort_by_key(|region| region.range.start);
+    regions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn this_function_is_in_a_module() {
+        let here = Avma(this_function_is_in_a_module as fn() as usize);
+        let regions = executable_regions();
+        assert_eq!(regions.is_empty(), !crate::TARGET_SUPPORTED);
+        for pair in regions.windows(2) {
+            assert!(pair[0].range().start <= pair[1].range().start);
+        }
+        if crate::TARGET_SUPPORTED {
+            let region = regions
+                .iter()
+                .find(|region| region.range().contains(&here))
+                .unwrap();
+            assert!(matches!(region.source(), ExecutableSource::Module(_)));
+        }
+    }
+}
@@ -0,0 +1,434 @@
+//! A module table for crash handlers.
+//!
+//! A crash handler runs in a signal handler or an exception filter, where the
+//! process may be holding the loader's or the allocator's locks, so it can't
+//! enumerate the loaded libraries or allocate. This module keeps a table of
+//! them with their names and identifiers already formatted, which `read` gives
+//! out without allocating, locking or making system calls.
+//!
+//! The table is allocated once by `install` and holds at most `CAPACITY`
+//! modules. It is kept up to date by the platform's loader notifications where
+//! there are some (macOS, iOS and Windows). Elsewhere `update` has to be
+//! called after libraries were loaded or unloaded, for example after calling
+//! `dlopen`, and it's fine to call it where it isn't needed.
+//!
+//! There are two copies of the table. Updates are written to the one no crash
+//! handler is reading from, which then takes the other's place.
+//!
+//! ```
+//! findshlibs::crash_table::install();
+//!
+//! // In the crash handler:
+//! findshlibs::crash_table::read(|table| {
+//!     for module in table.modules() {
+//!         // Write out `module.range()`, `module.code_id()`, ...
+//!     }
+//! });
+//! ```
+
+use crate::{native_mod, Avma, Bias, SharedLibrary, SharedLibraryId, TargetSharedLibrary};
+
+use std::fmt::{self, Write};
+use std::ops::Range;
+use std::ptr;
+use std::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+use std::sync::{Mutex, Once};
+use std::thread;
+
+/// The most modules the table holds.
+pub const CAPACITY: usize = 512;
+
+const ID_LEN: usize = 64;
+const NAME_LEN: usize = 256;
+
+/// A module in the crash table.
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct CrashModule {
+    start: usize,
+    end: usize,
+    bias: usize,
+    code_id: [u8; ID_LEN],
+    code_id_len: u8,
+    debug_id: [u8; ID_LEN],
+    debug_id_len: u8,
+    name_truncated: bool,
+    name_len: u16,
+    name: [u8; NAME_LEN],
+}
+
+impl CrashModule {
+    const EMPTY: CrashModule = CrashModule {
+        start: 0,
+        end: 0,
+        bias: 0,
+        code_id: [0; ID_LEN],
+        code_id_len: 0,
+        debug_id: [0; ID_LEN],
+        debug_id_len: 0,
+        name_truncated: false,
+        name_len: 0,
+        name: [0; NAME_LEN],
+    };
+
+    fn new<S: SharedLibrary>(shlib: &S) -> CrashModule {
+        let mut module = CrashModule::EMPTY;
+        let start = shlib.actual_load_addr().0;
+        module.start = start;
+        module.end = start.saturating_add(shlib.len());
+        module.bias = shlib.virtual_memory_bias().0;
+        module.code_id_len = format_id(&mut module.code_id, shlib.id());
+        module.debug_id_len = format_id(&mut module.debug_id, shlib.debug_id());
+        // The end of a path says the most about it.
+        let name = shlib.name_bytes();
+        let kept = &name[name.len().saturating_sub(NAME_LEN)..];
+        module.name[..kept.len()].copy_from_slice(kept);
+        module.name_len = kept.len() as u16;
+        module.name_truncated = kept.len() < name.len();
+        module
+    }
+
+    /// Get the address range the module is loaded at.
+    pub fn range(&self) -> Range<Avma> {
+        Avma(self.start)..Avma(self.end)
+    }
+
+    /// Get the module's virtual memory bias.
+    pub fn bias(&self) -> Bias {
+        Bias(self.bias)
+    }
+
+    /// Get the module's name as bytes, like `SharedLibrary::name_bytes`.
+    ///
+    /// Names longer than 256 bytes lose their beginning, see
+    /// `is_name_truncated`.
+    pub fn name(&self) -> &[u8] {
+        &self.name[..usize::from(self.name_len)]
+    }
+
+    /// Returns `true` if the beginning of the module's name had to be cut off.
+    pub fn is_name_truncated(&self) -> bool {
+        self.name_truncated
+    }
+
+    /// Get the module's `id` as it is displayed, or an empty string if it has
+    /// none.
+    pub fn code_id(&self) -> &str {
+        id_str(&self.code_id[..usize::from(self.code_id_len)])
+    }
+
+    /// Get the module's `debug_id` as it is displayed, or an empty string if
+    /// it has none.
+    pub fn debug_id(&self) -> &str {
+        id_str(&self.debug_id[..usize::from(self.debug_id_len)])
+    }
+}
+
+impl fmt::Debug for CrashModule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CrashModule")
+            .field("range", &self.range())
+            .field("bias", &self.bias())
+            .field("name", &String::from_utf8_lossy(self.name()))
+            .field("code_id", &self.code_id())
+            .field("debug_id", &self.debug_id())
+            .finish()
+    }
+}
+
+fn format_id(buf: &mut [u8; ID_LEN], id: Option<SharedLibraryId>) -> u8 {
+    struct Buf<'a>(&'a mut [u8], usize);
+
+    impl Write for Buf<'_> {
+        fn write_str(&mut self, s: &str) -> fmt::Result {
+            let end = self.1 + s.len();
+            if end > self.0.len() {
+                return Err(fmt::Error);
+            }
+            self.0[self.1..end].copy_from_slice(s.as_bytes());
+            self.1 = end;
+            Ok(())
+        }
+    }
+
+    let id = match id {
+        Some(id) => id,
+        None => return 0,
+    };
+    let mut writer = Buf(&mut buf[..], 0);
+    // An identifier that doesn't fit is left out rather than cut short, since
+    // a partial one would only ever match the wrong file.
+    match write!(writer, "{}", id) {
+        Ok(()) => writer.1 as u8,
+        Err(_) => 0,
+    }
+}
+
+fn id_str(bytes: &[u8]) -> &str {
+    // Identifiers are formatted in ASCII.
+    std::str::from_utf8(bytes).unwrap_or("")
+}
+
+/// The modules in the crash table.
+pub struct CrashTable {
+    len: usize,
+    dropped: usize,
+    modules: [CrashModule; CAPACITY],
+}
+
+impl CrashTable {
+    /// Get the modules, in no particular order.
+    pub fn modules(&self) -> &[CrashModule] {
+        &self.modules[..self.len]
+    }
+
+    /// Get the number of loaded modules that didn't fit into the table.
+    pub fn dropped(&self) -> usize {
+        self.dropped
+    }
+
+    fn push(&mut self, module: CrashModule) {
+        if self.len == CAPACITY {
+            self.dropped += 1;
+        } else {
+            self.modules[self.len] = module;
+            self.len += 1;
+        }
+    }
+
+    #[cfg(any(target_os = "macos", target_os = "ios", target_os = "windows", test))]
+    fn remove(&mut self, start: usize) {
+        if let Some(index) = self.modules().iter().position(|m| m.start == start) {
+            self.modules.copy_within(index + 1..self.len, index);
+            self.len -= 1;
+        } else if self.dropped > 0 {
+            self.dropped -= 1;
+        }
+    }
+
+    fn fill(&mut self) {
+        self.len = 0;
+        self.dropped = 0;
+        TargetSharedLibrary::each(|shlib| self.push(CrashModule::new(shlib)));
+    }
+}
+
+impl fmt::Debug for CrashTable {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CrashTable")
+            .field("modules", &self.modules())
+            .field("dropped", &self.dropped)
+            .finish()
+    }
+}
+
+static TABLES: [AtomicPtr<CrashTable>; 2] = [
+    AtomicPtr::new(ptr::null_mut()),
+    AtomicPtr::new(ptr::null_mut()),
+];
+static READERS: [AtomicUsize; 2] = [AtomicUsize::new(0), AtomicUsize::new(0)];
+static ACTIVE: AtomicUsize = AtomicUsize::new(0);
+
+/// Serializes writers and remembers the change count the table was last filled
+/// at.
+static WRITER: Mutex<Option<u64>> = Mutex::new(None);
+static INSTALL: Once = Once::new();
+
+/// Allocate the crash table and fill it with the libraries that are loaded.
+///
+/// Calling this again does nothing.
+pub fn install() {
+    INSTALL.call_once(|| {
+        for table in &TABLES {
+            let new = Box::new(CrashTable {
+                len: 0,
+                dropped: 0,
+                modules: [CrashModule::EMPTY; CAPACITY],
+            });
+            table.store(Box::into_raw(new), Ordering::SeqCst);
+        }
+        if OBSERVED {
+            observe();
+        } else {
+            update();
+        }
+    });
+}
+
+/// Refill the crash table if libraries were loaded or unloaded since it was
+/// last filled.
+///
+/// Does nothing if the table isn't installed or if loader notifications keep
+/// it up to date.
+pub fn update() {
+    // The notifications come with the loader's lock held, so enumerating the
+    // libraries while holding the writer lock could deadlock with them.
+    if OBSERVED || TABLES[0].load(Ordering::SeqCst).is_null() {
+        return;
+    }
+    let count = native_mod::change_count();
+    let mut filled_at = WRITER.lock().unwrap();
+    if count.is_some() && *filled_at == count {
+        return;
+    }
+    write(|table| table.fill());
+    *filled_at = count;
+}
+
+/// Call `f` with the crash table, or return `None` if it isn't installed.
+///
+/// This doesn't allocate, lock or make system calls, so it can be called from
+/// a signal handler. It waits for no one, but `f` should return quickly since
+/// updates wait for it to.
+pub fn read<F, R>(f: F) -> Option<R>
+where
+    F: FnOnce(&CrashTable) -> R,
+{
+    struct Reading(usize);
+
+    impl Drop for Reading {
+        fn drop(&mut self) {
+            READERS[self.0].fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+
+    let reading = loop {
+        let active = ACTIVE.load(Ordering::SeqCst);
+        let reading = Reading(active);
+        READERS[active].fetch_add(1, Ordering::SeqCst);
+        // A writer that swapped the tables in between may be writing to this
+        // one already.
+        if ACTIVE.load(Ordering::SeqCst) == active {
+            break reading;
+        }
+    };
+    let table = TABLES[reading.0].load(Ordering::SeqCst);
+    if table.is_null() {
+        return None;
+    }
+    // Safety: writers leave the active table alone and wait for the readers
+    // of the other one to finish before writing to it.
+    Some(f(unsafe { &*table }))
+}
+
+/// Apply `change` to a copy of the active table and make it the active one.
+///
+/// The caller has to hold the `WRITER` lock.
+fn write<F>(change: F)
+where
+    F: FnOnce(&mut CrashTable),
+{
+    let active = ACTIVE.load(Ordering::SeqCst);
+    let inactive = 1 - active;
+    while READERS[inactive].load(Ordering::SeqCst) != 0 {
+        thread::yield_now();
+    }
+    // Safety: nobody reads the inactive table and other writers are locked
+    // out.
+    unsafe {
+        let current = &*TABLES[active].load(Ordering::SeqCst);
+        let next = &mut *TABLES[inactive].load(Ordering::SeqCst);
+        next.len = current.len;
+        next.dropped = current.dropped;
+        next.modules[..current.len].copy_from_slice(current.modules());
+        change(next);
+    }
+    ACTIVE.store(inactive, Ordering::SeqCst);
+}
+
+const OBSERVED: bool = cfg!(any(
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "windows"
+));
+
+#[cfg(any(target_os = "macos", target_os = "ios", target_os = "windows"))]
+fn observe() {
+    #[cfg(any(target_os = "macos", target_os = "ios"))]
+    use crate::macos::{register_image_observer, ImageEvent};
+    #[cfg(target_os = "windows")]
+    use crate::windows::{register_image_observer, ImageEvent};
+
+    register_image_observer(|event, shlib| {
+        let _writer = WRITER.lock().unwrap();
+        let module = CrashModule::new(shlib);
+        match event {
+            // The images that are already loaded are replayed.
+            ImageEvent::Added => write(|table| {
+                if !table.modules().iter().any(|m| m.start == module.start) {
+                    table.push(module);
+                }
+            }),
+            ImageEvent::Removed => write(|table| table.remove(module.start)),
+        }
+    });
+}
+
+#[cfg(not(any(target_os = "macos", target_os = "ios", target_os = "windows")))]
+fn observe() {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn install_and_read() {
+        let addr = Avma(install_and_read as fn() as usize);
+        install();
+        install();
+        update();
+        let found = read(|table| {
+            assert_eq!(table.dropped(), 0);
+            table
+                .modules()
+                .iter()
+                .find(|m| m.range().start <= addr && addr < m.range().end)
+                .copied()
+        })
+        .unwrap()
+        .expect("the test executable should be in the table");
+        assert!(!found.name().is_empty());
+
+        let mut id = None;
+        TargetSharedLibrary::each(|shlib| {
+            if shlib.actual_load_addr() == found.range().start {
+                id = shlib.id();
+            }
+        });
+        let id = id.map(|id| id.to_string()).unwrap_or_default();
+        assert_eq!(found.code_id(), id);
+    }
+
+    #[test]
+    fn overflow() {
+        let mut table = Box::new(CrashTable {
+            len: 0,
+            dropped: 0,
+            modules: [CrashModule::EMPTY; CAPACITY],
+        });
+        for start in 0..CAPACITY + 2 {
+            table.push(CrashModule {
+                start,
+                ..CrashModule::EMPTY
+            });
+        }
+        assert_eq!(table.modules().len(), CAPACITY);
+        assert_eq!(table.dropped(), 2);
+
+        table.remove(3);
+        assert_eq!(table.modules().len(), CAPACITY - 1);
+        assert_eq!(table.modules()[3].start, 4);
+        table.remove(CAPACITY + 1);
+        assert_eq!(table.dropped(), 1);
+    }
+
+    #[test]
+    fn formatting() {
+        let mut buf = [0; ID_LEN];
+        let len = format_id(&mut buf, Some(SharedLibraryId::PeSignature(1, 0x2000)));
+        assert_eq!(id_str(&buf[..usize::from(len)]), "000000012000");
+        let len = format_id(&mut buf, Some(SharedLibraryId::GnuBuildId(vec![0xab; 40])));
+        assert_eq!(len, 0);
+        assert_eq!(format_id(&mut buf, None), 0);
+    }
+}
@@ -0,0 +1,258 @@
+//! Recording what enumerating the shared libraries did.
+//!
+//! When `each` reports fewer libraries than expected, or none at all, it
+//! helps to know whether the loader listed none, or listed some that were
+//! skipped and why, and where the time went. `capture` records this for the
+//! enumerations a closure runs on the current thread:
+//!
+//! ```
+//! use findshlibs::{diagnostics, SharedLibrary, TargetSharedLibrary};
+//!
+//! let ((), diagnostics) = diagnostics::capture(|| {
+//!     TargetSharedLibrary::each(|_| {});
+//! });
+//! println!(
+//!     "visited {} libraries and skipped {}",
+//!     diagnostics.visited(),
+//!     diagnostics.skipped().len(),
+//! );
+//! ```
+//!
+//! Nothing is recorded outside of `capture`.
+//!
+//! The time is split into phases:
+//!
+//! * `"list"`: asking the loader which libraries are loaded, on macOS, iOS
+//!   and Windows.
+//! * `"visit"`: looking at each library and calling back with it. On Linux,
+//!   Android, illumos and Solaris the libraries are listed while they are
+//!   visited.
+
+use std::cell::RefCell;
+use std::ffi::{OsStr, OsString};
+use std::time::{Duration, Instant};
+
+/// Why a library that the loader listed wasn't passed to the callback.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum SkipReason {
+    /// The library's headers couldn't be read.
+    InvalidHeader,
+    /// The library's file name couldn't be found.
+    FileName,
+    /// The loader's information about the library couldn't be found.
+    Information,
+    /// The library's memory couldn't be queried.
+    VirtualQuery,
+    /// The library was unloaded after it was listed.
+    NotMapped,
+}
+
+/// A library that was skipped.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Skipped {
+    address: usize,
+    name: Option<OsString>,
+    reason: SkipReason,
+}
+
+impl Skipped {
+    /// Get the address the loader listed the library at.
+    pub fn address(&self) -> usize {
+        self.address
+    }
+
+    /// Get the library's name, if it was found.
+    pub fn name(&self) -> Option<&OsStr> {
+        self.name.as_deref()
+    }
+
+    /// Get why the library was skipped.
+    pub fn reason(&self) -> SkipReason {
+        self.reason
+    }
+}
+
+/// What the enumerations run in `capture` did.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Diagnostics {
+    enumerations: usize,
+    visited: usize,
+    skipped: Vec<Skipped>,
+    phases: Vec<(&'static str, Duration)>,
+}
+
+impl Diagnostics {
+    /// Get the number of times the libraries were enumerated.
+    pub fn enumerations(&self) -> usize {
+        self.enumerations
+    }
+
+    /// Get the number of libraries that were passed to a callback.
+    pub fn visited(&self) -> usize {
+        self.visited
+    }
+
+    /// Get the libraries that were skipped, in the order they were listed.
+    pub fn skipped(&self) -> &[Skipped] {
+        &self.skipped
+    }
+
+    /// Get the time spent in each phase, in the order they first ran.
+    pub fn phases(&self) -> &[(&'static str, Duration)] {
+        &self.phases
+    }
+
+    /// Get the time spent in the phase with the given name.
+    pub fn phase(&self, name: &str) -> Option<Duration> {
+        self.phases
+            .iter()
+            .find(|(phase, _)| *phase == name)
+            .map(|(_, duration)| *duration)
+    }
+}
+
+thread_local! {
+    static CAPTURED: RefCell<Option<Diagnostics>> = const { RefCell::new(None) };
+}
+
+/// Call `f`, recording what the enumerations it runs on this thread do.
+///
+/// Enumerations on other threads, like those of `collect_all_par`, aren't
+/// recorded. A nested `capture` records its enumerations only for itself.
+pub fn capture<F, R>(f: F) -> (R, Diagnostics)
+where
+    F: FnOnce() -> R,
+{
+    struct Restore(Option<Diagnostics>);
+
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            let outer = self.0.take();
+            let _ = CAPTURED.try_with(|captured| *captured.borrow_mut() = outer);
+        }
+    }
+
+    let outer = CAPTURED.with(|captured| captured.replace(Some(Diagnostics::default())));
+    let restore = Restore(outer);
+    let result = f();
+    let diagnostics = CAPTURED.with(|captured| captured.borrow_mut().take());
+    drop(restore);
+    (result, diagnostics.unwrap_or_default())
+}
+
+fn record<F>(f: F)
+where
+    F: FnOnce(&mut Diagnostics),
+{
+    let _ = CAPTURED.try_with(|captured| {
+        if let Some(diagnostics) = captured.borrow_mut().as_mut() {
+            f(diagnostics);
+        }
+    });
+}
+
+/// Start timing a phase, if anything is being recorded.
+pub(crate) fn start() -> Option<Instant> {
+    let mut capturing = false;
+    let _ = CAPTURED.try_with(|captured| capturing = captured.borrow().is_some());
+    if capturing {
+        Some(Instant::now())
+    } else {
+        None
+    }
+}
+
+/// Add the time since `started` to the phase `name`.
+pub(crate) fn finish(started: Option<Instant>, name: &'static str) {
+    let started = match started {
+        Some(started) => started,
+        None => return,
+    };
+    let elapsed = started.elapsed();
+    record(|diagnostics| {
+        match diagnostics
+            .phases
+            .iter_mut()
+            .find(|(phase, _)| *phase == name)
+        {
+            Some((_, duration)) => *duration += elapsed,
+            None => diagnostics.phases.push((name, elapsed)),
+        }
+    });
+}
+
+/// Count an enumeration.
+pub(crate) fn enumerated() {
+    record(|diagnostics| diagnostics.enumerations += 1);
+}
+
+/// Count a library that is passed to a callback.
+#[cfg(any(
+    target_os = "linux",
+    all(target_os = "android", feature = "dl_iterate_phdr"),
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "illumos",
+    target_os = "solaris",
+    target_os = "windows"
+))]
+pub(crate) fn visited() {
+    record(|diagnostics| diagnostics.visited += 1);
+}
+
+/// Note a library that is skipped.
+#[cfg(any(target_os = "macos", target_os = "ios", target_os = "windows"))]
+pub(crate) fn skipped(address: usize, name: Option<&OsStr>, reason: SkipReason) {
+    record(|diagnostics| {
+        diagnostics.skipped.push(Skipped {
+            address,
+            name: name.map(OsStr::to_os_string),
+            reason,
+        })
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{SharedLibrary, TargetSharedLibrary};
+
+    fn capturing() -> bool {
+        CAPTURED.with(|captured| captured.borrow().is_some())
+    }
+
+    #[test]
+    fn only_while_capturing() {
+        assert!(!capturing());
+        let ((), diagnostics) = capture(|| assert!(capturing()));
+        assert_eq!(diagnostics, Diagnostics::default());
+        assert!(!capturing());
+    }
+
+    #[test]
+    fn counts_enumerations() {
+        let (count, diagnostics) = capture(|| {
+            let mut count = 0;
+            TargetSharedLibrary::each(|_| count += 1);
+            TargetSharedLibrary::each(|_| count += 1);
+            count
+        });
+        assert_eq!(diagnostics.enumerations(), 2);
+        assert_eq!(diagnostics.visited(), count);
+        assert!(diagnostics.phase("visit").is_some());
+        assert_eq!(diagnostics.phase("nope"), None);
+    }
+
+    #[test]
+    fn nested_captures() {
+        let (inner, outer) = capture(|| {
+            let ((), inner) = capture(|| TargetSharedLibrary::each(|_| {}));
+            TargetSharedLibrary::each(|_| {});
+            TargetSharedLibrary::each(|_| {});
+            inner
+        });
+        assert_eq!(inner.enumerations(), 1);
+        assert_eq!(outer.enumerations(), 2);
+        assert!(!capturing());
+    }
+}
@@ -0,0 +1,315 @@
+//! Symbolicating addresses with the DWARF debug information of shared
+//! libraries.
+//!
+//! `SharedLibrary::addr2line_context` loads the debug information of a
+//! library into an `addr2line::Context`, which is queried with stated
+//! addresses. A `Symbolizer` pairs the context with the library's bias, so
+//! that it can be asked about the addresses of the running process directly.
+//!
+//! The debug information is looked for in the library's object file first.
+//! Stripped ELF objects are followed to their separate debug file, by build
+//! ID under `/usr/lib/debug/.build-id` and by `.gnu_debuglink` next to the
+//! object, in its `.debug` directory and under `/usr/lib/debug`. Mach-O
+//! images are followed to their dSYM bundle.
+
+pub use addr2line;
+
+use crate::{Avma, Bias, SharedLibrary, SharedLibraryId, Svma};
+
+use addr2line::{gimli, Context, FrameIter, Location};
+use memmap2::Mmap;
+use object::read::macho::{FatArch, MachOFatFile32, MachOFatFile64};
+use object::{FileKind, Object, ObjectSection};
+
+use std::borrow::Cow;
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+/// The reader the sections of a `Context` are loaded with.
+pub type Reader = gimli::EndianRcSlice<gimli::RunTimeEndian>;
+
+const DEBUG_DIR: &str = "/usr/lib/debug";
+
+/// An `addr2line::Context` for a shared library, queried with actual
+/// addresses.
+pub struct Symbolizer {
+    context: Context<Reader>,
+    bias: Bias,
+}
+
+impl Symbolizer {
+    /// Load the DWARF debug information of `shlib`.
+    pub fn new<L: SharedLibrary>(shlib: &L) -> io::Result<Symbolizer> {
+        Ok(Symbolizer {
+            context: shlib.addr2line_context()?,
+            bias: shlib.virtual_memory_bias(),
+        })
+    }
+
+    /// Get the context, which is queried with stated addresses.
+    pub fn context(&self) -> &Context<Reader> {
+        &self.context
+    }
+
+    /// Take the context, which is queried with stated addresses.
+    pub fn into_context(self) -> Context<Reader> {
+        self.context
+    }
+
+    /// Get the stated address the debug information knows `address` by.
+    pub fn avma_to_svma(&self, address: Avma) -> Svma {
+        Svma(address.0.wrapping_sub(self.bias.0))
+    }
+
+    /// Find the source location of the instruction at `address`.
+    ///
+    /// Return addresses point past their call instruction, so subtract one
+    /// from them first.
+    pub fn find_location(&self, address: Avma) -> Result<Option<Location<'_>>, gimli::Error> {
+        let svma = self.avma_to_svma(address);
+        self.context.find_location(svma.0 as u64)
+    }
+
+    /// Find the frames of the functions inlined at `address`, innermost
+    /// first.
+    ///
+    /// Split DWARF units are not loaded, so their frames are missing.
+    pub fn find_frames(&self, address: Avma) -> Result<FrameIter<'_, Reader>, gimli::Error> {
+        let svma = self.avma_to_svma(address);
+        self.context.find_frames(svma.0 as u64).skip_all_loads()
+    }
+}
+
+impl fmt::Debug for Symbolizer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Symbolizer")
+            .field("bias", &self.bias)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A file the debug information of an object may be in, and how to tell
+/// that it is the right one.
+#[derive(Debug)]
+struct DebugFile {
+    path: PathBuf,
+    build_id: Option<Vec<u8>>,
+    crc: Option<u32>,
+}
+
+fn invalid_data<E>(error: E) -> io::Error
+where
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+fn map(path: &Path) -> io::Result<Mmap> {
+    let file = File::open(path)?;
+    unsafe { Mmap::map(&file) }
+}
+
+/// Parse the object in `data`, which for a universal binary is the slice
+/// with `uuid`.
+fn parse(data: &[u8], uuid: Option<[u8; 16]>) -> io::Result<object::File<'_>> {
+    let data = match FileKind::parse(data).map_err(invalid_data)? {
+        FileKind::MachOFat32 => fat_slice(
+            MachOFatFile32::parse(data).map_err(invalid_data)?.arches(),
+            data,
+            uuid,
+        )?,
+        FileKind::MachOFat64 => fat_slice(
+            MachOFatFile64::parse(data).map_err(invalid_data)?.arches(),
+            data,
+            uuid,
+        )?,
+        _ => data,
+    };
+    object::File::parse(data).map_err(invalid_data)
+}
+
+fn fat_slice<'data, A: FatArch>(
+    arches: &[A],
+    data: &'data [u8],
+    uuid: Option<[u8; 16]>,
+) -> io::Result<&'data [u8]> {
+    for arch in arches {
+        let slice = arch.data(data).map_err(invalid_data)?;
+        let slice_uuid = object::File::parse(slice)
+            .ok()
+            .and_then(|object| object.mach_uuid().ok().flatten());
+        if uuid.is_some() && slice_uuid == uuid {
+            return Ok(slice);
+        }
+    }
+    Err(invalid_data(
+        "no architecture slice of the file matches the image",
+    ))
+}
+
+fn has_dwarf(object: &object::File) -> bool {
+    object.section_by_name(".debug_info").is_some()
+}
+
+fn load_context(object: &object::File) -> io::Result<Context<Reader>> {
+    let endian = if object.is_little_endian() {
+        gimli::RunTimeEndian::Little
+    } else {
+        gimli::RunTimeEndian::Big
+    };
+    let dwarf = gimli::Dwarf::load(|id| -> io::Result<Reader> {
+        let data = match object.section_by_name(id.name()) {
+            Some(section) => section.uncompressed_data().map_err(invalid_data)?,
+            None => Cow::Borrowed(&[][..]),
+        };
+        Ok(gimli::EndianRcSlice::new(Rc::from(&*data), endian))
+    })?;
+    Context::from_dwarf(dwarf).map_err(invalid_data)
+}
+
+/// Get the files the debug information of the stripped `object` at `path`
+/// may be in, in the order they are tried.
+fn debug_files(path: &Path, object: &object::File) -> Vec<DebugFile> {
+    let mut files = vec![];
+    if let Ok(Some(build_id)) = object.build_id() {
+        if build_id.len() >= 2 {
+            let hex: String = build_id.iter().map(|b| format!("{:02x}", b)).collect();
+            let path = Path::new(DEBUG_DIR)
+                .join(".build-id")
+                .join(&hex[..2])
+                .join(format!("{}.debug", &hex[2..]));
+            files.push(DebugFile {
+                path,
+                build_id: Some(build_id.to_vec()),
+                crc: None,
+            });
+        }
+    }
+    if let (Ok(Some((name, crc))), Some(dir)) = (object.gnu_debuglink(), path.parent()) {
+        if let Ok(name) = std::str::from_utf8(name) {
+            let global = Path::new(DEBUG_DIR).join(dir.strip_prefix("/").unwrap_or(dir));
+            for dir in [dir.to_owned(), dir.join(".debug"), global] {
+                files.push(DebugFile {
+                    path: dir.join(name),
+                    build_id: None,
+                    crc: Some(crc),
+                });
+            }
+        }
+    }
+    #[cfg(any(target_os = "macos", target_os = "ios"))]
+    if let Ok(Some(uuid)) = object.mach_uuid() {
+        if let Some(dsym) = crate::macos::find_dsym(&uuid, path) {
+            files.push(DebugFile {
+                path: dsym.dwarf_file().to_owned(),
+                build_id: None,
+                crc: None,
+            });
+        }
+    }
+    files
+}
+
+/// Compute the CRC-32 that `.gnu_debuglink` records of the debug file.
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+/// Load the DWARF debug information of `shlib` into a context.
+pub(crate) fn context<L: SharedLibrary>(shlib: &L) -> io::Result<Context<Reader>> {
+    let path = Path::new(shlib.code_file());
+    let uuid = match shlib.id() {
+        Some(SharedLibraryId::Uuid(uuid)) => Some(uuid),
+        _ => None,
+    };
+
+    let files = {
+        let map = map(path)?;
+        let object = parse(&map, uuid)?;
+        if has_dwarf(&object) {
+            return load_context(&object);
+        }
+        debug_files(path, &object)
+    };
+
+    for file in files {
+        let map = match map(&file.path) {
+            Ok(map) => map,
+            Err(_) => continue,
+        };
+        if file.crc.is_some_and(|crc| crc != crc32(&map)) {
+            continue;
+        }
+        let object = match parse(&map, uuid) {
+            Ok(object) => object,
+            Err(_) => continue,
+        };
+        if let Some(build_id) = &file.build_id {
+            if object.build_id().ok().flatten() != Some(&build_id[..]) {
+                continue;
+            }
+        }
+        if has_dwarf(&object) {
+            return load_context(&object);
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::NotFound,
+        "no DWARF debug information was found",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Segment, TargetSharedLibrary};
+
+    #[test]
+    fn debuglink_crc() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    }
+
+    #[test]
+    fn symbolize_this_function() {
+        let here = Avma(symbolize_this_function as fn() as usize);
+        let mut found = false;
+        TargetSharedLibrary::each(|shlib| {
+            if !shlib
+                .segments()
+                .any(|segment| segment.is_code() && segment.contains_avma(shlib, here))
+            {
+                return;
+            }
+            found = true;
+
+            // Elsewhere, the debug information of tests is left in object
+            // files or PDBs.
+            if cfg!(target_os = "linux") {
+                let symbolizer = Symbolizer::new(shlib).unwrap();
+                assert_eq!(symbolizer.avma_to_svma(here), shlib.avma_to_svma(here));
+                let location = symbolizer.find_location(here).unwrap().unwrap();
+                assert!(location.file.unwrap().ends_with("dwarf.rs"));
+                let mut frames = symbolizer.find_frames(here).unwrap();
+                let frame = frames.next().unwrap().unwrap();
+                let name = frame.function.unwrap().demangle().unwrap().into_owned();
+                assert!(name.ends_with("symbolize_this_function"), "{}", name);
+            }
+        });
+        assert_eq!(found, crate::TARGET_SUPPORTED);
+    }
+}
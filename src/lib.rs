@@ -38,6 +38,7 @@
 //! * Windows
 //! * Android
 //! * iOS
+//! * illumos and Solaris
 //!
 //! If a platform is not supported then a fallback implementation is used that
 //! does nothing.  To see if your platform does something at runtime the
@@ -102,22 +103,70 @@ pub mod macos;
 
 #[cfg(any(
     target_os = "linux",
-    all(target_os = "android", feature = "dl_iterate_phdr")
+    all(target_os = "android", feature = "dl_iterate_phdr"),
+    target_os = "illumos",
+    target_os = "solaris"
 ))]
 pub mod linux;
 
 #[cfg(target_os = "windows")]
 pub mod windows;
 
-use std::ffi::OsStr;
+use std::borrow::Cow;
+use std::ffi::{CStr, OsStr};
 use std::fmt::{self, Debug};
-use std::usize;
+use std::ops::Range;
 
 pub mod unsupported;
 
+#[cfg(feature = "memmap2")]
+pub mod backing_file;
+
+#[cfg(feature = "addr2line")]
+pub mod dwarf;
+
+mod census;
+pub use crate::census::{executable_regions, ExecutableRegion, ExecutableSource};
+
+pub mod crash_table;
+
+pub mod diagnostics;
+
+pub mod unwind;
+
+mod module_map;
+pub use crate::module_map::{global_map, ModuleMap};
+
+#[cfg(feature = "minidump")]
+pub mod minidump;
+
+pub mod offline;
+
+#[cfg(feature = "samply")]
+pub mod samply;
+
+#[cfg(feature = "sentry")]
+pub mod sentry;
+
+mod loaded;
+mod snapshot;
+mod verify;
+mod watcher;
+pub use crate::loaded::{find_loaded, is_loaded};
+#[cfg(feature = "rayon")]
+pub use crate::snapshot::collect_all_par;
+pub use crate::snapshot::{
+    find_by_id, CachedLibraries, NameStyle, OwnedSegment, OwnedSegmentIter, OwnedSharedLibrary,
+    Snapshot, SortOrder,
+};
+pub use crate::verify::FileMatch;
+pub use crate::watcher::{ModuleEvent, ModuleWatcher, WatcherThread};
+
 #[cfg(any(
     target_os = "linux",
-    all(target_os = "android", feature = "dl_iterate_phdr")
+    all(target_os = "android", feature = "dl_iterate_phdr"),
+    target_os = "illumos",
+    target_os = "solaris"
 ))]
 use crate::linux as native_mod;
 
@@ -132,6 +181,8 @@ use crate::windows as native_mod;
     target_os = "ios",
     target_os = "linux",
     all(target_os = "android", feature = "dl_iterate_phdr"),
+    target_os = "illumos",
+    target_os = "solaris",
     target_os = "windows"
 )))]
 use unsupported as native_mod;
@@ -140,12 +191,18 @@ use unsupported as native_mod;
 /// implementation for the target operating system.
 pub type TargetSharedLibrary<'a> = native_mod::SharedLibrary<'a>;
 
+/// The [`Segment` trait](./trait.Segment.html) implementation for the target
+/// operating system.
+pub type TargetSegment<'a> = native_mod::Segment<'a>;
+
 /// An indicator if this platform is supported.
 pub const TARGET_SUPPORTED: bool = cfg!(any(
     target_os = "macos",
     target_os = "ios",
     target_os = "linux",
     all(target_os = "android", feature = "dl_iterate_phdr"),
+    target_os = "illumos",
+    target_os = "solaris",
     target_os = "windows"
 ));
 
@@ -239,6 +296,81 @@ pub trait Segment: Sized + Debug {
         self.is_code()
     }
 
+    /// Returns `true` if this is a loaded segment of writable data.
+    ///
+    /// By default, this is a loaded segment that is mapped writable but not
+    /// executable.
+    #[inline]
+    fn is_data(&self) -> bool {
+        self.is_load()
+            && self
+                .permissions()
+                .is_some_and(|perms| perms.is_writable() && !perms.is_executable())
+    }
+
+    /// Returns `true` if this is a loaded segment of read-only data.
+    ///
+    /// By default, this is a loaded segment that is mapped readable, but
+    /// neither writable nor executable.
+    #[inline]
+    fn is_readonly_data(&self) -> bool {
+        self.is_load()
+            && self.permissions().is_some_and(|perms| {
+                perms.is_readable() && !perms.is_writable() && !perms.is_executable()
+            })
+    }
+
+    /// Returns `true` if some of this segment is zero-filled memory that isn't
+    /// backed by the object file, like `.bss`.
+    ///
+    /// Data segments often end in zero-filled memory, so a segment can be both
+    /// data and bss.
+    #[inline]
+    fn is_bss(&self) -> bool {
+        false
+    }
+
+    /// Get the memory protection this segment is mapped with, if known.
+    ///
+    /// This is the protection the object file asks for. The process may have
+    /// changed it since the segment was mapped, e.g. with `mprotect`.
+    #[inline]
+    fn permissions(&self) -> Option<Permissions> {
+        None
+    }
+
+    /// Get the range of the object file that this segment's contents are
+    /// loaded from, if known.
+    ///
+    /// Offsets are relative to the start of the object, which for a Mach-O
+    /// image in a universal binary is the start of its architecture slice.
+    /// The range is empty for segments that aren't backed by the file, and
+    /// shorter than the segment when it ends in zero-filled memory.
+    #[inline]
+    fn file_range(&self) -> Option<Range<u64>> {
+        None
+    }
+
+    /// Get the type the object file gives this segment, as the platform
+    /// encodes it.
+    ///
+    /// This is the `p_type` of an ELF program header and the `cmd` of a Mach-O
+    /// segment's load command. PE sections don't have a type.
+    #[inline]
+    fn raw_type(&self) -> Option<u32> {
+        None
+    }
+
+    /// Get the flags the object file gives this segment, as the platform
+    /// encodes them.
+    ///
+    /// These are the `p_flags` of an ELF program header, the `flags` of a
+    /// Mach-O segment and the `Characteristics` of a PE section.
+    #[inline]
+    fn raw_flags(&self) -> Option<u64> {
+        None
+    }
+
     /// Get this segment's stated virtual address of this segment.
     ///
     /// This is the virtual memory address without the bias applied. See the
@@ -254,40 +386,150 @@ pub trait Segment: Sized + Debug {
     ///
     /// This is the virtual memory address with the bias applied. See the module
     /// documentation for details.
+    ///
+    /// Address arithmetic wraps around, like it does for the loader: a bias
+    /// can be "negative" when an image is loaded below its stated address.
     #[inline]
     fn actual_virtual_memory_address(&self, shlib: &Self::SharedLibrary) -> Avma {
+        self.wrapping_actual_virtual_memory_address(shlib)
+    }
+
+    /// Get this segment's actual virtual memory address, or `None` if adding
+    /// the bias overflows.
+    ///
+    /// The bias is treated as signed here, so that applying a "negative" bias
+    /// only fails if the address would end up below zero.
+    #[inline]
+    fn checked_actual_virtual_memory_address(&self, shlib: &Self::SharedLibrary) -> Option<Avma> {
+        let svma = self.stated_virtual_memory_address();
+        let bias = shlib.virtual_memory_bias();
+        svma.0.checked_add_signed(bias.0 as isize).map(Avma)
+    }
+
+    /// Get this segment's actual virtual memory address, wrapping around at
+    /// the end of the address space.
+    #[inline]
+    fn wrapping_actual_virtual_memory_address(&self, shlib: &Self::SharedLibrary) -> Avma {
         let svma = self.stated_virtual_memory_address();
         let bias = shlib.virtual_memory_bias();
-        Avma(svma.0 + bias.0)
+        Avma(svma.0.wrapping_add(bias.0))
+    }
+
+    /// Get the stated address just past the end of this segment, or `None` if
+    /// the segment extends past the end of the address space.
+    #[inline]
+    fn checked_stated_end(&self) -> Option<Svma> {
+        let start = self.stated_virtual_memory_address().0;
+        start.checked_add(self.len()).map(Svma)
+    }
+
+    /// Get the actual address just past the end of this segment, or `None` if
+    /// it can't be represented.
+    #[inline]
+    fn checked_actual_end(&self, shlib: &Self::SharedLibrary) -> Option<Avma> {
+        let start = self.checked_actual_virtual_memory_address(shlib)?.0;
+        start.checked_add(self.len()).map(Avma)
+    }
+
+    /// Get the stated addresses this segment covers.
+    ///
+    /// The end saturates at the end of the address space, so a segment that
+    /// would wrap around it is cut short there instead of yielding an empty
+    /// range.
+    #[inline]
+    fn stated_range(&self) -> Range<Svma> {
+        let start = self.stated_virtual_memory_address();
+        start..Svma(start.0.saturating_add(self.len()))
+    }
+
+    /// Get the actual addresses this segment covers.
+    ///
+    /// The start wraps around like `actual_virtual_memory_address`, and the
+    /// end saturates at the end of the address space like `stated_range`.
+    #[inline]
+    fn actual_range(&self, shlib: &Self::SharedLibrary) -> Range<Avma> {
+        let start = self.actual_virtual_memory_address(shlib);
+        start..Avma(start.0.saturating_add(self.len()))
     }
 
     /// Does this segment contain the given address?
+    ///
+    /// A segment that wraps around the end of the address space contains the
+    /// addresses on both sides of it.
     #[inline]
     fn contains_svma(&self, address: Svma) -> bool {
         let start = self.stated_virtual_memory_address().0;
-        let end = start + self.len();
-        let address = address.0;
-        start <= address && address < end
+        address.0.wrapping_sub(start) < self.len()
     }
 
     /// Does this segment contain the given address?
+    ///
+    /// A segment that wraps around the end of the address space contains the
+    /// addresses on both sides of it.
     #[inline]
     fn contains_avma(&self, shlib: &Self::SharedLibrary, address: Avma) -> bool {
         let start = self.actual_virtual_memory_address(shlib).0;
-        let end = start + self.len();
-        let address = address.0;
-        start <= address && address < end
+        address.0.wrapping_sub(start) < self.len()
+    }
+}
+
+/// The memory protection of a segment.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Permissions {
+    read: bool,
+    write: bool,
+    execute: bool,
+}
+
+impl Permissions {
+    /// Construct a new `Permissions`.
+    pub fn new(read: bool, write: bool, execute: bool) -> Permissions {
+        Permissions {
+            read,
+            write,
+            execute,
+        }
+    }
+
+    /// Returns `true` if the memory can be read.
+    pub fn is_readable(&self) -> bool {
+        self.read
+    }
+
+    /// Returns `true` if the memory can be written.
+    pub fn is_writable(&self) -> bool {
+        self.write
+    }
+
+    /// Returns `true` if the memory can be executed.
+    pub fn is_executable(&self) -> bool {
+        self.execute
+    }
+}
+
+impl fmt::Display for Permissions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}{}{}",
+            if self.read { 'r' } else { '-' },
+            if self.write { 'w' } else { '-' },
+            if self.execute { 'x' } else { '-' },
+        )
     }
 }
 
 /// Represents an ID for a shared library.
-#[derive(PartialEq, Eq, Hash)]
+#[derive(Clone, PartialEq, Eq, Hash)]
 pub enum SharedLibraryId {
     /// A UUID (used on mac)
     Uuid([u8; 16]),
     /// A GNU build ID
     GnuBuildId(Vec<u8>),
     /// The PE timestamp and size
+    ///
+    /// For reproducible builds the timestamp is derived from a hash of the
+    /// image rather than being a time.
     PeSignature(u32, u32),
     /// A PDB GUID and age,
     PdbSignature([u8; 16], u32),
@@ -297,10 +539,10 @@ impl SharedLibraryId {
     /// Returns the raw bytes of the shared library ID.
     pub fn as_bytes(&self) -> &[u8] {
         match *self {
-            SharedLibraryId::Uuid(ref bytes) => &*bytes,
+            SharedLibraryId::Uuid(ref bytes) => bytes,
             SharedLibraryId::GnuBuildId(ref bytes) => bytes,
             SharedLibraryId::PeSignature(_, _) => &[][..],
-            SharedLibraryId::PdbSignature(ref bytes, _) => &*bytes,
+            SharedLibraryId::PdbSignature(ref bytes, _) => bytes,
         }
     }
 }
@@ -350,6 +592,43 @@ impl fmt::Debug for SharedLibraryId {
     }
 }
 
+/// A symbol that an address was resolved to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Symbol<'a> {
+    name: &'a CStr,
+    address: Avma,
+}
+
+impl<'a> Symbol<'a> {
+    /// Construct a symbol from its (mangled) name and address.
+    pub fn new(name: &'a CStr, address: Avma) -> Symbol<'a> {
+        Symbol { name, address }
+    }
+
+    /// Get the (mangled) name of this symbol.
+    pub fn name(&self) -> &'a CStr {
+        self.name
+    }
+
+    /// Get the address this symbol starts at.
+    pub fn address(&self) -> Avma {
+        self.address
+    }
+}
+
+/// The kind of object a shared library was loaded from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum SharedLibraryKind {
+    /// The main executable of the process.
+    Executable,
+    /// A shared library that other objects can link against.
+    Library,
+    /// A plugin that is only ever loaded at runtime, such as a macOS bundle.
+    Plugin,
+    /// The dynamic linker itself.
+    DynamicLinker,
+}
+
 /// A trait representing a shared library that is loaded in this process.
 #[allow(clippy::len_without_is_empty)]
 pub trait SharedLibrary: Sized + Debug {
@@ -362,11 +641,44 @@ pub trait SharedLibrary: Sized + Debug {
     /// Get the name of this shared library.
     fn name(&self) -> &OsStr;
 
+    /// Get the name of this shared library as bytes, without converting it.
+    ///
+    /// On Unix these are the bytes the loader reports. On Windows they are
+    /// UTF-8 for every name that is valid Unicode, and otherwise the WTF-8
+    /// encoding that `OsStr` uses.
+    fn name_bytes(&self) -> &[u8] {
+        self.name().as_encoded_bytes()
+    }
+
     /// Get the name of the debug file with this shared library if there is one.
     fn debug_name(&self) -> Option<&OsStr> {
         None
     }
 
+    /// Get the path of this shared library's binary, which symbol servers
+    /// call its code file.
+    fn code_file(&self) -> &OsStr {
+        self.name()
+    }
+
+    /// Get the name of the file with this shared library's debug information,
+    /// which symbol servers call its debug file.
+    ///
+    /// This is the PDB named by the image on Windows, the dSYM that `dsymutil`
+    /// creates next to the image on macOS, and the code file itself on other
+    /// platforms. By default, it is the `debug_name` if there is one, and
+    /// otherwise the code file.
+    fn debug_file(&self) -> Option<Cow<'_, OsStr>> {
+        Some(Cow::Borrowed(
+            self.debug_name().unwrap_or_else(|| self.code_file()),
+        ))
+    }
+
+    /// Get the kind of object this shared library was loaded from, if known.
+    fn kind(&self) -> Option<SharedLibraryKind> {
+        None
+    }
+
     /// Get the code-id of this shared library if available.
     fn id(&self) -> Option<SharedLibraryId>;
 
@@ -380,11 +692,12 @@ pub trait SharedLibrary: Sized + Debug {
     ///
     /// This address maps to the `Avma` of the first segment loaded into
     /// memory. Depending on the platform, this segment may not contain code.
+    /// For a library without loaded segments, this is its bias.
     fn actual_load_addr(&self) -> Avma {
         self.segments()
             .find(|x| x.is_load())
             .map(|x| x.actual_virtual_memory_address(self))
-            .unwrap_or(Avma(usize::MAX))
+            .unwrap_or(Avma(self.virtual_memory_bias().0))
     }
 
     #[inline]
@@ -399,27 +712,35 @@ pub trait SharedLibrary: Sized + Debug {
     ///
     /// This address maps to the `Svma` of the first segment loaded into
     /// memory. Depending on the platform, this segment may not contain code.
+    /// For a library without loaded segments, this is zero.
     fn stated_load_addr(&self) -> Svma {
         self.segments()
             .find(|x| x.is_load())
             .map(|x| x.stated_virtual_memory_address())
-            .unwrap_or(Svma(usize::MAX))
+            .unwrap_or(Svma(0))
     }
 
     /// Returns the size of the image.
     ///
     /// This typically is the size of the executable code segment.  This is
     /// normally used by server side symbolication systems to determine when
-    /// an IP no longer falls into an image.
+    /// an IP no longer falls into an image. It is zero for a library without
+    /// loaded segments.
     fn len(&self) -> usize {
         let end_address = self
             .segments()
             .filter(|x| x.is_load())
-            .map(|x| x.actual_virtual_memory_address(self).0 + x.len())
-            .max()
-            .unwrap_or(usize::MAX);
-
-        end_address - self.actual_load_addr().0
+            .map(|x| {
+                x.actual_virtual_memory_address(self)
+                    .0
+                    .wrapping_add(x.len())
+            })
+            .max();
+
+        match end_address {
+            Some(end_address) => end_address.wrapping_sub(self.actual_load_addr().0),
+            None => 0,
+        }
     }
 
     /// Iterate over this shared library's segments.
@@ -432,10 +753,104 @@ pub trait SharedLibrary: Sized + Debug {
 
     /// Given an AVMA within this shared library, convert it back to an SVMA by
     /// removing this shared library's bias.
+    ///
+    /// Like `Segment::actual_virtual_memory_address`, this wraps around.
     #[inline]
     fn avma_to_svma(&self, address: Avma) -> Svma {
         let bias = self.virtual_memory_bias();
-        Svma(address.0 - bias.0)
+        Svma(address.0.wrapping_sub(bias.0))
+    }
+
+    /// Get the offset of `address` from the address this shared library is
+    /// loaded at, see `actual_load_addr`.
+    ///
+    /// On Windows this is the RVA, elsewhere it is the offset from the start
+    /// of the first loaded segment, which is what symbol formats like
+    /// Breakpad's key on. Unlike `avma_to_svma`, this does not depend on the
+    /// addresses the library states. Returns `None` if `address` isn't within
+    /// `len()` bytes of the load address.
+    fn relative_address(&self, address: Avma) -> Option<u64> {
+        let offset = address.0.checked_sub(self.actual_load_addr().0)?;
+        if offset < self.len() {
+            Some(offset as u64)
+        } else {
+            None
+        }
+    }
+
+    /// Check whether the file at `code_file()` is still the one this shared
+    /// library was loaded from, by comparing its id to the file's.
+    ///
+    /// A mismatch means the file was replaced since the library was loaded,
+    /// e.g. by an upgrade, and symbols for it don't fit this library. See the
+    /// `verify` module for where the id is read from in each format.
+    fn verify_backing_file(&self) -> FileMatch {
+        verify::verify_backing_file(self)
+    }
+
+    /// Find the segment of this shared library that contains `address`.
+    ///
+    /// Segments loaded into memory are preferred over the ones describing
+    /// part of them, like ELF's `PT_DYNAMIC` and `PT_GNU_RELRO`.
+    fn segment_containing_avma(&self, address: Avma) -> Option<Self::Segment> {
+        let mut found = None;
+        for segment in self.segments() {
+            if segment.contains_avma(self, address) {
+                if segment.is_load() {
+                    return Some(segment);
+                }
+                found.get_or_insert(segment);
+            }
+        }
+        found
+    }
+
+    /// Find the segment of this shared library that contains `address`.
+    ///
+    /// Segments loaded into memory are preferred over the ones describing
+    /// part of them, like ELF's `PT_DYNAMIC` and `PT_GNU_RELRO`.
+    fn segment_containing_svma(&self, address: Svma) -> Option<Self::Segment> {
+        let mut found = None;
+        for segment in self.segments() {
+            if segment.contains_svma(address) {
+                if segment.is_load() {
+                    return Some(segment);
+                }
+                found.get_or_insert(segment);
+            }
+        }
+        found
+    }
+
+    /// Load this shared library's DWARF debug information into an
+    /// `addr2line::Context`.
+    ///
+    /// The context is queried with stated addresses, see `avma_to_svma`, or
+    /// `dwarf::Symbolizer` for a context that takes actual ones. See the
+    /// `dwarf` module for where the debug information is looked for.
+    #[cfg(feature = "addr2line")]
+    fn addr2line_context(&self) -> std::io::Result<addr2line::Context<dwarf::Reader>> {
+        dwarf::context(self)
+    }
+
+    /// Find the symbol covering `address` within this shared library.
+    ///
+    /// This is meant for lightweight in-process symbolication and only knows
+    /// about the symbols the platform keeps in memory, which is often just
+    /// the exported ones. Returns `None` if `address` isn't inside this shared
+    /// library or no symbol is known for it.
+    fn symbol_for_avma(&self, address: Avma) -> Option<Symbol<'_>> {
+        let _ = address;
+        None
+    }
+
+    /// Get the names of the shared libraries this one is linked against.
+    ///
+    /// These are the names recorded by the linker, which are often just file
+    /// names rather than paths. Backends that don't know about dependencies
+    /// return an empty list.
+    fn dependencies(&self) -> Vec<&OsStr> {
+        Vec::new()
     }
 
     /// Find all shared libraries in this process and invoke `f` with each one.
@@ -461,6 +876,26 @@ impl From<()> for IterationControl {
     }
 }
 
+/// Find every segment of every shared library in this process and invoke `f`
+/// with each one, along with the library it belongs to.
+///
+/// The segments of a library are visited together, and the libraries in the
+/// order `SharedLibrary::each` visits them.
+pub fn each_segment<F, C>(mut f: F)
+where
+    F: FnMut(&TargetSharedLibrary, &TargetSegment) -> C,
+    C: Into<IterationControl>,
+{
+    TargetSharedLibrary::each(|shlib| {
+        for segment in shlib.segments() {
+            if f(shlib, &segment).into() == IterationControl::Break {
+                return IterationControl::Break;
+            }
+        }
+        IterationControl::Continue
+    });
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -470,7 +905,7 @@ mod tests {
         use std::panic;
 
         match panic::catch_unwind(|| {
-            TargetSharedLibrary::each(|_| panic!("uh oh"));
+            TargetSharedLibrary::each::<_, ()>(|_| panic!("uh oh"));
         }) {
             Ok(()) => panic!("Expected a panic, but didn't get one"),
             Err(any) => {
@@ -491,4 +926,76 @@ mod tests {
             assert_eq!(lib.avma_to_svma(avma), svma);
         });
     }
+
+    #[test]
+    fn each_segment_visits_all() {
+        let mut expected = vec![];
+        TargetSharedLibrary::each(|shlib| {
+            for segment in shlib.segments() {
+                expected.push((shlib.name().to_owned(), segment.name().to_owned()));
+            }
+        });
+        let mut visited = vec![];
+        each_segment(|shlib, segment| {
+            visited.push((shlib.name().to_owned(), segment.name().to_owned()));
+        });
+        assert_eq!(visited, expected);
+
+        let mut count = 0;
+        each_segment(|_, _| {
+            count += 1;
+            IterationControl::Break
+        });
+        assert_eq!(count, usize::from(!expected.is_empty()));
+    }
+
+    #[test]
+    fn relative_addresses() {
+        let here = Avma(relative_addresses as fn() as usize);
+        let mut found = false;
+        TargetSharedLibrary::each(|shlib| {
+            let base = shlib.actual_load_addr();
+            if shlib.len() != 0 {
+                assert_eq!(shlib.relative_address(base), Some(0));
+            }
+            assert_eq!(shlib.relative_address(Avma(base.0 + shlib.len())), None);
+            if base.0 != 0 {
+                assert_eq!(shlib.relative_address(Avma(base.0 - 1)), None);
+            }
+            if let Some(offset) = shlib.relative_address(here) {
+                found = true;
+                assert_eq!(offset, (here.0 - base.0) as u64);
+            }
+        });
+        assert_eq!(found, TARGET_SUPPORTED);
+    }
+
+    #[test]
+    fn name_bytes() {
+        TargetSharedLibrary::each(|shlib| {
+            let bytes = SharedLibrary::name_bytes(shlib);
+            assert_eq!(bytes, shlib.name().as_encoded_bytes());
+            if let Some(name) = shlib.name().to_str() {
+                assert_eq!(bytes, name.as_bytes());
+            }
+        });
+    }
+
+    #[test]
+    fn code_and_debug_files() {
+        TargetSharedLibrary::each(|lib| {
+            assert_eq!(lib.code_file(), lib.name());
+            if let Some(debug_name) = lib.debug_name() {
+                assert_eq!(lib.debug_file().unwrap(), debug_name);
+            } else if cfg!(target_os = "linux") {
+                assert_eq!(lib.debug_file().unwrap(), lib.code_file());
+            }
+        });
+    }
+
+    #[test]
+    fn display_permissions() {
+        assert_eq!(Permissions::new(true, false, true).to_string(), "r-x");
+        assert_eq!(Permissions::default().to_string(), "---");
+    }
 }
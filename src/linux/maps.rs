@@ -0,0 +1,207 @@
+//! Finding the special mappings of the address space, which aren't loaded
+//! from an object file.
+//!
+//! The kernel sets up a few mappings of its own, like the vDSO's data pages
+//! and the legacy `vsyscall` page, next to the stack and heap. The dynamic
+//! linker doesn't know about any of them except the vDSO, which it reports
+//! like a shared library, so they are read from the names `/proc/self/maps`
+//! gives them.
+
+use crate::{Avma, ExecutableRegion, ExecutableSource, IterationControl, Permissions};
+
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::ops::Range;
+
+/// What a special mapping is for.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum SpecialMappingKind {
+    /// `[vvar]` and `[vvar_vclock]`: data the vDSO reads, like the time.
+    Vvar,
+    /// `[vsyscall]`: the legacy fixed-address page of system call entry
+    /// points on x86-64.
+    Vsyscall,
+    /// `[stack]`: the stack of the main thread.
+    Stack,
+    /// `[heap]`: the memory `brk` hands out.
+    Heap,
+    /// Any other mapping with a name in brackets, like `[vectors]` on ARM or
+    /// the `[anon:...]` names of Android. The name is without the brackets.
+    Other(String),
+}
+
+impl SpecialMappingKind {
+    fn from_name(name: &str) -> SpecialMappingKind {
+        match name {
+            "vvar" | "vvar_vclock" => SpecialMappingKind::Vvar,
+            "vsyscall" => SpecialMappingKind::Vsyscall,
+            "stack" => SpecialMappingKind::Stack,
+            "heap" => SpecialMappingKind::Heap,
+            name => SpecialMappingKind::Other(name.to_owned()),
+        }
+    }
+}
+
+/// A mapping of the address space that isn't part of a shared library.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SpecialMapping {
+    range: Range<Avma>,
+    permissions: Permissions,
+    kind: SpecialMappingKind,
+}
+
+impl SpecialMapping {
+    /// Get the addresses the mapping covers.
+    pub fn range(&self) -> Range<Avma> {
+        self.range.clone()
+    }
+
+    /// Get the protection the mapping currently has.
+    pub fn permissions(&self) -> Permissions {
+        self.permissions
+    }
+
+    /// Get what the mapping is for.
+    pub fn kind(&self) -> &SpecialMappingKind {
+        &self.kind
+    }
+
+    /// Parse a line of `/proc/self/maps`, unless it is for a file or an
+    /// anonymous mapping without a name.
+    fn from_maps_line(line: &str) -> Option<SpecialMapping> {
+        let (range, permissions, name) = parse_maps_line(line)?;
+        let name = name.strip_prefix('[')?.strip_suffix(']')?;
+        // The vDSO is reported as a shared library.
+        if name == "vdso" {
+            return None;
+        }
+        Some(SpecialMapping {
+            range,
+            permissions,
+            kind: SpecialMappingKind::from_name(name),
+        })
+    }
+}
+
+/// Parse a line of `/proc/self/maps` into the mapping's addresses,
+/// protection and name, which is empty for anonymous mappings.
+fn parse_maps_line(line: &str) -> Option<(Range<Avma>, Permissions, &str)> {
+    let mut fields = line.splitn(6, ' ');
+    let (start, end) = fields.next()?.split_once('-')?;
+    let perms = fields.next()?.as_bytes();
+    // The offset, device and inode.
+    fields.nth(2)?;
+    let name = fields.next().unwrap_or("").trim_start();
+
+    let start = usize::from_str_radix(start, 16).ok()?;
+    let end = usize::from_str_radix(end, 16).ok()?;
+    let permissions = Permissions::new(
+        perms.first() == Some(&b'r'),
+        perms.get(1) == Some(&b'w'),
+        perms.get(2) == Some(&b'x'),
+    );
+    Some((Avma(start)..Avma(end), permissions, name))
+}
+
+/// Find the special mappings of the current process, like the stack, the
+/// heap and the pages the kernel maps for the vDSO, and invoke `f` with each
+/// one, in the order of their addresses.
+///
+/// Together with the segments of the shared libraries, these cover the
+/// address space except for the mappings the program makes itself, with
+/// `mmap` or through its allocator. Fails if `/proc/self/maps` can't be
+/// read, e.g. in sandboxes without `/proc`.
+pub fn each_special_mapping<F, C>(mut f: F) -> io::Result<()>
+where
+    F: FnMut(&SpecialMapping) -> C,
+    C: Into<IterationControl>,
+{
+    let maps = fs::read_to_string("/proc/self/maps")?;
+    for mapping in maps.lines().filter_map(SpecialMapping::from_maps_line) {
+        if f(&mapping).into() == IterationControl::Break {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Get every executable mapping of the current process, or none if
+/// `/proc/self/maps` can't be read.
+pub(crate) fn executable_mappings() -> Vec<ExecutableRegion> {
+    let maps = match fs::read_to_string("/proc/self/maps") {
+        Ok(maps) => maps,
+        Err(_) => return vec![],
+    };
+    maps.lines()
+        .filter_map(parse_maps_line)
+        .filter(|(_, permissions, _)| permissions.is_executable())
+        .map(|(range, _, name)| {
+            let source = match name
+                .strip_prefix('[')
+                .and_then(|name| name.strip_suffix(']'))
+            {
+                Some(name) => ExecutableSource::Special(name.to_owned()),
+                None if name.is_empty() => ExecutableSource::Anonymous,
+                None => ExecutableSource::File(OsString::from(name)),
+            };
+            ExecutableRegion::new(range, source)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_lines() {
+        let stack = SpecialMapping::from_maps_line(
+            "7e2d4000-7e2f5000 rw-p 00000000 00:00 0                          [stack]",
+        )
+        .unwrap();
+        assert_eq!(stack.kind(), &SpecialMappingKind::Stack);
+        assert_eq!(stack.range(), Avma(0x7e2d_4000)..Avma(0x7e2f_5000));
+        assert_eq!(stack.permissions(), Permissions::new(true, true, false));
+
+        let vsyscall = SpecialMapping::from_maps_line(
+            "ff600000-ff601000 --xp 00000000 00:00 0                  [vsyscall]",
+        )
+        .unwrap();
+        assert_eq!(vsyscall.kind(), &SpecialMappingKind::Vsyscall);
+        assert_eq!(vsyscall.permissions(), Permissions::new(false, false, true));
+
+        let named = SpecialMapping::from_maps_line(
+            "7f2a00000000-7f2a00021000 rw-p 00000000 00:00 0    [anon:dalvik-main space]",
+        )
+        .unwrap();
+        assert_eq!(
+            named.kind(),
+            &SpecialMappingKind::Other("anon:dalvik-main space".to_owned())
+        );
+
+        for line in [
+            "7f1c2a000000-7f1c2a028000 r--p 00000000 08:01 1835 /usr/lib/libc.so.6",
+            "7f1c2a200000-7f1c2a201000 rw-p 00000000 00:00 0",
+            "7ffc5e3f0000-7ffc5e3f2000 r-xp 00000000 00:00 0                          [vdso]",
+        ] {
+            assert_eq!(SpecialMapping::from_maps_line(line), None);
+        }
+        let (range, permissions, name) =
+            parse_maps_line("7e200000-7e201000 rwxp 00000000 00:00 0").unwrap();
+        assert_eq!(range, Avma(0x7e20_0000)..Avma(0x7e20_1000));
+        assert!(permissions.is_executable());
+        assert_eq!(name, "");
+    }
+
+    #[test]
+    fn main_thread_stack() {
+        let mut kinds = vec![];
+        each_special_mapping(|mapping| {
+            assert!(mapping.range().start < mapping.range().end);
+            kinds.push(mapping.kind().clone());
+        })
+        .unwrap();
+        assert!(kinds.contains(&SpecialMappingKind::Stack));
+    }
+}
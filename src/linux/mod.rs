@@ -1,24 +1,68 @@
 //! Linux-specific implementation of the `SharedLibrary` trait.
+//!
+//! Android, illumos and Solaris load ELF objects the same way and share this
+//! implementation.
 
 use libc;
 
 use crate::Segment as SegmentTrait;
 use crate::SharedLibrary as SharedLibraryTrait;
-use crate::{Bias, IterationControl, SharedLibraryId, Svma};
+use crate::{
+    diagnostics, Avma, Bias, IterationControl, OwnedSharedLibrary, Permissions, SharedLibraryId,
+    SharedLibraryKind, Svma, Symbol,
+};
 
 use std::any::Any;
 use std::borrow::Cow;
+use std::cell::OnceCell;
+use std::convert::TryInto;
 use std::env::current_exe;
 use std::ffi::{CStr, CString, OsStr};
 use std::fmt;
 use std::iter;
 use std::marker::PhantomData;
 use std::mem;
+use std::ops::Range;
 use std::os::unix::ffi::OsStrExt;
 use std::os::unix::ffi::OsStringExt;
 use std::panic;
+use std::path::PathBuf;
+use std::ptr;
 use std::slice;
-use std::usize;
+use std::sync::OnceLock;
+
+mod maps;
+
+pub(crate) use self::maps::executable_mappings;
+pub use self::maps::{each_special_mapping, SpecialMapping, SpecialMappingKind};
+
+// illumos and Solaris have the same ELF structures, but `libc` only knows
+// some of the constants for them.
+#[cfg(not(any(target_os = "illumos", target_os = "solaris")))]
+use libc as elf;
+
+#[cfg(any(target_os = "illumos", target_os = "solaris"))]
+#[allow(non_camel_case_types)]
+mod elf {
+    pub use libc::*;
+
+    pub type Elf32_Word = u32;
+
+    pub const PT_NULL: u32 = 0;
+    pub const PT_LOAD: u32 = 1;
+    pub const PT_DYNAMIC: u32 = 2;
+    pub const PT_INTERP: u32 = 3;
+    pub const PT_NOTE: u32 = 4;
+    pub const PT_SHLIB: u32 = 5;
+    pub const PT_PHDR: u32 = 6;
+    pub const PT_TLS: u32 = 7;
+    pub const PT_GNU_EH_FRAME: u32 = 0x6474_e550;
+    pub const PT_GNU_STACK: u32 = 0x6474_e551;
+    pub const PT_GNU_RELRO: u32 = 0x6474_e552;
+
+    pub const AT_PHDR: c_ulong = 3;
+    pub const AT_BASE: c_ulong = 7;
+}
 
 #[cfg(target_pointer_width = "32")]
 type Phdr = libc::Elf32_Phdr;
@@ -28,15 +72,187 @@ type Phdr = libc::Elf64_Phdr;
 
 const NT_GNU_BUILD_ID: u32 = 3;
 
+const PF_X: u32 = 0x1;
+const PF_W: u32 = 0x2;
+const PF_R: u32 = 0x4;
+
+// Segment types that not every version of `libc` knows about.
+const PT_GNU_PROPERTY: u32 = 0x6474_e553;
+const PT_GNU_SFRAME: u32 = 0x6474_e554;
+const PT_OPENBSD_MUTABLE: u32 = 0x65a3_dbe5;
+const PT_OPENBSD_RANDOMIZE: u32 = 0x65a3_dbe6;
+const PT_OPENBSD_WXNEEDED: u32 = 0x65a3_dbe7;
+const PT_OPENBSD_NOBTCFI: u32 = 0x65a3_dbe8;
+const PT_OPENBSD_SYSCALLS: u32 = 0x65a3_dbe9;
+const PT_OPENBSD_BOOTDATA: u32 = 0x65a4_1be6;
+const PT_SUNW_UNWIND: u32 = 0x6464_e550;
+
+// Processor-specific segment types, which overlap between architectures.
+const PT_ARM_EXIDX: u32 = 0x7000_0001;
+const PT_AARCH64_MEMTAG_MTE: u32 = 0x7000_0002;
+const PT_RISCV_ATTRIBUTES: u32 = 0x7000_0003;
+
 // Normally we would use `Elf32_Nhdr` on 32-bit platforms and `Elf64_Nhdr` on
 // 64-bit platforms. However, in practice it seems that only `Elf32_Nhdr` is
 // used, and reading through binutil's `readelf` source confirms this.
 #[repr(C)]
 #[derive(Debug, Clone, Copy)]
 struct Nhdr {
-    pub n_namesz: libc::Elf32_Word,
-    pub n_descsz: libc::Elf32_Word,
-    pub n_type: libc::Elf32_Word,
+    pub n_namesz: elf::Elf32_Word,
+    pub n_descsz: elf::Elf32_Word,
+    pub n_type: elf::Elf32_Word,
+}
+
+/// The type of an ELF segment, from its `p_type`.
+///
+/// Processor-specific types share their values between architectures, so
+/// they only get their own variant on the architecture they belong to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum SegmentKind {
+    /// `PT_NULL`: Unused.
+    Null,
+    /// `PT_LOAD`: Loaded into memory.
+    Load,
+    /// `PT_DYNAMIC`: Dynamic linking information.
+    Dynamic,
+    /// `PT_INTERP`: The path of the program interpreter.
+    Interp,
+    /// `PT_NOTE`: Auxiliary information, such as the build id.
+    Note,
+    /// `PT_SHLIB`: Reserved.
+    Shlib,
+    /// `PT_PHDR`: The program header table itself.
+    Phdr,
+    /// `PT_TLS`: The thread-local storage template.
+    Tls,
+    /// `PT_GNU_EH_FRAME`: The `.eh_frame_hdr` unwind table lookup section.
+    GnuEhFrame,
+    /// `PT_GNU_STACK`: The permissions the stack should have.
+    GnuStack,
+    /// `PT_GNU_RELRO`: Made read-only after relocation.
+    GnuRelro,
+    /// `PT_GNU_PROPERTY`: The `.note.gnu.property` section.
+    GnuProperty,
+    /// `PT_GNU_SFRAME`: The `.sframe` stack trace section.
+    GnuSframe,
+    /// `PT_OPENBSD_MUTABLE`: OpenBSD: memory that stays mutable.
+    OpenbsdMutable,
+    /// `PT_OPENBSD_RANDOMIZE`: OpenBSD: filled with random data when loaded.
+    OpenbsdRandomize,
+    /// `PT_OPENBSD_WXNEEDED`: OpenBSD: the program needs writable and executable memory.
+    OpenbsdWxneeded,
+    /// `PT_OPENBSD_NOBTCFI`: OpenBSD: the program doesn't support branch target CFI.
+    OpenbsdNobtcfi,
+    /// `PT_OPENBSD_SYSCALLS`: OpenBSD: the locations of system call stubs.
+    OpenbsdSyscalls,
+    /// `PT_OPENBSD_BOOTDATA`: OpenBSD: boot arguments.
+    OpenbsdBootdata,
+    /// `PT_SUNW_UNWIND`: illumos and Solaris: the `.eh_frame_hdr` section, as
+    /// their link editor calls it.
+    SunwUnwind,
+    /// `PT_ARM_EXIDX`: ARM: the `.ARM.exidx` unwind table.
+    ArmExidx,
+    /// `PT_AARCH64_MEMTAG_MTE`: AArch64: MTE memory tags.
+    Aarch64MemtagMte,
+    /// `PT_RISCV_ATTRIBUTES`: RISC-V: the `.riscv.attributes` section.
+    RiscvAttributes,
+    /// A segment type without a variant of its own.
+    Other(u32),
+}
+
+impl SegmentKind {
+    /// Get the raw `p_type` of this kind of segment.
+    pub fn p_type(self) -> u32 {
+        match self {
+            SegmentKind::Null => elf::PT_NULL,
+            SegmentKind::Load => elf::PT_LOAD,
+            SegmentKind::Dynamic => elf::PT_DYNAMIC,
+            SegmentKind::Interp => elf::PT_INTERP,
+            SegmentKind::Note => elf::PT_NOTE,
+            SegmentKind::Shlib => elf::PT_SHLIB,
+            SegmentKind::Phdr => elf::PT_PHDR,
+            SegmentKind::Tls => elf::PT_TLS,
+            SegmentKind::GnuEhFrame => elf::PT_GNU_EH_FRAME,
+            SegmentKind::GnuStack => elf::PT_GNU_STACK,
+            SegmentKind::GnuRelro => elf::PT_GNU_RELRO,
+            SegmentKind::GnuProperty => PT_GNU_PROPERTY,
+            SegmentKind::GnuSframe => PT_GNU_SFRAME,
+            SegmentKind::OpenbsdMutable => PT_OPENBSD_MUTABLE,
+            SegmentKind::OpenbsdRandomize => PT_OPENBSD_RANDOMIZE,
+            SegmentKind::OpenbsdWxneeded => PT_OPENBSD_WXNEEDED,
+            SegmentKind::OpenbsdNobtcfi => PT_OPENBSD_NOBTCFI,
+            SegmentKind::OpenbsdSyscalls => PT_OPENBSD_SYSCALLS,
+            SegmentKind::OpenbsdBootdata => PT_OPENBSD_BOOTDATA,
+            SegmentKind::SunwUnwind => PT_SUNW_UNWIND,
+            SegmentKind::ArmExidx => PT_ARM_EXIDX,
+            SegmentKind::Aarch64MemtagMte => PT_AARCH64_MEMTAG_MTE,
+            SegmentKind::RiscvAttributes => PT_RISCV_ATTRIBUTES,
+            SegmentKind::Other(p_type) => p_type,
+        }
+    }
+
+    /// Get the name of this kind of segment, without the `PT_` prefix.
+    pub fn name(self) -> &'static str {
+        match self {
+            SegmentKind::Null => "NULL",
+            SegmentKind::Load => "LOAD",
+            SegmentKind::Dynamic => "DYNAMIC",
+            SegmentKind::Interp => "INTERP",
+            SegmentKind::Note => "NOTE",
+            SegmentKind::Shlib => "SHLIB",
+            SegmentKind::Phdr => "PHDR",
+            SegmentKind::Tls => "TLS",
+            SegmentKind::GnuEhFrame => "GNU_EH_FRAME",
+            SegmentKind::GnuStack => "GNU_STACK",
+            SegmentKind::GnuRelro => "GNU_RELRO",
+            SegmentKind::GnuProperty => "GNU_PROPERTY",
+            SegmentKind::GnuSframe => "GNU_SFRAME",
+            SegmentKind::OpenbsdMutable => "OPENBSD_MUTABLE",
+            SegmentKind::OpenbsdRandomize => "OPENBSD_RANDOMIZE",
+            SegmentKind::OpenbsdWxneeded => "OPENBSD_WXNEEDED",
+            SegmentKind::OpenbsdNobtcfi => "OPENBSD_NOBTCFI",
+            SegmentKind::OpenbsdSyscalls => "OPENBSD_SYSCALLS",
+            SegmentKind::OpenbsdBootdata => "OPENBSD_BOOTDATA",
+            SegmentKind::SunwUnwind => "SUNW_UNWIND",
+            SegmentKind::ArmExidx => "ARM_EXIDX",
+            SegmentKind::Aarch64MemtagMte => "AARCH64_MEMTAG_MTE",
+            SegmentKind::RiscvAttributes => "RISCV_ATTRIBUTES",
+            SegmentKind::Other(_) => "(unknown segment type)",
+        }
+    }
+}
+
+impl From<u32> for SegmentKind {
+    fn from(p_type: u32) -> SegmentKind {
+        match p_type {
+            elf::PT_NULL => SegmentKind::Null,
+            elf::PT_LOAD => SegmentKind::Load,
+            elf::PT_DYNAMIC => SegmentKind::Dynamic,
+            elf::PT_INTERP => SegmentKind::Interp,
+            elf::PT_NOTE => SegmentKind::Note,
+            elf::PT_SHLIB => SegmentKind::Shlib,
+            elf::PT_PHDR => SegmentKind::Phdr,
+            elf::PT_TLS => SegmentKind::Tls,
+            elf::PT_GNU_EH_FRAME => SegmentKind::GnuEhFrame,
+            elf::PT_GNU_STACK => SegmentKind::GnuStack,
+            elf::PT_GNU_RELRO => SegmentKind::GnuRelro,
+            PT_GNU_PROPERTY => SegmentKind::GnuProperty,
+            PT_GNU_SFRAME => SegmentKind::GnuSframe,
+            PT_OPENBSD_MUTABLE => SegmentKind::OpenbsdMutable,
+            PT_OPENBSD_RANDOMIZE => SegmentKind::OpenbsdRandomize,
+            PT_OPENBSD_WXNEEDED => SegmentKind::OpenbsdWxneeded,
+            PT_OPENBSD_NOBTCFI => SegmentKind::OpenbsdNobtcfi,
+            PT_OPENBSD_SYSCALLS => SegmentKind::OpenbsdSyscalls,
+            PT_OPENBSD_BOOTDATA => SegmentKind::OpenbsdBootdata,
+            PT_SUNW_UNWIND => SegmentKind::SunwUnwind,
+            PT_ARM_EXIDX if cfg!(target_arch = "arm") => SegmentKind::ArmExidx,
+            PT_AARCH64_MEMTAG_MTE if cfg!(target_arch = "aarch64") => SegmentKind::Aarch64MemtagMte,
+            PT_RISCV_ATTRIBUTES if cfg!(any(target_arch = "riscv32", target_arch = "riscv64")) => {
+                SegmentKind::RiscvAttributes
+            }
+            other => SegmentKind::Other(other),
+        }
+    }
 }
 
 /// A mapped segment in an ELF file.
@@ -58,8 +274,13 @@ impl<'a> Segment<'a> {
         slice::from_raw_parts(avma as *const u8, phdr.p_memsz as usize)
     }
 
+    /// Get the type of this segment.
+    pub fn kind(&self) -> SegmentKind {
+        SegmentKind::from(self.phdr().p_type)
+    }
+
     fn is_note(&self) -> bool {
-        self.phdr().p_type == libc::PT_NOTE
+        self.phdr().p_type == elf::PT_NOTE
     }
 
     /// Parse the contents of a `PT_NOTE` segment.
@@ -74,7 +295,7 @@ impl<'a> Segment<'a> {
     unsafe fn notes(
         &self,
         shlib: &SharedLibrary<'a>,
-    ) -> impl Iterator<Item = (libc::Elf32_Word, &'a [u8], &'a [u8])> {
+    ) -> impl Iterator<Item = (elf::Elf32_Word, &'a [u8], &'a [u8])> {
         // `man 5 readelf` says that all of the `Nhdr`, name, and descriptor are
         // always 4-byte aligned, but we copy this alignment behavior from
         // `readelf` since that seems to match reality in practice.
@@ -98,7 +319,12 @@ impl<'a> Segment<'a> {
         let mut data = self.data(shlib);
 
         iter::from_fn(move || {
-            if (data.as_ptr() as usize % alignment) != 0 {
+            // `align_up` only supports these alignments, so anything else
+            // would fail after the first header anyway.
+            if alignment != 4 && alignment != 8 {
+                return None;
+            }
+            if (data.as_ptr() as usize & (alignment - 1)) != 0 {
                 return None;
             }
 
@@ -142,34 +368,51 @@ impl<'a> SegmentTrait for Segment<'a> {
     type SharedLibrary = SharedLibrary<'a>;
 
     fn name(&self) -> &str {
-        unsafe {
-            match self.phdr.as_ref().unwrap().p_type {
-                libc::PT_NULL => "NULL",
-                libc::PT_LOAD => "LOAD",
-                libc::PT_DYNAMIC => "DYNAMIC",
-                libc::PT_INTERP => "INTERP",
-                libc::PT_NOTE => "NOTE",
-                libc::PT_SHLIB => "SHLIB",
-                libc::PT_PHDR => "PHDR",
-                libc::PT_TLS => "TLS",
-                libc::PT_GNU_EH_FRAME => "GNU_EH_FRAME",
-                libc::PT_GNU_STACK => "GNU_STACK",
-                libc::PT_GNU_RELRO => "GNU_RELRO",
-                _ => "(unknown segment type)",
-            }
-        }
+        self.kind().name()
     }
 
     #[inline]
     fn is_code(&self) -> bool {
         let hdr = self.phdr();
-        // 0x1 is PT_X for executable
-        hdr.p_type == libc::PT_LOAD && (hdr.p_flags & 0x1) != 0
+        hdr.p_type == elf::PT_LOAD && (hdr.p_flags & PF_X) != 0
     }
 
     #[inline]
     fn is_load(&self) -> bool {
-        self.phdr().p_type == libc::PT_LOAD
+        self.phdr().p_type == elf::PT_LOAD
+    }
+
+    #[inline]
+    fn is_bss(&self) -> bool {
+        let hdr = self.phdr();
+        hdr.p_type == elf::PT_LOAD && hdr.p_memsz > hdr.p_filesz
+    }
+
+    #[inline]
+    fn permissions(&self) -> Option<Permissions> {
+        let flags = self.phdr().p_flags;
+        Some(Permissions::new(
+            flags & PF_R != 0,
+            flags & PF_W != 0,
+            flags & PF_X != 0,
+        ))
+    }
+
+    #[inline]
+    fn file_range(&self) -> Option<Range<u64>> {
+        let hdr = self.phdr();
+        let start: u64 = hdr.p_offset as _;
+        Some(start..start.saturating_add(hdr.p_filesz as _))
+    }
+
+    #[inline]
+    fn raw_type(&self) -> Option<u32> {
+        Some(self.phdr().p_type)
+    }
+
+    #[inline]
+    fn raw_flags(&self) -> Option<u64> {
+        Some(u64::from(self.phdr().p_flags))
     }
 
     #[inline]
@@ -194,7 +437,7 @@ impl<'a> Iterator for SegmentIter<'a> {
     #[inline]
     fn next(&mut self) -> Option<Self::Item> {
         self.inner.next().map(|phdr| Segment {
-            phdr: phdr,
+            phdr,
             shlib: PhantomData,
         })
     }
@@ -202,11 +445,95 @@ impl<'a> Iterator for SegmentIter<'a> {
 
 impl<'a> fmt::Debug for SegmentIter<'a> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let ref phdr = self.inner.as_slice()[0];
+        let mut debug = f.debug_struct("SegmentIter");
+        if let Some(phdr) = self.inner.as_slice().first() {
+            debug.field("phdr", &DebugPhdr(phdr));
+        }
+        debug.finish()
+    }
+}
 
-        f.debug_struct("SegmentIter")
-            .field("phdr", &DebugPhdr(phdr))
-            .finish()
+static EXECUTABLE_PATH: OnceLock<Option<CString>> = OnceLock::new();
+
+/// Get the path of the executable, which is only looked up once.
+fn executable_path() -> Option<&'static CStr> {
+    EXECUTABLE_PATH
+        .get_or_init(|| {
+            let exe = current_exe().ok()?;
+            CString::new(exe.into_os_string().into_vec()).ok()
+        })
+        .as_deref()
+}
+
+/// Set the path reported as the name of the executable.
+///
+/// The dynamic linker doesn't know the executable's path, so it is read
+/// from `/proc/self/exe` the first time it is needed. That fails in
+/// sandboxes without `/proc`, and may not be the path the user expects
+/// anyway, which this allows working around.
+///
+/// This has to be called before shared libraries are first iterated over.
+/// Returns the path back if the executable's path was already determined,
+/// or if it contains a NUL byte.
+pub fn set_executable_path(path: PathBuf) -> Result<(), PathBuf> {
+    let bytes = path.clone().into_os_string().into_vec();
+    let path_c = match CString::new(bytes) {
+        Ok(path) => path,
+        Err(_) => return Err(path),
+    };
+    EXECUTABLE_PATH.set(Some(path_c)).map_err(|_| path)
+}
+
+/// Get the value of an entry of the aux vector, or 0 if it has none.
+#[cfg(not(any(target_os = "illumos", target_os = "solaris")))]
+fn aux_value(key: libc::c_ulong) -> usize {
+    unsafe { libc::getauxval(key) as usize }
+}
+
+/// Get the value of an entry of the aux vector, or 0 if it has none.
+///
+/// There is no `getauxval`, but the aux vector can be read from
+/// `/proc/self/auxv`, which is only done once.
+#[cfg(any(target_os = "illumos", target_os = "solaris"))]
+fn aux_value(key: libc::c_ulong) -> usize {
+    static AUXV: OnceLock<Vec<(libc::c_ulong, usize)>> = OnceLock::new();
+
+    // Each entry is an `int` type, padded to a word, and a word of value.
+    const WORD: usize = mem::size_of::<usize>();
+    let auxv = AUXV.get_or_init(|| {
+        let data = std::fs::read("/proc/self/auxv").unwrap_or_default();
+        data.chunks_exact(2 * WORD)
+            .map(|entry| {
+                let a_type = i32::from_ne_bytes(entry[..4].try_into().unwrap());
+                let a_val = usize::from_ne_bytes(entry[WORD..].try_into().unwrap());
+                (a_type as libc::c_ulong, a_val)
+            })
+            .take_while(|&(a_type, _)| a_type != 0)
+            .collect()
+    });
+    auxv.iter()
+        .find(|&&(a_type, _)| a_type == key)
+        .map_or(0, |&(_, a_val)| a_val)
+}
+
+/// Keeps a shared library loaded for as long as it is held.
+///
+/// See [`SharedLibrary::pin`](struct.SharedLibrary.html#method.pin).
+#[derive(Debug)]
+pub struct PinnedLibrary {
+    handle: *mut libc::c_void,
+}
+
+// The handle is only ever passed to `dlclose`, which can be called from any
+// thread.
+unsafe impl Send for PinnedLibrary {}
+unsafe impl Sync for PinnedLibrary {}
+
+impl Drop for PinnedLibrary {
+    fn drop(&mut self) {
+        unsafe {
+            libc::dlclose(self.handle);
+        }
     }
 }
 
@@ -216,6 +543,9 @@ pub struct SharedLibrary<'a> {
     addr: *const u8,
     name: Cow<'a, CStr>,
     headers: &'a [Phdr],
+    is_first_lib: bool,
+    // The notes are only searched for the build ID once.
+    id: OnceCell<Option<SharedLibraryId>>,
 }
 
 struct IterState<F> {
@@ -231,8 +561,8 @@ impl<'a> SharedLibrary<'a> {
     unsafe fn new(info: &'a libc::dl_phdr_info, size: usize, is_first_lib: bool) -> Self {
         // try to get the name from the dl_phdr_info.  If that fails there are two
         // cases we can and need to deal with.  The first one is if we are the first
-        // loaded library in which case the name is the executable which we can
-        // discover via env::current_exe (reads the proc/self symlink).
+        // loaded library in which case the name is the executable, whose path
+        // `executable_path` looks up once (or was set by the user).
         //
         // Otherwise if we have a no name we might be a dylib that was loaded with
         // dlopen in which case we can use dladdr to recover the name.
@@ -243,22 +573,32 @@ impl<'a> SharedLibrary<'a> {
         });
         if name.to_bytes().is_empty() {
             if is_first_lib {
-                if let Ok(exe) = current_exe() {
-                    name = Cow::Owned(CString::from_vec_unchecked(exe.into_os_string().into_vec()));
+                if let Some(exe) = executable_path() {
+                    name = Cow::Borrowed(exe);
                 }
             } else {
                 let mut dlinfo: libc::Dl_info = mem::zeroed();
-                if libc::dladdr(info.dlpi_addr as *const libc::c_void, &mut dlinfo) != 0 {
+                if libc::dladdr(info.dlpi_addr as *const libc::c_void, &mut dlinfo) != 0
+                    && !dlinfo.dli_fname.is_null()
+                {
                     name = Cow::Owned(CString::from(CStr::from_ptr(dlinfo.dli_fname)));
                 }
             }
         }
 
         SharedLibrary {
-            size: size,
+            size,
             addr: info.dlpi_addr as usize as *const _,
             name,
-            headers: slice::from_raw_parts(info.dlpi_phdr, info.dlpi_phnum as usize),
+            // `slice::from_raw_parts` needs a non-null pointer, even for an
+            // empty slice.
+            headers: if info.dlpi_phdr.is_null() || info.dlpi_phnum == 0 {
+                &[]
+            } else {
+                slice::from_raw_parts(info.dlpi_phdr, info.dlpi_phnum as usize)
+            },
+            is_first_lib,
+            id: OnceCell::new(),
         }
     }
 
@@ -271,16 +611,13 @@ impl<'a> SharedLibrary<'a> {
         F: FnMut(&Self) -> C,
         C: Into<IterationControl>,
     {
-        if (*info).dlpi_phdr.is_null() {
-            return CONTINUE;
-        }
-
         let state = &mut *(state as *mut IterState<F>);
         state.idx += 1;
 
         match panic::catch_unwind(panic::AssertUnwindSafe(|| {
             let info = info.as_ref().unwrap();
             let shlib = SharedLibrary::new(info, size, state.idx == 1);
+            diagnostics::visited();
 
             (state.f)(&shlib).into()
         })) {
@@ -293,21 +630,136 @@ impl<'a> SharedLibrary<'a> {
         }
     }
 
-    fn note_segments(&self) -> impl Iterator<Item = Segment<'a>> {
-        self.segments().filter(|s| s.is_note())
+    /// Create a `SharedLibrary` from an entry that `dl_iterate_phdr` passes
+    /// to a callback, for code that already runs its own iteration, e.g.
+    /// from a C library's callback, and doesn't want to iterate again.
+    ///
+    /// The executable is the entry whose program headers are the ones the
+    /// kernel reports with `AT_PHDR`, rather than the first entry, since the
+    /// order of the caller's iteration isn't known. Its name is filled in
+    /// like `each` does.
+    ///
+    /// # Safety
+    ///
+    /// `info` must describe an object loaded in the current process, with a
+    /// name and program headers that stay valid for `'a`. Within a
+    /// `dl_iterate_phdr` callback this holds for the `dl_phdr_info` it is
+    /// passed, but the object may be unloaded once the callback returns.
+    pub unsafe fn from_dl_phdr_info(info: &'a libc::dl_phdr_info) -> Self {
+        let phdr = aux_value(elf::AT_PHDR);
+        let is_first_lib = phdr != 0 && info.dlpi_phdr as usize == phdr;
+        SharedLibrary::new(info, mem::size_of::<libc::dl_phdr_info>(), is_first_lib)
     }
-}
 
-impl<'a> SharedLibraryTrait for SharedLibrary<'a> {
-    type Segment = Segment<'a>;
-    type SegmentIter = SegmentIter<'a>;
+    /// Get the name of the shared library as the dynamic linker reports it,
+    /// without converting it.
+    pub fn name_cstr(&self) -> &CStr {
+        &self.name
+    }
 
-    #[inline]
-    fn name(&self) -> &OsStr {
-        OsStr::from_bytes(self.name.to_bytes())
+    /// Get the name of the shared library as bytes, without the trailing NUL.
+    pub fn name_bytes(&self) -> &[u8] {
+        self.name.to_bytes()
     }
 
-    fn id(&self) -> Option<SharedLibraryId> {
+    /// Get the addresses of the `PT_GNU_SFRAME` segment, which holds the
+    /// `.sframe` stack trace information that newer toolchains emit as a
+    /// lighter alternative to `.eh_frame` for unwinding.
+    pub fn sframe(&self) -> Option<Range<Avma>> {
+        let segment = self
+            .segments()
+            .find(|segment| segment.kind() == SegmentKind::GnuSframe)?;
+        Some(segment.actual_range(self))
+    }
+
+    /// Get the addresses of the `PT_GNU_EH_FRAME` or `PT_SUNW_UNWIND`
+    /// segment, which holds the `.eh_frame_hdr` section, the search table for
+    /// the `.eh_frame` unwind information.
+    pub fn eh_frame_hdr(&self) -> Option<Range<Avma>> {
+        let segment = self.segments().find(|segment| {
+            matches!(
+                segment.kind(),
+                SegmentKind::GnuEhFrame | SegmentKind::SunwUnwind
+            )
+        })?;
+        Some(segment.actual_range(self))
+    }
+
+    /// Get the addresses of the `.eh_frame` section, as `.eh_frame_hdr`
+    /// points to it.
+    ///
+    /// The program headers don't say how long the section is, so the range
+    /// runs to the end of the loaded segment containing it. Unwinders stop
+    /// at the terminator at the end of the section anyway.
+    pub fn eh_frame(&self) -> Option<Range<Avma>> {
+        let hdr = self.eh_frame_hdr()?;
+        if hdr.end.0.saturating_sub(hdr.start.0) < 4 {
+            return None;
+        }
+        // Safety: the segment is loaded for as long as the library is.
+        let data =
+            unsafe { slice::from_raw_parts(hdr.start.0 as *const u8, hdr.end.0 - hdr.start.0) };
+        // The version must be 1, and the pointer is the first field after the
+        // four bytes of version and encodings.
+        if data[0] != 1 {
+            return None;
+        }
+        let encoding = data[1];
+        let field = &data[4..];
+        let value = match encoding & 0x0f {
+            DW_EH_PE_ABSPTR => {
+                let bytes = field.get(..mem::size_of::<usize>())?;
+                usize::from_ne_bytes(bytes.try_into().ok()?)
+            }
+            DW_EH_PE_UDATA4 => u32::from_ne_bytes(field.get(..4)?.try_into().ok()?) as usize,
+            DW_EH_PE_SDATA4 => i32::from_ne_bytes(field.get(..4)?.try_into().ok()?) as usize,
+            DW_EH_PE_UDATA8 | DW_EH_PE_SDATA8 => {
+                u64::from_ne_bytes(field.get(..8)?.try_into().ok()?) as usize
+            }
+            _ => return None,
+        };
+        let start = match encoding & 0x70 {
+            // Absolute pointers aren't relocated in a read-only section.
+            0x00 => self.virtual_memory_bias().0.wrapping_add(value),
+            DW_EH_PE_PCREL => (hdr.start.0 + 4).wrapping_add(value),
+            DW_EH_PE_DATAREL => hdr.start.0.wrapping_add(value),
+            _ => return None,
+        };
+        let segment = self
+            .segments()
+            .find(|segment| segment.is_load() && segment.contains_avma(self, Avma(start)))?;
+        Some(Avma(start)..segment.actual_range(self).end)
+    }
+
+    /// Keep this shared library loaded until the returned guard is dropped,
+    /// so that addresses in it stay valid after the callback returns.
+    ///
+    /// The reference is taken with `dlopen(RTLD_NOLOAD)` on the library's
+    /// name, so this returns `None` for objects the dynamic linker can't open
+    /// by name, such as the vDSO.
+    pub fn pin(&self) -> Option<PinnedLibrary> {
+        let name = if self.is_first_lib {
+            // The executable is never unloaded, but hand out a guard anyway.
+            ptr::null()
+        } else if self.name.to_bytes().is_empty() {
+            // An empty name would open the executable instead.
+            return None;
+        } else {
+            self.name.as_ptr()
+        };
+        let handle = unsafe { libc::dlopen(name, libc::RTLD_LAZY | libc::RTLD_NOLOAD) };
+        if handle.is_null() {
+            None
+        } else {
+            Some(PinnedLibrary { handle })
+        }
+    }
+
+    fn note_segments(&self) -> impl Iterator<Item = Segment<'a>> {
+        self.segments().filter(|s| s.is_note())
+    }
+
+    fn find_build_id(&self) -> Option<SharedLibraryId> {
         // Search for `PT_NOTE` segments, containing auxiliary information.
         // Such segments contain a series of "notes" and one kind of note is
         // `NT_GNU_BUILD_ID`, whose payload contains a unique identifier
@@ -322,6 +774,46 @@ impl<'a> SharedLibraryTrait for SharedLibrary<'a> {
 
         None
     }
+}
+
+// The pointer encodings of `.eh_frame_hdr`, from the LSB's `DW_EH_PE_*`.
+const DW_EH_PE_ABSPTR: u8 = 0x00;
+const DW_EH_PE_UDATA4: u8 = 0x03;
+const DW_EH_PE_UDATA8: u8 = 0x04;
+const DW_EH_PE_SDATA4: u8 = 0x0b;
+const DW_EH_PE_SDATA8: u8 = 0x0c;
+const DW_EH_PE_PCREL: u8 = 0x10;
+const DW_EH_PE_DATAREL: u8 = 0x30;
+
+impl<'a> SharedLibraryTrait for SharedLibrary<'a> {
+    type Segment = Segment<'a>;
+    type SegmentIter = SegmentIter<'a>;
+
+    #[inline]
+    fn name(&self) -> &OsStr {
+        OsStr::from_bytes(self.name.to_bytes())
+    }
+
+    #[inline]
+    fn name_bytes(&self) -> &[u8] {
+        self.name.to_bytes()
+    }
+
+    fn kind(&self) -> Option<SharedLibraryKind> {
+        // The executable is always reported first, and the kernel tells us
+        // where it mapped the dynamic linker through the aux vector.
+        if self.is_first_lib {
+            Some(SharedLibraryKind::Executable)
+        } else if self.addr as usize == aux_value(elf::AT_BASE) {
+            Some(SharedLibraryKind::DynamicLinker)
+        } else {
+            Some(SharedLibraryKind::Library)
+        }
+    }
+
+    fn id(&self) -> Option<SharedLibraryId> {
+        self.id.get_or_init(|| self.find_build_id()).clone()
+    }
 
     #[inline]
     fn segments(&self) -> Self::SegmentIter {
@@ -335,6 +827,27 @@ impl<'a> SharedLibraryTrait for SharedLibrary<'a> {
         Bias(self.addr as usize)
     }
 
+    fn symbol_for_avma(&self, address: Avma) -> Option<Symbol<'_>> {
+        if !self
+            .segments()
+            .any(|seg| seg.is_load() && seg.contains_avma(self, address))
+        {
+            return None;
+        }
+
+        // `dladdr` only knows about the dynamic symbol table, but that is
+        // the one that is guaranteed to be mapped.
+        let mut info: libc::Dl_info = unsafe { mem::zeroed() };
+        if unsafe { libc::dladdr(address.0 as *const _, &mut info) } == 0
+            || info.dli_sname.is_null()
+        {
+            return None;
+        }
+
+        let name = unsafe { CStr::from_ptr(info.dli_sname) };
+        Some(Symbol::new(name, Avma(info.dli_saddr as usize)))
+    }
+
     #[inline]
     fn each<F, C>(f: F)
     where
@@ -342,14 +855,17 @@ impl<'a> SharedLibraryTrait for SharedLibrary<'a> {
         C: Into<IterationControl>,
     {
         let mut state = IterState {
-            f: f,
+            f,
             panic: None,
             idx: 0,
         };
 
+        diagnostics::enumerated();
+        let visiting = diagnostics::start();
         unsafe {
             libc::dl_iterate_phdr(Some(Self::callback::<F, C>), &mut state as *mut _ as *mut _);
         }
+        diagnostics::finish(visiting, "visit");
 
         if let Some(panic) = state.panic {
             panic::resume_unwind(panic);
@@ -366,20 +882,82 @@ impl<'a> fmt::Debug for SharedLibrary<'a> {
         )?;
         write!(f, "name: {:?}, headers: [", self.name)?;
 
-        // Debug does not usually have a trailing comma in the list,
-        // last element must be formatted separately.
-        let l = self.headers.len();
-        self.headers[..(l - 1)]
-            .into_iter()
-            .map(|phdr| write!(f, "{:?}, ", &DebugPhdr(phdr)))
-            .collect::<fmt::Result>()?;
-
-        write!(f, "{:?}", &DebugPhdr(&self.headers[l - 1]))?;
+        // Debug does not usually have a trailing comma in the list, so the
+        // separator goes before every element but the first.
+        for (index, phdr) in self.headers.iter().enumerate() {
+            if index != 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{:?}", &DebugPhdr(phdr))?;
+        }
 
         write!(f, "] }}")
     }
 }
 
+/// Get the number of shared libraries loaded and unloaded so far, from the
+/// `dlpi_adds` and `dlpi_subs` counters of `dl_iterate_phdr`.
+///
+/// Returns `None` if the C library doesn't provide the counters.
+pub(crate) fn change_count() -> Option<u64> {
+    unsafe extern "C" fn callback(
+        info: *mut libc::dl_phdr_info,
+        size: usize,
+        count: *mut libc::c_void,
+    ) -> libc::c_int {
+        let info = &*info;
+        let end = &info.dlpi_subs as *const _ as usize + mem::size_of_val(&info.dlpi_subs);
+        if size >= end - info as *const _ as usize {
+            let count = &mut *(count as *mut Option<u64>);
+            *count = Some(info.dlpi_adds.wrapping_add(info.dlpi_subs));
+        }
+        // The counters are the same for every entry.
+        BREAK
+    }
+
+    let mut count: Option<u64> = None;
+    unsafe {
+        libc::dl_iterate_phdr(Some(callback), &mut count as *mut _ as *mut _);
+    }
+    count
+}
+
+/// Take a reference on the already loaded object `name` with
+/// `dlopen(RTLD_NOLOAD)`, which doesn't load anything.
+fn open_loaded(name: &OsStr) -> Option<PinnedLibrary> {
+    // An empty name would open the executable instead.
+    if name.is_empty() {
+        return None;
+    }
+    let name = CString::new(name.as_bytes()).ok()?;
+    let handle = unsafe { libc::dlopen(name.as_ptr(), libc::RTLD_LAZY | libc::RTLD_NOLOAD) };
+    if handle.is_null() {
+        None
+    } else {
+        Some(PinnedLibrary { handle })
+    }
+}
+
+/// Ask the dynamic linker whether `name` is loaded, the way `dlopen` would
+/// look for it.
+pub(crate) fn probe_loaded(name: &OsStr) -> Option<bool> {
+    Some(open_loaded(name).is_some())
+}
+
+/// Find the loaded library that `dlopen` would return for `name`.
+pub(crate) fn find_loaded(name: &OsStr) -> Option<OwnedSharedLibrary> {
+    let pinned = open_loaded(name)?;
+    let mut found = None;
+    SharedLibrary::each(|shlib| match shlib.pin() {
+        Some(other) if other.handle == pinned.handle => {
+            found = Some(OwnedSharedLibrary::from_shared_library(shlib));
+            IterationControl::Break
+        }
+        _ => IterationControl::Continue,
+    });
+    found
+}
+
 struct DebugPhdr<'a>(&'a Phdr);
 
 impl<'a> fmt::Debug for DebugPhdr<'a> {
@@ -420,6 +998,36 @@ mod tests {
         assert!(found_libc);
     }
 
+    #[test]
+    fn from_own_callback() {
+        type Entry = (
+            std::ffi::OsString,
+            Option<crate::SharedLibraryId>,
+            Option<crate::SharedLibraryKind>,
+        );
+
+        unsafe extern "C" fn callback(
+            info: *mut libc::dl_phdr_info,
+            _size: usize,
+            libraries: *mut libc::c_void,
+        ) -> libc::c_int {
+            let libraries = &mut *(libraries as *mut Vec<Entry>);
+            let shlib = linux::SharedLibrary::from_dl_phdr_info(&*info);
+            libraries.push((shlib.name().to_owned(), shlib.id(), shlib.kind()));
+            0
+        }
+
+        let mut libraries: Vec<Entry> = vec![];
+        unsafe {
+            libc::dl_iterate_phdr(Some(callback), &mut libraries as *mut _ as *mut _);
+        }
+        let mut expected = vec![];
+        linux::SharedLibrary::each(|shlib| {
+            expected.push((shlib.name().to_owned(), shlib.id(), shlib.kind()));
+        });
+        assert_eq!(libraries, expected);
+    }
+
     #[test]
     fn can_break() {
         let mut first_count = 0;
@@ -457,6 +1065,174 @@ mod tests {
         assert!(names.iter().any(|x| x.contains("libc.so")));
     }
 
+    #[test]
+    fn raw_names() {
+        use std::os::unix::ffi::OsStrExt;
+
+        linux::SharedLibrary::each(|shlib| {
+            assert_eq!(shlib.name_bytes(), shlib.name().as_bytes());
+            assert_eq!(shlib.name_cstr().to_bytes(), shlib.name_bytes());
+        });
+    }
+
+    #[test]
+    fn data_segments() {
+        static mut ZEROED: [u8; 0x10000] = [0; 0x10000];
+        static READONLY: [u8; 4] = *b"abcd";
+
+        let zeroed = std::ptr::addr_of!(ZEROED) as usize;
+        let readonly = READONLY.as_ptr() as usize;
+        let mut checked = 0;
+        linux::SharedLibrary::each(|shlib| {
+            for segment in shlib.segments() {
+                let contains = |address| segment.contains_avma(shlib, crate::Avma(address));
+                if contains(zeroed) {
+                    assert!(segment.is_data() && segment.is_bss(), "{:?}", segment);
+                    checked += 1;
+                }
+                if contains(readonly) {
+                    assert!(!segment.is_data() && !segment.is_bss(), "{:?}", segment);
+                    assert_eq!(segment.is_readonly_data(), !segment.is_code());
+                    checked += 1;
+                }
+            }
+        });
+        assert_eq!(checked, 2);
+    }
+
+    #[test]
+    fn aux_vector() {
+        // The kernel always tells us where the executable's program headers
+        // are.
+        assert_ne!(linux::aux_value(super::elf::AT_PHDR), 0);
+        assert_eq!(linux::aux_value(0x7fff_ffff), 0);
+    }
+
+    #[test]
+    fn segment_kinds() {
+        linux::SharedLibrary::each(|shlib| {
+            for segment in shlib.segments() {
+                let kind = segment.kind();
+                assert_eq!(linux::SegmentKind::from(kind.p_type()), kind);
+                assert_eq!(segment.name(), kind.name());
+                assert_eq!(segment.raw_type(), Some(kind.p_type()));
+                assert_eq!(segment.raw_flags(), Some(u64::from(segment.phdr().p_flags)));
+            }
+        });
+
+        assert_eq!(
+            linux::SegmentKind::from(0x6474_e554),
+            linux::SegmentKind::GnuSframe
+        );
+        assert_eq!(
+            linux::SegmentKind::from(0x6464_e550),
+            linux::SegmentKind::SunwUnwind
+        );
+        assert_eq!(
+            linux::SegmentKind::from(0x1234),
+            linux::SegmentKind::Other(0x1234)
+        );
+        let exidx = linux::SegmentKind::from(0x7000_0001);
+        assert_eq!(
+            exidx == linux::SegmentKind::ArmExidx,
+            cfg!(target_arch = "arm")
+        );
+        assert_eq!(exidx.p_type(), 0x7000_0001);
+    }
+
+    #[test]
+    fn pin() {
+        let mut pins = vec![];
+        linux::SharedLibrary::each(|shlib| {
+            let pinned = shlib.pin();
+            let name = shlib.name().to_string_lossy();
+            if name.contains("libc.so") || shlib.is_first_lib {
+                assert!(pinned.is_some(), "{}", name);
+            }
+            pins.extend(pinned);
+        });
+        assert!(pins.len() >= 2);
+        // Released from another thread.
+        std::thread::spawn(move || drop(pins)).join().unwrap();
+    }
+
+    #[test]
+    fn sframe() {
+        use crate::Avma;
+
+        let mut headers: [super::Phdr; 2] = unsafe { std::mem::zeroed() };
+        headers[0].p_type = super::elf::PT_LOAD;
+        headers[0].p_memsz = 0x2000;
+        headers[1].p_type = 0x6474_e554;
+        headers[1].p_vaddr = 0x100;
+        headers[1].p_memsz = 0x20;
+
+        let mut info: libc::dl_phdr_info = unsafe { std::mem::zeroed() };
+        info.dlpi_addr = 0x1000;
+        info.dlpi_name = b"sframe.so\0".as_ptr() as *const _;
+        info.dlpi_phdr = headers.as_ptr();
+        info.dlpi_phnum = headers.len() as _;
+        let shlib =
+            unsafe { linux::SharedLibrary::new(&info, std::mem::size_of_val(&info), false) };
+        assert_eq!(shlib.sframe(), Some(Avma(0x1100)..Avma(0x1120)));
+
+        info.dlpi_phnum = 1;
+        let shlib =
+            unsafe { linux::SharedLibrary::new(&info, std::mem::size_of_val(&info), false) };
+        assert_eq!(shlib.sframe(), None);
+    }
+
+    #[test]
+    fn eh_frame() {
+        let mut found = 0;
+        linux::SharedLibrary::each(|shlib| {
+            if shlib.eh_frame_hdr().is_none() {
+                return;
+            }
+            let eh_frame = shlib.eh_frame().expect("eh_frame_hdr points at eh_frame");
+            assert!(eh_frame.start < eh_frame.end);
+            // The section starts with the length of a CIE.
+            let length = unsafe { (eh_frame.start.0 as *const u32).read_unaligned() };
+            assert_ne!(length, 0);
+            found += 1;
+        });
+        assert!(found > 0);
+    }
+
+    #[test]
+    fn no_program_headers() {
+        use crate::{Avma, Svma};
+
+        let mut info: libc::dl_phdr_info = unsafe { std::mem::zeroed() };
+        info.dlpi_addr = 0x1000;
+        info.dlpi_name = b"headerless.so\0".as_ptr() as *const _;
+        let shlib =
+            unsafe { linux::SharedLibrary::new(&info, std::mem::size_of_val(&info), false) };
+
+        assert!(format!("{:?}", shlib).contains("headers: []"));
+        assert_eq!(format!("{:?}", shlib.segments()), "SegmentIter");
+        assert_eq!(shlib.segments().count(), 0);
+        assert_eq!(shlib.actual_load_addr(), Avma(0x1000));
+        assert_eq!(shlib.stated_load_addr(), Svma(0));
+        assert_eq!(shlib.len(), 0);
+        assert_eq!(shlib.id(), None);
+    }
+
+    #[test]
+    fn executable_path() {
+        let exe = std::env::current_exe().unwrap();
+        let mut first = None;
+        linux::SharedLibrary::each(|shlib| {
+            first = Some(shlib.name().to_owned());
+            IterationControl::Break
+        });
+        assert_eq!(first.unwrap(), exe.as_os_str());
+
+        // Too late to override it now.
+        let path = std::path::PathBuf::from("/elsewhere/exe");
+        assert_eq!(linux::set_executable_path(path.clone()), Err(path));
+    }
+
     #[test]
     #[cfg(target_os = "linux")]
     fn get_id() {
@@ -490,6 +1266,52 @@ mod tests {
         });
     }
 
+    #[test]
+    fn get_kind() {
+        use crate::SharedLibraryKind;
+
+        let mut kinds = vec![];
+        linux::SharedLibrary::each(|shlib| {
+            kinds.push(shlib.kind().unwrap());
+        });
+
+        assert_eq!(kinds[0], SharedLibraryKind::Executable);
+        let count = |kind| kinds.iter().filter(|k| **k == kind).count();
+        assert_eq!(count(SharedLibraryKind::Executable), 1);
+        assert_eq!(count(SharedLibraryKind::DynamicLinker), 1);
+    }
+
+    #[test]
+    fn segment_permissions() {
+        linux::SharedLibrary::each(|shlib| {
+            for seg in shlib.segments() {
+                let permissions = seg.permissions().unwrap();
+                if seg.is_code() {
+                    assert!(permissions.is_executable());
+                }
+                if seg.is_load() {
+                    assert!(permissions.is_readable() || permissions.is_executable());
+                }
+            }
+        });
+    }
+
+    #[test]
+    fn symbol_for_avma() {
+        use crate::Avma;
+
+        let dlopen = Avma(libc::dlopen as *const () as usize);
+        let mut found = false;
+        linux::SharedLibrary::each(|shlib| {
+            if let Some(symbol) = shlib.symbol_for_avma(dlopen) {
+                found = true;
+                assert!(symbol.name().to_bytes().starts_with(b"dlopen"));
+                assert_eq!(symbol.address(), dlopen);
+            }
+        });
+        assert!(found);
+    }
+
     #[test]
     fn have_load_segment() {
         linux::SharedLibrary::each(|shlib| {
@@ -504,4 +1326,11 @@ mod tests {
             assert!(found_load);
         });
     }
+
+    #[test]
+    fn change_count() {
+        // glibc and musl both have had the counters for a long time.
+        let count = linux::change_count().unwrap();
+        assert!(count > 0);
+    }
 }
@@ -0,0 +1,102 @@
+//! Checking whether a particular shared library is loaded.
+//!
+//! Plugin hosts and instrumentation often only need to know whether one
+//! library is loaded. The loader can answer that without listing every
+//! library:
+//!
+//! * On Unix with `dlopen(RTLD_NOLOAD)`, which finds a library like `dlopen`
+//!   does, by path or by soname, but never loads it.
+//! * On Windows with `GetModuleHandleExW`, which finds a module like
+//!   `GetModuleHandleW` does, by path or by file name, without taking a
+//!   reference on it.
+//!
+//! Libraries the loader can't find by name, like the vDSO or the executable,
+//! are looked up among the loaded libraries by name instead.
+
+use crate::{native_mod, CachedLibraries, OwnedSharedLibrary, SharedLibrary};
+
+use std::ffi::OsStr;
+use std::path::Path;
+
+/// Returns `true` if the shared library `name` is loaded.
+///
+/// `name` is either a path or a file name like `libz.so.1` or
+/// `kernel32.dll`.
+pub fn is_loaded<S: AsRef<OsStr>>(name: S) -> bool {
+    let name = name.as_ref();
+    native_mod::probe_loaded(name) == Some(true) || find_by_name(name).is_some()
+}
+
+/// Find the loaded shared library `name`, see `is_loaded`.
+pub fn find_loaded<S: AsRef<OsStr>>(name: S) -> Option<OwnedSharedLibrary> {
+    let name = name.as_ref();
+    find_by_name(name).or_else(|| native_mod::find_loaded(name))
+}
+
+fn find_by_name(name: &OsStr) -> Option<OwnedSharedLibrary> {
+    if name.is_empty() {
+        return None;
+    }
+    // A bare file name matches the file name of a library, a path only the
+    // same path.
+    let has_directory = Path::new(name).file_name() != Some(name);
+    CachedLibraries::get()
+        .iter()
+        .find(|shlib| {
+            if has_directory {
+                same_name(shlib.name(), name)
+            } else {
+                same_name(shlib.basename(), name)
+            }
+        })
+        .cloned()
+}
+
+#[cfg(target_os = "windows")]
+fn same_name(a: &OsStr, b: &OsStr) -> bool {
+    // The file system and the loader ignore case.
+    a.to_string_lossy().to_lowercase() == b.to_string_lossy().to_lowercase()
+}
+
+#[cfg(not(target_os = "windows"))]
+fn same_name(a: &OsStr, b: &OsStr) -> bool {
+    a == b
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TargetSharedLibrary;
+
+    #[test]
+    fn loaded_libraries() {
+        let mut names = vec![];
+        TargetSharedLibrary::each(|shlib| names.push(shlib.name().to_owned()));
+        for name in names.iter().filter(|name| !name.is_empty()) {
+            assert!(is_loaded(name), "{:?}", name);
+            let found = find_loaded(name).unwrap();
+            assert_eq!(found.name(), name);
+            let basename = Path::new(name).file_name().unwrap();
+            assert!(is_loaded(basename), "{:?}", basename);
+        }
+    }
+
+    #[test]
+    fn not_loaded() {
+        assert!(!is_loaded("libfindshlibs-does-not-exist.so"));
+        assert!(!is_loaded("/nonexistent/libfindshlibs.so"));
+        assert!(!is_loaded(""));
+        assert_eq!(find_loaded("libfindshlibs-does-not-exist.so"), None);
+    }
+
+    #[cfg(all(target_os = "linux", target_env = "gnu"))]
+    #[test]
+    fn by_soname() {
+        // `dlopen` knows libc by its soname, whatever the file is called.
+        let libc = OsStr::new("libc.so.6");
+        assert_eq!(native_mod::probe_loaded(libc), Some(true));
+        let found = native_mod::find_loaded(libc).unwrap();
+        assert_eq!(found.kind(), Some(crate::SharedLibraryKind::Library));
+        assert!(is_loaded(libc));
+    }
+}
@@ -0,0 +1,237 @@
+//! Parsing the embedded code signature referenced by `LC_CODE_SIGNATURE`.
+//!
+//! The signature is a big-endian "super blob" holding an index of blobs, of
+//! which the code directories describe the signed code. The cdhash that
+//! identifies the signed code is the hash of the code directory itself,
+//! truncated to 20 bytes.
+
+use crate::verify::u32_at;
+use std::convert::TryFrom;
+
+const CSMAGIC_EMBEDDED_SIGNATURE: u32 = 0xfade_0cc0;
+const CSMAGIC_CODEDIRECTORY: u32 = 0xfade_0c02;
+
+const CSSLOT_CODEDIRECTORY: u32 = 0;
+const CSSLOT_ALTERNATE_CODEDIRECTORIES: u32 = 0x1000;
+const CSSLOT_ALTERNATE_CODEDIRECTORY_MAX: u32 = 5;
+
+const CS_HASHTYPE_SHA1: u8 = 1;
+const CS_HASHTYPE_SHA256: u8 = 2;
+const CS_HASHTYPE_SHA256_TRUNCATED: u8 = 3;
+const CS_HASHTYPE_SHA384: u8 = 4;
+
+const CS_CDHASH_LEN: usize = 20;
+
+const CS_SUPPORTSTEAMID: u32 = 0x20200;
+
+extern "C" {
+    fn CC_SHA1(data: *const libc::c_void, len: u32, md: *mut u8) -> *mut u8;
+    fn CC_SHA256(data: *const libc::c_void, len: u32, md: *mut u8) -> *mut u8;
+    fn CC_SHA384(data: *const libc::c_void, len: u32, md: *mut u8) -> *mut u8;
+}
+
+/// What an image's embedded code signature says about it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CodeSignature {
+    identifier: Option<String>,
+    team_id: Option<String>,
+    flags: u32,
+    cdhash: Option<[u8; CS_CDHASH_LEN]>,
+}
+
+impl CodeSignature {
+    /// Get the signing identifier, e.g. `com.apple.dyld`.
+    pub fn identifier(&self) -> Option<&str> {
+        self.identifier.as_deref()
+    }
+
+    /// Get the identifier of the team that signed the code, if the code
+    /// directory records one. Ad-hoc and platform binaries have none.
+    pub fn team_id(&self) -> Option<&str> {
+        self.team_id.as_deref()
+    }
+
+    /// Get the raw `CS_*` flags of the code directory.
+    pub fn flags(&self) -> u32 {
+        self.flags
+    }
+
+    /// Get the cdhash, if the code directory uses a known hash type.
+    pub fn cdhash(&self) -> Option<[u8; CS_CDHASH_LEN]> {
+        self.cdhash
+    }
+}
+
+fn read_string(data: &[u8], offset: usize) -> Option<String> {
+    let data = data.get(offset..)?;
+    let len = data.iter().position(|b| *b == 0)?;
+    Some(String::from_utf8_lossy(&data[..len]).into_owned())
+}
+
+/// How much to prefer a code directory with this hash type.
+fn hash_rank(hash_type: u8) -> u8 {
+    match hash_type {
+        CS_HASHTYPE_SHA1 => 1,
+        CS_HASHTYPE_SHA256_TRUNCATED => 2,
+        CS_HASHTYPE_SHA256 => 3,
+        CS_HASHTYPE_SHA384 => 4,
+        _ => 0,
+    }
+}
+
+fn cdhash(hash_type: u8, directory: &[u8]) -> Option<[u8; CS_CDHASH_LEN]> {
+    let len = u32::try_from(directory.len()).ok()?;
+    let data = directory.as_ptr() as *const libc::c_void;
+    let mut digest = [0; 48];
+    unsafe {
+        match hash_type {
+            CS_HASHTYPE_SHA1 => CC_SHA1(data, len, digest.as_mut_ptr()),
+            CS_HASHTYPE_SHA256 | CS_HASHTYPE_SHA256_TRUNCATED => {
+                CC_SHA256(data, len, digest.as_mut_ptr())
+            }
+            CS_HASHTYPE_SHA384 => CC_SHA384(data, len, digest.as_mut_ptr()),
+            _ => return None,
+        };
+    }
+    let mut cdhash = [0; CS_CDHASH_LEN];
+    cdhash.copy_from_slice(&digest[..CS_CDHASH_LEN]);
+    Some(cdhash)
+}
+
+/// Parse the embedded signature `data`.
+///
+/// Returns `None` unless it contains at least one well-formed code
+/// directory.
+pub(crate) fn parse(data: &[u8]) -> Option<CodeSignature> {
+    if u32_at(data, 0, false)? != CSMAGIC_EMBEDDED_SIGNATURE {
+        return None;
+    }
+    let length = (u32_at(data, 4, false)? as usize).min(data.len());
+    let data = &data[..length];
+    let count = u32_at(data, 8, false)? as usize;
+
+    // Images may carry alternate code directories using different hashes;
+    // the cdhash is taken from the strongest one.
+    let mut best: Option<(u8, &[u8])> = None;
+    for index in 0..count {
+        let entry = 12usize.checked_add(index.checked_mul(8)?)?;
+        let slot = u32_at(data, entry, false)?;
+        let is_directory = slot == CSSLOT_CODEDIRECTORY
+            || (CSSLOT_ALTERNATE_CODEDIRECTORIES
+                ..CSSLOT_ALTERNATE_CODEDIRECTORIES + CSSLOT_ALTERNATE_CODEDIRECTORY_MAX)
+                .contains(&slot);
+        if !is_directory {
+            continue;
+        }
+
+        let offset = u32_at(data, entry + 4, false)? as usize;
+        if u32_at(data, offset, false)? != CSMAGIC_CODEDIRECTORY {
+            continue;
+        }
+        let length = u32_at(data, offset + 4, false)? as usize;
+        let directory = match data.get(offset..offset.checked_add(length)?) {
+            Some(directory) => directory,
+            None => continue,
+        };
+        // `hashType` follows the nine 32-bit fields and `hashSize`.
+        let hash_type = match directory.get(37) {
+            Some(&hash_type) => hash_type,
+            None => continue,
+        };
+        match best {
+            Some((best_type, _)) if hash_rank(best_type) >= hash_rank(hash_type) => {}
+            _ => best = Some((hash_type, directory)),
+        }
+    }
+
+    let (hash_type, directory) = best?;
+    let version = u32_at(directory, 8, false)?;
+    let flags = u32_at(directory, 12, false)?;
+    let identifier = read_string(directory, u32_at(directory, 20, false)? as usize);
+    let team_id = if version >= CS_SUPPORTSTEAMID {
+        match u32_at(directory, 48, false)? {
+            0 => None,
+            offset => read_string(directory, offset as usize),
+        }
+    } else {
+        None
+    };
+
+    Some(CodeSignature {
+        identifier,
+        team_id,
+        flags,
+        cdhash: cdhash(hash_type, directory),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Build a signature with a single code directory.
+    fn signature(hash_type: u8, team_id: Option<&str>) -> Vec<u8> {
+        let mut directory = vec![];
+        let push = |data: &mut Vec<u8>, value: u32| data.extend_from_slice(&value.to_be_bytes());
+
+        let ident = b"com.example.test\0";
+        let team = team_id.map(|team| format!("{}\0", team).into_bytes());
+        let fixed = 52;
+        push(&mut directory, CSMAGIC_CODEDIRECTORY);
+        let length = fixed + ident.len() + team.as_ref().map_or(0, |team| team.len());
+        push(&mut directory, length as u32);
+        push(&mut directory, CS_SUPPORTSTEAMID);
+        push(&mut directory, 0x2_0002);
+        push(&mut directory, 0); // hashOffset
+        push(&mut directory, fixed as u32); // identOffset
+        push(&mut directory, 0); // nSpecialSlots
+        push(&mut directory, 0); // nCodeSlots
+        push(&mut directory, 0); // codeLimit
+        directory.extend_from_slice(&[32, hash_type, 0, 12]);
+        push(&mut directory, 0); // spare2
+        push(&mut directory, 0); // scatterOffset
+        let team_offset = if team.is_some() {
+            fixed + ident.len()
+        } else {
+            0
+        };
+        push(&mut directory, team_offset as u32);
+        assert_eq!(directory.len(), fixed);
+        directory.extend_from_slice(ident);
+        if let Some(team) = team {
+            directory.extend_from_slice(&team);
+        }
+
+        let mut data = vec![];
+        push(&mut data, CSMAGIC_EMBEDDED_SIGNATURE);
+        push(&mut data, (20 + directory.len()) as u32);
+        push(&mut data, 1);
+        push(&mut data, CSSLOT_CODEDIRECTORY);
+        push(&mut data, 20);
+        data.extend_from_slice(&directory);
+        data
+    }
+
+    #[test]
+    fn team_id() {
+        let signature = parse(&signature(CS_HASHTYPE_SHA256, Some("ABCDE12345"))).unwrap();
+        assert_eq!(signature.identifier(), Some("com.example.test"));
+        assert_eq!(signature.team_id(), Some("ABCDE12345"));
+        assert_eq!(signature.flags(), 0x2_0002);
+        assert!(signature.cdhash().is_some());
+    }
+
+    #[test]
+    fn ad_hoc() {
+        let signature = parse(&signature(CS_HASHTYPE_SHA1, None)).unwrap();
+        assert_eq!(signature.team_id(), None);
+        assert!(signature.cdhash().is_some());
+    }
+
+    #[test]
+    fn truncated() {
+        let data = signature(CS_HASHTYPE_SHA256, None);
+        assert_eq!(parse(&data[..30]), None);
+        assert_eq!(parse(&[0; 16]), None);
+    }
+}
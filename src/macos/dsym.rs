@@ -0,0 +1,195 @@
+//! Finding the dSYM bundle that holds the debug information of an image.
+//!
+//! A dSYM is a bundle named after the binary, e.g. `Foo.app.dSYM` for
+//! `Foo.app/Contents/MacOS/Foo`, containing the DWARF file
+//! `Contents/Resources/DWARF/Foo`. Xcode leaves it next to the build
+//! products, both in DerivedData and in archives.
+
+use super::{read_file_uuid, read_u32_be, read_u64_be, FAT_MAGIC, FAT_MAGIC_64, MAX_FAT_ARCHS};
+
+use std::env;
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+
+const BUNDLE_EXTENSIONS: &[&str] = &["app", "appex", "bundle", "framework", "xpc"];
+
+/// A place where the dSYM of an image may be.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DsymCandidate {
+    bundle: PathBuf,
+    dwarf_file: PathBuf,
+}
+
+impl DsymCandidate {
+    fn new(bundle: PathBuf, binary_name: &Path) -> DsymCandidate {
+        let dwarf_file = bundle.join("Contents/Resources/DWARF").join(binary_name);
+        DsymCandidate { bundle, dwarf_file }
+    }
+
+    /// Get the path of the `.dSYM` bundle.
+    pub fn bundle(&self) -> &Path {
+        &self.bundle
+    }
+
+    /// Get the path of the DWARF file inside the bundle.
+    pub fn dwarf_file(&self) -> &Path {
+        &self.dwarf_file
+    }
+
+    /// Returns `true` if the DWARF file exists and has the given UUID in
+    /// any of its slices.
+    pub fn matches(&self, uuid: &[u8; 16]) -> bool {
+        contains_uuid(&self.dwarf_file, uuid).unwrap_or(false)
+    }
+}
+
+/// Get the places where the dSYM of the binary at `path` may be, most
+/// likely first.
+///
+/// These are next to the binary or its enclosing bundle, and then in the
+/// build products of Xcode's DerivedData and archives. The candidates are
+/// not checked for existence.
+pub fn dsym_candidates(path: &Path) -> Vec<DsymCandidate> {
+    let binary_name = match path.file_name() {
+        Some(name) => Path::new(name),
+        None => return vec![],
+    };
+
+    // A binary inside a bundle has its dSYM named after the bundle.
+    let bundle = path.ancestors().skip(1).find(|dir| {
+        dir.extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| BUNDLE_EXTENSIONS.contains(&ext))
+    });
+
+    let mut dsym_names = vec![];
+    let mut dirs = vec![];
+    if let Some(bundle) = bundle {
+        dsym_names.push(dsym_name(bundle));
+        dirs.extend(bundle.parent().map(Path::to_path_buf));
+    }
+    dsym_names.push(dsym_name(path));
+    dirs.extend(path.parent().map(Path::to_path_buf));
+
+    if let Some(home) = env::var_os("HOME") {
+        let xcode = Path::new(&home).join("Library/Developer/Xcode");
+        // DerivedData/<project>/Build/Products/<configuration>
+        for project in subdirs(&xcode.join("DerivedData")) {
+            dirs.extend(subdirs(&project.join("Build/Products")));
+        }
+        // Archives/<date>/<name>.xcarchive/dSYMs
+        for date in subdirs(&xcode.join("Archives")) {
+            for archive in subdirs(&date) {
+                dirs.push(archive.join("dSYMs"));
+            }
+        }
+    }
+
+    let mut candidates = vec![];
+    for dir in &dirs {
+        for name in &dsym_names {
+            let candidate = DsymCandidate::new(dir.join(name), binary_name);
+            if !candidates.contains(&candidate) {
+                candidates.push(candidate);
+            }
+        }
+    }
+    candidates
+}
+
+/// Find the dSYM of the binary at `path` whose DWARF file has the given
+/// UUID.
+pub fn find_dsym(uuid: &[u8; 16], path: &Path) -> Option<DsymCandidate> {
+    dsym_candidates(path)
+        .into_iter()
+        .find(|candidate| candidate.matches(uuid))
+}
+
+fn dsym_name(path: &Path) -> OsString {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".dSYM");
+    name
+}
+
+fn subdirs(dir: &Path) -> Vec<PathBuf> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return vec![],
+    };
+    let mut dirs: Vec<_> = entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_ok_and(|ty| ty.is_dir()))
+        .map(|entry| entry.path())
+        .collect();
+    // Keep the order stable between calls.
+    dirs.sort();
+    dirs
+}
+
+/// Check whether the (possibly universal) Mach-O file at `path` has a slice
+/// with the given UUID.
+fn contains_uuid(path: &Path, uuid: &[u8; 16]) -> io::Result<bool> {
+    let mut file = File::open(path)?;
+    let magic = read_u32_be(&mut file)?;
+    if magic != FAT_MAGIC && magic != FAT_MAGIC_64 {
+        return Ok(read_file_uuid(&mut file, 0)?.as_ref() == Some(uuid));
+    }
+
+    let nfat_arch = read_u32_be(&mut file)?;
+    if nfat_arch > MAX_FAT_ARCHS {
+        return Ok(false);
+    }
+
+    let mut offsets = vec![];
+    for _ in 0..nfat_arch {
+        // `cputype` and `cpusubtype`.
+        read_u64_be(&mut file)?;
+        if magic == FAT_MAGIC_64 {
+            offsets.push(read_u64_be(&mut file)?);
+            // `size`, `align` and `reserved`.
+            read_u64_be(&mut file)?;
+            read_u64_be(&mut file)?;
+        } else {
+            offsets.push(u64::from(read_u32_be(&mut file)?));
+            // `size` and `align`.
+            read_u64_be(&mut file)?;
+        }
+    }
+
+    for offset in offsets {
+        if read_file_uuid(&mut file, offset)?.as_ref() == Some(uuid) {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::dsym_candidates;
+    use std::path::Path;
+
+    #[test]
+    fn next_to_bundle() {
+        let candidates = dsym_candidates(Path::new("/build/Foo.app/Contents/MacOS/Foo"));
+        assert_eq!(candidates[0].bundle(), Path::new("/build/Foo.app.dSYM"));
+        assert_eq!(
+            candidates[0].dwarf_file(),
+            Path::new("/build/Foo.app.dSYM/Contents/Resources/DWARF/Foo")
+        );
+        assert!(candidates
+            .iter()
+            .any(|c| c.bundle() == Path::new("/build/Foo.app/Contents/MacOS/Foo.dSYM")));
+    }
+
+    #[test]
+    fn next_to_dylib() {
+        let candidates = dsym_candidates(Path::new("/usr/local/lib/libfoo.dylib"));
+        assert_eq!(
+            candidates[0].dwarf_file(),
+            Path::new("/usr/local/lib/libfoo.dylib.dSYM/Contents/Resources/DWARF/libfoo.dylib")
+        );
+    }
+}
@@ -0,0 +1,245 @@
+//! Walking the export trie of a Mach-O image.
+//!
+//! The trie is a prefix tree of symbol names. Each node starts with the
+//! ULEB128 size of its terminal information, which is empty for nodes that
+//! don't end a symbol name, followed by the number of child edges and then
+//! each edge's label and the ULEB128 offset of its child node.
+
+use crate::Avma;
+
+const EXPORT_SYMBOL_FLAGS_KIND_MASK: u64 = 0x03;
+const EXPORT_SYMBOL_FLAGS_KIND_THREAD_LOCAL: u64 = 0x01;
+const EXPORT_SYMBOL_FLAGS_KIND_ABSOLUTE: u64 = 0x02;
+const EXPORT_SYMBOL_FLAGS_WEAK_DEFINITION: u64 = 0x04;
+const EXPORT_SYMBOL_FLAGS_REEXPORT: u64 = 0x08;
+const EXPORT_SYMBOL_FLAGS_STUB_AND_RESOLVER: u64 = 0x10;
+
+/// Read an unsigned LEB128 number from the front of `data`.
+pub(crate) fn read_uleb128(data: &mut &[u8]) -> Option<u64> {
+    let mut result = 0;
+    let mut shift = 0;
+    loop {
+        let (&byte, rest) = data.split_first()?;
+        *data = rest;
+        if shift >= 64 {
+            return None;
+        }
+        result |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Some(result);
+        }
+        shift += 7;
+    }
+}
+
+/// Read a NUL-terminated string from the front of `data`.
+fn read_cstr<'a>(data: &mut &'a [u8]) -> Option<&'a [u8]> {
+    let len = data.iter().position(|b| *b == 0)?;
+    let s = &data[..len];
+    *data = &data[len + 1..];
+    Some(s)
+}
+
+/// A symbol exported by a Mach-O image.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ExportedSymbol {
+    name: String,
+    flags: u64,
+    address: Option<Avma>,
+    reexport: Option<(u64, String)>,
+}
+
+impl ExportedSymbol {
+    /// Get the (mangled) name of the symbol.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Get the raw `EXPORT_SYMBOL_FLAGS_*` value of the symbol.
+    pub fn flags(&self) -> u64 {
+        self.flags
+    }
+
+    /// Get the address of the symbol.
+    ///
+    /// This is `None` for symbols that are re-exported from another image.
+    /// For stub-and-resolver symbols this is the address of the stub.
+    pub fn address(&self) -> Option<Avma> {
+        self.address
+    }
+
+    /// Returns `true` if this is a weak definition.
+    pub fn is_weak(&self) -> bool {
+        self.flags & EXPORT_SYMBOL_FLAGS_WEAK_DEFINITION != 0
+    }
+
+    /// Returns `true` if this is a thread local variable.
+    pub fn is_thread_local(&self) -> bool {
+        self.flags & EXPORT_SYMBOL_FLAGS_KIND_MASK == EXPORT_SYMBOL_FLAGS_KIND_THREAD_LOCAL
+    }
+
+    /// If this symbol is re-exported from another image, get the ordinal of
+    /// that image among this image's dependencies and the name of the symbol
+    /// there.
+    pub fn reexport(&self) -> Option<(u64, &str)> {
+        self.reexport
+            .as_ref()
+            .map(|&(ordinal, ref name)| (ordinal, &name[..]))
+    }
+}
+
+/// An iterator over the symbols exported by a Mach-O image.
+#[derive(Debug)]
+pub struct ExportIter<'a> {
+    trie: &'a [u8],
+    base: usize,
+    // Nodes still to visit, along with the name leading to them.
+    stack: Vec<(usize, Vec<u8>)>,
+    // Bounds the walk in case a corrupt trie contains cycles.
+    budget: usize,
+}
+
+impl<'a> ExportIter<'a> {
+    /// Walk `trie`, resolving addresses relative to the image header at
+    /// `base`.
+    pub(crate) fn new(trie: &'a [u8], base: usize) -> ExportIter<'a> {
+        ExportIter {
+            trie,
+            base,
+            stack: if trie.is_empty() {
+                vec![]
+            } else {
+                vec![(0, vec![])]
+            },
+            budget: trie.len(),
+        }
+    }
+
+    /// Visit a single node, returning the symbol it terminates, if any.
+    fn visit(&mut self, offset: usize, name: Vec<u8>) -> Option<Option<ExportedSymbol>> {
+        let mut data = self.trie.get(offset..)?;
+
+        let terminal_size = read_uleb128(&mut data)? as usize;
+        if data.len() < terminal_size {
+            return None;
+        }
+        let (mut terminal, mut children) = data.split_at(terminal_size);
+
+        let child_count = *children.first()?;
+        children = &children[1..];
+        let mut edges = Vec::with_capacity(child_count as usize);
+        for _ in 0..child_count {
+            let label = read_cstr(&mut children)?;
+            let child = read_uleb128(&mut children)? as usize;
+            edges.push((label, child));
+        }
+        // Push in reverse so that children are visited in trie order.
+        for &(label, child) in edges.iter().rev() {
+            let mut child_name = name.clone();
+            child_name.extend_from_slice(label);
+            self.stack.push((child, child_name));
+        }
+
+        if terminal_size == 0 {
+            return Some(None);
+        }
+
+        let flags = read_uleb128(&mut terminal)?;
+        let mut symbol = ExportedSymbol {
+            name: String::from_utf8_lossy(&name).into_owned(),
+            flags,
+            address: None,
+            reexport: None,
+        };
+        if flags & EXPORT_SYMBOL_FLAGS_REEXPORT != 0 {
+            let ordinal = read_uleb128(&mut terminal)?;
+            let imported = read_cstr(&mut terminal)?;
+            let imported = if imported.is_empty() {
+                symbol.name.clone()
+            } else {
+                String::from_utf8_lossy(imported).into_owned()
+            };
+            symbol.reexport = Some((ordinal, imported));
+        } else {
+            let address = read_uleb128(&mut terminal)? as usize;
+            symbol.address = Some(Avma(
+                if flags & EXPORT_SYMBOL_FLAGS_KIND_MASK == EXPORT_SYMBOL_FLAGS_KIND_ABSOLUTE {
+                    address
+                } else {
+                    self.base.wrapping_add(address)
+                },
+            ));
+            if flags & EXPORT_SYMBOL_FLAGS_STUB_AND_RESOLVER != 0 {
+                read_uleb128(&mut terminal)?;
+            }
+        }
+        Some(Some(symbol))
+    }
+}
+
+impl<'a> Iterator for ExportIter<'a> {
+    type Item = ExportedSymbol;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some((offset, name)) = self.stack.pop() {
+            if self.budget == 0 {
+                break;
+            }
+            self.budget -= 1;
+
+            match self.visit(offset, name) {
+                Some(Some(symbol)) => return Some(symbol),
+                Some(None) => continue,
+                // Stop at the first malformed node rather than guessing.
+                None => break,
+            }
+        }
+
+        self.stack.clear();
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{read_uleb128, ExportIter};
+    use crate::Avma;
+
+    #[test]
+    fn uleb128() {
+        let mut data = &[0xe5, 0x8e, 0x26, 0x01][..];
+        assert_eq!(read_uleb128(&mut data), Some(624_485));
+        assert_eq!(data, &[0x01]);
+        assert_eq!(read_uleb128(&mut &[0x80][..]), None);
+    }
+
+    #[test]
+    fn walk_trie() {
+        // A trie exporting `_a` at 0x10 and `_ab` as a weak definition at
+        // 0x20.
+        let trie = [
+            // Root: no terminal, one child `_a` at offset 6.
+            0x00, 0x01, b'_', b'a', 0x00, 0x06,
+            // `_a`: terminal with flags 0 and address 0x10, one child `b` at
+            // offset 13.
+            0x02, 0x00, 0x10, 0x01, b'b', 0x00, 0x0d,
+            // `_ab`: weak definition at 0x20, no children.
+            0x02, 0x04, 0x20, 0x00,
+        ];
+        let symbols: Vec<_> = ExportIter::new(&trie, 0x1000).collect();
+        assert_eq!(symbols.len(), 2);
+        assert_eq!(symbols[0].name(), "_a");
+        assert_eq!(symbols[0].address(), Some(Avma(0x1010)));
+        assert!(!symbols[0].is_weak());
+        assert_eq!(symbols[1].name(), "_ab");
+        assert_eq!(symbols[1].address(), Some(Avma(0x1020)));
+        assert!(symbols[1].is_weak());
+    }
+
+    #[test]
+    fn cyclic_trie() {
+        // A root whose only child is itself.
+        let trie = [0x00, 0x01, b'a', 0x00, 0x00];
+        assert_eq!(ExportIter::new(&trie, 0).count(), 0);
+    }
+}
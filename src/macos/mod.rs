@@ -5,18 +5,94 @@
 use lazy_static::lazy_static;
 use libc;
 
+use crate::diagnostics::{self, SkipReason};
 use crate::Segment as SegmentTrait;
 use crate::SharedLibrary as SharedLibraryTrait;
-use crate::{Bias, IterationControl, SharedLibraryId, Svma};
+use crate::{
+    Avma, Bias, IterationControl, OwnedSharedLibrary, Permissions, SharedLibraryId,
+    SharedLibraryKind, Svma, Symbol,
+};
 
-use std::ffi::{CStr, OsStr};
+use std::borrow::Cow;
+use std::convert::TryFrom;
+use std::ffi::{CStr, CString, OsStr};
 use std::fmt;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
 use std::marker::PhantomData;
+use std::mem;
+use std::ops::Range;
 use std::os::unix::ffi::OsStrExt;
-use std::sync::Mutex;
-use std::usize;
+use std::panic;
+use std::path::Path;
+use std::ptr;
+use std::slice;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Once};
+use std::thread;
+
+mod codesign;
+mod dsym;
+mod exports;
+mod regions;
+mod remote;
+
+pub use self::codesign::CodeSignature;
+pub use self::dsym::{dsym_candidates, find_dsym, DsymCandidate};
+pub use self::exports::{ExportIter, ExportedSymbol};
+pub use self::remote::RemoteTask;
+
+pub(crate) use self::regions::executable_mappings;
 
 const LC_UUID: u32 = 27;
+const LC_SYMTAB: u32 = 0x2;
+const LC_UNIXTHREAD: u32 = 0x5;
+const LC_MAIN: u32 = 0x8000_0028;
+const LC_DYLD_INFO: u32 = 0x22;
+const LC_DYLD_INFO_ONLY: u32 = 0x8000_0022;
+const LC_DYLD_EXPORTS_TRIE: u32 = 0x8000_0033;
+const LC_CODE_SIGNATURE: u32 = 0x1d;
+const LC_FUNCTION_STARTS: u32 = 0x26;
+const LC_ENCRYPTION_INFO: u32 = 0x21;
+const LC_ENCRYPTION_INFO_64: u32 = 0x2c;
+
+const MH_DYLIB_IN_CACHE: u32 = 0x8000_0000;
+
+const CPU_ARCH_ABI64: i32 = 0x0100_0000;
+const CPU_ARCH_ABI64_32: i32 = 0x0200_0000;
+const CPU_TYPE_X86: i32 = 7;
+const CPU_TYPE_X86_64: i32 = CPU_TYPE_X86 | CPU_ARCH_ABI64;
+const CPU_TYPE_ARM: i32 = 12;
+const CPU_TYPE_ARM64: i32 = CPU_TYPE_ARM | CPU_ARCH_ABI64;
+const CPU_TYPE_ARM64_32: i32 = CPU_TYPE_ARM | CPU_ARCH_ABI64_32;
+
+const CPU_SUBTYPE_MASK: i32 = 0xff00_0000_u32 as i32;
+const CPU_SUBTYPE_X86_64_H: i32 = 8;
+const CPU_SUBTYPE_ARM_V7: i32 = 9;
+const CPU_SUBTYPE_ARM_V7S: i32 = 11;
+const CPU_SUBTYPE_ARM_V7K: i32 = 12;
+const CPU_SUBTYPE_ARM64E: i32 = 2;
+
+const FAT_MAGIC: u32 = 0xcafe_babe;
+const FAT_MAGIC_64: u32 = 0xcafe_babf;
+
+// Universal binaries only ever contain a handful of slices; anything above
+// this is a corrupt header.
+const MAX_FAT_ARCHS: u32 = 64;
+
+const VM_PROT_READ: libc::vm_prot_t = 0x1;
+const VM_PROT_WRITE: libc::vm_prot_t = 0x2;
+const VM_PROT_EXECUTE: libc::vm_prot_t = 0x4;
+
+const SECTION_TYPE: u32 = 0xff;
+const S_ZEROFILL: u32 = 0x1;
+const S_GB_ZEROFILL: u32 = 0xc;
+const S_THREAD_LOCAL_ZEROFILL: u32 = 0x12;
+
+const MH_EXECUTE: u32 = 0x2;
+const MH_DYLIB: u32 = 0x6;
+const MH_DYLINKER: u32 = 0x7;
+const MH_BUNDLE: u32 = 0x8;
 
 #[repr(C)]
 #[derive(Debug, Copy, Clone)]
@@ -26,13 +102,307 @@ struct uuid_command {
     uuid: [u8; 16usize],
 }
 
+// The common prefix of `encryption_info_command` and
+// `encryption_info_command_64`.
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+struct encryption_info_command {
+    cmd: u32,
+    cmdsize: u32,
+    cryptoff: u32,
+    cryptsize: u32,
+    cryptid: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+struct symtab_command {
+    cmd: u32,
+    cmdsize: u32,
+    symoff: u32,
+    nsyms: u32,
+    stroff: u32,
+    strsize: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+struct nlist {
+    n_strx: u32,
+    n_type: u8,
+    n_sect: u8,
+    n_desc: i16,
+    n_value: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+struct nlist_64 {
+    n_strx: u32,
+    n_type: u8,
+    n_sect: u8,
+    n_desc: u16,
+    n_value: u64,
+}
+
+const N_STAB: u8 = 0xe0;
+const N_TYPE: u8 = 0x0e;
+const N_SECT: u8 = 0x0e;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+struct section {
+    sectname: [u8; 16],
+    segname: [u8; 16],
+    addr: u32,
+    size: u32,
+    offset: u32,
+    align: u32,
+    reloff: u32,
+    nreloc: u32,
+    flags: u32,
+    reserved1: u32,
+    reserved2: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+struct section_64 {
+    sectname: [u8; 16],
+    segname: [u8; 16],
+    addr: u64,
+    size: u64,
+    offset: u32,
+    align: u32,
+    reloff: u32,
+    nreloc: u32,
+    flags: u32,
+    reserved1: u32,
+    reserved2: u32,
+    reserved3: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+struct linkedit_data_command {
+    cmd: u32,
+    cmdsize: u32,
+    dataoff: u32,
+    datasize: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+struct dyld_info_command {
+    cmd: u32,
+    cmdsize: u32,
+    rebase_off: u32,
+    rebase_size: u32,
+    bind_off: u32,
+    bind_size: u32,
+    weak_bind_off: u32,
+    weak_bind_size: u32,
+    lazy_bind_off: u32,
+    lazy_bind_size: u32,
+    export_off: u32,
+    export_size: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+struct entry_point_command {
+    cmd: u32,
+    cmdsize: u32,
+    entryoff: u64,
+    stacksize: u64,
+}
+
+// The fixed part of a `thread_command`, which is followed by `count` words of
+// register state for the given `flavor`.
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+struct thread_command {
+    cmd: u32,
+    cmdsize: u32,
+    flavor: u32,
+    count: u32,
+}
+
+const X86_THREAD_STATE32: u32 = 1;
+const X86_THREAD_STATE64: u32 = 4;
+const ARM_THREAD_STATE: u32 = 1;
+const ARM_THREAD_STATE64: u32 = 6;
+
+const TASK_DYLD_INFO: libc::task_flavor_t = 17;
+
+// How many times to try copying the image list before giving up because
+// dyld keeps changing it underneath us.
+const SNAPSHOT_ATTEMPTS: usize = 8;
+
+#[repr(C, packed(4))]
+#[derive(Debug, Copy, Clone)]
+struct task_dyld_info {
+    all_image_info_addr: libc::mach_vm_address_t,
+    all_image_info_size: libc::mach_vm_size_t,
+    all_image_info_format: libc::integer_t,
+}
+
+const TASK_DYLD_INFO_COUNT: libc::mach_msg_type_number_t =
+    (mem::size_of::<task_dyld_info>() / mem::size_of::<libc::natural_t>()) as _;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+struct dyld_image_info {
+    image_load_address: *const libc::mach_header,
+    image_file_path: *const libc::c_char,
+    image_file_mod_date: libc::uintptr_t,
+}
+
+// The layout of `struct dyld_all_image_infos` from `<mach-o/dyld_images.h>`,
+// up to and including the fields of version 15 (macOS 10.12). Later fields
+// are read only after checking `version`.
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+struct dyld_all_image_infos {
+    version: u32,
+    info_array_count: u32,
+    info_array: *const dyld_image_info,
+    notification: *const libc::c_void,
+    process_detached_from_shared_region: bool,
+    lib_system_initialized: bool,
+    dyld_image_load_address: *const libc::mach_header,
+    jit_info: *const libc::c_void,
+    dyld_version: *const libc::c_char,
+    error_message: *const libc::c_char,
+    termination_flags: libc::uintptr_t,
+    core_symbolication_shm_page: *const libc::c_void,
+    system_order_flag: libc::uintptr_t,
+    uuid_array_count: libc::uintptr_t,
+    uuid_array: *const libc::c_void,
+    dyld_all_image_infos_address: *const dyld_all_image_infos,
+    initial_image_count: libc::uintptr_t,
+    error_kind: libc::uintptr_t,
+    error_client_of_dylib_path: *const libc::c_char,
+    error_target_dylib_path: *const libc::c_char,
+    error_symbol: *const libc::c_char,
+    shared_cache_slide: libc::uintptr_t,
+    shared_cache_uuid: [u8; 16],
+    shared_cache_base_address: libc::uintptr_t,
+    info_array_change_timestamp: u64,
+    dyld_path: *const libc::c_char,
+}
+
+/// Get dyld's `dyld_all_image_infos` for the current process.
+#[allow(deprecated)]
+fn all_image_infos() -> Option<&'static dyld_all_image_infos> {
+    let mut info: task_dyld_info = unsafe { mem::zeroed() };
+    let mut count = TASK_DYLD_INFO_COUNT;
+    let ret = unsafe {
+        libc::task_info(
+            libc::mach_task_self(),
+            TASK_DYLD_INFO,
+            &mut info as *mut task_dyld_info as libc::task_info_t,
+            &mut count,
+        )
+    };
+    if ret != libc::KERN_SUCCESS {
+        return None;
+    }
+
+    // dyld owns this structure and keeps it alive for the lifetime of the
+    // process.
+    unsafe { (info.all_image_info_addr as usize as *const dyld_all_image_infos).as_ref() }
+}
+
+/// Copy the list of loaded images out of `dyld_all_image_infos`.
+///
+/// dyld doesn't take any lock that we could share while it updates the list,
+/// so instead this copies the list and then checks that it didn't change
+/// while being copied, retrying a few times if it did.
+///
+/// Returns a `(header, name)` pair per image, or `None` if no consistent
+/// snapshot could be taken. dyld frees the path of an image when it unloads
+/// it, so the names are copied as part of the snapshot. The snapshot doesn't
+/// keep the images loaded though, so their headers must not be read before
+/// they are pinned.
+fn image_infos() -> Option<Vec<(*const libc::mach_header, CString)>> {
+    let infos = all_image_infos()?;
+
+    // dyld sets `infoArray` to null while it modifies the list, and bumps
+    // `infoArrayChangeTimestamp` (version 14 and later) after every change.
+    let state = || unsafe {
+        (
+            ptr::read_volatile(&infos.info_array),
+            ptr::read_volatile(&infos.info_array_count),
+            if infos.version >= 14 {
+                ptr::read_volatile(&infos.info_array_change_timestamp)
+            } else {
+                0
+            },
+        )
+    };
+
+    for _ in 0..SNAPSHOT_ATTEMPTS {
+        let before = state();
+        let (array, count, _) = before;
+        if array.is_null() {
+            thread::yield_now();
+            continue;
+        }
+
+        let images: Vec<_> = (0..count as usize)
+            .map(|i| {
+                let image: dyld_image_info = unsafe { ptr::read_volatile(array.add(i)) };
+                (image.image_load_address, copy_path(image.image_file_path))
+            })
+            .collect();
+        if state() != before {
+            continue;
+        }
+
+        return Some(images);
+    }
+
+    None
+}
+
+/// Find dyld itself, which is not part of its own list of images.
+fn dyld_image() -> Option<(*const libc::mach_header, usize, *const libc::c_char)> {
+    let infos = all_image_infos()?;
+    if infos.version < 2 {
+        return None;
+    }
+
+    let header = infos.dyld_image_load_address;
+    let slide = unsafe { MachHeader::from_header_ptr(header) }?.slide();
+    let name = if infos.version >= 15 && !infos.dyld_path.is_null() {
+        infos.dyld_path
+    } else {
+        b"/usr/lib/dyld\0".as_ptr() as *const libc::c_char
+    };
+    Some((header, slide, name))
+}
+
+extern "C" {
+    fn _NSGetMachExecuteHeader() -> *const libc::mach_header;
+    fn _dyld_register_func_for_add_image(
+        func: unsafe extern "C" fn(*const libc::mach_header, libc::intptr_t),
+    );
+    fn _dyld_register_func_for_remove_image(
+        func: unsafe extern "C" fn(*const libc::mach_header, libc::intptr_t),
+    );
+}
+
 lazy_static! {
     /// A lock protecting dyld FFI calls.
     ///
-    /// MacOS does not provide an atomic way to iterate shared libraries, so
-    /// *you* must take this lock whenever dynamically adding or removing shared
-    /// libraries to ensure that there are no races with iterating shared
-    /// libraries.
+    /// Iteration used to rely on everyone taking this lock around `dlopen`
+    /// and `dlclose`. It now validates its snapshot of dyld's image list, and
+    /// pins each image with `dlopen(RTLD_NOLOAD)` before reading its headers,
+    /// so that images unloaded meanwhile are skipped rather than read. Taking
+    /// this lock is no longer necessary. `each` still takes it while it
+    /// copies the list, but not while it calls back, so holding it keeps new
+    /// iterations from starting. It will be removed in a future release.
     pub static ref DYLD_LOCK: Mutex<()> = Mutex::new(());
 }
 
@@ -53,6 +423,118 @@ impl<'a> fmt::Debug for Segment<'a> {
     }
 }
 
+impl<'a> Segment<'a> {
+    /// Get the segment's stated virtual address, or `None` if it doesn't fit
+    /// in a `usize`, as can happen for 64-bit images in 32-bit processes or
+    /// for corrupt images.
+    pub fn try_stated_virtual_memory_address(&self) -> Option<Svma> {
+        match *self {
+            Segment::Segment32(seg) => Some(Svma(seg.vmaddr as usize)),
+            Segment::Segment64(seg) => usize::try_from(seg.vmaddr).ok().map(Svma),
+        }
+    }
+
+    /// Get the segment's size in memory, or `None` if it doesn't fit in a
+    /// `usize`.
+    pub fn try_len(&self) -> Option<usize> {
+        match *self {
+            Segment::Segment32(seg) => Some(seg.vmsize as usize),
+            Segment::Segment64(seg) => usize::try_from(seg.vmsize).ok(),
+        }
+    }
+
+    fn file_offset(&self) -> u64 {
+        match *self {
+            Segment::Segment32(seg) => u64::from(seg.fileoff),
+            Segment::Segment64(seg) => seg.fileoff,
+        }
+    }
+
+    fn file_size(&self) -> u64 {
+        match *self {
+            Segment::Segment32(seg) => u64::from(seg.filesize),
+            Segment::Segment64(seg) => seg.filesize,
+        }
+    }
+
+    /// Get the names of the sections in this segment, without their NUL
+    /// padding, along with their flags and stated addresses.
+    fn sections(&self) -> Vec<(&'a [u8], u32, Range<u64>)> {
+        let (first, nsects, cmdsize, header_size, section_size) = match *self {
+            Segment::Segment32(seg) => (
+                (seg as *const libc::segment_command).wrapping_add(1) as *const u8,
+                seg.nsects,
+                seg.cmdsize,
+                mem::size_of::<libc::segment_command>(),
+                mem::size_of::<section>(),
+            ),
+            Segment::Segment64(seg) => (
+                (seg as *const libc::segment_command_64).wrapping_add(1) as *const u8,
+                seg.nsects,
+                seg.cmdsize,
+                mem::size_of::<libc::segment_command_64>(),
+                mem::size_of::<section_64>(),
+            ),
+        };
+
+        // Don't read sections beyond the end of the load command.
+        let available = (cmdsize as usize).saturating_sub(header_size) / section_size;
+        (0..(nsects as usize).min(available))
+            .map(|index| {
+                let section = unsafe { first.add(index * section_size) };
+                let (name, flags, addr, size) = match *self {
+                    Segment::Segment32(_) => {
+                        let section = unsafe { &*(section as *const section) };
+                        (
+                            &section.sectname,
+                            section.flags,
+                            u64::from(section.addr),
+                            u64::from(section.size),
+                        )
+                    }
+                    Segment::Segment64(_) => {
+                        let section = unsafe { &*(section as *const section_64) };
+                        (&section.sectname, section.flags, section.addr, section.size)
+                    }
+                };
+                let len = name.iter().position(|b| *b == 0).unwrap_or(name.len());
+                (&name[..len], flags, addr..addr.saturating_add(size))
+            })
+            .collect()
+    }
+
+    /// Get the raw `VM_PROT_*` protection this segment is initially mapped
+    /// with.
+    pub fn initprot(&self) -> libc::vm_prot_t {
+        match *self {
+            Segment::Segment32(seg) => seg.initprot,
+            Segment::Segment64(seg) => seg.initprot,
+        }
+    }
+
+    /// Get the raw `VM_PROT_*` protection this segment may at most be given,
+    /// e.g. by `mprotect`.
+    pub fn maxprot(&self) -> libc::vm_prot_t {
+        match *self {
+            Segment::Segment32(seg) => seg.maxprot,
+            Segment::Segment64(seg) => seg.maxprot,
+        }
+    }
+
+    /// Get the most permissive protection this segment may be given.
+    pub fn max_permissions(&self) -> Permissions {
+        vm_prot_to_permissions(self.maxprot())
+    }
+}
+
+fn vm_prot_to_permissions(prot: libc::vm_prot_t) -> Permissions {
+    Permissions::new(
+        prot & VM_PROT_READ != 0,
+        prot & VM_PROT_WRITE != 0,
+        prot & VM_PROT_EXECUTE != 0,
+    )
+}
+
 impl<'a> SegmentTrait for Segment<'a> {
     type SharedLibrary = SharedLibrary<'a>;
 
@@ -71,87 +553,221 @@ impl<'a> SegmentTrait for Segment<'a> {
     }
 
     #[inline]
-    fn stated_virtual_memory_address(&self) -> Svma {
+    fn is_data(&self) -> bool {
+        let prot = self.initprot();
+        prot & VM_PROT_WRITE != 0 && prot & VM_PROT_EXECUTE == 0 && self.name() != "__DATA_CONST"
+    }
+
+    /// `__DATA_CONST` counts as read-only data: it is only writable until
+    /// dyld is done binding it.
+    #[inline]
+    fn is_readonly_data(&self) -> bool {
+        let prot = self.initprot();
+        self.name() == "__DATA_CONST"
+            || (prot & VM_PROT_READ != 0 && prot & (VM_PROT_WRITE | VM_PROT_EXECUTE) == 0)
+    }
+
+    #[inline]
+    fn is_bss(&self) -> bool {
+        let zerofill = |flags: u32| {
+            matches!(
+                flags & SECTION_TYPE,
+                S_ZEROFILL | S_GB_ZEROFILL | S_THREAD_LOCAL_ZEROFILL
+            )
+        };
+        // Comparing the segment's sizes would also count `__PAGEZERO`, which
+        // isn't in the file at all, and `__LINKEDIT`, whose size in memory is
+        // rounded up to the page size.
+        self.sections().iter().any(|(_, flags, _)| zerofill(*flags))
+    }
+
+    #[inline]
+    fn permissions(&self) -> Option<Permissions> {
+        Some(vm_prot_to_permissions(self.initprot()))
+    }
+
+    #[inline]
+    fn file_range(&self) -> Option<Range<u64>> {
+        let start = self.file_offset();
+        Some(start..start.saturating_add(self.file_size()))
+    }
+
+    #[inline]
+    fn raw_type(&self) -> Option<u32> {
         match *self {
-            Segment::Segment32(seg) => Svma(seg.vmaddr as usize),
-            Segment::Segment64(seg) => {
-                assert!(seg.vmaddr <= (usize::MAX as u64));
-                Svma(seg.vmaddr as usize)
-            }
+            Segment::Segment32(seg) => Some(seg.cmd),
+            Segment::Segment64(seg) => Some(seg.cmd),
         }
     }
 
     #[inline]
-    fn len(&self) -> usize {
+    fn raw_flags(&self) -> Option<u64> {
         match *self {
-            Segment::Segment32(seg) => seg.vmsize as usize,
-            Segment::Segment64(seg) => {
-                assert!(seg.vmsize <= (usize::MAX as u64));
-                seg.vmsize as usize
-            }
+            Segment::Segment32(seg) => Some(u64::from(seg.flags)),
+            Segment::Segment64(seg) => Some(u64::from(seg.flags)),
         }
     }
+
+    /// Saturates at `usize::MAX` if the address doesn't fit, see
+    /// `try_stated_virtual_memory_address`.
+    #[inline]
+    fn stated_virtual_memory_address(&self) -> Svma {
+        self.try_stated_virtual_memory_address()
+            .unwrap_or(Svma(usize::MAX))
+    }
+
+    /// Saturates at `usize::MAX` if the size doesn't fit, see `try_len`.
+    #[inline]
+    fn len(&self) -> usize {
+        self.try_len().unwrap_or(usize::MAX)
+    }
 }
 
-/// An iterator over Mach-O segments.
+/// An iterator over the raw load commands of a Mach-O image.
 #[derive(Debug)]
-pub struct SegmentIter<'a> {
-    phantom: PhantomData<&'a SharedLibrary<'a>>,
+struct LoadCommandIter<'a> {
+    phantom: PhantomData<&'a libc::load_command>,
     commands: *const libc::load_command,
     num_commands: usize,
 }
 
-impl<'a> SegmentIter<'a> {
-    fn find_uuid(&self) -> Option<[u8; 16]> {
-        let mut num_commands = self.num_commands;
-        let mut commands = self.commands;
+impl<'a> Iterator for LoadCommandIter<'a> {
+    type Item = &'a libc::load_command;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.num_commands == 0 {
+            return None;
+        }
+        self.num_commands -= 1;
+
+        let this_command = unsafe { self.commands.as_ref().unwrap() };
+        let command_size = this_command.cmdsize as isize;
+        self.commands = unsafe { (self.commands as *const u8).offset(command_size) as *const _ };
+        Some(this_command)
+    }
+}
+
+/// The load commands of a Mach-O image that we care about, gathered in a
+/// single pass when the `SharedLibrary` is constructed.
+#[derive(Clone, Copy, Debug, Default)]
+struct LoadCommands {
+    uuid: Option<[u8; 16]>,
+    encryption_info: Option<encryption_info_command>,
+    entry_offset: Option<u64>,
+    thread_pc: Option<u64>,
+    // File offset and size of the export trie.
+    exports: Option<(u32, u32)>,
+    symtab: Option<symtab_command>,
+    // File offset and size of the embedded code signature.
+    code_signature: Option<(u32, u32)>,
+    // File offset and size of the function starts table.
+    function_starts: Option<(u32, u32)>,
+}
 
-        while num_commands > 0 {
-            num_commands -= 1;
-            let this_command = unsafe { commands.as_ref().unwrap() };
-            let command_size = this_command.cmdsize as isize;
-            if let LC_UUID = this_command.cmd {
-                let uuid_cmd = commands as *const uuid_command;
-                return Some(unsafe { (*uuid_cmd).uuid });
+impl LoadCommands {
+    fn parse(header: &MachHeader) -> LoadCommands {
+        let mut parsed = LoadCommands::default();
+        for command in header.load_commands() {
+            let command_ptr = command as *const libc::load_command;
+            match command.cmd {
+                LC_UUID if parsed.uuid.is_none() => {
+                    let uuid_cmd = command_ptr as *const uuid_command;
+                    parsed.uuid = Some(unsafe { (*uuid_cmd).uuid });
+                }
+                LC_ENCRYPTION_INFO | LC_ENCRYPTION_INFO_64 => {
+                    let encryption_cmd = command_ptr as *const encryption_info_command;
+                    parsed.encryption_info = Some(unsafe { *encryption_cmd });
+                }
+                LC_DYLD_EXPORTS_TRIE => {
+                    let linkedit_cmd = unsafe { &*(command_ptr as *const linkedit_data_command) };
+                    parsed.exports = Some((linkedit_cmd.dataoff, linkedit_cmd.datasize));
+                }
+                // `LC_DYLD_EXPORTS_TRIE` supersedes the export info in
+                // `LC_DYLD_INFO` when both are present.
+                LC_DYLD_INFO | LC_DYLD_INFO_ONLY if parsed.exports.is_none() => {
+                    let info_cmd = unsafe { &*(command_ptr as *const dyld_info_command) };
+                    if info_cmd.export_size != 0 {
+                        parsed.exports = Some((info_cmd.export_off, info_cmd.export_size));
+                    }
+                }
+                LC_CODE_SIGNATURE => {
+                    let linkedit_cmd = unsafe { &*(command_ptr as *const linkedit_data_command) };
+                    parsed.code_signature = Some((linkedit_cmd.dataoff, linkedit_cmd.datasize));
+                }
+                LC_FUNCTION_STARTS => {
+                    let linkedit_cmd = unsafe { &*(command_ptr as *const linkedit_data_command) };
+                    parsed.function_starts = Some((linkedit_cmd.dataoff, linkedit_cmd.datasize));
+                }
+                LC_SYMTAB => {
+                    parsed.symtab = Some(unsafe { *(command_ptr as *const symtab_command) });
+                }
+                LC_MAIN => {
+                    let entry_cmd = command_ptr as *const entry_point_command;
+                    parsed.entry_offset = Some(unsafe { (*entry_cmd).entryoff });
+                }
+                LC_UNIXTHREAD => {
+                    let thread_cmd = command_ptr as *const thread_command;
+                    parsed.thread_pc = unsafe { thread_pc(header.cputype(), thread_cmd) };
+                }
+                _ => {}
             }
-            commands = unsafe { (commands as *const u8).offset(command_size) as *const _ };
         }
+        parsed
+    }
+}
 
-        None
+/// Read the initial program counter out of an `LC_UNIXTHREAD` command.
+unsafe fn thread_pc(cputype: i32, command: *const thread_command) -> Option<u64> {
+    let command = &*command;
+    // The index of the program counter in the register state, and whether
+    // registers are 64 bits wide.
+    let (index, is_64) = match (cputype, command.flavor) {
+        (CPU_TYPE_X86, X86_THREAD_STATE32) => (10, false),
+        (CPU_TYPE_X86_64, X86_THREAD_STATE64) => (16, true),
+        (CPU_TYPE_ARM, ARM_THREAD_STATE) => (15, false),
+        (CPU_TYPE_ARM64, ARM_THREAD_STATE64) => (32, true),
+        _ => return None,
+    };
+
+    let state = (command as *const thread_command).offset(1) as *const u32;
+    let words = if is_64 { index * 2 + 2 } else { index + 1 };
+    let available = (command.cmdsize as usize).saturating_sub(mem::size_of::<thread_command>()) / 4;
+    if command.count < words || available < words as usize {
+        return None;
+    }
+
+    if is_64 {
+        Some(ptr::read_unaligned(
+            state.add(index as usize * 2) as *const u64
+        ))
+    } else {
+        Some(u64::from(*state.add(index as usize)))
     }
 }
 
+/// An iterator over Mach-O segments.
+#[derive(Debug)]
+pub struct SegmentIter<'a> {
+    commands: LoadCommandIter<'a>,
+}
+
 impl<'a> Iterator for SegmentIter<'a> {
     type Item = Segment<'a>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        while self.num_commands > 0 {
-            self.num_commands -= 1;
-
-            let this_command = unsafe { self.commands.as_ref().unwrap() };
-            let command_size = this_command.cmdsize as isize;
-
-            match this_command.cmd {
+        for command in &mut self.commands {
+            let command_ptr = command as *const libc::load_command;
+            match command.cmd {
                 libc::LC_SEGMENT => {
-                    let segment = self.commands as *const libc::segment_command;
-                    let segment = unsafe { segment.as_ref().unwrap() };
-                    self.commands =
-                        unsafe { (self.commands as *const u8).offset(command_size) as *const _ };
-                    return Some(Segment::Segment32(segment));
+                    let segment = command_ptr as *const libc::segment_command;
+                    return Some(Segment::Segment32(unsafe { segment.as_ref().unwrap() }));
                 }
                 libc::LC_SEGMENT_64 => {
-                    let segment = self.commands as *const libc::segment_command_64;
-                    let segment = unsafe { segment.as_ref().unwrap() };
-                    self.commands =
-                        unsafe { (self.commands as *const u8).offset(command_size) as *const _ };
-                    return Some(Segment::Segment64(segment));
-                }
-                _ => {
-                    // Some other kind of load command; skip to the next one.
-                    self.commands =
-                        unsafe { (self.commands as *const u8).offset(command_size) as *const _ };
-                    continue;
+                    let segment = command_ptr as *const libc::segment_command_64;
+                    return Some(Segment::Segment64(unsafe { segment.as_ref().unwrap() }));
                 }
+                // Some other kind of load command; skip to the next one.
+                _ => continue,
             }
         }
 
@@ -189,124 +805,1381 @@ impl<'a> MachHeader<'a> {
                 .map(MachHeader::Header64),
         })
     }
-}
-
-/// The MacOS implementation of the [SharedLibrary
-/// trait](../trait.SharedLibrary.html).
-///
-/// This wraps the `_dyld_image_count` and
-/// `_dyld_get_image_{header,vmaddr_slide,name}` system APIs from the
-/// `<mach-o/dyld.h>` header.
-pub struct SharedLibrary<'a> {
-    header: MachHeader<'a>,
-    slide: usize,
-    name: &'a CStr,
-}
-
-impl<'a> fmt::Debug for SharedLibrary<'a> {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        f.debug_struct("SharedLibrary")
-            .field("name", &self.name())
-            .field("id", &self.id())
-            .finish()
-    }
-}
 
-impl<'a> SharedLibrary<'a> {
-    fn new(header: MachHeader<'a>, slide: usize, name: &'a CStr) -> Self {
-        SharedLibrary {
-            header: header,
-            slide: slide,
-            name: name,
+    fn as_ptr(&self) -> *const libc::mach_header {
+        match *self {
+            MachHeader::Header32(header) => header as *const libc::mach_header,
+            MachHeader::Header64(header) => header as *const _ as *const libc::mach_header,
         }
     }
-}
-
-impl<'a> SharedLibraryTrait for SharedLibrary<'a> {
-    type Segment = Segment<'a>;
-    type SegmentIter = SegmentIter<'a>;
-
-    #[inline]
-    fn name(&self) -> &OsStr {
-        OsStr::from_bytes(self.name.to_bytes())
-    }
 
-    fn id(&self) -> Option<SharedLibraryId> {
-        self.segments().find_uuid().map(SharedLibraryId::Uuid)
-    }
-
-    fn segments(&self) -> Self::SegmentIter {
-        match self.header {
+    fn load_commands(&self) -> LoadCommandIter<'a> {
+        let (commands, num_commands) = match *self {
             MachHeader::Header32(header) => {
                 let num_commands = header.ncmds;
                 let header = header as *const libc::mach_header;
-                let commands = unsafe { header.offset(1) as *const libc::load_command };
-                SegmentIter {
-                    phantom: PhantomData,
-                    commands: commands,
-                    num_commands: num_commands as usize,
-                }
+                (
+                    unsafe { header.offset(1) as *const libc::load_command },
+                    num_commands,
+                )
             }
             MachHeader::Header64(header) => {
                 let num_commands = header.ncmds;
                 let header = header as *const libc::mach_header_64;
-                let commands = unsafe { header.offset(1) as *const libc::load_command };
-                SegmentIter {
-                    phantom: PhantomData,
-                    commands: commands,
-                    num_commands: num_commands as usize,
-                }
+                (
+                    unsafe { header.offset(1) as *const libc::load_command },
+                    num_commands,
+                )
             }
+        };
+        LoadCommandIter {
+            phantom: PhantomData,
+            commands,
+            num_commands: num_commands as usize,
         }
     }
 
-    #[inline]
-    fn virtual_memory_bias(&self) -> Bias {
-        Bias(self.slide)
+    fn segments(&self) -> SegmentIter<'a> {
+        SegmentIter {
+            commands: self.load_commands(),
+        }
     }
 
-    fn each<F, C>(mut f: F)
-    where
-        F: FnMut(&Self) -> C,
-        C: Into<IterationControl>,
-    {
-        // Make sure we have exclusive access to dyld so that (hopefully) no one
-        // else adds or removes shared libraries while we are iterating them.
-        let _dyld_lock = DYLD_LOCK.lock();
-
-        let count = unsafe { libc::_dyld_image_count() };
+    /// Compute the slide from where the `__TEXT` segment says it wants to be
+    /// loaded and where the header actually is.
+    fn slide(&self) -> usize {
+        self.segments()
+            .find(|segment| segment.name() == "__TEXT")
+            .map_or(0, |text| {
+                (self.as_ptr() as usize).wrapping_sub(text.stated_virtual_memory_address().0)
+            })
+    }
 
-        for image_idx in 0..count {
-            let (header, slide, name) = unsafe {
-                (
+    fn cputype(&self) -> i32 {
+        match *self {
+            MachHeader::Header32(header) => header.cputype,
+            MachHeader::Header64(header) => header.cputype,
+        }
+    }
+
+    fn cpusubtype(&self) -> i32 {
+        match *self {
+            MachHeader::Header32(header) => header.cpusubtype,
+            MachHeader::Header64(header) => header.cpusubtype,
+        }
+    }
+
+    fn filetype(&self) -> u32 {
+        match *self {
+            MachHeader::Header32(header) => header.filetype,
+            MachHeader::Header64(header) => header.filetype,
+        }
+    }
+
+    fn flags(&self) -> u32 {
+        match *self {
+            MachHeader::Header32(header) => header.flags,
+            MachHeader::Header64(header) => header.flags,
+        }
+    }
+}
+
+/// Get the address range the dyld shared cache is mapped at, if any.
+fn shared_cache_range() -> Option<(usize, usize)> {
+    type GetSharedCacheRange = unsafe extern "C" fn(*mut libc::size_t) -> *const libc::c_void;
+
+    // `_dyld_get_shared_cache_range` only exists on macOS 10.15 and later, so
+    // we can't link against it directly.
+    let sym = unsafe {
+        libc::dlsym(
+            libc::RTLD_DEFAULT,
+            b"_dyld_get_shared_cache_range\0".as_ptr() as *const libc::c_char,
+        )
+    };
+    if sym.is_null() {
+        return None;
+    }
+
+    let get_range: GetSharedCacheRange = unsafe { mem::transmute(sym) };
+    let mut len = 0;
+    let start = unsafe { get_range(&mut len) };
+    if start.is_null() || len == 0 {
+        None
+    } else {
+        Some((start as usize, len))
+    }
+}
+
+/// Which language runtimes have metadata in an image.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Capabilities {
+    objc: bool,
+    swift: bool,
+}
+
+impl Capabilities {
+    /// Returns `true` if the image contains Objective-C metadata, i.e. an
+    /// `__objc_imageinfo` section.
+    pub fn has_objc(&self) -> bool {
+        self.objc
+    }
+
+    /// Returns `true` if the image contains Swift metadata sections, such
+    /// as `__swift5_types`.
+    pub fn has_swift(&self) -> bool {
+        self.swift
+    }
+}
+
+/// The type of a Mach-O image, from the `filetype` field of its header.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum FileType {
+    /// `MH_EXECUTE`: the main executable.
+    Execute,
+    /// `MH_DYLIB`: a dynamic library.
+    Dylib,
+    /// `MH_BUNDLE`: a bundle, typically a plugin loaded with `dlopen`.
+    Bundle,
+    /// `MH_DYLINKER`: the dynamic linker, dyld.
+    Dylinker,
+    /// Any other file type.
+    Other(u32),
+}
+
+impl From<u32> for FileType {
+    fn from(filetype: u32) -> FileType {
+        match filetype {
+            MH_EXECUTE => FileType::Execute,
+            MH_DYLIB => FileType::Dylib,
+            MH_BUNDLE => FileType::Bundle,
+            MH_DYLINKER => FileType::Dylinker,
+            other => FileType::Other(other),
+        }
+    }
+}
+
+/// The architecture slice of a universal ("fat") binary that an image was
+/// loaded from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FatSlice {
+    offset: u64,
+    size: u64,
+}
+
+impl FatSlice {
+    /// Get the offset of the slice in the file, in bytes.
+    ///
+    /// This is zero for thin binaries.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Get the size of the slice, in bytes.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+}
+
+fn read_u32_be(file: &mut File) -> io::Result<u32> {
+    let mut buf = [0; 4];
+    file.read_exact(&mut buf)?;
+    Ok(u32::from_be_bytes(buf))
+}
+
+fn read_u64_be(file: &mut File) -> io::Result<u64> {
+    let mut buf = [0; 8];
+    file.read_exact(&mut buf)?;
+    Ok(u64::from_be_bytes(buf))
+}
+
+/// Read the `LC_UUID` of the Mach-O image starting at `offset` in `file`.
+fn read_file_uuid(file: &mut File, offset: u64) -> io::Result<Option<[u8; 16]>> {
+    // Read enough of the header to cover both `mach_header` and
+    // `mach_header_64`.
+    let mut header = [0; 32];
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(&mut header)?;
+
+    let word = |idx: usize| {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(&header[idx * 4..idx * 4 + 4]);
+        u32::from_ne_bytes(bytes)
+    };
+    let header_size = match word(0) {
+        libc::MH_MAGIC => mem::size_of::<libc::mach_header>(),
+        libc::MH_MAGIC_64 => mem::size_of::<libc::mach_header_64>(),
+        _ => return Ok(None),
+    };
+    let ncmds = word(4);
+    let sizeofcmds = word(5);
+
+    // Don't trust `sizeofcmds` enough to allocate it upfront.
+    let mut commands = Vec::new();
+    file.seek(SeekFrom::Start(offset + header_size as u64))?;
+    file.take(u64::from(sizeofcmds))
+        .read_to_end(&mut commands)?;
+
+    let mut commands = &commands[..];
+    for _ in 0..ncmds {
+        if commands.len() < mem::size_of::<uuid_command>() {
+            break;
+        }
+        let cmd = u32::from_ne_bytes([commands[0], commands[1], commands[2], commands[3]]);
+        let cmdsize = u32::from_ne_bytes([commands[4], commands[5], commands[6], commands[7]]);
+        if cmd == LC_UUID {
+            let mut uuid = [0; 16];
+            uuid.copy_from_slice(&commands[8..24]);
+            return Ok(Some(uuid));
+        }
+        if cmdsize == 0 || cmdsize as usize > commands.len() {
+            break;
+        }
+        commands = &commands[cmdsize as usize..];
+    }
+
+    Ok(None)
+}
+
+/// The dyld shared cache mapped into this process.
+///
+/// System libraries are prelinked into one large cache file rather than being
+/// loaded from individual files, so symbolication has to go through the
+/// cache identified by this UUID instead of the images' own paths.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SharedCache {
+    uuid: [u8; 16],
+    base: usize,
+    slide: usize,
+}
+
+impl SharedCache {
+    /// Get the shared cache mapped into this process, if there is one.
+    pub fn current() -> Option<SharedCache> {
+        let infos = all_image_infos()?;
+        if infos.version < 15 || infos.shared_cache_base_address == 0 {
+            return None;
+        }
+
+        Some(SharedCache {
+            uuid: infos.shared_cache_uuid,
+            base: infos.shared_cache_base_address,
+            slide: infos.shared_cache_slide,
+        })
+    }
+
+    /// Get the UUID of the shared cache.
+    pub fn id(&self) -> SharedLibraryId {
+        SharedLibraryId::Uuid(self.uuid)
+    }
+
+    /// Get the address the shared cache is mapped at.
+    pub fn actual_base_addr(&self) -> Avma {
+        Avma(self.base)
+    }
+
+    /// Get the address the shared cache prefers to be mapped at.
+    pub fn stated_base_addr(&self) -> Svma {
+        Svma(self.base.wrapping_sub(self.slide))
+    }
+
+    /// Get the slide applied to every image in the shared cache.
+    ///
+    /// See the module documentation for details.
+    pub fn virtual_memory_bias(&self) -> Bias {
+        Bias(self.slide)
+    }
+}
+
+/// Keeps a shared library loaded for as long as it is held.
+///
+/// See [`SharedLibrary::pin`](struct.SharedLibrary.html#method.pin).
+#[derive(Debug)]
+pub struct PinnedLibrary {
+    handle: *mut libc::c_void,
+}
+
+// The handle is only ever passed to `dlclose`, which can be called from any
+// thread.
+unsafe impl Send for PinnedLibrary {}
+unsafe impl Sync for PinnedLibrary {}
+
+impl Drop for PinnedLibrary {
+    fn drop(&mut self) {
+        unsafe {
+            libc::dlclose(self.handle);
+        }
+    }
+}
+
+/// The MacOS implementation of the [SharedLibrary
+/// trait](../trait.SharedLibrary.html).
+///
+/// Images are enumerated by reading the `dyld_all_image_infos` structure that
+/// dyld publishes through `task_info(TASK_DYLD_INFO)`, which gives a
+/// consistent snapshot of the loaded images in one pass. If that isn't
+/// available this falls back to the `_dyld_image_count` and
+/// `_dyld_get_image_{header,vmaddr_slide,name}` system APIs from the
+/// `<mach-o/dyld.h>` header.
+pub struct SharedLibrary<'a> {
+    header: MachHeader<'a>,
+    commands: LoadCommands,
+    slide: usize,
+    name: &'a CStr,
+    // For images read out of another task, where the header lives in that
+    // task rather than at `header`.
+    remote_header: Option<usize>,
+}
+
+impl<'a> fmt::Debug for SharedLibrary<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SharedLibrary")
+            .field("name", &self.name())
+            .field("id", &self.id())
+            .finish()
+    }
+}
+
+impl<'a> SharedLibrary<'a> {
+    fn new(header: MachHeader<'a>, slide: usize, name: &'a CStr) -> Self {
+        SharedLibrary {
+            commands: LoadCommands::parse(&header),
+            header,
+            slide,
+            name,
+            remote_header: None,
+        }
+    }
+
+    /// Create a `SharedLibrary` from what dyld passes to the callbacks of
+    /// `_dyld_register_func_for_add_image`, for code that registers its own.
+    ///
+    /// dyld doesn't pass the image's path along, so the caller supplies it
+    /// as `name`, e.g. from `dladdr` on `header` or `_dyld_get_image_name`.
+    /// Returns `None` if `header` isn't a Mach-O header of the current
+    /// architecture's byte order.
+    ///
+    /// # Safety
+    ///
+    /// `header` must point to the header of an image loaded in the current
+    /// task, with `slide` being the image's slide, and the image must stay
+    /// loaded for `'a`.
+    pub unsafe fn from_header(
+        header: *const libc::mach_header,
+        slide: libc::intptr_t,
+        name: &'a CStr,
+    ) -> Option<SharedLibrary<'a>> {
+        let header = MachHeader::from_header_ptr(header)?;
+        Some(SharedLibrary::new(header, slide as usize, name))
+    }
+
+    /// Get the path of the image as dyld reports it, without converting it.
+    pub fn name_cstr(&self) -> &'a CStr {
+        self.name
+    }
+
+    /// Get the path of the image as bytes, without the trailing NUL.
+    pub fn name_bytes(&self) -> &'a [u8] {
+        self.name.to_bytes()
+    }
+
+    /// Get the actual address of the Mach-O header.
+    fn header_addr(&self) -> usize {
+        self.remote_header
+            .unwrap_or_else(|| self.header.as_ptr() as usize)
+    }
+
+    /// Get the Mach-O file type of this image.
+    pub fn filetype(&self) -> FileType {
+        FileType::from(self.header.filetype())
+    }
+
+    /// Get the `cputype` of this image, e.g. `CPU_TYPE_ARM64`.
+    pub fn cpu_type(&self) -> i32 {
+        self.header.cputype()
+    }
+
+    /// Get the `cpusubtype` of this image, e.g. `CPU_SUBTYPE_ARM64E`.
+    ///
+    /// This includes the capability bits in the high byte, such as the
+    /// pointer authentication ABI version on arm64e.
+    pub fn cpu_subtype(&self) -> i32 {
+        self.header.cpusubtype()
+    }
+
+    /// Get the conventional name of the architecture slice this image was
+    /// loaded from, e.g. `"arm64e"` or `"x86_64h"`, if it is a known one.
+    pub fn arch_name(&self) -> Option<&'static str> {
+        let subtype = self.cpu_subtype() & !CPU_SUBTYPE_MASK;
+        match (self.cpu_type(), subtype) {
+            (CPU_TYPE_X86, _) => Some("i386"),
+            (CPU_TYPE_X86_64, CPU_SUBTYPE_X86_64_H) => Some("x86_64h"),
+            (CPU_TYPE_X86_64, _) => Some("x86_64"),
+            (CPU_TYPE_ARM, CPU_SUBTYPE_ARM_V7) => Some("armv7"),
+            (CPU_TYPE_ARM, CPU_SUBTYPE_ARM_V7S) => Some("armv7s"),
+            (CPU_TYPE_ARM, CPU_SUBTYPE_ARM_V7K) => Some("armv7k"),
+            (CPU_TYPE_ARM, _) => Some("arm"),
+            (CPU_TYPE_ARM64, CPU_SUBTYPE_ARM64E) => Some("arm64e"),
+            (CPU_TYPE_ARM64, _) => Some("arm64"),
+            (CPU_TYPE_ARM64_32, _) => Some("arm64_32"),
+            _ => None,
+        }
+    }
+
+    /// Find the architecture slice of the backing file that this image was
+    /// loaded from.
+    ///
+    /// This opens the file at `name()` and matches the slices of a universal
+    /// binary against this image's CPU type and UUID. Thin binaries yield a
+    /// slice covering the whole file. Returns `None` if the file can't be read
+    /// or has no matching slice, for example because it was replaced on disk
+    /// or because the image lives in the dyld shared cache.
+    pub fn fat_slice(&self) -> Option<FatSlice> {
+        self.find_fat_slice().ok().and_then(|slice| slice)
+    }
+
+    /// Find the dSYM bundle holding this image's debug information.
+    ///
+    /// See [`find_dsym`](fn.find_dsym.html) for where this looks.
+    pub fn find_dsym(&self) -> Option<DsymCandidate> {
+        let uuid = self.commands.uuid?;
+        find_dsym(&uuid, Path::new(self.name()))
+    }
+
+    fn find_fat_slice(&self) -> io::Result<Option<FatSlice>> {
+        let mut file = File::open(self.name())?;
+        let uuid = self.commands.uuid;
+        let matches_uuid = |file: &mut File, offset| -> io::Result<bool> {
+            Ok(uuid.is_none() || read_file_uuid(file, offset)? == uuid)
+        };
+
+        let magic = read_u32_be(&mut file)?;
+        if magic != FAT_MAGIC && magic != FAT_MAGIC_64 {
+            let size = file.metadata()?.len();
+            return Ok(if matches_uuid(&mut file, 0)? {
+                Some(FatSlice { offset: 0, size })
+            } else {
+                None
+            });
+        }
+
+        let nfat_arch = read_u32_be(&mut file)?;
+        if nfat_arch > MAX_FAT_ARCHS {
+            return Ok(None);
+        }
+
+        let mut candidates = Vec::new();
+        for _ in 0..nfat_arch {
+            let cputype = read_u32_be(&mut file)? as i32;
+            let cpusubtype = read_u32_be(&mut file)? as i32;
+            let (offset, size) = if magic == FAT_MAGIC_64 {
+                let slice = (read_u64_be(&mut file)?, read_u64_be(&mut file)?);
+                // `align` and `reserved`.
+                read_u64_be(&mut file)?;
+                slice
+            } else {
+                let slice = (
+                    u64::from(read_u32_be(&mut file)?),
+                    u64::from(read_u32_be(&mut file)?),
+                );
+                // `align`.
+                read_u32_be(&mut file)?;
+                slice
+            };
+
+            if cputype == self.cpu_type()
+                && (cpusubtype & !CPU_SUBTYPE_MASK) == (self.cpu_subtype() & !CPU_SUBTYPE_MASK)
+            {
+                candidates.push(FatSlice { offset, size });
+            }
+        }
+
+        for slice in candidates {
+            if matches_uuid(&mut file, slice.offset)? {
+                return Ok(Some(slice));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Get the in-memory contents of `size` bytes at file offset `offset`
+    /// within the `__LINKEDIT` segment.
+    fn linkedit_data(&self, offset: u32, size: u32) -> Option<&'a [u8]> {
+        // Only the headers of remote images are copied into this process.
+        if self.remote_header.is_some() {
+            return None;
+        }
+
+        let linkedit = self.segments().find(|seg| seg.name() == "__LINKEDIT")?;
+        let (offset, size) = (u64::from(offset), u64::from(size));
+        if offset < linkedit.file_offset()
+            || offset + size > linkedit.file_offset() + linkedit.file_size()
+        {
+            return None;
+        }
+
+        let start = linkedit
+            .actual_virtual_memory_address(self)
+            .0
+            .wrapping_add((offset - linkedit.file_offset()) as usize);
+        Some(unsafe { slice::from_raw_parts(start as *const u8, size as usize) })
+    }
+
+    /// Iterate over the symbols exported by this image, as recorded in its
+    /// dyld export trie.
+    pub fn exports(&self) -> ExportIter<'a> {
+        let trie = self
+            .commands
+            .exports
+            .and_then(|(offset, size)| self.linkedit_data(offset, size))
+            .unwrap_or(&[]);
+        ExportIter::new(trie, self.header_addr())
+    }
+
+    /// Returns `true` if this image has an embedded code signature.
+    pub fn has_code_signature(&self) -> bool {
+        self.commands.code_signature.is_some()
+    }
+
+    /// Parse this image's embedded code signature, if it has one and it is
+    /// mapped into memory.
+    pub fn code_signature(&self) -> Option<CodeSignature> {
+        let (offset, size) = self.commands.code_signature?;
+        codesign::parse(self.linkedit_data(offset, size)?)
+    }
+
+    fn function_starts_data(&self) -> Option<&'a [u8]> {
+        let (offset, size) = self.commands.function_starts?;
+        self.linkedit_data(offset, size)
+    }
+
+    /// Get where the compressed `LC_FUNCTION_STARTS` table is mapped, if this
+    /// image has one.
+    pub fn function_starts_range(&self) -> Option<Range<Avma>> {
+        let data = self.function_starts_data()?;
+        let start = data.as_ptr() as usize;
+        Some(Avma(start)..Avma(start + data.len()))
+    }
+
+    /// Iterate over the start addresses of the functions in this image, in
+    /// ascending order, as recorded by `LC_FUNCTION_STARTS`.
+    ///
+    /// This only covers functions the linker knew about, and doesn't say
+    /// where they end, but is available even when the image has been
+    /// stripped.
+    pub fn function_starts(&self) -> FunctionStarts<'a> {
+        // The offsets are relative to the start of `__TEXT`.
+        let text = self
+            .segments()
+            .find(|segment| segment.name() == "__TEXT")
+            .map(|text| text.actual_virtual_memory_address(self));
+        FunctionStarts {
+            data: match text {
+                Some(_) => self.function_starts_data().unwrap_or(&[]),
+                None => &[],
+            },
+            address: text.map_or(0, |text| text.0),
+        }
+    }
+
+    /// Find the symbol covering `address`, using the symbol table referenced by
+    /// `LC_SYMTAB`.
+    ///
+    /// This returns the defined symbol with the highest address that is not
+    /// above `address`.
+    fn symtab_symbol_for_avma(&self, address: Avma) -> Option<Symbol<'a>> {
+        let symtab = self.commands.symtab?;
+        let strings = self.linkedit_data(symtab.stroff, symtab.strsize)?;
+        let svma = address.0.wrapping_sub(self.slide) as u64;
+
+        // The symbol table isn't sorted by address, so this has to look at
+        // every entry.
+        let (n_strx, n_value) = match self.header {
+            MachHeader::Header32(_) => self.nearest_symbol::<nlist>(&symtab, svma, |sym| {
+                (sym.n_type, sym.n_strx, u64::from(sym.n_value))
+            })?,
+            MachHeader::Header64(_) => self.nearest_symbol::<nlist_64>(&symtab, svma, |sym| {
+                (sym.n_type, sym.n_strx, sym.n_value)
+            })?,
+        };
+
+        let name = strings.get(n_strx as usize..)?;
+        let name = CStr::from_bytes_with_nul(&name[..=name.iter().position(|b| *b == 0)?]).ok()?;
+        let address = Avma((n_value as usize).wrapping_add(self.slide));
+        Some(Symbol::new(name, address))
+    }
+
+    /// Find the `(n_strx, n_value)` of the defined symbol closest to, but not
+    /// above, `svma`.
+    fn nearest_symbol<N>(
+        &self,
+        symtab: &symtab_command,
+        svma: u64,
+        fields: impl Fn(&N) -> (u8, u32, u64),
+    ) -> Option<(u32, u64)> {
+        let size = (symtab.nsyms as usize).checked_mul(mem::size_of::<N>())?;
+        let symbols = self.linkedit_data(symtab.symoff, u32::try_from(size).ok()?)?;
+        let symbols =
+            unsafe { slice::from_raw_parts(symbols.as_ptr() as *const N, symtab.nsyms as usize) };
+
+        symbols
+            .iter()
+            .map(fields)
+            .filter(|&(n_type, _, n_value)| {
+                n_type & N_STAB == 0 && n_type & N_TYPE == N_SECT && n_value <= svma
+            })
+            .max_by_key(|&(_, _, n_value)| n_value)
+            .map(|(_, n_strx, n_value)| (n_strx, n_value))
+    }
+
+    /// Ask dyld which symbol covers `address` using `dladdr`.
+    fn dladdr_symbol_for_avma(&self, address: Avma) -> Option<Symbol<'a>> {
+        if self.remote_header.is_some() {
+            return None;
+        }
+
+        let mut info: libc::Dl_info = unsafe { mem::zeroed() };
+        if unsafe { libc::dladdr(address.0 as *const _, &mut info) } == 0
+            || !ptr::eq(
+                info.dli_fbase as *const libc::mach_header,
+                self.header.as_ptr(),
+            )
+            || info.dli_sname.is_null()
+        {
+            return None;
+        }
+
+        // The name lives in this image's string table.
+        let name = unsafe { CStr::from_ptr(info.dli_sname) };
+        Some(Symbol::new(name, Avma(info.dli_saddr as usize)))
+    }
+
+    /// Get the address of this image's entry point, if it has one.
+    ///
+    /// This is taken from `LC_MAIN`, falling back to the initial program
+    /// counter of `LC_UNIXTHREAD` for older executables and dyld itself.
+    /// Libraries usually have no entry point.
+    pub fn entry_point(&self) -> Option<Avma> {
+        if let Some(offset) = self.commands.entry_offset {
+            // `entryoff` is a file offset, and the header sits at the very
+            // start of the file mapping.
+            return Some(Avma(self.header_addr().wrapping_add(offset as usize)));
+        }
+
+        self.commands
+            .thread_pc
+            .map(|pc| Avma((pc as usize).wrapping_add(self.slide)))
+    }
+
+    /// Keep this image loaded until the returned guard is dropped, so that
+    /// addresses in it stay valid after the callback returns.
+    ///
+    /// The reference is taken with `dlopen(RTLD_NOLOAD)` on the image's path,
+    /// so this returns `None` for images that can't be opened that way, such
+    /// as dyld itself, and for images of other tasks.
+    pub fn pin(&self) -> Option<PinnedLibrary> {
+        if self.remote_header.is_some() || self.name.to_bytes().is_empty() {
+            return None;
+        }
+        let handle =
+            unsafe { libc::dlopen(self.name.as_ptr(), libc::RTLD_LAZY | libc::RTLD_NOLOAD) };
+        if handle.is_null() {
+            None
+        } else {
+            Some(PinnedLibrary { handle })
+        }
+    }
+
+    /// Get the addresses of the section `section` of the segment `segment`,
+    /// like `__TEXT,__unwind_info`.
+    pub fn section(&self, segment: &str, section: &str) -> Option<Range<Avma>> {
+        let range = self
+            .segments()
+            .filter(|seg| seg.name() == segment)
+            .flat_map(|seg| seg.sections())
+            .find(|(name, _, _)| *name == section.as_bytes())?
+            .2;
+        let start = usize::try_from(range.start).ok()?.wrapping_add(self.slide);
+        let len = usize::try_from(range.end - range.start).ok()?;
+        Some(Avma(start)..Avma(start.wrapping_add(len)))
+    }
+
+    /// Find out which language runtimes have metadata in this image.
+    pub fn capabilities(&self) -> Capabilities {
+        let mut capabilities = Capabilities::default();
+        for segment in self.segments() {
+            for (name, _, _) in segment.sections() {
+                // Objective-C 1 images keep their image info in `__OBJC`.
+                capabilities.objc |= name == b"__objc_imageinfo"
+                    || (name == b"__image_info" && segment.name() == "__OBJC");
+                capabilities.swift |= name.starts_with(b"__swift");
+            }
+        }
+        capabilities
+    }
+
+    /// Returns `true` if this image is encrypted, e.g. with FairPlay on iOS.
+    ///
+    /// The encrypted part of `__TEXT` is decrypted by the kernel when it is
+    /// mapped, so its contents in memory don't match the file on disk.
+    pub fn is_encrypted(&self) -> bool {
+        match self.commands.encryption_info {
+            Some(info) => info.cryptid != 0,
+            None => false,
+        }
+    }
+
+    /// Get the address range covered by encryption, if this image is
+    /// encrypted.
+    pub fn encrypted_range(&self) -> Option<Range<Avma>> {
+        let info = self.commands.encryption_info?;
+        if info.cryptid == 0 {
+            return None;
+        }
+
+        // `cryptoff` is a file offset, and the header sits at the very start
+        // of the file mapping.
+        let start = self.header_addr().wrapping_add(info.cryptoff as usize);
+        Some(Avma(start)..Avma(start.wrapping_add(info.cryptsize as usize)))
+    }
+
+    /// Returns `true` if this image was loaded from the dyld shared cache.
+    ///
+    /// Images in the shared cache have no standalone file on disk, so `name()`
+    /// can't be opened to read symbols or debug information from.
+    pub fn in_shared_cache(&self) -> bool {
+        if self.header.flags() & MH_DYLIB_IN_CACHE != 0 {
+            return true;
+        }
+
+        // Older versions of dyld don't set the header flag, so check whether
+        // the header lives inside the mapped shared cache instead.
+        match shared_cache_range() {
+            Some((start, len)) if self.remote_header.is_none() => {
+                let addr = self.header_addr();
+                addr >= start && addr - start < len
+            }
+            _ => false,
+        }
+    }
+
+    /// Get the shared cache this image was loaded from, if any.
+    ///
+    /// This is always `None` for images of a `RemoteTask`.
+    pub fn shared_cache(&self) -> Option<SharedCache> {
+        if self.remote_header.is_none() && self.in_shared_cache() {
+            SharedCache::current()
+        } else {
+            None
+        }
+    }
+
+    /// Get the unslid address of this image's Mach-O header within the shared
+    /// cache.
+    ///
+    /// This is the address symbolication backends look the image up by in
+    /// the shared cache file.
+    pub fn shared_cache_addr(&self) -> Option<Svma> {
+        self.shared_cache().map(|cache| {
+            Svma(
+                self.header_addr()
+                    .wrapping_sub(cache.virtual_memory_bias().0),
+            )
+        })
+    }
+}
+
+impl<'a> SharedLibraryTrait for SharedLibrary<'a> {
+    type Segment = Segment<'a>;
+    type SegmentIter = SegmentIter<'a>;
+
+    #[inline]
+    fn name(&self) -> &OsStr {
+        OsStr::from_bytes(self.name.to_bytes())
+    }
+
+    #[inline]
+    fn name_bytes(&self) -> &[u8] {
+        self.name.to_bytes()
+    }
+
+    fn debug_file(&self) -> Option<Cow<'_, OsStr>> {
+        let mut dsym = self.code_file().to_owned();
+        dsym.push(".dSYM");
+        Some(Cow::Owned(dsym))
+    }
+
+    fn kind(&self) -> Option<SharedLibraryKind> {
+        match self.filetype() {
+            FileType::Execute => Some(SharedLibraryKind::Executable),
+            FileType::Dylib => Some(SharedLibraryKind::Library),
+            FileType::Bundle => Some(SharedLibraryKind::Plugin),
+            FileType::Dylinker => Some(SharedLibraryKind::DynamicLinker),
+            FileType::Other(_) => None,
+        }
+    }
+
+    fn id(&self) -> Option<SharedLibraryId> {
+        self.commands.uuid.map(SharedLibraryId::Uuid)
+    }
+
+    #[inline]
+    fn segments(&self) -> Self::SegmentIter {
+        self.header.segments()
+    }
+
+    #[inline]
+    fn virtual_memory_bias(&self) -> Bias {
+        Bias(self.slide)
+    }
+
+    fn symbol_for_avma(&self, address: Avma) -> Option<Symbol<'_>> {
+        // `__PAGEZERO` covers the low addresses of executables without being
+        // mapped, so only look at segments that can be accessed.
+        if !self
+            .segments()
+            .any(|seg| seg.initprot() != 0 && seg.contains_avma(self, address))
+        {
+            return None;
+        }
+
+        // Fall back to dyld's view when the symbol table can't be used, for
+        // example because the image was relocated into the shared cache.
+        self.symtab_symbol_for_avma(address)
+            .or_else(|| self.dladdr_symbol_for_avma(address))
+    }
+
+    fn each<F, C>(mut f: F)
+    where
+        F: FnMut(&Self) -> C,
+        C: Into<IterationControl>,
+    {
+        diagnostics::enumerated();
+        let listing = diagnostics::start();
+
+        // Kept for callers that still take the lock around `dlopen` to keep
+        // iteration out; the snapshot doesn't depend on it, as it only holds
+        // copies of dyld's list and paths. It is only held while copying the
+        // list, so that iterating threads don't wait on each other's
+        // callbacks.
+        let dyld_lock = DYLD_LOCK.lock();
+
+        let mut images = image_infos().unwrap_or_else(|| {
+            // Fall back to asking dyld for each image one at a time.
+            let count = unsafe { libc::_dyld_image_count() };
+            (0..count)
+                .map(|image_idx| unsafe {
+                    (
+                        libc::_dyld_get_image_header(image_idx),
+                        copy_path(libc::_dyld_get_image_name(image_idx)),
+                    )
+                })
+                .collect()
+        });
+
+        // Crash stacks frequently contain dyld frames, so report it too.
+        let dyld = dyld_image();
+        if let Some(dyld) = dyld {
+            if !images.iter().any(|image| image.0 == dyld.0) {
+                images.push((dyld.0, copy_path(dyld.2)));
+            }
+        }
+        // Released before calling back, so a panicking callback can't poison
+        // it or leave it held while unwinding.
+        drop(dyld_lock);
+        diagnostics::finish(listing, "list");
+
+        let visiting = diagnostics::start();
+        let executable = unsafe { _NSGetMachExecuteHeader() };
+        for (header, listed) in images {
+            // Another thread can unload any image once the list is copied, so
+            // each one is pinned before its headers are read and for as long
+            // as the callback runs. dyld and the executable are never
+            // unloaded. dyld's path is part of `dyld_all_image_infos`, so it
+            // is never freed either.
+            let found = match dyld {
+                Some((dyld, _, path)) if dyld == header => Some((path, None)),
+                _ if header == executable => image_path(header).map(|path| (path, None)),
+                _ => pin_image(header, &listed).map(|(path, pinned)| (path, Some(pinned))),
+            };
+            let (name, _pinned) = match found {
+                Some(found) => found,
+                None => {
+                    let name = OsStr::from_bytes(listed.to_bytes());
+                    diagnostics::skipped(header as usize, Some(name), SkipReason::NotMapped);
+                    continue;
+                }
+            };
+            #[cfg(test)]
+            let _recorded = tests::PinnedHeader::set(_pinned.as_ref().map(|_| header));
+
+            let name = unsafe { CStr::from_ptr(name) };
+            let header = match unsafe { MachHeader::from_header_ptr(header) } {
+                Some(header) => header,
+                None => {
+                    let name = OsStr::from_bytes(name.to_bytes());
+                    diagnostics::skipped(header as usize, Some(name), SkipReason::InvalidHeader);
+                    continue;
+                }
+            };
+            let slide = header.slide();
+            let shlib = SharedLibrary::new(header, slide, name);
+            diagnostics::visited();
+
+            match f(&shlib).into() {
+                IterationControl::Break => break,
+                IterationControl::Continue => continue,
+            }
+        }
+        diagnostics::finish(visiting, "visit");
+    }
+}
+
+/// An iterator over the function start addresses of a Mach-O image.
+///
+/// See [`SharedLibrary::function_starts`](struct.SharedLibrary.html#method.function_starts).
+#[derive(Clone, Debug)]
+pub struct FunctionStarts<'a> {
+    data: &'a [u8],
+    address: usize,
+}
+
+impl<'a> Iterator for FunctionStarts<'a> {
+    type Item = Avma;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // Each entry is the ULEB128 delta from the previous function, and the
+        // table ends with a zero delta.
+        match exports::read_uleb128(&mut self.data) {
+            Some(delta) if delta != 0 => {
+                self.address = self.address.wrapping_add(delta as usize);
+                Some(Avma(self.address))
+            }
+            _ => {
+                self.data = &[];
+                None
+            }
+        }
+    }
+}
+
+/// What dyld knows about an address, as reported by `dladdr`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AddressInfo {
+    image_name: CString,
+    image_base: Avma,
+    symbol: Option<(CString, Avma)>,
+}
+
+impl AddressInfo {
+    /// Look up the image and symbol containing `address`.
+    ///
+    /// Returns `None` if `address` doesn't belong to any loaded image.
+    pub fn new(address: Avma) -> Option<AddressInfo> {
+        let mut info: libc::Dl_info = unsafe { mem::zeroed() };
+        if unsafe { libc::dladdr(address.0 as *const _, &mut info) } == 0
+            || info.dli_fname.is_null()
+        {
+            return None;
+        }
+
+        let symbol = if info.dli_sname.is_null() {
+            None
+        } else {
+            let name = unsafe { CStr::from_ptr(info.dli_sname) };
+            Some((name.to_owned(), Avma(info.dli_saddr as usize)))
+        };
+        Some(AddressInfo {
+            image_name: unsafe { CStr::from_ptr(info.dli_fname) }.to_owned(),
+            image_base: Avma(info.dli_fbase as usize),
+            symbol,
+        })
+    }
+
+    /// Get the path of the image containing the address.
+    pub fn image_name(&self) -> &OsStr {
+        OsStr::from_bytes(self.image_name.to_bytes())
+    }
+
+    /// Get the address of the Mach-O header of the image containing the
+    /// address.
+    pub fn image_base(&self) -> Avma {
+        self.image_base
+    }
+
+    /// Get the name of the nearest symbol below the address, if any.
+    pub fn symbol_name(&self) -> Option<&CStr> {
+        self.symbol.as_ref().map(|(name, _)| &name[..])
+    }
+
+    /// Get the address of the nearest symbol below the address, if any.
+    pub fn symbol_address(&self) -> Option<Avma> {
+        self.symbol.as_ref().map(|&(_, address)| address)
+    }
+}
+
+/// The kind of change reported to an image observer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImageEvent {
+    /// The image was just loaded.
+    Added,
+    /// The image is about to be unloaded.
+    Removed,
+}
+
+type ImageObserver = Arc<dyn Fn(ImageEvent, &SharedLibrary) + Send + Sync>;
+
+lazy_static! {
+    static ref IMAGE_OBSERVERS: Mutex<Vec<ImageObserver>> = Mutex::new(Vec::new());
+}
+
+static REGISTER_DYLD_CALLBACKS: Once = Once::new();
+
+/// Register `f` to be called whenever dyld adds or removes an image.
+///
+/// `f` is called with `ImageEvent::Added` for every image that is already
+/// loaded before this function returns, and then again for every image loaded
+/// afterwards. An image loaded concurrently with the registration may be
+/// reported as added twice.
+///
+/// dyld does not support unregistering callbacks, so observers stay registered
+/// for the lifetime of the process. `f` runs while dyld holds its own loader
+/// lock and must not load or unload images itself. Panics in `f` are caught
+/// and discarded, since they can't unwind through dyld.
+pub fn register_image_observer<F>(f: F)
+where
+    F: Fn(ImageEvent, &SharedLibrary) + Send + Sync + 'static,
+{
+    let observer: ImageObserver = Arc::new(f);
+    IMAGE_OBSERVERS.lock().unwrap().push(observer.clone());
+
+    let mut registered = false;
+    REGISTER_DYLD_CALLBACKS.call_once(|| {
+        // dyld replays every image that is already loaded when registering,
+        // which takes care of notifying the first observer about them, except
+        // for dyld itself.
+        unsafe {
+            _dyld_register_func_for_add_image(on_add_image);
+            _dyld_register_func_for_remove_image(on_remove_image);
+        }
+        if let Some((header, slide, name)) = dyld_image() {
+            if let Some(header) = unsafe { MachHeader::from_header_ptr(header) } {
+                let name = unsafe { CStr::from_ptr(name) };
+                observer(ImageEvent::Added, &SharedLibrary::new(header, slide, name));
+            }
+        }
+        registered = true;
+    });
+
+    if !registered {
+        SharedLibrary::each(|shlib| observer(ImageEvent::Added, shlib));
+    }
+}
+
+unsafe extern "C" fn on_add_image(header: *const libc::mach_header, slide: libc::intptr_t) {
+    notify_image_observers(ImageEvent::Added, header, slide);
+}
+
+unsafe extern "C" fn on_remove_image(header: *const libc::mach_header, slide: libc::intptr_t) {
+    notify_image_observers(ImageEvent::Removed, header, slide);
+}
+
+unsafe fn notify_image_observers(
+    event: ImageEvent,
+    header: *const libc::mach_header,
+    slide: libc::intptr_t,
+) {
+    let header = match MachHeader::from_header_ptr(header) {
+        Some(header) => header,
+        None => return,
+    };
+
+    // dyld doesn't pass the path along, but it can tell us which image the
+    // header belongs to.
+    let mut info: libc::Dl_info = mem::zeroed();
+    let name =
+        if libc::dladdr(header.as_ptr() as *const _, &mut info) != 0 && !info.dli_fname.is_null() {
+            CStr::from_ptr(info.dli_fname)
+        } else {
+            CStr::from_bytes_with_nul_unchecked(b"\0")
+        };
+    let shlib = SharedLibrary::new(header, slide as usize, name);
+
+    // Don't hold the lock while calling out so observers can register more
+    // observers.
+    let observers = IMAGE_OBSERVERS.lock().unwrap().clone();
+    for observer in observers {
+        let _ = panic::catch_unwind(panic::AssertUnwindSafe(|| observer(event, &shlib)));
+    }
+}
+
+static IMAGE_CHANGES: AtomicU64 = AtomicU64::new(0);
+
+static REGISTER_CHANGE_CALLBACKS: Once = Once::new();
+
+/// Get the number of times dyld added or removed an image since this was
+/// first called.
+pub(crate) fn change_count() -> Option<u64> {
+    REGISTER_CHANGE_CALLBACKS.call_once(|| unsafe {
+        _dyld_register_func_for_add_image(count_image_change);
+        _dyld_register_func_for_remove_image(count_image_change);
+    });
+    Some(IMAGE_CHANGES.load(Ordering::Acquire))
+}
+
+unsafe extern "C" fn count_image_change(_header: *const libc::mach_header, _slide: libc::intptr_t) {
+    IMAGE_CHANGES.fetch_add(1, Ordering::AcqRel);
+}
+
+/// Take a reference on the already loaded object `name` with
+/// `dlopen(RTLD_NOLOAD)`, which doesn't load anything.
+fn open_loaded(name: &OsStr) -> Option<PinnedLibrary> {
+    // An empty name would open the executable instead.
+    if name.is_empty() {
+        return None;
+    }
+    let name = CString::new(name.as_bytes()).ok()?;
+    let handle = unsafe { libc::dlopen(name.as_ptr(), libc::RTLD_LAZY | libc::RTLD_NOLOAD) };
+    if handle.is_null() {
+        None
+    } else {
+        Some(PinnedLibrary { handle })
+    }
+}
+
+/// Copy a path dyld listed an image under, which may be null.
+fn copy_path(name: *const libc::c_char) -> CString {
+    if name.is_null() {
+        CString::default()
+    } else {
+        unsafe { CStr::from_ptr(name) }.to_owned()
+    }
+}
+
+/// Take a reference on the image at `header`, which dyld listed as `name`.
+///
+/// Returns `None` if the image was unloaded since it was listed. Its path
+/// may have been loaded again somewhere else in the meantime, so the image
+/// is only pinned if dyld still has an image at `header`. Returns dyld's
+/// path of the pinned image too, which stays valid while it is pinned.
+fn pin_image(
+    header: *const libc::mach_header,
+    name: &CStr,
+) -> Option<(*const libc::c_char, PinnedLibrary)> {
+    let pinned = open_loaded(OsStr::from_bytes(name.to_bytes()))?;
+    Some((image_path(header)?, pinned))
+}
+
+/// Get dyld's path of the image at `header`, or `None` if there is no image
+/// there. The path stays valid for as long as the image is loaded.
+fn image_path(header: *const libc::mach_header) -> Option<*const libc::c_char> {
+    let mut info: libc::Dl_info = unsafe { mem::zeroed() };
+    let found = unsafe { libc::dladdr(header as *const libc::c_void, &mut info) };
+    if found == 0
+        || info.dli_fbase as *const libc::mach_header != header
+        || info.dli_fname.is_null()
+    {
+        return None;
+    }
+    Some(info.dli_fname)
+}
+
+/// Ask dyld whether `name` is loaded, the way `dlopen` would look for it.
+pub(crate) fn probe_loaded(name: &OsStr) -> Option<bool> {
+    Some(open_loaded(name).is_some())
+}
+
+/// Find the loaded image that `dlopen` would return for `name`.
+pub(crate) fn find_loaded(name: &OsStr) -> Option<OwnedSharedLibrary> {
+    let pinned = open_loaded(name)?;
+    let mut found = None;
+    SharedLibrary::each(|shlib| match shlib.pin() {
+        Some(other) if other.handle == pinned.handle => {
+            found = Some(OwnedSharedLibrary::from_shared_library(shlib));
+            IterationControl::Break
+        }
+        _ => IterationControl::Continue,
+    });
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::macos;
+    use crate::{IterationControl, Permissions, Segment, SharedLibrary, SharedLibraryKind};
+    use std::cell::Cell;
+
+    #[test]
+    fn from_header() {
+        let count = unsafe { libc::_dyld_image_count() };
+        assert!(count > 0);
+        for image_idx in 0..count {
+            let shlib = unsafe {
+                macos::SharedLibrary::from_header(
                     libc::_dyld_get_image_header(image_idx),
                     libc::_dyld_get_image_vmaddr_slide(image_idx),
-                    libc::_dyld_get_image_name(image_idx),
+                    std::ffi::CStr::from_ptr(libc::_dyld_get_image_name(image_idx)),
                 )
-            };
+            }
+            .unwrap();
+            assert!(shlib.id().is_some(), "{:?}", shlib.name());
+            assert_eq!(shlib.virtual_memory_bias().0, unsafe {
+                libc::_dyld_get_image_vmaddr_slide(image_idx)
+            } as usize);
+        }
 
-            if let Some(header) = unsafe { MachHeader::from_header_ptr(header) } {
-                assert!(
-                    !name.is_null(),
-                    "If we have a header pointer, name should be valid"
+        let not_a_header = [0u32; 8];
+        let name = std::ffi::CStr::from_bytes_with_nul(b"\0").unwrap();
+        let shlib = unsafe {
+            macos::SharedLibrary::from_header(not_a_header.as_ptr() as *const _, 0, name)
+        };
+        assert!(shlib.is_none());
+    }
+
+    #[test]
+    fn try_segment_sizes() {
+        macos::SharedLibrary::each(|shlib| {
+            for segment in shlib.segments() {
+                assert_eq!(segment.try_len(), Some(segment.len()));
+                assert_eq!(
+                    segment.try_stated_virtual_memory_address(),
+                    Some(segment.stated_virtual_memory_address())
                 );
+            }
+        });
+    }
 
-                let name = unsafe { CStr::from_ptr(name) };
-                let shlib = SharedLibrary::new(header, slide as usize, name);
+    #[test]
+    fn data_segments() {
+        static mut ZEROED: [u8; 0x10000] = [0; 0x10000];
+
+        let zeroed = crate::Avma(std::ptr::addr_of!(ZEROED) as usize);
+        let mut checked = 0;
+        macos::SharedLibrary::each(|shlib| {
+            for segment in shlib.segments() {
+                if segment.contains_avma(shlib, zeroed) {
+                    assert!(segment.is_data() && segment.is_bss(), "{:?}", segment);
+                    assert!(!segment.is_readonly_data() && !segment.is_code());
+                    assert!(matches!(
+                        segment.raw_type(),
+                        Some(libc::LC_SEGMENT | libc::LC_SEGMENT_64)
+                    ));
+                    checked += 1;
+                }
+            }
+        });
+        assert_eq!(checked, 1);
+    }
 
-                match f(&shlib).into() {
-                    IterationControl::Break => break,
-                    IterationControl::Continue => continue,
+    #[test]
+    fn unmapped_and_linkedit_segments_are_not_bss() {
+        let mut checked = 0;
+        macos::SharedLibrary::each(|shlib| {
+            for segment in shlib.segments() {
+                if segment.name() == "__PAGEZERO" || segment.name() == "__LINKEDIT" {
+                    assert!(!segment.is_bss(), "{:?}", segment);
+                    checked += 1;
                 }
             }
+        });
+        assert!(checked > 0);
+    }
+
+    #[test]
+    fn pin() {
+        let mut pins = vec![];
+        macos::SharedLibrary::each(|shlib| {
+            let pinned = shlib.pin();
+            if shlib.name().to_string_lossy().contains("libSystem") {
+                assert!(pinned.is_some());
+            }
+            pins.extend(pinned);
+        });
+        assert!(!pins.is_empty());
+        std::thread::spawn(move || drop(pins)).join().unwrap();
+    }
+
+    #[test]
+    fn dsym_debug_file() {
+        use std::os::unix::ffi::OsStrExt;
+
+        macos::SharedLibrary::each(|shlib| {
+            let debug_file = shlib.debug_file().unwrap();
+            let code_file = shlib.code_file().as_bytes();
+            assert_eq!(&debug_file.as_bytes()[..code_file.len()], code_file);
+            assert!(debug_file.as_bytes().ends_with(b".dSYM"));
+        });
+    }
+
+    thread_local! {
+        static PINNED_HEADER: Cell<Option<*const libc::mach_header>> = const { Cell::new(None) };
+    }
+
+    /// Records the image `each` holds a pin on, while it calls back with it.
+    pub(super) struct PinnedHeader(Option<*const libc::mach_header>);
+
+    impl PinnedHeader {
+        pub(super) fn set(header: Option<*const libc::mach_header>) -> PinnedHeader {
+            PinnedHeader(PINNED_HEADER.with(|pinned| pinned.replace(header)))
         }
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use crate::macos;
-    use crate::{IterationControl, Segment, SharedLibrary};
+    impl Drop for PinnedHeader {
+        fn drop(&mut self) {
+            PINNED_HEADER.with(|pinned| pinned.set(self.0));
+        }
+    }
+
+    #[test]
+    fn each_pins_images() {
+        let dyld = macos::dyld_image().map(|dyld| dyld.0);
+        let executable = unsafe { macos::_NSGetMachExecuteHeader() };
+        let mut pinned = 0;
+        macos::SharedLibrary::each(|shlib| {
+            let header = shlib.header.as_ptr();
+            if Some(header) == dyld || header == executable {
+                return;
+            }
+            assert_eq!(
+                PINNED_HEADER.with(Cell::get),
+                Some(header),
+                "{:?} was not pinned",
+                shlib.name()
+            );
+            pinned += 1;
+        });
+        assert!(pinned > 0);
+        assert_eq!(PINNED_HEADER.with(Cell::get), None);
+    }
+
+    #[test]
+    fn nested_each() {
+        // The lock isn't held while calling back, so this doesn't deadlock.
+        let mut outer = 0;
+        macos::SharedLibrary::each(|_| {
+            outer += 1;
+            let mut inner = 0;
+            macos::SharedLibrary::each(|_| {
+                inner += 1;
+            });
+            assert!(inner > 0);
+            IterationControl::Break
+        });
+        assert_eq!(outer, 1);
+    }
+
+    #[test]
+    fn panic_in_each() {
+        use std::panic;
+
+        let caught = panic::catch_unwind(|| {
+            macos::SharedLibrary::each::<_, ()>(|_| panic!("uh oh"));
+        })
+        .unwrap_err();
+        assert_eq!(*caught.downcast_ref::<&'static str>().unwrap(), "uh oh");
+
+        // The lock was released before calling back, so it isn't poisoned.
+        assert!(macos::DYLD_LOCK.lock().is_ok());
+    }
 
     #[test]
     fn have_libdyld() {
@@ -321,6 +2194,20 @@ mod tests {
         assert!(found_dyld);
     }
 
+    #[test]
+    fn have_dyld() {
+        let mut found_dyld = false;
+        macos::SharedLibrary::each(|shlib| {
+            if shlib.filetype() == macos::FileType::Dylinker {
+                assert!(!found_dyld, "dyld reported twice");
+                found_dyld = true;
+                assert!(shlib.id().is_some());
+                assert_eq!(shlib.kind(), Some(SharedLibraryKind::DynamicLinker));
+            }
+        });
+        assert!(found_dyld);
+    }
+
     #[test]
     fn can_break() {
         let mut first_count = 0;
@@ -342,6 +2229,50 @@ mod tests {
         assert_eq!(second_count, first_count - 1);
     }
 
+    #[test]
+    fn snapshot_matches_dyld() {
+        let images = macos::image_infos().expect("no dyld_all_image_infos");
+        let count = unsafe { libc::_dyld_image_count() };
+        assert_eq!(images.len(), count as usize);
+
+        for image_idx in 0..count {
+            let header = unsafe { libc::_dyld_get_image_header(image_idx) };
+            let slide = unsafe { libc::_dyld_get_image_vmaddr_slide(image_idx) };
+            let name = unsafe { std::ffi::CStr::from_ptr(libc::_dyld_get_image_name(image_idx)) };
+            let image = images.iter().find(|image| image.0 == header).unwrap();
+            assert_eq!(image.1.as_c_str(), name);
+            let header = unsafe { macos::MachHeader::from_header_ptr(header) }.unwrap();
+            assert_eq!(header.slide(), slide as usize);
+        }
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn remote_task() {
+        let mut local = vec![];
+        macos::SharedLibrary::each(|shlib| {
+            local.push((
+                shlib.name().to_owned(),
+                shlib.id(),
+                shlib.virtual_memory_bias(),
+            ));
+        });
+
+        let task = macos::RemoteTask::new(unsafe { libc::mach_task_self() });
+        let mut remote = vec![];
+        task.each(|shlib| {
+            assert!(shlib.exports().next().is_none());
+            remote.push((
+                shlib.name().to_owned(),
+                shlib.id(),
+                shlib.virtual_memory_bias(),
+            ));
+        })
+        .expect("reading our own task");
+
+        assert_eq!(local, remote);
+    }
+
     #[test]
     fn get_name() {
         macos::SharedLibrary::each(|shlib| {
@@ -356,6 +2287,197 @@ mod tests {
         });
     }
 
+    #[test]
+    fn in_shared_cache() {
+        let mut found_dyld = false;
+        macos::SharedLibrary::each(|shlib| {
+            let name = shlib.name.to_bytes();
+            if name.ends_with(b"/libdyld.dylib") {
+                found_dyld = true;
+                assert!(shlib.in_shared_cache());
+            }
+            if name
+                .rsplit(|c| *c == b'/')
+                .next()
+                .unwrap()
+                .starts_with(b"findshlibs")
+            {
+                assert!(!shlib.in_shared_cache());
+            }
+        });
+        assert!(found_dyld);
+    }
+
+    #[test]
+    fn shared_cache() {
+        let cache = macos::SharedCache::current().expect("no shared cache");
+        assert!(cache.id().as_bytes().iter().any(|b| *b != 0));
+
+        let mut found_cached = false;
+        macos::SharedLibrary::each(|shlib| {
+            if let Some(addr) = shlib.shared_cache_addr() {
+                found_cached = true;
+                assert_eq!(shlib.shared_cache(), Some(cache));
+                assert!(addr >= cache.stated_base_addr());
+                assert_eq!(shlib.virtual_memory_bias(), cache.virtual_memory_bias());
+            }
+        });
+        assert!(found_cached);
+    }
+
+    #[test]
+    fn image_observer() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+
+        let added = Arc::new(AtomicUsize::new(0));
+        let observer_added = added.clone();
+        macos::register_image_observer(move |event, shlib| {
+            if event == macos::ImageEvent::Added && shlib.id().is_some() {
+                observer_added.fetch_add(1, Ordering::SeqCst);
+            }
+        });
+
+        let mut count = 0;
+        macos::SharedLibrary::each(|_| count += 1);
+        assert!(added.load(Ordering::SeqCst) >= count);
+    }
+
+    #[test]
+    fn filetype() {
+        let mut executables = 0;
+        macos::SharedLibrary::each(|shlib| {
+            if shlib.filetype() == macos::FileType::Execute {
+                executables += 1;
+                assert_eq!(shlib.kind(), Some(SharedLibraryKind::Executable));
+            }
+            if shlib.name.to_bytes().ends_with(b"/libdyld.dylib") {
+                assert_eq!(shlib.filetype(), macos::FileType::Dylib);
+            }
+        });
+        assert_eq!(executables, 1);
+    }
+
+    #[test]
+    fn cpu_type() {
+        macos::SharedLibrary::each(|shlib| {
+            let arch = shlib.arch_name().expect("unknown architecture");
+            if cfg!(target_arch = "x86_64") {
+                assert!(arch.starts_with("x86_64"));
+            } else if cfg!(target_arch = "aarch64") {
+                assert!(arch.starts_with("arm64"));
+            }
+        });
+    }
+
+    #[test]
+    fn fat_slice() {
+        let mut found_exe = false;
+        macos::SharedLibrary::each(|shlib| {
+            if shlib.filetype() == macos::FileType::Execute {
+                found_exe = true;
+                let slice = shlib.fat_slice().expect("no slice for the executable");
+                let len = std::fs::metadata(shlib.name()).unwrap().len();
+                assert!(slice.offset() + slice.size() <= len);
+            } else if shlib.in_shared_cache() {
+                assert!(shlib.fat_slice().is_none());
+            }
+        });
+        assert!(found_exe);
+    }
+
+    #[test]
+    fn entry_point() {
+        let mut found_exe = false;
+        macos::SharedLibrary::each(|shlib| {
+            if shlib.filetype() != macos::FileType::Execute {
+                return;
+            }
+            found_exe = true;
+
+            let entry = shlib.entry_point().expect("executable has no entry point");
+            let text = shlib.segments().find(|seg| seg.is_code()).unwrap();
+            assert!(text.contains_avma(shlib, entry));
+        });
+        assert!(found_exe);
+    }
+
+    #[test]
+    fn exports() {
+        let dlopen = unsafe {
+            libc::dlsym(
+                libc::RTLD_DEFAULT,
+                b"dlopen\0".as_ptr() as *const libc::c_char,
+            )
+        };
+        assert!(!dlopen.is_null());
+
+        let mut found_dlopen = false;
+        macos::SharedLibrary::each(|shlib| {
+            for symbol in shlib.exports() {
+                if symbol.name() == "_dlopen" && symbol.reexport().is_none() {
+                    found_dlopen = true;
+                    assert_eq!(symbol.address(), Some(crate::Avma(dlopen as usize)));
+                }
+            }
+        });
+        assert!(found_dlopen);
+    }
+
+    #[test]
+    fn symbol_for_avma() {
+        let avma = crate::Avma(symbol_for_avma as fn() as usize);
+        let mut found = false;
+        macos::SharedLibrary::each(|shlib| {
+            if shlib.filetype() != macos::FileType::Execute {
+                return;
+            }
+            let symbol = shlib.symbol_for_avma(avma).expect("no symbol found");
+            let name = symbol.name().to_string_lossy();
+            assert!(name.contains("symbol_for_avma"), "{}", name);
+            assert!(symbol.address() <= avma);
+            let pagezero = shlib.segments().find(|seg| seg.name() == "__PAGEZERO");
+            if let Some(pagezero) = pagezero {
+                let unmapped = pagezero.actual_virtual_memory_address(shlib);
+                assert!(shlib.symbol_for_avma(unmapped).is_none());
+            }
+            found = true;
+        });
+        assert!(found);
+    }
+
+    #[test]
+    fn address_info() {
+        let dlopen = unsafe {
+            libc::dlsym(
+                libc::RTLD_DEFAULT,
+                b"dlopen\0".as_ptr() as *const libc::c_char,
+            )
+        };
+        let info = macos::AddressInfo::new(crate::Avma(dlopen as usize)).unwrap();
+        assert_eq!(info.symbol_name().unwrap().to_bytes(), b"dlopen");
+        assert_eq!(info.symbol_address(), Some(crate::Avma(dlopen as usize)));
+
+        let mut found = false;
+        macos::SharedLibrary::each(|shlib| {
+            let symbol = shlib.symbol_for_avma(crate::Avma(dlopen as usize));
+            if let Some(symbol) = symbol {
+                found = true;
+                assert_eq!(shlib.name(), info.image_name());
+                assert_eq!(symbol.address(), crate::Avma(dlopen as usize));
+            }
+        });
+        assert!(found);
+    }
+
+    #[test]
+    fn not_encrypted() {
+        macos::SharedLibrary::each(|shlib| {
+            assert!(!shlib.is_encrypted());
+            assert!(shlib.encrypted_range().is_none());
+        });
+    }
+
     #[test]
     fn have_text_or_pagezero() {
         macos::SharedLibrary::each(|shlib| {
@@ -371,4 +2493,90 @@ mod tests {
             assert!(found_text_or_pagezero);
         });
     }
+
+    #[test]
+    fn function_starts() {
+        let this_fn = function_starts as fn() as usize;
+        let mut found = false;
+        macos::SharedLibrary::each(|shlib| {
+            let text = match shlib.segments().find(|seg| seg.is_code()) {
+                Some(text) => text,
+                None => return,
+            };
+            if !text.contains_avma(shlib, crate::Avma(this_fn)) {
+                return;
+            }
+            found = true;
+
+            assert!(shlib.function_starts_range().is_some());
+            let starts: Vec<_> = shlib.function_starts().collect();
+            assert!(starts.windows(2).all(|pair| pair[0] < pair[1]));
+            assert!(starts.contains(&crate::Avma(this_fn)));
+        });
+        assert!(found);
+    }
+
+    #[test]
+    fn capabilities() {
+        macos::SharedLibrary::each(|shlib| {
+            let capabilities = shlib.capabilities();
+            let name = shlib.name().to_string_lossy();
+            if name.ends_with("/libobjc.A.dylib") || name.ends_with("/Foundation") {
+                assert!(capabilities.has_objc());
+            }
+            if shlib.filetype() == macos::FileType::Execute {
+                // Test binaries are pure Rust.
+                assert_eq!(capabilities, macos::Capabilities::default());
+            }
+        });
+    }
+
+    #[test]
+    fn sections() {
+        let here = crate::Avma(sections as fn() as usize);
+        let mut found = false;
+        macos::SharedLibrary::each(|shlib| {
+            if let Some(text) = shlib.section("__TEXT", "__text") {
+                if text.contains(&here) {
+                    assert!(shlib.section("__TEXT", "__unwind_info").is_some());
+                    found = true;
+                }
+            }
+            assert_eq!(shlib.section("__TEXT", "__nope"), None);
+        });
+        assert!(found);
+    }
+
+    #[test]
+    fn code_signature() {
+        macos::SharedLibrary::each(|shlib| {
+            if shlib.filetype() == macos::FileType::Dylinker {
+                // dyld is always signed by Apple.
+                assert!(shlib.has_code_signature());
+                let signature = shlib.code_signature().expect("unparseable signature");
+                assert_eq!(signature.identifier(), Some("com.apple.dyld"));
+                assert!(signature.cdhash().is_some());
+            }
+        });
+    }
+
+    #[test]
+    fn segment_permissions() {
+        macos::SharedLibrary::each(|shlib| {
+            for seg in shlib.segments() {
+                let permissions = seg.permissions().unwrap();
+                let max = seg.max_permissions();
+                match seg.name() {
+                    "__TEXT" => {
+                        assert!(permissions.is_readable());
+                        assert!(permissions.is_executable());
+                        assert!(!permissions.is_writable());
+                    }
+                    "__PAGEZERO" => assert_eq!(permissions, Permissions::default()),
+                    _ => {}
+                }
+                assert!(!permissions.is_writable() || max.is_writable());
+            }
+        });
+    }
 }
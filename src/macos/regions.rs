@@ -0,0 +1,105 @@
+//! Walking the VM regions of the current task, to find the executable memory
+//! outside of images for `executable_regions`.
+
+use crate::{Avma, ExecutableRegion, ExecutableSource};
+
+use std::convert::TryFrom;
+use std::ffi::OsString;
+use std::mem;
+use std::os::unix::ffi::OsStringExt;
+
+const VM_REGION_BASIC_INFO_64: libc::c_int = 9;
+
+#[repr(C, packed(4))]
+#[derive(Clone, Copy)]
+#[allow(non_camel_case_types)]
+struct vm_region_basic_info_64 {
+    protection: libc::vm_prot_t,
+    max_protection: libc::vm_prot_t,
+    inheritance: libc::c_uint,
+    shared: libc::boolean_t,
+    reserved: libc::boolean_t,
+    offset: u64,
+    behavior: libc::c_int,
+    user_wired_count: libc::c_ushort,
+}
+
+extern "C" {
+    fn mach_vm_region(
+        target_task: libc::vm_map_t,
+        address: *mut libc::mach_vm_address_t,
+        size: *mut libc::mach_vm_size_t,
+        flavor: libc::c_int,
+        info: *mut libc::c_int,
+        info_count: *mut libc::mach_msg_type_number_t,
+        object_name: *mut libc::mach_port_t,
+    ) -> libc::kern_return_t;
+}
+
+/// Get the path of the file mapped at `address`, if any.
+fn region_file_name(address: u64) -> Option<OsString> {
+    let mut buffer = vec![0u8; libc::PATH_MAX as usize];
+    let len = unsafe {
+        libc::proc_regionfilename(
+            libc::getpid(),
+            address,
+            buffer.as_mut_ptr().cast(),
+            buffer.len() as u32,
+        )
+    };
+    if len <= 0 {
+        return None;
+    }
+    buffer.truncate(len as usize);
+    Some(OsString::from_vec(buffer))
+}
+
+/// Get every executable region of the current task.
+pub(crate) fn executable_mappings() -> Vec<ExecutableRegion> {
+    let task = unsafe { libc::mach_task_self() };
+    let mut regions = vec![];
+    let mut address: libc::mach_vm_address_t = 0;
+    loop {
+        let mut size: libc::mach_vm_size_t = 0;
+        let mut info: vm_region_basic_info_64 = unsafe { mem::zeroed() };
+        let mut count = (mem::size_of::<vm_region_basic_info_64>() / mem::size_of::<libc::c_int>())
+            as libc::mach_msg_type_number_t;
+        let mut object_name: libc::mach_port_t = 0;
+        let ret = unsafe {
+            mach_vm_region(
+                task,
+                &mut address,
+                &mut size,
+                VM_REGION_BASIC_INFO_64,
+                (&mut info as *mut vm_region_basic_info_64).cast(),
+                &mut count,
+                &mut object_name,
+            )
+        };
+        // Past the last region, this fails with `KERN_INVALID_ADDRESS`.
+        if ret != libc::KERN_SUCCESS {
+            break;
+        }
+
+        let start = address;
+        let end = match start.checked_add(size) {
+            Some(end) if size != 0 => end,
+            _ => break,
+        };
+        address = end;
+        if info.protection & libc::VM_PROT_EXECUTE == 0 {
+            continue;
+        }
+
+        let source = match region_file_name(start) {
+            Some(path) => ExecutableSource::File(path),
+            None => ExecutableSource::Anonymous,
+        };
+        let (start, end) = match (usize::try_from(start), usize::try_from(end)) {
+            (Ok(start), Ok(end)) => (start, end),
+            _ => break,
+        };
+        regions.push(ExecutableRegion::new(Avma(start)..Avma(end), source));
+    }
+    regions
+}
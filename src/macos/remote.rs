@@ -0,0 +1,345 @@
+//! Enumerating the images of another task.
+//!
+//! This reads the target's `dyld_all_image_infos` and the Mach-O headers of
+//! its images with `mach_vm_read_overwrite`, so it works without any
+//! cooperation from the target. Only the headers and load commands are
+//! copied, so information that lives in `__LINKEDIT`, such as exports and
+//! symbols, is not available for remote images.
+
+use super::{dyld_all_image_infos, dyld_image_info, task_dyld_info, MachHeader, SharedLibrary};
+use super::{TASK_DYLD_INFO, TASK_DYLD_INFO_COUNT};
+use crate::{IterationControl, Segment as SegmentTrait};
+
+use std::ffi::CStr;
+use std::io;
+use std::mem;
+use std::slice;
+
+const TASK_DYLD_ALL_IMAGE_INFO_32: libc::integer_t = 0;
+const TASK_DYLD_ALL_IMAGE_INFO_64: libc::integer_t = 1;
+
+// Sanity limits so that a corrupt or hostile target can't make us allocate
+// unbounded amounts of memory.
+const MAX_IMAGES: u32 = 1 << 16;
+const MAX_SIZEOFCMDS: u32 = 1 << 20;
+const MAX_PATH: usize = 4096;
+
+// Reads of strings are split at this boundary so that a string ending just
+// before an unmapped page can still be read.
+const READ_CHUNK: u64 = 4096;
+
+extern "C" {
+    fn mach_vm_read_overwrite(
+        target_task: libc::vm_map_t,
+        address: libc::mach_vm_address_t,
+        size: libc::mach_vm_size_t,
+        data: libc::mach_vm_address_t,
+        outsize: *mut libc::mach_vm_size_t,
+    ) -> libc::kern_return_t;
+    fn mach_port_deallocate(
+        task: libc::mach_port_t,
+        name: libc::mach_port_t,
+    ) -> libc::kern_return_t;
+}
+
+fn kern_error(call: &str, ret: libc::kern_return_t) -> io::Error {
+    io::Error::other(format!("{} failed with kern_return_t {}", call, ret))
+}
+
+/// Another task whose images can be enumerated.
+///
+/// ```no_run
+/// # fn main() -> std::io::Result<()> {
+/// use findshlibs::macos::RemoteTask;
+/// use findshlibs::SharedLibrary;
+///
+/// let task = RemoteTask::for_pid(1234)?;
+/// task.each(|shlib| {
+///     println!("{}", shlib.name().to_string_lossy());
+/// })?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct RemoteTask {
+    task: libc::mach_port_t,
+    owned: bool,
+}
+
+impl RemoteTask {
+    /// Use an existing send right to a task port.
+    ///
+    /// The port is not deallocated when the `RemoteTask` is dropped.
+    pub fn new(task: libc::mach_port_t) -> RemoteTask {
+        RemoteTask { task, owned: false }
+    }
+
+    /// Get the task port of the process `pid` with `task_for_pid`.
+    ///
+    /// This requires the caller to be root or to have the
+    /// `com.apple.security.cs.debugger` entitlement, and the target must
+    /// allow being debugged.
+    #[allow(deprecated)]
+    pub fn for_pid(pid: libc::pid_t) -> io::Result<RemoteTask> {
+        let mut task = 0;
+        let ret = unsafe { libc::task_for_pid(libc::mach_task_self(), pid, &mut task) };
+        if ret != libc::KERN_SUCCESS {
+            return Err(kern_error("task_for_pid", ret));
+        }
+        Ok(RemoteTask { task, owned: true })
+    }
+
+    /// Get the underlying task port.
+    pub fn task_port(&self) -> libc::mach_port_t {
+        self.task
+    }
+
+    /// Iterate over the images loaded in the task, including dyld itself.
+    ///
+    /// The `SharedLibrary`s passed to `f` borrow copies of the remote
+    /// headers, so their segments, UUIDs and names are available but
+    /// `exports()` is always empty and `symbol_for_avma` always returns
+    /// `None`.
+    pub fn each<F, C>(&self, mut f: F) -> io::Result<()>
+    where
+        F: FnMut(&SharedLibrary) -> C,
+        C: Into<IterationControl>,
+    {
+        let infos = self.all_image_infos()?;
+
+        let mut images = self.image_infos(&infos)?;
+        if infos.version >= 2 {
+            let dyld = infos.dyld_image_load_address as u64;
+            if dyld != 0 && !images.iter().any(|image| image.0 == dyld) {
+                let name = if infos.version >= 15 {
+                    infos.dyld_path as u64
+                } else {
+                    0
+                };
+                images.push((dyld, name));
+            }
+        }
+
+        for (address, name) in images {
+            let buffer = match self.read_load_commands(address)? {
+                Some(buffer) => buffer,
+                None => continue,
+            };
+            let header = match unsafe {
+                MachHeader::from_header_ptr(buffer.as_ptr() as *const libc::mach_header)
+            } {
+                Some(header) => header,
+                None => continue,
+            };
+
+            let mut name = match name {
+                0 => b"/usr/lib/dyld".to_vec(),
+                name => self.read_cstr(name)?,
+            };
+            name.push(0);
+            let name = CStr::from_bytes_with_nul(&name).unwrap();
+
+            // `MachHeader::slide` would use the address of our copy.
+            let slide = header
+                .segments()
+                .find(|segment| segment.name() == "__TEXT")
+                .map_or(0, |text| {
+                    (address as usize).wrapping_sub(text.stated_virtual_memory_address().0)
+                });
+
+            let mut shlib = SharedLibrary::new(header, slide, name);
+            shlib.remote_header = Some(address as usize);
+
+            match f(&shlib).into() {
+                IterationControl::Break => break,
+                IterationControl::Continue => continue,
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Copy `buf.len()` bytes at `address` out of the task.
+    fn read(&self, address: u64, buf: &mut [u8]) -> io::Result<()> {
+        let mut outsize = 0;
+        let ret = unsafe {
+            mach_vm_read_overwrite(
+                self.task,
+                address,
+                buf.len() as libc::mach_vm_size_t,
+                buf.as_mut_ptr() as libc::mach_vm_address_t,
+                &mut outsize,
+            )
+        };
+        if ret != libc::KERN_SUCCESS {
+            return Err(kern_error("mach_vm_read_overwrite", ret));
+        }
+        if outsize != buf.len() as libc::mach_vm_size_t {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "short read from remote task",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Copy a NUL-terminated string at `address` out of the task.
+    fn read_cstr(&self, mut address: u64) -> io::Result<Vec<u8>> {
+        let mut result = vec![];
+        let mut chunk = [0; READ_CHUNK as usize];
+        while result.len() < MAX_PATH {
+            let len = (READ_CHUNK - address % READ_CHUNK) as usize;
+            let chunk = &mut chunk[..len];
+            self.read(address, chunk)?;
+            match chunk.iter().position(|b| *b == 0) {
+                Some(end) => {
+                    result.extend_from_slice(&chunk[..end]);
+                    return Ok(result);
+                }
+                None => result.extend_from_slice(chunk),
+            }
+            address += len as u64;
+        }
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "unterminated string in remote task",
+        ))
+    }
+
+    fn all_image_infos(&self) -> io::Result<dyld_all_image_infos> {
+        let mut info: task_dyld_info = unsafe { mem::zeroed() };
+        let mut count = TASK_DYLD_INFO_COUNT;
+        let ret = unsafe {
+            libc::task_info(
+                self.task,
+                TASK_DYLD_INFO,
+                &mut info as *mut task_dyld_info as libc::task_info_t,
+                &mut count,
+            )
+        };
+        if ret != libc::KERN_SUCCESS {
+            return Err(kern_error("task_info", ret));
+        }
+
+        // The remote structures are only laid out like ours if the target
+        // has the same pointer width.
+        let format = if cfg!(target_pointer_width = "64") {
+            TASK_DYLD_ALL_IMAGE_INFO_64
+        } else {
+            TASK_DYLD_ALL_IMAGE_INFO_32
+        };
+        if info.all_image_info_format != format {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "remote task has a different pointer width",
+            ));
+        }
+
+        // Older versions of dyld have a shorter structure, in which case the
+        // missing fields are left zeroed.
+        let mut infos: dyld_all_image_infos = unsafe { mem::zeroed() };
+        let size = (info.all_image_info_size as usize).min(mem::size_of_val(&infos));
+        let bytes = unsafe { slice::from_raw_parts_mut(&mut infos as *mut _ as *mut u8, size) };
+        self.read(info.all_image_info_addr, bytes)?;
+        Ok(infos)
+    }
+
+    /// Copy the `(header address, path address)` of every image out of the
+    /// task's info array.
+    fn image_infos(&self, infos: &dyld_all_image_infos) -> io::Result<Vec<(u64, u64)>> {
+        if infos.info_array.is_null() {
+            return Err(io::Error::new(
+                io::ErrorKind::WouldBlock,
+                "dyld is updating the remote image list",
+            ));
+        }
+        if infos.info_array_count > MAX_IMAGES {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "too many images in remote task",
+            ));
+        }
+
+        let count = infos.info_array_count as usize;
+        let mut array: Vec<dyld_image_info> = Vec::with_capacity(count);
+        unsafe {
+            let bytes = slice::from_raw_parts_mut(
+                array.as_mut_ptr() as *mut u8,
+                count * mem::size_of::<dyld_image_info>(),
+            );
+            self.read(infos.info_array as u64, bytes)?;
+            array.set_len(count);
+        }
+
+        Ok(array
+            .iter()
+            .map(|image| {
+                (
+                    image.image_load_address as u64,
+                    image.image_file_path as u64,
+                )
+            })
+            .collect())
+    }
+
+    /// Copy the header and load commands of the image at `address`.
+    ///
+    /// The copy is kept in a `u64` buffer so that it is suitably aligned to
+    /// be viewed as a header. Returns `None` if this isn't a Mach-O header
+    /// or its load commands are malformed.
+    fn read_load_commands(&self, address: u64) -> io::Result<Option<Vec<u64>>> {
+        let mut header = [0; mem::size_of::<libc::mach_header_64>()];
+        self.read(address, &mut header)?;
+        let word = |offset: usize| {
+            u32::from_ne_bytes([
+                header[offset],
+                header[offset + 1],
+                header[offset + 2],
+                header[offset + 3],
+            ])
+        };
+
+        let header_size = match word(0) {
+            libc::MH_MAGIC => mem::size_of::<libc::mach_header>(),
+            libc::MH_MAGIC_64 => mem::size_of::<libc::mach_header_64>(),
+            _ => return Ok(None),
+        };
+        let ncmds = word(16) as usize;
+        let sizeofcmds = word(20);
+        if sizeofcmds > MAX_SIZEOFCMDS {
+            return Ok(None);
+        }
+
+        let len = header_size + sizeofcmds as usize;
+        let mut buffer = vec![0u64; len.div_ceil(8)];
+        let bytes = unsafe { slice::from_raw_parts_mut(buffer.as_mut_ptr() as *mut u8, len) };
+        self.read(address, bytes)?;
+
+        // Local images are trusted to have consistent load commands, but make
+        // sure the copy's commands don't run off the end of the buffer.
+        let mut offset = header_size;
+        for _ in 0..ncmds {
+            let cmdsize = match bytes.get(offset + 4..offset + 8) {
+                Some(size) => u32::from_ne_bytes([size[0], size[1], size[2], size[3]]) as usize,
+                None => return Ok(None),
+            };
+            if cmdsize < mem::size_of::<libc::load_command>() || len - offset < cmdsize {
+                return Ok(None);
+            }
+            offset += cmdsize;
+        }
+
+        Ok(Some(buffer))
+    }
+}
+
+impl Drop for RemoteTask {
+    #[allow(deprecated)]
+    fn drop(&mut self) {
+        if self.owned {
+            unsafe {
+                mach_port_deallocate(libc::mach_task_self(), self.task);
+            }
+        }
+    }
+}
@@ -0,0 +1,383 @@
+//! Shared libraries recorded in a minidump rather than loaded in this
+//! process.
+//!
+//! Crash reporters like Breakpad and Crashpad write minidumps, whose module
+//! list records where each module of the crashed process was loaded and the
+//! CodeView record that identifies its debug information. `Minidump` reads
+//! the module list, so that code symbolicating a dump can share the
+//! `SharedLibrary` interface with code collecting modules in-process.
+//!
+//! A minidump only records where a module is and how big it is, so each
+//! module has a single segment spanning its image.
+
+use crate::offline::codeview;
+use crate::verify::{read_at, u32_at, u64_at};
+use crate::Segment as SegmentTrait;
+use crate::SharedLibrary as SharedLibraryTrait;
+use crate::{Bias, IterationControl, SharedLibraryId, Svma};
+
+use std::convert::TryFrom;
+use std::ffi::{OsStr, OsString};
+use std::fs::File;
+use std::io::{self, Read, Seek};
+use std::path::Path;
+use std::slice;
+
+const MINIDUMP_SIGNATURE: [u8; 4] = *b"MDMP";
+const MODULE_LIST_STREAM: u32 = 4;
+const MINIDUMP_MODULE_SIZE: usize = 108;
+// The CodeView record Breakpad writes for ELF modules, which holds the
+// module's build id. Like `RSDS`, its signature is a little-endian `u32`.
+const CV_SIGNATURE_ELF: [u8; 4] = 0x4270_454c_u32.to_le_bytes();
+// Module names and CodeView records are short, so anything bigger is a
+// corrupt dump.
+const MAX_RECORD: u32 = 0x1_0000;
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// The single segment of a module in a minidump, which spans its image.
+#[derive(Clone, Debug)]
+pub struct Segment {
+    len: usize,
+}
+
+impl SegmentTrait for Segment {
+    type SharedLibrary = SharedLibrary;
+
+    #[inline]
+    fn name(&self) -> &str {
+        ""
+    }
+
+    /// The dump doesn't tell code from data, so the whole image counts as
+    /// code.
+    #[inline]
+    fn is_code(&self) -> bool {
+        true
+    }
+
+    #[inline]
+    fn stated_virtual_memory_address(&self) -> Svma {
+        Svma(0)
+    }
+
+    #[inline]
+    fn len(&self) -> usize {
+        self.len
+    }
+}
+
+/// An iterator over the segment of a module in a minidump.
+#[derive(Clone, Debug)]
+pub struct SegmentIter {
+    inner: Option<Segment>,
+}
+
+impl Iterator for SegmentIter {
+    type Item = Segment;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.take()
+    }
+}
+
+/// A module recorded in a minidump.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SharedLibrary {
+    name: OsString,
+    base: u64,
+    size: u32,
+    id: Option<SharedLibraryId>,
+    debug_id: Option<SharedLibraryId>,
+    debug_name: Option<OsString>,
+}
+
+impl SharedLibrary {
+    /// Get the address the module was loaded at in the dumped process.
+    ///
+    /// Unlike `actual_load_addr`, this doesn't saturate for 64-bit processes
+    /// dumped on 32-bit hosts.
+    pub fn base_address(&self) -> u64 {
+        self.base
+    }
+}
+
+impl SharedLibraryTrait for SharedLibrary {
+    type Segment = Segment;
+    type SegmentIter = SegmentIter;
+
+    #[inline]
+    fn name(&self) -> &OsStr {
+        &self.name
+    }
+
+    #[inline]
+    fn debug_name(&self) -> Option<&OsStr> {
+        self.debug_name.as_deref()
+    }
+
+    /// This is the timestamp and image size for modules of Windows, and the
+    /// build id for ELF modules.
+    #[inline]
+    fn id(&self) -> Option<SharedLibraryId> {
+        self.id.clone()
+    }
+
+    #[inline]
+    fn debug_id(&self) -> Option<SharedLibraryId> {
+        self.debug_id.clone().or_else(|| self.id())
+    }
+
+    #[inline]
+    fn segments(&self) -> Self::SegmentIter {
+        SegmentIter {
+            inner: Some(Segment {
+                len: self.size as usize,
+            }),
+        }
+    }
+
+    #[inline]
+    fn virtual_memory_bias(&self) -> Bias {
+        Bias(usize::try_from(self.base).unwrap_or(usize::MAX))
+    }
+
+    /// Modules of a minidump aren't loaded in this process, so there are
+    /// none to find.
+    fn each<F, C>(_f: F)
+    where
+        F: FnMut(&Self) -> C,
+        C: Into<IterationControl>,
+    {
+    }
+}
+
+/// The module list of a minidump.
+#[derive(Clone, Debug)]
+pub struct Minidump {
+    modules: Vec<SharedLibrary>,
+}
+
+impl Minidump {
+    /// Read the module list of the minidump at `path`.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Minidump> {
+        Minidump::read(File::open(path)?)
+    }
+
+    /// Read the module list of a minidump.
+    ///
+    /// Only the header, the stream directory and the module list are read,
+    /// not the memory of the dumped process. Fails with
+    /// `io::ErrorKind::InvalidData` if this isn't a minidump, it has no
+    /// module list or the module list is cut off.
+    pub fn read<R: Read + Seek>(mut reader: R) -> io::Result<Minidump> {
+        let truncated = || invalid("minidump is truncated");
+        let header = read_at(&mut reader, 0, 32).ok_or_else(truncated)?;
+        if header[..4] != MINIDUMP_SIGNATURE {
+            return Err(invalid("not a minidump"));
+        }
+        let stream_count = u32_at(&header, 8, true).ok_or_else(truncated)?;
+        let directory_rva = u32_at(&header, 12, true).ok_or_else(truncated)?;
+        let directory = read_at(
+            &mut reader,
+            directory_rva.into(),
+            (stream_count as usize)
+                .checked_mul(12)
+                .ok_or_else(truncated)?,
+        )
+        .ok_or_else(truncated)?;
+        let module_list = directory
+            .chunks_exact(12)
+            .find(|entry| u32_at(entry, 0, true) == Some(MODULE_LIST_STREAM))
+            .and_then(|entry| u32_at(entry, 8, true))
+            .ok_or_else(|| invalid("minidump has no module list"))?;
+
+        let count = read_at(&mut reader, module_list.into(), 4)
+            .and_then(|count| u32_at(&count, 0, true))
+            .ok_or_else(truncated)?;
+        let entries = read_at(
+            &mut reader,
+            u64::from(module_list) + 4,
+            (count as usize)
+                .checked_mul(MINIDUMP_MODULE_SIZE)
+                .ok_or_else(truncated)?,
+        )
+        .ok_or_else(truncated)?;
+
+        let mut modules = Vec::with_capacity(count as usize);
+        for entry in entries.chunks_exact(MINIDUMP_MODULE_SIZE) {
+            modules.push(read_module(&mut reader, entry).ok_or_else(truncated)?);
+        }
+        Ok(Minidump { modules })
+    }
+
+    /// Get the modules of the dumped process, in the order the dump lists
+    /// them.
+    pub fn modules(&self) -> &[SharedLibrary] {
+        &self.modules
+    }
+
+    /// Iterate over the modules of the dumped process.
+    pub fn iter(&self) -> slice::Iter<'_, SharedLibrary> {
+        self.modules.iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a Minidump {
+    type Item = &'a SharedLibrary;
+    type IntoIter = slice::Iter<'a, SharedLibrary>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// Read a `MINIDUMP_MODULE`, along with its name and CodeView record.
+fn read_module<R: Read + Seek>(reader: &mut R, entry: &[u8]) -> Option<SharedLibrary> {
+    let base = u64_at(entry, 0, true)?;
+    let size = u32_at(entry, 8, true)?;
+    let timestamp = u32_at(entry, 16, true)?;
+
+    // A `MINIDUMP_STRING`: the length in bytes, then UTF-16.
+    let name_rva = u32_at(entry, 20, true)?;
+    let name_len = u32_at(&read_at(reader, name_rva.into(), 4)?, 0, true)?.min(MAX_RECORD);
+    let name = read_at(reader, u64::from(name_rva) + 4, name_len as usize)?;
+    let name: Vec<u16> = name
+        .chunks_exact(2)
+        .map(|unit| u16::from_le_bytes([unit[0], unit[1]]))
+        .collect();
+    let name = OsString::from(String::from_utf16_lossy(&name));
+
+    let mut shlib = SharedLibrary {
+        name,
+        base,
+        size,
+        id: None,
+        debug_id: None,
+        debug_name: None,
+    };
+    let cv_size = u32_at(entry, 76, true)?;
+    let cv_rva = u32_at(entry, 80, true)?;
+    let record = if cv_size != 0 && cv_size <= MAX_RECORD {
+        read_at(reader, cv_rva.into(), cv_size as usize)?
+    } else {
+        vec![]
+    };
+    if record.starts_with(&CV_SIGNATURE_ELF) {
+        shlib.id = Some(SharedLibraryId::GnuBuildId(record[4..].to_vec()));
+    } else {
+        if let Some((debug_id, debug_name)) = codeview(&record) {
+            shlib.debug_id = Some(debug_id);
+            shlib.debug_name = Some(debug_name);
+        }
+        // Only PE images have a timestamp.
+        if timestamp != 0 {
+            shlib.id = Some(SharedLibraryId::PeSignature(timestamp, size));
+        }
+    }
+    Some(shlib)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Avma;
+
+    use std::io::Cursor;
+
+    /// Build a minidump with a module list of `modules`, given as their
+    /// base, size, timestamp, name and CodeView record.
+    fn minidump(modules: &[(u64, u32, u32, &str, &[u8])]) -> Vec<u8> {
+        let mut dump = vec![];
+        dump.extend_from_slice(&MINIDUMP_SIGNATURE);
+        for word in [0xa793u32, 1, 32, 0, 0, 0, 0] {
+            dump.extend_from_slice(&word.to_le_bytes());
+        }
+        for word in [MODULE_LIST_STREAM, 0, 44] {
+            dump.extend_from_slice(&word.to_le_bytes());
+        }
+        dump.extend_from_slice(&(modules.len() as u32).to_le_bytes());
+
+        let mut data = 44 + 4 + modules.len() * MINIDUMP_MODULE_SIZE;
+        let mut records = vec![];
+        for &(base, size, timestamp, name, record) in modules {
+            let mut entry = vec![0; MINIDUMP_MODULE_SIZE];
+            entry[..8].copy_from_slice(&base.to_le_bytes());
+            entry[8..12].copy_from_slice(&size.to_le_bytes());
+            entry[16..20].copy_from_slice(&timestamp.to_le_bytes());
+            entry[20..24].copy_from_slice(&(data as u32).to_le_bytes());
+            let name: Vec<u8> = name.encode_utf16().flat_map(u16::to_le_bytes).collect();
+            records.extend_from_slice(&(name.len() as u32).to_le_bytes());
+            records.extend_from_slice(&name);
+            data += 4 + name.len();
+            entry[76..80].copy_from_slice(&(record.len() as u32).to_le_bytes());
+            entry[80..84].copy_from_slice(&(data as u32).to_le_bytes());
+            records.extend_from_slice(record);
+            data += record.len();
+            dump.extend_from_slice(&entry);
+        }
+        dump.extend_from_slice(&records);
+        dump
+    }
+
+    #[test]
+    fn module_list() {
+        let mut pdb70 = b"RSDS".to_vec();
+        pdb70.extend_from_slice(&[7; 16]);
+        pdb70.extend_from_slice(&3u32.to_le_bytes());
+        pdb70.extend_from_slice(b"C:\\build\\app.pdb\0");
+        // `MD_CVINFOELF_SIGNATURE`, as Breakpad and Crashpad write it.
+        let mut elf = 0x4270_454c_u32.to_le_bytes().to_vec();
+        assert_eq!(elf, b"LEpB");
+        elf.extend_from_slice(&[0xab; 20]);
+
+        let dump = minidump(&[
+            (0x7ff6_0000_0000, 0x5000, 0x5ab3_8b4f, "C:\\app.exe", &pdb70),
+            (0x7f00_1234_0000, 0x2000, 0, "/usr/lib/libc.so.6", &elf),
+            (0x1000_0000, 0x1000, 0, "unknown.so", &[]),
+        ]);
+        let dump = Minidump::read(Cursor::new(dump)).unwrap();
+        let modules = dump.modules();
+        assert_eq!(modules.len(), 3);
+        assert_eq!(dump.iter().count(), 3);
+
+        let app = &modules[0];
+        assert_eq!(app.name(), "C:\\app.exe");
+        assert_eq!(app.base_address(), 0x7ff6_0000_0000);
+        assert_eq!(
+            app.id(),
+            Some(SharedLibraryId::PeSignature(0x5ab3_8b4f, 0x5000))
+        );
+        assert_eq!(
+            app.debug_id(),
+            Some(SharedLibraryId::PdbSignature([7; 16], 3))
+        );
+        assert_eq!(app.debug_name(), Some(OsStr::new("C:\\build\\app.pdb")));
+
+        let libc = &modules[1];
+        assert_eq!(libc.id(), Some(SharedLibraryId::GnuBuildId(vec![0xab; 20])));
+        assert_eq!(libc.debug_id(), libc.id());
+        assert_eq!(libc.debug_name(), None);
+
+        let unknown = &modules[2];
+        assert_eq!(unknown.id(), None);
+        assert_eq!(unknown.segments().count(), 1);
+        assert_eq!(unknown.actual_load_addr(), Avma(0x1000_0000));
+        assert_eq!(unknown.len(), 0x1000);
+        assert_eq!(unknown.relative_address(Avma(0x1000_0010)), Some(0x10));
+    }
+
+    #[test]
+    fn not_a_minidump() {
+        let error = Minidump::read(Cursor::new(b"\x7fELF".to_vec())).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+
+        let mut dump = minidump(&[(0x1000_0000, 0x1000, 0, "cut off", &[])]);
+        dump.truncate(60);
+        let error = Minidump::read(Cursor::new(dump)).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,195 @@
+//! Resolving addresses to the shared libraries containing them.
+
+use crate::{
+    Avma, CachedLibraries, OwnedSharedLibrary, Permissions, Segment, SharedLibrary, Snapshot,
+};
+
+use std::ops::Range;
+use std::sync::{Arc, Mutex};
+
+/// Returns `true` if `segment` takes up memory in the process.
+///
+/// Only Linux tells loaded segments apart; elsewhere `is_load` defaults to
+/// `is_code`, which would leave out data. Any segment that is mapped with some
+/// access counts, which still leaves out `__PAGEZERO`.
+pub(crate) fn is_mapped<S: Segment>(segment: &S) -> bool {
+    segment.len() != 0
+        && (segment.is_load()
+            || segment.permissions().unwrap_or_default() != Permissions::default())
+}
+
+/// A lookup table from addresses to the shared libraries of a `Snapshot`.
+///
+/// Finding the library an address belongs to with `each` means looking at
+/// every segment of every library, for every address. A `ModuleMap` sorts the
+/// loaded segments once, so that `module_for` is a binary search.
+#[derive(Clone, Debug)]
+pub struct ModuleMap {
+    snapshot: Arc<Snapshot>,
+    // Sorted, non-overlapping address ranges, with the index of the library
+    // they belong to.
+    ranges: Vec<(Range<usize>, usize)>,
+}
+
+impl ModuleMap {
+    /// Build a map of the shared libraries that are loaded right now.
+    pub fn new() -> ModuleMap {
+        ModuleMap::from_snapshot(CachedLibraries::get())
+    }
+
+    /// Build a map of the shared libraries in `snapshot`.
+    pub fn from_snapshot(snapshot: Arc<Snapshot>) -> ModuleMap {
+        let mut ranges = vec![];
+        for (index, shlib) in snapshot.iter().enumerate() {
+            for segment in shlib.segments().filter(is_mapped) {
+                let start = segment.actual_virtual_memory_address(shlib).0;
+                let end = start.saturating_add(segment.len());
+                if start < end {
+                    ranges.push((start..end, index));
+                }
+            }
+        }
+        ranges.sort_by_key(|(range, index)| (range.start, *index));
+
+        // Libraries shouldn't overlap, but when they do the address is
+        // attributed to the library whose segment starts first.
+        let mut merged: Vec<(Range<usize>, usize)> = Vec::with_capacity(ranges.len());
+        for (mut range, index) in ranges {
+            if let Some((last, _)) = merged.last() {
+                range.start = range.start.max(last.end);
+            }
+            if range.start < range.end {
+                merged.push((range, index));
+            }
+        }
+
+        ModuleMap {
+            snapshot,
+            ranges: merged,
+        }
+    }
+
+    /// Get the snapshot the map was built from.
+    pub fn snapshot(&self) -> &Arc<Snapshot> {
+        &self.snapshot
+    }
+
+    /// Find the shared library with a loaded segment containing `address`.
+    pub fn module_for(&self, address: Avma) -> Option<&OwnedSharedLibrary> {
+        let address = address.0;
+        let next = self
+            .ranges
+            .partition_point(|(range, _)| range.start <= address);
+        let (range, index) = self.ranges.get(next.checked_sub(1)?)?;
+        if range.contains(&address) {
+            self.snapshot.libraries().get(*index)
+        } else {
+            None
+        }
+    }
+
+    /// Rebuild the map if shared libraries were loaded or unloaded since its
+    /// snapshot was taken, returning whether it was rebuilt.
+    pub fn rebuild_if_changed(&mut self) -> bool {
+        if self.snapshot.is_current() {
+            return false;
+        }
+        *self = ModuleMap::new();
+        true
+    }
+}
+
+impl Default for ModuleMap {
+    fn default() -> Self {
+        ModuleMap::new()
+    }
+}
+
+static GLOBAL_MAP: Mutex<Option<Arc<ModuleMap>>> = Mutex::new(None);
+
+/// Get a process-wide map of the shared libraries that are loaded right now.
+///
+/// The map is built on first use and rebuilt when `CachedLibraries` notices
+/// that libraries were loaded or unloaded, so code deep inside a program,
+/// like a panic hook or an allocation profiler, can resolve addresses without
+/// keeping a map of its own. The returned map stays valid after a rebuild,
+/// but it doesn't learn about the libraries loaded since.
+pub fn global_map() -> Arc<ModuleMap> {
+    let mut global = GLOBAL_MAP
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    if let Some(map) = global.as_ref() {
+        if map.snapshot().is_current() {
+            return map.clone();
+        }
+    }
+    let snapshot = CachedLibraries::get();
+    if let Some(map) = global.as_ref() {
+        // Another caller may have refreshed the cached snapshot already.
+        if Arc::ptr_eq(map.snapshot(), &snapshot) {
+            return map.clone();
+        }
+    }
+    let map = Arc::new(ModuleMap::from_snapshot(snapshot));
+    *global = Some(map.clone());
+    map
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_every_segment() {
+        let map = ModuleMap::new();
+        for shlib in map.snapshot().iter() {
+            for segment in shlib.segments().filter(is_mapped) {
+                let start = segment.actual_virtual_memory_address(shlib);
+                let found = map.module_for(start).unwrap();
+                assert_eq!(found.name(), shlib.name());
+            }
+        }
+        assert!(map.module_for(Avma(0)).is_none());
+    }
+
+    static DATA: [u8; 4] = [1, 2, 3, 4];
+
+    #[test]
+    fn finds_statics_and_functions() {
+        if !crate::TARGET_SUPPORTED {
+            return;
+        }
+        let map = ModuleMap::new();
+        let function = Avma(finds_statics_and_functions as fn() as usize);
+        let data = Avma(DATA.as_ptr() as usize);
+        let shlib = map.module_for(function).unwrap();
+        assert_eq!(map.module_for(data).unwrap().name(), shlib.name());
+    }
+
+    #[test]
+    fn global_map_is_shared() {
+        let map = global_map();
+        if map.snapshot().is_current() {
+            let again = global_map();
+            assert!(Arc::ptr_eq(&map, &again) || !again.snapshot().is_current());
+        }
+        if crate::TARGET_SUPPORTED {
+            let here = Avma(global_map_is_shared as fn() as usize);
+            assert!(global_map().module_for(here).is_some());
+        }
+    }
+
+    #[test]
+    fn finds_this_code() {
+        if !crate::TARGET_SUPPORTED {
+            return;
+        }
+        let mut map = ModuleMap::new();
+        map.rebuild_if_changed();
+        let here = Avma(finds_this_code as fn() as usize);
+        let shlib = map.module_for(here).unwrap();
+        assert!(shlib
+            .segments()
+            .any(|segment| segment.is_code() && segment.contains_avma(shlib, here)));
+    }
+}
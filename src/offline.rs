@@ -0,0 +1,828 @@
+//! Shared libraries described by captured memory rather than loaded in this
+//! process.
+//!
+//! Crash dumps and debugger protocols hand out the memory of another process
+//! rather than the process itself. Given the bytes of an image starting at
+//! its header and the address they were captured at,
+//! `SharedLibrary::parse` reads the same headers the native backends read
+//! in-process, for ELF, Mach-O and PE images alike and on any platform:
+//!
+//! * For ELF, the program headers, and the build id if the note segment was
+//!   captured too.
+//! * For Mach-O, the segment and `LC_UUID` load commands.
+//! * For PE, the section headers, and the CodeView record if the debug
+//!   directory was captured too.
+//!
+//! Only little-endian Mach-O and PE images are understood, which is all of
+//! them in practice.
+
+use crate::verify::{gnu_build_id, read, u16_at, u32_at, u64_at};
+use crate::Segment as SegmentTrait;
+use crate::SharedLibrary as SharedLibraryTrait;
+use crate::{Avma, Bias, IterationControl, Permissions, SharedLibraryId, SharedLibraryKind, Svma};
+
+use std::convert::TryFrom;
+use std::ffi::{OsStr, OsString};
+use std::ops::Range;
+use std::vec;
+
+const PT_LOAD: u32 = 1;
+const PT_INTERP: u32 = 3;
+const PT_NOTE: u32 = 4;
+const PF_X: u32 = 1;
+const PF_W: u32 = 2;
+const PF_R: u32 = 4;
+const ET_EXEC: u16 = 2;
+const ET_DYN: u16 = 3;
+
+const MH_MAGIC: u32 = 0xfeed_face;
+const MH_MAGIC_64: u32 = 0xfeed_facf;
+const MH_EXECUTE: u32 = 0x2;
+const MH_DYLIB: u32 = 0x6;
+const MH_DYLINKER: u32 = 0x7;
+const MH_BUNDLE: u32 = 0x8;
+const LC_SEGMENT: u32 = 0x1;
+const LC_SEGMENT_64: u32 = 0x19;
+const LC_UUID: u32 = 0x1b;
+const VM_PROT_READ: u32 = 0x1;
+const VM_PROT_WRITE: u32 = 0x2;
+const VM_PROT_EXECUTE: u32 = 0x4;
+const SECTION_TYPE: u32 = 0xff;
+const S_ZEROFILL: u32 = 0x1;
+const S_GB_ZEROFILL: u32 = 0xc;
+const S_THREAD_LOCAL_ZEROFILL: u32 = 0x12;
+
+const IMAGE_NT_OPTIONAL_HDR64_MAGIC: u16 = 0x20b;
+const IMAGE_FILE_DLL: u16 = 0x2000;
+const IMAGE_DIRECTORY_ENTRY_DEBUG: usize = 6;
+const IMAGE_DEBUG_TYPE_CODEVIEW: u32 = 2;
+const IMAGE_SCN_CNT_CODE: u32 = 0x20;
+const IMAGE_SCN_CNT_INITIALIZED_DATA: u32 = 0x40;
+const IMAGE_SCN_CNT_UNINITIALIZED_DATA: u32 = 0x80;
+const IMAGE_SCN_MEM_EXECUTE: u32 = 0x2000_0000;
+const IMAGE_SCN_MEM_READ: u32 = 0x4000_0000;
+const IMAGE_SCN_MEM_WRITE: u32 = 0x8000_0000;
+
+/// The object file format of an image.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ImageFormat {
+    /// ELF, as on Linux, Android and the BSDs.
+    Elf,
+    /// Mach-O, as on macOS and iOS.
+    MachO,
+    /// PE, as on Windows.
+    Pe,
+}
+
+/// A segment of an image parsed from captured memory.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Segment {
+    name: String,
+    is_code: bool,
+    is_load: bool,
+    is_data: bool,
+    is_readonly_data: bool,
+    is_bss: bool,
+    permissions: Permissions,
+    file_range: Range<u64>,
+    raw_type: Option<u32>,
+    raw_flags: u64,
+    stated_virtual_memory_address: Svma,
+    len: usize,
+}
+
+impl SegmentTrait for Segment {
+    type SharedLibrary = SharedLibrary;
+
+    #[inline]
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    #[inline]
+    fn is_code(&self) -> bool {
+        self.is_code
+    }
+
+    #[inline]
+    fn is_load(&self) -> bool {
+        self.is_load
+    }
+
+    #[inline]
+    fn is_data(&self) -> bool {
+        self.is_data
+    }
+
+    #[inline]
+    fn is_readonly_data(&self) -> bool {
+        self.is_readonly_data
+    }
+
+    #[inline]
+    fn is_bss(&self) -> bool {
+        self.is_bss
+    }
+
+    #[inline]
+    fn permissions(&self) -> Option<Permissions> {
+        Some(self.permissions)
+    }
+
+    #[inline]
+    fn file_range(&self) -> Option<Range<u64>> {
+        Some(self.file_range.clone())
+    }
+
+    #[inline]
+    fn raw_type(&self) -> Option<u32> {
+        self.raw_type
+    }
+
+    #[inline]
+    fn raw_flags(&self) -> Option<u64> {
+        Some(self.raw_flags)
+    }
+
+    #[inline]
+    fn stated_virtual_memory_address(&self) -> Svma {
+        self.stated_virtual_memory_address
+    }
+
+    #[inline]
+    fn len(&self) -> usize {
+        self.len
+    }
+}
+
+/// An iterator over the segments of an image parsed from captured memory.
+#[derive(Clone, Debug)]
+pub struct SegmentIter {
+    inner: vec::IntoIter<Segment>,
+}
+
+impl Iterator for SegmentIter {
+    type Item = Segment;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next()
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+/// An image parsed from memory captured from some process.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SharedLibrary {
+    name: OsString,
+    format: ImageFormat,
+    kind: Option<SharedLibraryKind>,
+    id: Option<SharedLibraryId>,
+    debug_id: Option<SharedLibraryId>,
+    debug_name: Option<OsString>,
+    virtual_memory_bias: Bias,
+    segments: Vec<Segment>,
+}
+
+impl SharedLibrary {
+    /// Parse the headers of an image in `memory`, which was captured at
+    /// `load_address` and starts with the image's ELF, Mach-O or DOS header.
+    ///
+    /// Only the headers have to be captured. Ids that live further into the
+    /// image, like ELF's build id note and PE's CodeView record, are only
+    /// found if `memory` reaches that far, laid out as the image was in
+    /// memory rather than in its file. Returns `None` if `memory` doesn't
+    /// start with a header this understands, or the headers are cut off.
+    pub fn parse(name: OsString, memory: &[u8], load_address: Avma) -> Option<SharedLibrary> {
+        let mut shlib = if memory.starts_with(b"\x7fELF") {
+            parse_elf(memory, load_address)?
+        } else if memory.starts_with(b"MZ") {
+            parse_pe(memory, load_address)?
+        } else {
+            parse_macho(memory, load_address)?
+        };
+        shlib.name = name;
+        Some(shlib)
+    }
+
+    /// Get the object file format of the image.
+    pub fn format(&self) -> ImageFormat {
+        self.format
+    }
+}
+
+impl SharedLibraryTrait for SharedLibrary {
+    type Segment = Segment;
+    type SegmentIter = SegmentIter;
+
+    #[inline]
+    fn name(&self) -> &OsStr {
+        &self.name
+    }
+
+    #[inline]
+    fn debug_name(&self) -> Option<&OsStr> {
+        self.debug_name.as_deref()
+    }
+
+    #[inline]
+    fn kind(&self) -> Option<SharedLibraryKind> {
+        self.kind
+    }
+
+    #[inline]
+    fn id(&self) -> Option<SharedLibraryId> {
+        self.id.clone()
+    }
+
+    #[inline]
+    fn debug_id(&self) -> Option<SharedLibraryId> {
+        self.debug_id.clone().or_else(|| self.id())
+    }
+
+    fn segments(&self) -> Self::SegmentIter {
+        SegmentIter {
+            inner: self.segments.clone().into_iter(),
+        }
+    }
+
+    #[inline]
+    fn virtual_memory_bias(&self) -> Bias {
+        self.virtual_memory_bias
+    }
+
+    /// Images parsed from captured memory aren't loaded in this process, so
+    /// there are none to find.
+    fn each<F, C>(_f: F)
+    where
+        F: FnMut(&Self) -> C,
+        C: Into<IterationControl>,
+    {
+    }
+}
+
+/// Get the bytes `memory` holds of the `len` bytes at `offset`.
+fn captured(memory: &[u8], offset: u64, len: u64) -> Option<&[u8]> {
+    let start = usize::try_from(offset).ok()?;
+    let end = start.checked_add(usize::try_from(len).ok()?)?;
+    memory.get(start..end)
+}
+
+fn empty_library(format: ImageFormat) -> SharedLibrary {
+    SharedLibrary {
+        name: OsString::new(),
+        format,
+        kind: None,
+        id: None,
+        debug_id: None,
+        debug_name: None,
+        virtual_memory_bias: Bias(0),
+        segments: vec![],
+    }
+}
+
+fn elf_segment_name(p_type: u32) -> &'static str {
+    match p_type {
+        0 => "NULL",
+        PT_LOAD => "LOAD",
+        2 => "DYNAMIC",
+        PT_INTERP => "INTERP",
+        PT_NOTE => "NOTE",
+        5 => "SHLIB",
+        6 => "PHDR",
+        7 => "TLS",
+        0x6474_e550 => "GNU_EH_FRAME",
+        0x6474_e551 => "GNU_STACK",
+        0x6474_e552 => "GNU_RELRO",
+        0x6474_e553 => "GNU_PROPERTY",
+        0x6474_e554 => "GNU_SFRAME",
+        _ => "(unknown segment type)",
+    }
+}
+
+fn parse_elf(memory: &[u8], load_address: Avma) -> Option<SharedLibrary> {
+    let is_64 = *memory.get(4)? == 2;
+    let le = *memory.get(5)? == 1;
+    let word_at = |data: &[u8], offset| match is_64 {
+        true => u64_at(data, offset, le),
+        false => u32_at(data, offset, le).map(u64::from),
+    };
+    let e_type = u16_at(memory, 16, le)?;
+    let (phoff, phentsize, phnum) = match is_64 {
+        true => (
+            word_at(memory, 32)?,
+            u16_at(memory, 54, le)?,
+            u16_at(memory, 56, le)?,
+        ),
+        false => (
+            word_at(memory, 28)?,
+            u16_at(memory, 42, le)?,
+            u16_at(memory, 44, le)?,
+        ),
+    };
+    let phentsize = u64::from(phentsize);
+    let headers = captured(memory, phoff, phentsize * u64::from(phnum))?;
+
+    let mut shlib = empty_library(ImageFormat::Elf);
+    let mut notes = vec![];
+    let mut has_interp = false;
+    for header in headers.chunks_exact(phentsize.max(1) as usize) {
+        let p_type = u32_at(header, 0, le)?;
+        let (flags, offset, vaddr, filesz, memsz, align) = match is_64 {
+            true => (
+                u32_at(header, 4, le)?,
+                word_at(header, 8)?,
+                word_at(header, 16)?,
+                word_at(header, 32)?,
+                word_at(header, 40)?,
+                word_at(header, 48)?,
+            ),
+            false => (
+                u32_at(header, 24, le)?,
+                word_at(header, 4)?,
+                word_at(header, 8)?,
+                word_at(header, 16)?,
+                word_at(header, 20)?,
+                word_at(header, 28)?,
+            ),
+        };
+        let is_load = p_type == PT_LOAD;
+        let permissions = Permissions::new(flags & PF_R != 0, flags & PF_W != 0, flags & PF_X != 0);
+        if is_load && shlib.segments.iter().all(|segment| !segment.is_load) {
+            // The header is at the start of the first loaded segment.
+            let header_vaddr = vaddr.wrapping_sub(offset) as usize;
+            shlib.virtual_memory_bias = Bias(load_address.0.wrapping_sub(header_vaddr));
+        }
+        match p_type {
+            PT_NOTE => notes.push((vaddr, memsz, align)),
+            PT_INTERP => has_interp = true,
+            _ => {}
+        }
+        shlib.segments.push(Segment {
+            name: elf_segment_name(p_type).to_owned(),
+            is_code: is_load && permissions.is_executable(),
+            is_load,
+            is_data: is_load && permissions.is_writable() && !permissions.is_executable(),
+            is_readonly_data: is_load
+                && permissions.is_readable()
+                && !permissions.is_writable()
+                && !permissions.is_executable(),
+            is_bss: is_load && memsz > filesz,
+            permissions,
+            file_range: offset..offset.saturating_add(filesz),
+            raw_type: Some(p_type),
+            raw_flags: u64::from(flags),
+            stated_virtual_memory_address: Svma(vaddr as usize),
+            len: memsz as usize,
+        });
+    }
+
+    // Notes are found at their address, relative to the header's.
+    let bias = shlib.virtual_memory_bias.0;
+    shlib.id = notes.into_iter().find_map(|(vaddr, size, align)| {
+        let offset = (vaddr as usize)
+            .wrapping_add(bias)
+            .wrapping_sub(load_address.0);
+        let notes = captured(memory, offset as u64, size)?;
+        gnu_build_id(notes, align, le).map(SharedLibraryId::GnuBuildId)
+    });
+    shlib.kind = match e_type {
+        ET_EXEC => Some(SharedLibraryKind::Executable),
+        ET_DYN if has_interp => Some(SharedLibraryKind::Executable),
+        ET_DYN => Some(SharedLibraryKind::Library),
+        _ => None,
+    };
+    Some(shlib)
+}
+
+fn parse_macho(memory: &[u8], load_address: Avma) -> Option<SharedLibrary> {
+    let header_size = match u32_at(memory, 0, true)? {
+        MH_MAGIC => 28,
+        MH_MAGIC_64 => 32,
+        _ => return None,
+    };
+    let filetype = u32_at(memory, 12, true)?;
+    let ncmds = u32_at(memory, 16, true)?;
+
+    let mut shlib = empty_library(ImageFormat::MachO);
+    let mut offset = header_size;
+    for _ in 0..ncmds {
+        let cmd = u32_at(memory, offset, true)?;
+        let cmdsize = u32_at(memory, offset + 4, true)? as usize;
+        let command = memory.get(offset..offset.checked_add(cmdsize)?)?;
+        match cmd {
+            LC_SEGMENT | LC_SEGMENT_64 => {
+                let name = command.get(8..24)?;
+                let name = name.split(|&byte| byte == 0).next().unwrap_or(name);
+                let name = String::from_utf8_lossy(name).into_owned();
+                let (vmaddr, vmsize, fileoff, filesize, initprot, flags) = if cmd == LC_SEGMENT {
+                    (
+                        u64::from(u32_at(command, 24, true)?),
+                        u64::from(u32_at(command, 28, true)?),
+                        u64::from(u32_at(command, 32, true)?),
+                        u64::from(u32_at(command, 36, true)?),
+                        u32_at(command, 44, true)?,
+                        u32_at(command, 52, true)?,
+                    )
+                } else {
+                    (
+                        u64_at(command, 24, true)?,
+                        u64_at(command, 32, true)?,
+                        u64_at(command, 40, true)?,
+                        u64_at(command, 48, true)?,
+                        u32_at(command, 60, true)?,
+                        u32_at(command, 68, true)?,
+                    )
+                };
+                // Segment sizes are no indication: `__PAGEZERO` isn't in the
+                // file at all and `__LINKEDIT` is rounded up in memory. Only
+                // zerofill sections are bss.
+                let (nsects, header_size, section_size, flags_offset) = if cmd == LC_SEGMENT {
+                    (u32_at(command, 48, true)?, 56, 68, 56)
+                } else {
+                    (u32_at(command, 64, true)?, 72, 80, 64)
+                };
+                let is_bss = (0..nsects as usize)
+                    .map_while(|index| {
+                        let section = header_size + index * section_size;
+                        u32_at(command, section + flags_offset, true)
+                    })
+                    .any(|flags| {
+                        matches!(
+                            flags & SECTION_TYPE,
+                            S_ZEROFILL | S_GB_ZEROFILL | S_THREAD_LOCAL_ZEROFILL
+                        )
+                    });
+                let permissions = Permissions::new(
+                    initprot & VM_PROT_READ != 0,
+                    initprot & VM_PROT_WRITE != 0,
+                    initprot & VM_PROT_EXECUTE != 0,
+                );
+                let is_code = name == "__TEXT";
+                if is_code {
+                    shlib.virtual_memory_bias = Bias(load_address.0.wrapping_sub(vmaddr as usize));
+                }
+                shlib.segments.push(Segment {
+                    is_code,
+                    is_load: is_code,
+                    is_data: permissions.is_writable()
+                        && !permissions.is_executable()
+                        && name != "__DATA_CONST",
+                    is_readonly_data: name == "__DATA_CONST"
+                        || (permissions.is_readable()
+                            && !permissions.is_writable()
+                            && !permissions.is_executable()),
+                    is_bss,
+                    permissions,
+                    file_range: fileoff..fileoff.saturating_add(filesize),
+                    raw_type: Some(cmd),
+                    raw_flags: u64::from(flags),
+                    stated_virtual_memory_address: Svma(vmaddr as usize),
+                    len: vmsize as usize,
+                    name,
+                });
+            }
+            LC_UUID => shlib.id = read(command, 8).map(SharedLibraryId::Uuid),
+            _ => {}
+        }
+        if cmdsize < 8 {
+            return None;
+        }
+        offset += cmdsize;
+    }
+    shlib.kind = match filetype {
+        MH_EXECUTE => Some(SharedLibraryKind::Executable),
+        MH_DYLIB => Some(SharedLibraryKind::Library),
+        MH_DYLINKER => Some(SharedLibraryKind::DynamicLinker),
+        MH_BUNDLE => Some(SharedLibraryKind::Plugin),
+        _ => None,
+    };
+    Some(shlib)
+}
+
+fn parse_pe(memory: &[u8], load_address: Avma) -> Option<SharedLibrary> {
+    let nt = u32_at(memory, 0x3c, true)? as usize;
+    if memory.get(nt..nt.checked_add(4)?)? != b"PE\0\0" {
+        return None;
+    }
+    let file_header = nt + 4;
+    let characteristics = u16_at(memory, file_header + 18, true)?;
+    let number_of_sections = u16_at(memory, file_header + 2, true)?;
+    let timestamp = u32_at(memory, file_header + 4, true)?;
+    let optional_size = usize::from(u16_at(memory, file_header + 16, true)?);
+    let optional = file_header + 20;
+    let is_64 = u16_at(memory, optional, true)? == IMAGE_NT_OPTIONAL_HDR64_MAGIC;
+    let section_alignment = u32_at(memory, optional + 32, true)?;
+    let size_of_image = u32_at(memory, optional + 56, true)?;
+    let (directory_count, directories) = match is_64 {
+        true => (u32_at(memory, optional + 108, true)?, optional + 112),
+        false => (u32_at(memory, optional + 92, true)?, optional + 96),
+    };
+
+    let mut shlib = empty_library(ImageFormat::Pe);
+    shlib.virtual_memory_bias = Bias(load_address.0);
+    shlib.id = Some(SharedLibraryId::PeSignature(timestamp, size_of_image));
+    shlib.kind = Some(match characteristics & IMAGE_FILE_DLL {
+        0 => SharedLibraryKind::Executable,
+        _ => SharedLibraryKind::Library,
+    });
+
+    let sections = optional.checked_add(optional_size)?;
+    for section in 0..usize::from(number_of_sections) {
+        let section = memory.get(sections + section * 40..)?.get(..40)?;
+        let name = section[..8].split(|&byte| byte == 0).next().unwrap_or(&[]);
+        let virtual_size = u32_at(section, 8, true)?;
+        let virtual_address = u32_at(section, 12, true)?;
+        let raw_size = u32_at(section, 16, true)?;
+        let raw_pointer = u32_at(section, 20, true)?;
+        let flags = u32_at(section, 36, true)?;
+        let len = match (virtual_size, section_alignment) {
+            (0, 0) => raw_size as usize,
+            (0, alignment) => {
+                let alignment = alignment as usize;
+                (raw_size as usize)
+                    .checked_add(alignment - 1)
+                    .map_or(raw_size as usize, |size| size / alignment * alignment)
+            }
+            (virtual_size, _) => virtual_size as usize,
+        };
+        shlib.segments.push(Segment {
+            name: String::from_utf8_lossy(name).into_owned(),
+            is_code: flags & IMAGE_SCN_CNT_CODE != 0,
+            is_load: flags & IMAGE_SCN_CNT_CODE != 0,
+            is_data: flags & (IMAGE_SCN_CNT_INITIALIZED_DATA | IMAGE_SCN_CNT_UNINITIALIZED_DATA)
+                != 0
+                && flags & IMAGE_SCN_MEM_WRITE != 0
+                && flags & IMAGE_SCN_MEM_EXECUTE == 0,
+            is_readonly_data: flags & IMAGE_SCN_CNT_INITIALIZED_DATA != 0
+                && flags & (IMAGE_SCN_MEM_WRITE | IMAGE_SCN_MEM_EXECUTE) == 0,
+            is_bss: flags & IMAGE_SCN_CNT_UNINITIALIZED_DATA != 0 || virtual_size > raw_size,
+            permissions: Permissions::new(
+                flags & IMAGE_SCN_MEM_READ != 0,
+                flags & IMAGE_SCN_MEM_WRITE != 0,
+                flags & IMAGE_SCN_MEM_EXECUTE != 0,
+            ),
+            file_range: u64::from(raw_pointer)..u64::from(raw_pointer) + u64::from(raw_size),
+            raw_type: None,
+            raw_flags: u64::from(flags),
+            stated_virtual_memory_address: Svma(virtual_address as usize),
+            len,
+        });
+    }
+
+    // The debug directory and the CodeView record it points to are found at
+    // their RVA, if they were captured.
+    if directory_count as usize > IMAGE_DIRECTORY_ENTRY_DEBUG {
+        let directory = directories + IMAGE_DIRECTORY_ENTRY_DEBUG * 8;
+        let rva = u32_at(memory, directory, true)?;
+        let size = u32_at(memory, directory + 4, true)?;
+        let entries = captured(memory, rva.into(), size.into()).unwrap_or(&[]);
+        for entry in entries.chunks_exact(28) {
+            if u32_at(entry, 12, true) != Some(IMAGE_DEBUG_TYPE_CODEVIEW) {
+                continue;
+            }
+            let size = u32_at(entry, 16, true)?;
+            let rva = u32_at(entry, 20, true)?;
+            if let Some(record) = captured(memory, rva.into(), size.into()) {
+                if let Some((debug_id, debug_name)) = codeview(record) {
+                    shlib.debug_id = Some(debug_id);
+                    shlib.debug_name = Some(debug_name);
+                    break;
+                }
+            }
+        }
+    }
+    Some(shlib)
+}
+
+/// Get the PDB signature and path of a CodeView record.
+pub(crate) fn codeview(record: &[u8]) -> Option<(SharedLibraryId, OsString)> {
+    let (id, path) = match record.get(..4)? {
+        b"RSDS" => {
+            let age = u32_at(record, 20, true)?;
+            (SharedLibraryId::PdbSignature(read(record, 4)?, age), 24)
+        }
+        // PDB 2.0 files are identified by a timestamp rather than a GUID,
+        // which goes where the first field of the GUID would be.
+        b"NB10" => {
+            let mut signature = [0; 16];
+            signature[..4].copy_from_slice(&read::<4>(record, 8)?);
+            let age = u32_at(record, 12, true)?;
+            (SharedLibraryId::PdbSignature(signature, age), 16)
+        }
+        _ => return None,
+    };
+    let path = record.get(path..)?;
+    let path = path.split(|&byte| byte == 0).next().unwrap_or(path);
+    Some((
+        id,
+        OsString::from(String::from_utf8_lossy(path).into_owned()),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TargetSharedLibrary;
+
+    #[test]
+    fn loaded_images() {
+        let mut parsed = 0;
+        TargetSharedLibrary::each(|shlib| {
+            let (address, len) = if cfg!(target_os = "windows") {
+                // The headers are in the first page of the image.
+                (Avma(shlib.virtual_memory_bias().0), 0x1000)
+            } else {
+                // Elsewhere, they are at the start of the first loaded
+                // segment, if it starts at the start of the file.
+                let header = match shlib.segments().find(|segment| segment.is_load()) {
+                    Some(segment) => segment,
+                    None => return,
+                };
+                match header.file_range() {
+                    Some(range)
+                        if range.start == 0
+                            && header
+                                .permissions()
+                                .is_some_and(|perms| perms.is_readable()) =>
+                    {
+                        (shlib.actual_load_addr(), range.end as usize)
+                    }
+                    _ => return,
+                }
+            };
+            let memory = unsafe { std::slice::from_raw_parts(address.0 as *const u8, len) };
+            let offline = SharedLibrary::parse(shlib.name().to_owned(), memory, address).unwrap();
+
+            assert_eq!(offline.name(), shlib.name());
+            assert_eq!(offline.virtual_memory_bias(), shlib.virtual_memory_bias());
+            assert_eq!(offline.actual_load_addr(), shlib.actual_load_addr());
+            assert_eq!(offline.len(), shlib.len());
+            assert_eq!(offline.segments().count(), shlib.segments().count());
+            // Build id notes are usually in the first loaded segment.
+            if offline.id().is_some() {
+                assert_eq!(offline.id(), shlib.id());
+            }
+            parsed += 1;
+        });
+        assert_eq!(parsed > 0, crate::TARGET_SUPPORTED);
+    }
+
+    #[test]
+    fn macho_header() {
+        let mut image = vec![];
+        for word in [MH_MAGIC_64, 0, 0, MH_DYLIB, 2, 72 + 24, 0, 0] {
+            image.extend_from_slice(&word.to_le_bytes());
+        }
+        image.extend_from_slice(&LC_SEGMENT_64.to_le_bytes());
+        image.extend_from_slice(&72u32.to_le_bytes());
+        image.extend_from_slice(b"__TEXT\0\0\0\0\0\0\0\0\0\0");
+        for word in [0x1000_0000u64, 0x4000, 0, 0x4000] {
+            image.extend_from_slice(&word.to_le_bytes());
+        }
+        for word in [5u32, 5, 0, 0] {
+            image.extend_from_slice(&word.to_le_bytes());
+        }
+        image.extend_from_slice(&LC_UUID.to_le_bytes());
+        image.extend_from_slice(&24u32.to_le_bytes());
+        image.extend_from_slice(&[3; 16]);
+
+        let address = Avma(0x1000_5000);
+        let shlib = SharedLibrary::parse("libfoo.dylib".into(), &image, address).unwrap();
+        assert_eq!(shlib.format(), ImageFormat::MachO);
+        assert_eq!(shlib.kind(), Some(SharedLibraryKind::Library));
+        assert_eq!(shlib.id(), Some(SharedLibraryId::Uuid([3; 16])));
+        assert_eq!(shlib.virtual_memory_bias(), Bias(0x5000));
+        assert_eq!(shlib.actual_load_addr(), address);
+        let text = shlib.segments().next().unwrap();
+        assert_eq!(text.name(), "__TEXT");
+        assert!(text.is_code());
+        assert_eq!(text.len(), 0x4000);
+        assert_eq!(
+            text.permissions(),
+            Some(Permissions::new(true, false, true))
+        );
+
+        assert!(SharedLibrary::parse("cut off".into(), &image[..60], address).is_none());
+    }
+
+    #[test]
+    fn macho_zerofill() {
+        let segment = |name: &[u8], vmsize: u64, filesize: u64, zerofill: Option<u32>| {
+            let mut command = vec![];
+            command.extend_from_slice(&LC_SEGMENT_64.to_le_bytes());
+            let cmdsize = if zerofill.is_some() { 72 + 80 } else { 72 };
+            command.extend_from_slice(&(cmdsize as u32).to_le_bytes());
+            let mut segname = [0; 16];
+            segname[..name.len()].copy_from_slice(name);
+            command.extend_from_slice(&segname);
+            for word in [0x1000_0000u64, vmsize, 0, filesize] {
+                command.extend_from_slice(&word.to_le_bytes());
+            }
+            for word in [3u32, 3, zerofill.is_some() as u32, 0] {
+                command.extend_from_slice(&word.to_le_bytes());
+            }
+            if let Some(flags) = zerofill {
+                command.extend_from_slice(b"__bss\0\0\0\0\0\0\0\0\0\0\0");
+                command.extend_from_slice(&segname);
+                command.extend_from_slice(&[0; 32]);
+                for word in [flags, 0, 0, 0] {
+                    command.extend_from_slice(&word.to_le_bytes());
+                }
+            }
+            command
+        };
+        let commands = [
+            segment(b"__PAGEZERO", 0x1000_0000, 0, None),
+            segment(b"__DATA", 0x2000, 0x1000, Some(S_ZEROFILL)),
+            segment(b"__LINKEDIT", 0x1000, 0x10, None),
+        ]
+        .concat();
+        let mut image = vec![];
+        for word in [MH_MAGIC_64, 0, 0, MH_DYLIB, 3, commands.len() as u32, 0, 0] {
+            image.extend_from_slice(&word.to_le_bytes());
+        }
+        image.extend_from_slice(&commands);
+
+        let shlib = SharedLibrary::parse("libfoo.dylib".into(), &image, Avma(0x1000)).unwrap();
+        let bss: Vec<_> = shlib
+            .segments()
+            .map(|segment| (segment.name().to_owned(), segment.is_bss()))
+            .collect();
+        assert_eq!(
+            bss,
+            [
+                ("__PAGEZERO".to_owned(), false),
+                ("__DATA".to_owned(), true),
+                ("__LINKEDIT".to_owned(), false),
+            ]
+        );
+    }
+
+    #[test]
+    fn pe_headers() {
+        let mut image = vec![0; 0x400];
+        image[..2].copy_from_slice(b"MZ");
+        image[0x3c..0x40].copy_from_slice(&0x80u32.to_le_bytes());
+        image[0x80..0x84].copy_from_slice(b"PE\0\0");
+        // The file header.
+        image[0x86..0x88].copy_from_slice(&1u16.to_le_bytes());
+        image[0x88..0x8c].copy_from_slice(&0x5ab3_8b4fu32.to_le_bytes());
+        image[0x94..0x96].copy_from_slice(&240u16.to_le_bytes());
+        image[0x96..0x98].copy_from_slice(&IMAGE_FILE_DLL.to_le_bytes());
+        // The PE32+ optional header, with a debug directory.
+        let optional = 0x98;
+        image[optional..optional + 2].copy_from_slice(&IMAGE_NT_OPTIONAL_HDR64_MAGIC.to_le_bytes());
+        image[optional + 32..optional + 36].copy_from_slice(&0x1000u32.to_le_bytes());
+        image[optional + 56..optional + 60].copy_from_slice(&0x2000u32.to_le_bytes());
+        image[optional + 108..optional + 112].copy_from_slice(&16u32.to_le_bytes());
+        let debug = optional + 112 + IMAGE_DIRECTORY_ENTRY_DEBUG * 8;
+        image[debug..debug + 4].copy_from_slice(&0x300u32.to_le_bytes());
+        image[debug + 4..debug + 8].copy_from_slice(&28u32.to_le_bytes());
+        // A `.text` section.
+        let section = optional + 240;
+        image[section..section + 5].copy_from_slice(b".text");
+        image[section + 8..section + 12].copy_from_slice(&0x800u32.to_le_bytes());
+        image[section + 12..section + 16].copy_from_slice(&0x1000u32.to_le_bytes());
+        let flags = IMAGE_SCN_CNT_CODE | IMAGE_SCN_MEM_EXECUTE | IMAGE_SCN_MEM_READ;
+        image[section + 36..section + 40].copy_from_slice(&flags.to_le_bytes());
+        // The debug directory entry and its CodeView record.
+        image[0x300 + 12..0x300 + 16].copy_from_slice(&IMAGE_DEBUG_TYPE_CODEVIEW.to_le_bytes());
+        image[0x300 + 16..0x300 + 20].copy_from_slice(&32u32.to_le_bytes());
+        image[0x300 + 20..0x300 + 24].copy_from_slice(&0x340u32.to_le_bytes());
+        image[0x340..0x344].copy_from_slice(b"RSDS");
+        image[0x344..0x354].copy_from_slice(&[9; 16]);
+        image[0x354..0x358].copy_from_slice(&2u32.to_le_bytes());
+        image[0x358..0x35f].copy_from_slice(b"foo.pdb");
+
+        let address = Avma(0x7ff0_0000);
+        let shlib = SharedLibrary::parse("foo.dll".into(), &image, address).unwrap();
+        assert_eq!(shlib.format(), ImageFormat::Pe);
+        assert_eq!(shlib.kind(), Some(SharedLibraryKind::Library));
+        assert_eq!(
+            shlib.id(),
+            Some(SharedLibraryId::PeSignature(0x5ab3_8b4f, 0x2000))
+        );
+        assert_eq!(
+            shlib.debug_id(),
+            Some(SharedLibraryId::PdbSignature([9; 16], 2))
+        );
+        assert_eq!(shlib.debug_name(), Some(OsStr::new("foo.pdb")));
+        assert_eq!(shlib.virtual_memory_bias(), Bias(0x7ff0_0000));
+        let text = shlib.segments().next().unwrap();
+        assert_eq!(text.name(), ".text");
+        assert!(text.is_code());
+        assert_eq!(
+            text.actual_virtual_memory_address(&shlib),
+            Avma(0x7ff0_1000)
+        );
+
+        // Without the debug directory, there is only the PE signature.
+        let headers = SharedLibrary::parse("foo.dll".into(), &image[..0x300], address).unwrap();
+        assert_eq!(headers.debug_id(), headers.id());
+    }
+}
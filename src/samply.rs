@@ -0,0 +1,195 @@
+//! Describing the loaded shared libraries the way samply expects.
+//!
+//! Profilers built on `samply-symbols` and `wholesym` identify libraries with
+//! a `LibraryInfo`, and place them in the address space with the addresses
+//! they are mapped at. `libraries` gathers both for the current process.
+
+use crate::module_map::is_mapped;
+use crate::{Avma, Segment, SharedLibrary, SharedLibraryId, TargetSharedLibrary};
+
+use samply_symbols::debugid::DebugId;
+use samply_symbols::{CodeId, DebugIdExt, ElfBuildId, LibraryInfo, PeCodeId};
+use uuid::Uuid;
+
+use std::ops::Range;
+
+/// A shared library, with what samply needs to symbolicate addresses in it.
+#[derive(Clone, Debug)]
+pub struct Library {
+    info: LibraryInfo,
+    base_avma: Avma,
+    mapped_ranges: Vec<Range<Avma>>,
+}
+
+impl Library {
+    /// Describe `shlib` and the ranges it is mapped at.
+    ///
+    /// `shlib` doesn't have to be loaded in this process, so an offline
+    /// library or a minidump module works too, but the architecture is always
+    /// that of the current process.
+    pub fn from_shared_library<L: SharedLibrary>(shlib: &L) -> Library {
+        let mapped_ranges = shlib
+            .segments()
+            .filter(is_mapped)
+            .map(|segment| segment.actual_range(shlib))
+            .collect();
+        Library {
+            info: library_info(shlib),
+            base_avma: shlib.actual_load_addr(),
+            mapped_ranges,
+        }
+    }
+
+    /// Get the library's `LibraryInfo`.
+    pub fn info(&self) -> &LibraryInfo {
+        &self.info
+    }
+
+    /// Take the library's `LibraryInfo`.
+    pub fn into_info(self) -> LibraryInfo {
+        self.info
+    }
+
+    /// Get the address the library is loaded at, which addresses relative to
+    /// the library are relative to.
+    pub fn base_avma(&self) -> Avma {
+        self.base_avma
+    }
+
+    /// Get the addresses of the library's mapped segments, code and data.
+    pub fn mapped_ranges(&self) -> &[Range<Avma>] {
+        &self.mapped_ranges
+    }
+}
+
+/// Get the name samply uses for the architecture of the current process.
+fn arch() -> &'static str {
+    match std::env::consts::ARCH {
+        "aarch64" if cfg!(any(target_os = "macos", target_os = "ios")) => "arm64",
+        "x86" if cfg!(any(target_os = "macos", target_os = "ios")) => "i386",
+        arch => arch,
+    }
+}
+
+/// Get the file name at the end of `path`.
+fn file_name(path: &str) -> String {
+    // PDB paths are Windows paths, whatever the platform.
+    path.rsplit(['/', '\\']).next().unwrap_or(path).to_owned()
+}
+
+/// Build the `LibraryInfo` for `shlib`, with the architecture of the current
+/// process.
+pub fn library_info<L: SharedLibrary>(shlib: &L) -> LibraryInfo {
+    let path = shlib.code_file().to_string_lossy().into_owned();
+    let debug_path = shlib
+        .debug_file()
+        .map(|debug_file| debug_file.to_string_lossy().into_owned());
+    LibraryInfo {
+        debug_name: debug_path.as_deref().map(file_name),
+        debug_id: shlib.debug_id().map(|id| debug_id(&id)),
+        debug_path,
+        name: Some(file_name(&path)),
+        code_id: shlib.id().and_then(|id| code_id(&id)),
+        path: Some(path),
+        arch: Some(arch().to_owned()),
+    }
+}
+
+fn debug_id(id: &SharedLibraryId) -> DebugId {
+    match *id {
+        SharedLibraryId::Uuid(bytes) => DebugId::from_uuid(Uuid::from_bytes(bytes)),
+        // Like Breakpad and Sentry, read build ids as little-endian whatever
+        // the host, so a library gets the same id wherever it's described.
+        SharedLibraryId::GnuBuildId(ref bytes) => DebugId::from_identifier(bytes, true),
+        SharedLibraryId::PdbSignature(guid, age) => DebugId::from_guid_age(&guid, age).unwrap(),
+        // Without a PDB, all there is to go by is the timestamp.
+        SharedLibraryId::PeSignature(timestamp, _) => {
+            DebugId::from_identifier(&timestamp.to_le_bytes(), true)
+        }
+    }
+}
+
+fn code_id(id: &SharedLibraryId) -> Option<CodeId> {
+    match *id {
+        SharedLibraryId::Uuid(bytes) => Some(CodeId::MachoUuid(Uuid::from_bytes(bytes))),
+        SharedLibraryId::GnuBuildId(ref bytes) => {
+            Some(CodeId::ElfBuildId(ElfBuildId::from_bytes(bytes)))
+        }
+        SharedLibraryId::PeSignature(timestamp, image_size) => Some(CodeId::PeCodeId(PeCodeId {
+            timestamp,
+            image_size,
+        })),
+        // A PDB signature identifies the debug file, not the binary.
+        SharedLibraryId::PdbSignature(..) => None,
+    }
+}
+
+/// Describe the shared libraries loaded in the current process.
+pub fn libraries() -> Vec<Library> {
+    let mut libraries = vec![];
+    TargetSharedLibrary::each(|shlib| {
+        libraries.push(Library::from_shared_library(shlib));
+    });
+    libraries
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ids() {
+        let guid = [
+            0x78, 0x56, 0x34, 0x12, 0x34, 0x12, 0x78, 0x56, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06,
+            0x07, 0x08,
+        ];
+        assert_eq!(
+            debug_id(&SharedLibraryId::PdbSignature(guid, 0x1a))
+                .breakpad()
+                .to_string(),
+            "123456781234567801020304050607081a"
+        );
+        assert_eq!(
+            code_id(&SharedLibraryId::PeSignature(0x5ab3_8b4f, 0x1c000))
+                .unwrap()
+                .to_string(),
+            "5AB38B4F1c000"
+        );
+        let build_id = vec![0x78, 0x56, 0x34, 0x12, 0xab];
+        assert_eq!(
+            code_id(&SharedLibraryId::GnuBuildId(build_id.clone()))
+                .unwrap()
+                .to_string(),
+            "78563412ab"
+        );
+        assert_eq!(
+            debug_id(&SharedLibraryId::GnuBuildId(build_id)).to_string(),
+            "12345678-00ab-0000-0000-000000000000"
+        );
+        assert_eq!(file_name(r"C:\build\foo.pdb"), "foo.pdb");
+        assert_eq!(file_name("/usr/lib/libc.so.6"), "libc.so.6");
+    }
+
+    #[test]
+    fn this_process() {
+        let here = Avma(this_process as fn() as usize);
+        let libraries = libraries();
+        assert_eq!(libraries.is_empty(), !crate::TARGET_SUPPORTED);
+        for library in &libraries {
+            let info = library.info();
+            assert!(info.path.is_some() && info.name.is_some());
+            for range in library.mapped_ranges() {
+                assert!(range.start <= range.end);
+            }
+        }
+        if crate::TARGET_SUPPORTED {
+            static DATA: [u8; 4] = [1, 2, 3, 4];
+            for address in [here, Avma(DATA.as_ptr() as usize)] {
+                assert!(libraries.iter().any(|library| library
+                    .mapped_ranges()
+                    .iter()
+                    .any(|range| range.contains(&address))));
+            }
+        }
+    }
+}
@@ -0,0 +1,223 @@
+//! Describing the loaded shared libraries the way Sentry expects.
+//!
+//! Sentry symbolicates events with the `debug_meta.images` list, which has an
+//! entry for every loaded image with its address, size, identifiers and
+//! files. `debug_images` builds that list for the current process, and the
+//! entries serialize to exactly the JSON the protocol expects.
+
+use crate::{SharedLibrary, SharedLibraryId, TargetSharedLibrary};
+
+use serde::Serialize;
+
+use std::fmt::Write;
+
+/// The type of the images of the target operating system.
+const IMAGE_TYPE: &str = if cfg!(any(target_os = "macos", target_os = "ios")) {
+    "macho"
+} else if cfg!(target_os = "windows") {
+    "pe"
+} else if cfg!(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "illumos",
+    target_os = "solaris"
+)) {
+    "elf"
+} else {
+    "symbolic"
+};
+
+/// An entry of Sentry's `debug_meta.images`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct DebugImage {
+    #[serde(rename = "type")]
+    image_type: &'static str,
+    image_addr: String,
+    image_size: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    image_vmaddr: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    code_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    debug_id: Option<String>,
+    code_file: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    debug_file: Option<String>,
+}
+
+impl DebugImage {
+    /// Describe `shlib` as a debug image.
+    ///
+    /// `shlib` doesn't have to be loaded in this process, so an offline
+    /// library or a minidump module works too, but the image type is always
+    /// that of the target operating system.
+    pub fn from_shared_library<L: SharedLibrary>(shlib: &L) -> DebugImage {
+        let stated_load_addr = shlib.stated_load_addr().0;
+        DebugImage {
+            image_type: IMAGE_TYPE,
+            image_addr: format!("{:#x}", shlib.actual_load_addr().0),
+            image_size: shlib.len() as u64,
+            image_vmaddr: if stated_load_addr != 0 {
+                Some(format!("{:#x}", stated_load_addr))
+            } else {
+                None
+            },
+            code_id: shlib.id().and_then(|id| code_id(&id)),
+            debug_id: shlib.debug_id().map(|id| debug_id(&id)),
+            code_file: shlib.code_file().to_string_lossy().into_owned(),
+            debug_file: shlib
+                .debug_file()
+                .map(|debug_file| debug_file.to_string_lossy().into_owned()),
+        }
+    }
+
+    /// Get the type of the image: `elf`, `macho` or `pe`.
+    pub fn image_type(&self) -> &str {
+        self.image_type
+    }
+
+    /// Get the address the image is loaded at, as a hexadecimal string.
+    pub fn image_addr(&self) -> &str {
+        &self.image_addr
+    }
+
+    /// Get the size of the image in bytes.
+    pub fn image_size(&self) -> u64 {
+        self.image_size
+    }
+
+    /// Get the address the image prefers to be loaded at, as a hexadecimal
+    /// string, unless it is zero.
+    pub fn image_vmaddr(&self) -> Option<&str> {
+        self.image_vmaddr.as_deref()
+    }
+
+    /// Get the identifier of the image's binary.
+    pub fn code_id(&self) -> Option<&str> {
+        self.code_id.as_deref()
+    }
+
+    /// Get the identifier of the image's debug information.
+    pub fn debug_id(&self) -> Option<&str> {
+        self.debug_id.as_deref()
+    }
+
+    /// Get the path of the image's binary.
+    pub fn code_file(&self) -> &str {
+        &self.code_file
+    }
+
+    /// Get the name of the image's debug file.
+    pub fn debug_file(&self) -> Option<&str> {
+        self.debug_file.as_deref()
+    }
+}
+
+/// Format `id` as a code id. Mach-O images don't have one apart from the
+/// UUID, which is their debug id.
+fn code_id(id: &SharedLibraryId) -> Option<String> {
+    match *id {
+        SharedLibraryId::Uuid(_) => None,
+        _ => Some(id.to_string()),
+    }
+}
+
+/// Format `id` as a debug id, which is a UUID followed by the age of PDBs.
+///
+/// PDB GUIDs are stored with their first three fields in little-endian
+/// order, and GNU build IDs are read the same way after being truncated or
+/// padded to 16 bytes, whatever the byte order of the image or the host.
+fn debug_id(id: &SharedLibraryId) -> String {
+    let (mut uuid, age, little_endian) = match *id {
+        SharedLibraryId::Uuid(bytes) => (bytes, 0, false),
+        SharedLibraryId::PdbSignature(bytes, age) => (bytes, age, true),
+        SharedLibraryId::GnuBuildId(ref bytes) => {
+            let mut uuid = [0; 16];
+            let len = bytes.len().min(16);
+            uuid[..len].copy_from_slice(&bytes[..len]);
+            (uuid, 0, true)
+        }
+        // Without a PDB, all there is to go by is the code id.
+        SharedLibraryId::PeSignature(timestamp, _) => {
+            let mut uuid = [0; 16];
+            uuid[..4].copy_from_slice(&timestamp.to_le_bytes());
+            (uuid, 0, true)
+        }
+    };
+    if little_endian {
+        uuid[..4].reverse();
+        uuid[4..6].reverse();
+        uuid[6..8].reverse();
+    }
+
+    let mut debug_id = String::with_capacity(45);
+    for (idx, byte) in uuid.iter().enumerate() {
+        if idx == 4 || idx == 6 || idx == 8 || idx == 10 {
+            debug_id.push('-');
+        }
+        write!(debug_id, "{:02x}", byte).unwrap();
+    }
+    if age != 0 {
+        write!(debug_id, "-{:x}", age).unwrap();
+    }
+    debug_id
+}
+
+/// Describe the shared libraries loaded in the current process, for
+/// `debug_meta.images`.
+pub fn debug_images() -> Vec<DebugImage> {
+    let mut images = vec![];
+    TargetSharedLibrary::each(|shlib| {
+        images.push(DebugImage::from_shared_library(shlib));
+    });
+    images
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn debug_ids() {
+        let guid = [
+            0x78, 0x56, 0x34, 0x12, 0x34, 0x12, 0x78, 0x56, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06,
+            0x07, 0x08,
+        ];
+        assert_eq!(
+            debug_id(&SharedLibraryId::PdbSignature(guid, 0x1a)),
+            "12345678-1234-5678-0102-030405060708-1a"
+        );
+        assert_eq!(
+            debug_id(&SharedLibraryId::Uuid(guid)),
+            "78563412-3412-7856-0102-030405060708"
+        );
+        assert_eq!(
+            debug_id(&SharedLibraryId::GnuBuildId(vec![
+                0x78, 0x56, 0x34, 0x12, 0xab
+            ])),
+            "12345678-00ab-0000-0000-000000000000"
+        );
+        assert_eq!(
+            code_id(&SharedLibraryId::PeSignature(0x5ab3_8b4f, 0x1c000)).unwrap(),
+            "5AB38B4F1c000"
+        );
+        assert_eq!(code_id(&SharedLibraryId::Uuid(guid)), None);
+    }
+
+    #[test]
+    fn serialized_images() {
+        let images = debug_images();
+        assert_eq!(images.is_empty(), !crate::TARGET_SUPPORTED);
+        for image in &images {
+            let json = serde_json::to_value(image).unwrap();
+            assert_eq!(json["type"], image.image_type());
+            assert_eq!(json["image_addr"], image.image_addr());
+            assert_eq!(json["image_size"], image.image_size());
+            assert_eq!(json["code_file"], image.code_file());
+            assert!(image.image_addr().starts_with("0x"));
+            if image.debug_id().is_none() {
+                assert!(json.get("debug_id").is_none());
+            }
+        }
+    }
+}
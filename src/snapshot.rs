@@ -0,0 +1,881 @@
+//! Owned snapshots of the shared libraries loaded in the process.
+//!
+//! `SharedLibrary::each` asks the OS for the list of loaded libraries every
+//! time it is called, and the libraries it hands out borrow from the loader's
+//! data structures, so they can't outlive the callback. A `Snapshot` copies
+//! everything the `SharedLibrary` and `Segment` traits expose, and
+//! `CachedLibraries` keeps one around for as long as the set of loaded
+//! libraries stays the same.
+
+use crate::native_mod;
+use crate::{
+    executable_regions, Avma, Bias, ExecutableSource, IterationControl, Permissions, Segment,
+    SharedLibrary, SharedLibraryId, SharedLibraryKind, Svma, TargetSharedLibrary,
+};
+
+use std::borrow::Cow;
+use std::ffi::{OsStr, OsString};
+use std::ops::Range;
+use std::path::Path;
+use std::slice;
+use std::sync::{Arc, Mutex};
+use std::vec;
+
+/// A copy of a segment of a shared library.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OwnedSegment {
+    name: String,
+    is_code: bool,
+    is_load: bool,
+    is_data: bool,
+    is_readonly_data: bool,
+    is_bss: bool,
+    permissions: Option<Permissions>,
+    file_range: Option<Range<u64>>,
+    raw_type: Option<u32>,
+    raw_flags: Option<u64>,
+    stated_virtual_memory_address: Svma,
+    len: usize,
+}
+
+impl OwnedSegment {
+    /// Copy a segment of any `SharedLibrary` implementation.
+    pub fn from_segment<S: Segment>(segment: &S) -> OwnedSegment {
+        OwnedSegment {
+            name: segment.name().to_owned(),
+            is_code: segment.is_code(),
+            is_load: segment.is_load(),
+            is_data: segment.is_data(),
+            is_readonly_data: segment.is_readonly_data(),
+            is_bss: segment.is_bss(),
+            permissions: segment.permissions(),
+            file_range: segment.file_range(),
+            raw_type: segment.raw_type(),
+            raw_flags: segment.raw_flags(),
+            stated_virtual_memory_address: segment.stated_virtual_memory_address(),
+            len: segment.len(),
+        }
+    }
+}
+
+impl Segment for OwnedSegment {
+    type SharedLibrary = OwnedSharedLibrary;
+
+    #[inline]
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    #[inline]
+    fn is_code(&self) -> bool {
+        self.is_code
+    }
+
+    #[inline]
+    fn is_load(&self) -> bool {
+        self.is_load
+    }
+
+    #[inline]
+    fn is_data(&self) -> bool {
+        self.is_data
+    }
+
+    #[inline]
+    fn is_readonly_data(&self) -> bool {
+        self.is_readonly_data
+    }
+
+    #[inline]
+    fn is_bss(&self) -> bool {
+        self.is_bss
+    }
+
+    #[inline]
+    fn permissions(&self) -> Option<Permissions> {
+        self.permissions
+    }
+
+    #[inline]
+    fn file_range(&self) -> Option<Range<u64>> {
+        self.file_range.clone()
+    }
+
+    #[inline]
+    fn raw_type(&self) -> Option<u32> {
+        self.raw_type
+    }
+
+    #[inline]
+    fn raw_flags(&self) -> Option<u64> {
+        self.raw_flags
+    }
+
+    #[inline]
+    fn stated_virtual_memory_address(&self) -> Svma {
+        self.stated_virtual_memory_address
+    }
+
+    #[inline]
+    fn len(&self) -> usize {
+        self.len
+    }
+}
+
+/// A copy of a shared library that doesn't borrow from the loader.
+///
+/// Only what the `SharedLibrary` trait exposes is copied; the contents of the
+/// library's memory are not. Its `each` iterates over the libraries of
+/// `CachedLibraries::get()`.
+///
+/// The load address, length and code range, which the `SharedLibrary` trait
+/// computes from the segments on every call, are computed once up front.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OwnedSharedLibrary {
+    name: OsString,
+    debug_name: Option<OsString>,
+    debug_file: Option<OsString>,
+    kind: Option<SharedLibraryKind>,
+    id: Option<SharedLibraryId>,
+    debug_id: Option<SharedLibraryId>,
+    virtual_memory_bias: Bias,
+    segments: Vec<OwnedSegment>,
+    actual_load_addr: Avma,
+    stated_load_addr: Svma,
+    len: usize,
+    code_range: Option<Range<Avma>>,
+}
+
+impl OwnedSharedLibrary {
+    /// Copy any `SharedLibrary` implementation.
+    pub fn from_shared_library<L: SharedLibrary>(shlib: &L) -> OwnedSharedLibrary {
+        let code_range = shlib
+            .segments()
+            .filter(|segment| segment.is_code())
+            .map(|segment| segment.actual_range(shlib))
+            .reduce(|a, b| a.start.min(b.start)..a.end.max(b.end));
+        OwnedSharedLibrary {
+            name: shlib.name().to_owned(),
+            debug_name: shlib.debug_name().map(OsStr::to_owned),
+            debug_file: shlib.debug_file().map(Cow::into_owned),
+            kind: shlib.kind(),
+            id: shlib.id(),
+            debug_id: shlib.debug_id(),
+            virtual_memory_bias: shlib.virtual_memory_bias(),
+            segments: shlib
+                .segments()
+                .map(|segment| OwnedSegment::from_segment(&segment))
+                .collect(),
+            actual_load_addr: shlib.actual_load_addr(),
+            stated_load_addr: shlib.stated_load_addr(),
+            len: shlib.len(),
+            code_range,
+        }
+    }
+
+    /// Get the addresses spanned by the library's code segments, from the
+    /// start of the first to the end of the last.
+    pub fn code_range(&self) -> Option<Range<Avma>> {
+        self.code_range.clone()
+    }
+
+    /// Get the library's segments without copying them, unlike `segments`.
+    pub fn owned_segments(&self) -> &[OwnedSegment] {
+        &self.segments
+    }
+
+    /// Get the last component of the library's name, its file name.
+    ///
+    /// This is the whole name if it has no directories, like the bare
+    /// sonames Linux reports for some libraries.
+    pub fn basename(&self) -> &OsStr {
+        basename(&self.name)
+    }
+}
+
+fn basename(name: &OsStr) -> &OsStr {
+    Path::new(name).file_name().unwrap_or(name)
+}
+
+/// The order of the libraries in a snapshot, see `Snapshot::sorted_by`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum SortOrder {
+    /// The order `each` reported them in, which differs between platforms
+    /// and can differ between runs.
+    #[default]
+    AsReported,
+    /// By the address each library is loaded at.
+    LoadAddress,
+    /// By name, comparing the bytes of `SharedLibrary::name_bytes`, and by
+    /// load address for libraries with the same name.
+    Name,
+}
+
+/// How the names of the libraries in a snapshot are reported, see
+/// `Snapshot::with_name_style`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum NameStyle {
+    /// The names as the platform reports them. Windows, macOS and iOS report
+    /// full paths while Linux and Android report whatever was passed to
+    /// `dlopen`, which may be a bare soname or a relative path.
+    #[default]
+    AsReported,
+    /// Full paths. Names that aren't, like bare sonames, are replaced with
+    /// the path of the file the library's code is mapped from if the
+    /// platform tells, and are kept otherwise, like for the vDSO.
+    FullPath,
+    /// Only the file names, without directories.
+    Basename,
+}
+
+/// An iterator over the segments of an `OwnedSharedLibrary`.
+#[derive(Clone, Debug)]
+pub struct OwnedSegmentIter {
+    inner: vec::IntoIter<OwnedSegment>,
+}
+
+impl Iterator for OwnedSegmentIter {
+    type Item = OwnedSegment;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next()
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl SharedLibrary for OwnedSharedLibrary {
+    type Segment = OwnedSegment;
+    type SegmentIter = OwnedSegmentIter;
+
+    #[inline]
+    fn name(&self) -> &OsStr {
+        &self.name
+    }
+
+    #[inline]
+    fn debug_name(&self) -> Option<&OsStr> {
+        self.debug_name.as_deref()
+    }
+
+    #[inline]
+    fn debug_file(&self) -> Option<Cow<'_, OsStr>> {
+        self.debug_file.as_deref().map(Cow::Borrowed)
+    }
+
+    #[inline]
+    fn kind(&self) -> Option<SharedLibraryKind> {
+        self.kind
+    }
+
+    #[inline]
+    fn id(&self) -> Option<SharedLibraryId> {
+        self.id.clone()
+    }
+
+    #[inline]
+    fn debug_id(&self) -> Option<SharedLibraryId> {
+        self.debug_id.clone()
+    }
+
+    fn segments(&self) -> Self::SegmentIter {
+        OwnedSegmentIter {
+            inner: self.segments.clone().into_iter(),
+        }
+    }
+
+    #[inline]
+    fn virtual_memory_bias(&self) -> Bias {
+        self.virtual_memory_bias
+    }
+
+    #[inline]
+    fn actual_load_addr(&self) -> Avma {
+        self.actual_load_addr
+    }
+
+    #[inline]
+    fn stated_load_addr(&self) -> Svma {
+        self.stated_load_addr
+    }
+
+    #[inline]
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn each<F, C>(mut f: F)
+    where
+        F: FnMut(&Self) -> C,
+        C: Into<IterationControl>,
+    {
+        let snapshot = CachedLibraries::get();
+        for shlib in snapshot.libraries() {
+            if f(shlib).into() == IterationControl::Break {
+                break;
+            }
+        }
+    }
+}
+
+/// A copy of every shared library loaded in the process at some point.
+#[derive(Clone, Debug)]
+pub struct Snapshot {
+    libraries: Vec<OwnedSharedLibrary>,
+    change_count: Option<u64>,
+}
+
+impl Snapshot {
+    /// Copy the shared libraries that are loaded right now.
+    pub fn take() -> Snapshot {
+        // Read the counter first, so that a library loaded while the
+        // snapshot is taken makes it stale rather than going unnoticed.
+        let change_count = native_mod::change_count();
+        let mut libraries = Vec::new();
+        TargetSharedLibrary::each(|shlib| {
+            libraries.push(OwnedSharedLibrary::from_shared_library(shlib));
+        });
+        Snapshot {
+            libraries,
+            change_count,
+        }
+    }
+
+    /// Get the shared libraries in the order `each` reported them, unless
+    /// they were sorted.
+    pub fn libraries(&self) -> &[OwnedSharedLibrary] {
+        &self.libraries
+    }
+
+    /// Iterate over the shared libraries in the order of `libraries`.
+    pub fn iter(&self) -> slice::Iter<'_, OwnedSharedLibrary> {
+        self.libraries.iter()
+    }
+
+    /// Sort the libraries in the given order.
+    ///
+    /// No two libraries are loaded at the same address, so the order only
+    /// depends on the libraries and not on the order they were reported in.
+    /// Sorting by name uses the names as they are, so a name style should be
+    /// applied first.
+    pub fn sorted_by(mut self, order: SortOrder) -> Snapshot {
+        match order {
+            SortOrder::AsReported => {}
+            SortOrder::LoadAddress => self.libraries.sort_by_key(|shlib| shlib.actual_load_addr),
+            SortOrder::Name => self.libraries.sort_by(|a, b| {
+                a.name_bytes()
+                    .cmp(b.name_bytes())
+                    .then(a.actual_load_addr.cmp(&b.actual_load_addr))
+            }),
+        }
+        self
+    }
+
+    /// Report the names of the libraries in the given style.
+    ///
+    /// `OwnedSharedLibrary::basename` always gives the file name, so with
+    /// `NameStyle::FullPath` both are available. Full paths are found from the
+    /// memory of the current process, so this should be called before the
+    /// libraries could be unloaded.
+    pub fn with_name_style(mut self, style: NameStyle) -> Snapshot {
+        match style {
+            NameStyle::AsReported => {}
+            NameStyle::FullPath => {
+                if self
+                    .libraries
+                    .iter()
+                    .any(|shlib| !is_full_path(&shlib.name))
+                {
+                    let regions = executable_regions();
+                    for shlib in &mut self.libraries {
+                        if is_full_path(&shlib.name) {
+                            continue;
+                        }
+                        let code = match &shlib.code_range {
+                            Some(code) => code.start,
+                            None => continue,
+                        };
+                        let path = regions.iter().find_map(|region| match region.source() {
+                            ExecutableSource::File(path) if region.range().contains(&code) => {
+                                Some(path)
+                            }
+                            _ => None,
+                        });
+                        if let Some(path) = path {
+                            shlib.name = path.clone();
+                        }
+                    }
+                }
+            }
+            NameStyle::Basename => {
+                for shlib in &mut self.libraries {
+                    shlib.name = basename(&shlib.name).to_owned();
+                }
+            }
+        }
+        self
+    }
+
+    /// Find the shared library whose code-id or debug-id is `id`.
+    ///
+    /// If the same image is loaded more than once, this is the first one in
+    /// the order of `libraries`.
+    pub fn find_by_id(&self, id: &SharedLibraryId) -> Option<&OwnedSharedLibrary> {
+        self.libraries
+            .iter()
+            .find(|shlib| shlib.id.as_ref() == Some(id) || shlib.debug_id.as_ref() == Some(id))
+    }
+
+    /// Returns `true` if no shared library was loaded or unloaded since the
+    /// snapshot was taken.
+    ///
+    /// This is always `false` where the platform gives no way to tell.
+    pub fn is_current(&self) -> bool {
+        self.change_count.is_some() && self.change_count == native_mod::change_count()
+    }
+}
+
+impl IntoIterator for Snapshot {
+    type Item = OwnedSharedLibrary;
+    type IntoIter = vec::IntoIter<OwnedSharedLibrary>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.libraries.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a Snapshot {
+    type Item = &'a OwnedSharedLibrary;
+    type IntoIter = slice::Iter<'a, OwnedSharedLibrary>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+#[cfg(feature = "rayon")]
+impl Snapshot {
+    /// Iterate over the shared libraries in parallel.
+    pub fn par_iter(&self) -> rayon::slice::Iter<'_, OwnedSharedLibrary> {
+        use rayon::iter::IntoParallelRefIterator;
+
+        self.libraries.par_iter()
+    }
+}
+
+#[cfg(feature = "rayon")]
+impl<'a> rayon::iter::IntoParallelIterator for &'a Snapshot {
+    type Iter = rayon::slice::Iter<'a, OwnedSharedLibrary>;
+    type Item = &'a OwnedSharedLibrary;
+
+    fn into_par_iter(self) -> Self::Iter {
+        self.par_iter()
+    }
+}
+
+#[cfg(feature = "rayon")]
+impl rayon::iter::IntoParallelIterator for Snapshot {
+    type Iter = rayon::vec::IntoIter<OwnedSharedLibrary>;
+    type Item = OwnedSharedLibrary;
+
+    fn into_par_iter(self) -> Self::Iter {
+        self.libraries.into_par_iter()
+    }
+}
+
+fn is_full_path(name: &OsStr) -> bool {
+    Path::new(name).is_absolute()
+}
+
+/// Get a snapshot of the loaded shared libraries and call `f` with each of
+/// them on rayon's thread pool.
+///
+/// This is meant for heavyweight work, like hashing code or parsing symbol
+/// tables, which can't be done in parallel from within `each`'s callback.
+/// The results are in the same order as the snapshot's libraries.
+#[cfg(feature = "rayon")]
+pub fn collect_all_par<F, T>(f: F) -> (Arc<Snapshot>, Vec<T>)
+where
+    F: Fn(&OwnedSharedLibrary) -> T + Sync + Send,
+    T: Send,
+{
+    use rayon::iter::ParallelIterator;
+
+    let snapshot = CachedLibraries::get();
+    let results = snapshot.par_iter().map(f).collect();
+    (snapshot, results)
+}
+
+/// Find the loaded shared library whose code-id or debug-id is `id`, like a
+/// build id, UUID or PDB signature received from a symbol server.
+///
+/// This looks through the snapshot of `CachedLibraries`, so it only walks the
+/// loaded libraries again when they changed.
+pub fn find_by_id(id: &SharedLibraryId) -> Option<OwnedSharedLibrary> {
+    CachedLibraries::get().find_by_id(id).cloned()
+}
+
+/// A process-wide cache of the loaded shared libraries.
+///
+/// Most programs load all their libraries at startup and call `each` far more
+/// often than the set of libraries changes. `CachedLibraries::get` only takes
+/// a new snapshot when the loader reports that libraries were loaded or
+/// unloaded since the last one:
+///
+/// * On Linux and Android through the `dlpi_adds` and `dlpi_subs` counters
+///   of `dl_iterate_phdr`.
+/// * On macOS and iOS through dyld's add and remove image callbacks.
+/// * On Windows through DLL load notifications.
+///
+/// Where none of these is available, every call takes a new snapshot.
+#[derive(Debug)]
+pub struct CachedLibraries {
+    _private: (),
+}
+
+static CACHED_SNAPSHOT: Mutex<Option<Arc<Snapshot>>> = Mutex::new(None);
+
+impl CachedLibraries {
+    /// Get a snapshot of the shared libraries that are loaded right now,
+    /// reusing the previous one if nothing changed since it was taken.
+    pub fn get() -> Arc<Snapshot> {
+        let mut cached = CACHED_SNAPSHOT
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if let Some(snapshot) = cached.as_ref() {
+            if snapshot.is_current() {
+                return snapshot.clone();
+            }
+        }
+        let snapshot = Arc::new(Snapshot::take());
+        *cached = Some(snapshot.clone());
+        snapshot
+    }
+
+    /// Drop the cached snapshot, so that the next `get` takes a new one.
+    pub fn invalidate() {
+        let mut cached = CACHED_SNAPSHOT
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        *cached = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snapshot_matches_each() {
+        let snapshot = Snapshot::take();
+        let mut names = vec![];
+        TargetSharedLibrary::each(|shlib| {
+            names.push(shlib.name().to_owned());
+        });
+        if !snapshot.is_current() {
+            return;
+        }
+        let snapshot_names: Vec<_> = snapshot
+            .iter()
+            .map(|shlib| shlib.name().to_owned())
+            .collect();
+        assert_eq!(snapshot_names, names);
+
+        for shlib in snapshot.iter() {
+            assert_eq!(
+                shlib.avma_to_svma(shlib.actual_load_addr()),
+                shlib.stated_load_addr()
+            );
+            assert_eq!(shlib.segments().count(), shlib.segments.len());
+        }
+    }
+
+    fn synthetic_library(bias: usize, segments: Vec<OwnedSegment>) -> OwnedSharedLibrary {
+        OwnedSharedLibrary {
+            name: OsString::from("synthetic"),
+            debug_name: None,
+            debug_file: None,
+            kind: None,
+            id: None,
+            debug_id: None,
+            virtual_memory_bias: Bias(bias),
+            segments,
+            actual_load_addr: Avma(0),
+            stated_load_addr: Svma(0),
+            len: 0,
+            code_range: None,
+        }
+    }
+
+    fn segment(svma: usize, len: usize) -> OwnedSegment {
+        OwnedSegment {
+            name: "LOAD".to_owned(),
+            is_code: true,
+            is_load: true,
+            is_data: false,
+            is_readonly_data: false,
+            is_bss: false,
+            permissions: None,
+            file_range: None,
+            raw_type: None,
+            raw_flags: None,
+            stated_virtual_memory_address: Svma(svma),
+            len,
+        }
+    }
+
+    #[test]
+    fn name_styles() {
+        let snapshot = Snapshot::take();
+        let reported = snapshot.clone().with_name_style(NameStyle::AsReported);
+        let full = snapshot.clone().with_name_style(NameStyle::FullPath);
+        let basenames = snapshot.clone().with_name_style(NameStyle::Basename);
+        assert_eq!(full.libraries().len(), snapshot.libraries().len());
+        for (i, shlib) in snapshot.iter().enumerate() {
+            assert_eq!(reported.libraries()[i], *shlib);
+            let full = &full.libraries()[i];
+            if full.name() != shlib.name() {
+                assert!(Path::new(full.name()).is_absolute());
+            }
+            if shlib.kind() == Some(SharedLibraryKind::Executable) {
+                assert!(Path::new(full.name()).is_absolute(), "{:?}", full);
+            }
+            let basename = &basenames.libraries()[i];
+            assert_eq!(basename.name(), shlib.basename());
+            assert_eq!(basename.basename(), shlib.basename());
+        }
+        assert_eq!(basename(OsStr::new("libc.so.6")), "libc.so.6");
+    }
+
+    #[test]
+    fn sorted_snapshots() {
+        let snapshot = Snapshot::take();
+        let by_address = snapshot.clone().sorted_by(SortOrder::LoadAddress);
+        let by_name = snapshot.clone().sorted_by(SortOrder::Name);
+        assert_eq!(
+            snapshot
+                .clone()
+                .sorted_by(SortOrder::AsReported)
+                .libraries(),
+            snapshot.libraries()
+        );
+        assert_eq!(by_address.libraries().len(), snapshot.libraries().len());
+        assert_eq!(by_name.libraries().len(), snapshot.libraries().len());
+        for pair in by_address.libraries().windows(2) {
+            assert!(pair[0].actual_load_addr() <= pair[1].actual_load_addr());
+        }
+        for pair in by_name.libraries().windows(2) {
+            assert!(
+                (pair[0].name_bytes(), pair[0].actual_load_addr())
+                    <= (pair[1].name_bytes(), pair[1].actual_load_addr())
+            );
+        }
+
+        // Sorting doesn't depend on the order the libraries were reported in.
+        let mut reversed = snapshot.clone();
+        reversed.libraries.reverse();
+        assert_eq!(
+            reversed
+                .clone()
+                .sorted_by(SortOrder::LoadAddress)
+                .libraries(),
+            by_address.libraries()
+        );
+        assert_eq!(
+            reversed.sorted_by(SortOrder::Name).libraries(),
+            by_name.libraries()
+        );
+    }
+
+    #[test]
+    fn iterate_snapshot() {
+        let snapshot = Snapshot::take();
+        let mut names = vec![];
+        for shlib in &snapshot {
+            assert_eq!(
+                shlib.owned_segments(),
+                &shlib.segments().collect::<Vec<_>>()[..]
+            );
+            names.push(shlib.name().to_owned());
+        }
+        let owned: Vec<_> = snapshot.clone().into_iter().collect();
+        assert_eq!(owned, snapshot.libraries());
+        assert_eq!(
+            names,
+            owned.iter().map(|shlib| shlib.name()).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn find_libraries_by_id() {
+        let mut first = synthetic_library(0x1000, vec![]);
+        first.id = Some(SharedLibraryId::GnuBuildId(vec![1, 2, 3]));
+        let mut second = synthetic_library(0x2000, vec![]);
+        second.id = Some(SharedLibraryId::PeSignature(0x1234, 0x5000));
+        second.debug_id = Some(SharedLibraryId::PdbSignature([7; 16], 1));
+        let snapshot = Snapshot {
+            libraries: vec![first, second],
+            change_count: None,
+        };
+
+        let find = |id| {
+            snapshot
+                .find_by_id(&id)
+                .map(|shlib| shlib.virtual_memory_bias())
+        };
+        assert_eq!(
+            find(SharedLibraryId::GnuBuildId(vec![1, 2, 3])),
+            Some(Bias(0x1000))
+        );
+        assert_eq!(
+            find(SharedLibraryId::PeSignature(0x1234, 0x5000)),
+            Some(Bias(0x2000))
+        );
+        assert_eq!(
+            find(SharedLibraryId::PdbSignature([7; 16], 1)),
+            Some(Bias(0x2000))
+        );
+        assert_eq!(find(SharedLibraryId::PdbSignature([7; 16], 2)), None);
+
+        TargetSharedLibrary::each(|shlib| {
+            if let Some(id) = shlib.id() {
+                let found = find_by_id(&id).unwrap();
+                assert_eq!(found.id(), Some(id));
+                return IterationControl::Break;
+            }
+            IterationControl::Continue
+        });
+    }
+
+    #[test]
+    fn wrapping_address_math() {
+        let high = segment(usize::MAX - 0xfff, 0x1000);
+        let shlib = synthetic_library(0x2000, vec![high.clone()]);
+        assert_eq!(high.actual_virtual_memory_address(&shlib), Avma(0x1000));
+        assert_eq!(high.checked_actual_virtual_memory_address(&shlib), None);
+        assert_eq!(high.checked_stated_end(), None);
+        assert_eq!(high.checked_actual_end(&shlib), None);
+        assert!(high.contains_svma(Svma(usize::MAX)));
+        assert!(!high.contains_svma(Svma(0)));
+        assert!(high.contains_avma(&shlib, Avma(0x1fff)));
+        assert!(!high.contains_avma(&shlib, Avma(0x2000)));
+        assert_eq!(shlib.avma_to_svma(Avma(0x1000)), Svma(usize::MAX - 0xfff));
+        assert_eq!(
+            high.stated_range(),
+            Svma(usize::MAX - 0xfff)..Svma(usize::MAX)
+        );
+        assert_eq!(high.actual_range(&shlib), Avma(0x1000)..Avma(0x2000));
+
+        let low = segment(0x1000, 0x1000);
+        let shlib = synthetic_library(0x4000, vec![low.clone()]);
+        assert_eq!(
+            low.checked_actual_virtual_memory_address(&shlib),
+            Some(Avma(0x5000))
+        );
+        assert_eq!(low.checked_stated_end(), Some(Svma(0x2000)));
+        assert_eq!(low.checked_actual_end(&shlib), Some(Avma(0x6000)));
+        assert_eq!(low.stated_range(), Svma(0x1000)..Svma(0x2000));
+        assert_eq!(low.actual_range(&shlib), Avma(0x5000)..Avma(0x6000));
+    }
+
+    #[test]
+    fn negative_bias() {
+        // Loaded below its stated address, like a prelinked object can be.
+        let prelinked = segment(0x10_0000, 0x1000);
+        let shlib = synthetic_library(0usize.wrapping_sub(0x8000), vec![prelinked.clone()]);
+        assert_eq!(
+            prelinked.checked_actual_virtual_memory_address(&shlib),
+            Some(Avma(0xf_8000))
+        );
+        assert_eq!(prelinked.checked_actual_end(&shlib), Some(Avma(0xf_9000)));
+        assert_eq!(
+            prelinked.actual_virtual_memory_address(&shlib),
+            Avma(0xf_8000)
+        );
+
+        let low = segment(0x1000, 0x1000);
+        let shlib = synthetic_library(0usize.wrapping_sub(0x2000), vec![low.clone()]);
+        assert_eq!(low.checked_actual_virtual_memory_address(&shlib), None);
+        assert_eq!(low.checked_actual_end(&shlib), None);
+    }
+
+    #[test]
+    fn segment_containing() {
+        let mut dynamic = segment(0x1800, 0x100);
+        dynamic.name = "DYNAMIC".to_owned();
+        dynamic.is_load = false;
+        let mut notes = segment(0x3000, 0x100);
+        notes.is_load = false;
+        let shlib = synthetic_library(
+            0x10000,
+            vec![
+                dynamic,
+                segment(0x1000, 0x1000),
+                segment(0x2000, 0x1000),
+                notes.clone(),
+            ],
+        );
+
+        let found = shlib.segment_containing_svma(Svma(0x1800)).unwrap();
+        assert_eq!(found.name(), "LOAD");
+        assert_eq!(found.stated_virtual_memory_address(), Svma(0x1000));
+        let found = shlib.segment_containing_avma(Avma(0x12000)).unwrap();
+        assert_eq!(found.stated_virtual_memory_address(), Svma(0x2000));
+        assert_eq!(shlib.segment_containing_svma(Svma(0x3010)), Some(notes));
+        assert_eq!(shlib.segment_containing_avma(Avma(0x1800)), None);
+    }
+
+    #[test]
+    fn precomputed_values() {
+        TargetSharedLibrary::each(|shlib| {
+            let owned = OwnedSharedLibrary::from_shared_library(shlib);
+            assert_eq!(owned.actual_load_addr(), shlib.actual_load_addr());
+            assert_eq!(owned.stated_load_addr(), shlib.stated_load_addr());
+            assert_eq!(owned.len(), shlib.len());
+            assert_eq!(owned.id(), shlib.id());
+            for (owned, segment) in owned.owned_segments().iter().zip(shlib.segments()) {
+                assert_eq!(owned.raw_type(), segment.raw_type());
+                assert_eq!(owned.raw_flags(), segment.raw_flags());
+            }
+
+            let code_range = owned.code_range();
+            let has_code = shlib.segments().any(|segment| segment.is_code());
+            assert_eq!(code_range.is_some(), has_code);
+            for segment in shlib.segments().filter(|segment| segment.is_code()) {
+                let start = segment.actual_virtual_memory_address(shlib);
+                let range = code_range.clone().unwrap();
+                assert!(range.start <= start && start.0 + segment.len() <= range.end.0);
+            }
+        });
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn parallel() {
+        let (snapshot, counts) = collect_all_par(|shlib| shlib.segments().count());
+        let expected: Vec<_> = snapshot
+            .iter()
+            .map(|shlib| shlib.segments().count())
+            .collect();
+        assert_eq!(counts, expected);
+    }
+
+    #[test]
+    fn cached_snapshot_is_reused() {
+        let first = CachedLibraries::get();
+        let second = CachedLibraries::get();
+        if first.is_current() {
+            assert!(Arc::ptr_eq(&first, &second));
+        }
+
+        let mut count = 0;
+        OwnedSharedLibrary::each(|_| {
+            count += 1;
+        });
+        assert_eq!(count > 0, crate::TARGET_SUPPORTED);
+    }
+}
@@ -3,11 +3,10 @@
 
 use crate::Segment as SegmentTrait;
 use crate::SharedLibrary as SharedLibraryTrait;
-use crate::{Bias, IterationControl, SharedLibraryId, Svma};
+use crate::{diagnostics, Bias, IterationControl, SharedLibraryId, Svma};
 
 use std::ffi::OsStr;
 use std::marker::PhantomData;
-use std::usize;
 
 /// An unsupported segment
 #[derive(Debug)]
@@ -84,5 +83,33 @@ impl<'a> SharedLibraryTrait for SharedLibrary<'a> {
         F: FnMut(&Self) -> C,
         C: Into<IterationControl>,
     {
+        // Nothing is visited, but it still counts as an enumeration.
+        let started = diagnostics::start();
+        diagnostics::enumerated();
+        diagnostics::finish(started, "visit");
     }
 }
+
+/// Nothing is ever loaded, so the count never changes.
+#[allow(dead_code)]
+pub(crate) fn change_count() -> Option<u64> {
+    Some(0)
+}
+
+/// Nothing is ever loaded.
+#[allow(dead_code)]
+pub(crate) fn probe_loaded(_name: &OsStr) -> Option<bool> {
+    Some(false)
+}
+
+/// Nothing is ever loaded.
+#[allow(dead_code)]
+pub(crate) fn find_loaded(_name: &OsStr) -> Option<crate::OwnedSharedLibrary> {
+    None
+}
+
+/// The address space can't be asked about other mappings.
+#[allow(dead_code)]
+pub(crate) fn executable_mappings() -> Vec<crate::ExecutableRegion> {
+    Vec::new()
+}
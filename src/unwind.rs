@@ -0,0 +1,196 @@
+//! Locating the unwind sections of the loaded shared libraries.
+//!
+//! In-process unwinders have to be told where each module is and where its
+//! unwind information is: the `.eh_frame` and `.eh_frame_hdr` sections of ELF
+//! and Mach-O images, the `__unwind_info` section of Mach-O images and the
+//! exception directory (`.pdata`) of PE images. `modules` finds these for the
+//! current process.
+//!
+//! The addresses are actual addresses. Stated addresses are found by
+//! subtracting the module's `bias`.
+//!
+//! There is no conversion into the module types of `framehop` or the
+//! `unwinding` crate: neither is a dependency of findshlibs. A `framehop`
+//! module is built from `name`, `avma_range` and `base_avma`, with section
+//! information whose addresses are these minus `bias`.
+
+use crate::{Avma, Bias, Segment, SharedLibrary, SharedLibraryId, TargetSharedLibrary};
+
+use std::ffi::{OsStr, OsString};
+use std::ops::Range;
+
+/// A shared library of the current process, with the addresses of its unwind
+/// information.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UnwindModule {
+    name: OsString,
+    id: Option<SharedLibraryId>,
+    bias: Bias,
+    base_avma: Avma,
+    avma_range: Range<Avma>,
+    text: Option<Range<Avma>>,
+    eh_frame: Option<Range<Avma>>,
+    eh_frame_hdr: Option<Range<Avma>>,
+    unwind_info: Option<Range<Avma>>,
+    pdata: Option<Range<Avma>>,
+}
+
+impl UnwindModule {
+    /// Describe a shared library of the current process.
+    pub fn new(shlib: &TargetSharedLibrary) -> UnwindModule {
+        let base_avma = shlib.actual_load_addr();
+        let mut module = UnwindModule {
+            name: shlib.name().to_owned(),
+            id: shlib.id(),
+            bias: shlib.virtual_memory_bias(),
+            base_avma,
+            avma_range: base_avma..Avma(base_avma.0.saturating_add(shlib.len())),
+            text: shlib
+                .segments()
+                .filter(|segment| segment.is_code())
+                .map(|segment| segment.actual_range(shlib))
+                .reduce(|a, b| a.start.min(b.start)..a.end.max(b.end)),
+            eh_frame: None,
+            eh_frame_hdr: None,
+            unwind_info: None,
+            pdata: None,
+        };
+        module.find_sections(shlib);
+        module
+    }
+
+    #[cfg(any(
+        target_os = "linux",
+        all(target_os = "android", feature = "dl_iterate_phdr"),
+        target_os = "illumos",
+        target_os = "solaris"
+    ))]
+    fn find_sections(&mut self, shlib: &TargetSharedLibrary) {
+        self.eh_frame_hdr = shlib.eh_frame_hdr();
+        self.eh_frame = shlib.eh_frame();
+    }
+
+    #[cfg(any(target_os = "macos", target_os = "ios"))]
+    fn find_sections(&mut self, shlib: &TargetSharedLibrary) {
+        // The unwind information describes `__text` rather than the whole
+        // `__TEXT` segment, which also holds the headers.
+        if let Some(text) = shlib.section("__TEXT", "__text") {
+            self.text = Some(text);
+        }
+        self.eh_frame = shlib.section("__TEXT", "__eh_frame");
+        self.unwind_info = shlib.section("__TEXT", "__unwind_info");
+    }
+
+    #[cfg(target_os = "windows")]
+    fn find_sections(&mut self, shlib: &TargetSharedLibrary) {
+        self.pdata = shlib.exception_directory();
+    }
+
+    #[cfg(not(any(
+        target_os = "macos",
+        target_os = "ios",
+        target_os = "linux",
+        all(target_os = "android", feature = "dl_iterate_phdr"),
+        target_os = "illumos",
+        target_os = "solaris",
+        target_os = "windows"
+    )))]
+    fn find_sections(&mut self, _shlib: &TargetSharedLibrary) {}
+
+    /// Get the name of the module.
+    pub fn name(&self) -> &OsStr {
+        &self.name
+    }
+
+    /// Get the module's code-id, if it has one.
+    pub fn id(&self) -> Option<&SharedLibraryId> {
+        self.id.as_ref()
+    }
+
+    /// Get the module's virtual memory bias.
+    pub fn bias(&self) -> Bias {
+        self.bias
+    }
+
+    /// Get the address the module is loaded at.
+    pub fn base_avma(&self) -> Avma {
+        self.base_avma
+    }
+
+    /// Get the addresses the module spans.
+    pub fn avma_range(&self) -> Range<Avma> {
+        self.avma_range.clone()
+    }
+
+    /// Get the addresses of the module's code: the `__text` section of Mach-O
+    /// images, and the code segments of the others.
+    pub fn text(&self) -> Option<Range<Avma>> {
+        self.text.clone()
+    }
+
+    /// Get the addresses of the `.eh_frame` or `__eh_frame` section.
+    ///
+    /// On Linux, Android, illumos and Solaris the range runs to the end of the
+    /// segment containing the section, see `linux::SharedLibrary::eh_frame`.
+    pub fn eh_frame(&self) -> Option<Range<Avma>> {
+        self.eh_frame.clone()
+    }
+
+    /// Get the addresses of the `.eh_frame_hdr` section, on Linux, Android,
+    /// illumos and Solaris.
+    pub fn eh_frame_hdr(&self) -> Option<Range<Avma>> {
+        self.eh_frame_hdr.clone()
+    }
+
+    /// Get the addresses of the `__unwind_info` section, on macOS and iOS.
+    pub fn unwind_info(&self) -> Option<Range<Avma>> {
+        self.unwind_info.clone()
+    }
+
+    /// Get the addresses of the exception directory, on Windows.
+    pub fn pdata(&self) -> Option<Range<Avma>> {
+        self.pdata.clone()
+    }
+}
+
+/// Describe every shared library of the current process.
+pub fn modules() -> Vec<UnwindModule> {
+    let mut modules = vec![];
+    TargetSharedLibrary::each(|shlib| modules.push(UnwindModule::new(shlib)));
+    modules
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn this_module() {
+        if !crate::TARGET_SUPPORTED {
+            return;
+        }
+        let here = Avma(this_module as fn() as usize);
+        let modules = modules();
+        let module = modules
+            .iter()
+            .find(|module| module.text().is_some_and(|text| text.contains(&here)))
+            .expect("the test executable has code");
+        assert!(module.avma_range().contains(&here));
+        assert_eq!(module.avma_range().start, module.base_avma());
+        if cfg!(any(
+            target_os = "linux",
+            target_os = "android",
+            target_os = "illumos",
+            target_os = "solaris"
+        )) {
+            assert!(module.eh_frame_hdr().is_some());
+            assert!(module.eh_frame().is_some());
+        }
+        if cfg!(any(target_os = "macos", target_os = "ios")) {
+            assert!(module.unwind_info().is_some());
+        }
+        if cfg!(all(target_os = "windows", not(target_arch = "x86"))) {
+            assert!(module.pdata().is_some());
+        }
+    }
+}
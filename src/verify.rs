@@ -0,0 +1,390 @@
+//! Checking that the file a shared library was loaded from is still the same.
+//!
+//! Package managers replace libraries while programs have them loaded, so
+//! the file at a library's path can be a newer build than what is mapped.
+//! Symbols looked up or uploaded for the file would then be wrong for the
+//! process. `SharedLibrary::verify_backing_file` reads the id out of the file
+//! and compares it to the id of the loaded image:
+//!
+//! * The `NT_GNU_BUILD_ID` note of an ELF file.
+//! * The `LC_UUID` command of a Mach-O file, or of any architecture slice of
+//!   a universal binary.
+//! * The timestamp and image size in the headers of a PE file.
+
+use crate::{SharedLibrary, SharedLibraryId};
+
+use std::convert::TryInto;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+
+pub(crate) const FAT_MAGIC: u32 = 0xcafe_babe;
+pub(crate) const FAT_MAGIC_64: u32 = 0xcafe_babf;
+pub(crate) const MH_MAGIC: u32 = 0xfeed_face;
+pub(crate) const MH_MAGIC_64: u32 = 0xfeed_facf;
+pub(crate) const LC_UUID: u32 = 0x1b;
+
+const ELF_MAGIC: [u8; 4] = *b"\x7fELF";
+const PT_NOTE: u32 = 4;
+const NT_GNU_BUILD_ID: u32 = 3;
+const DOS_MAGIC: [u8; 2] = *b"MZ";
+const PE_MAGIC: [u8; 4] = *b"PE\0\0";
+
+// Headers and notes are small, so anything bigger is a corrupt file.
+const MAX_READ: usize = 1 << 24;
+
+/// Whether the file a shared library was loaded from still matches it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum FileMatch {
+    /// The file has the same id as the loaded image.
+    Match,
+    /// The file has a different id, or none, so it was replaced since the
+    /// image was loaded.
+    Mismatch,
+    /// There is no telling: the image has no id, the file can't be read or
+    /// its format doesn't have that kind of id.
+    Unknown,
+}
+
+pub(crate) fn verify_backing_file<L: SharedLibrary>(shlib: &L) -> FileMatch {
+    let id = match shlib.id() {
+        Some(id) => id,
+        None => return FileMatch::Unknown,
+    };
+    match File::open(shlib.code_file()) {
+        Ok(mut file) => verify(&mut file, &id),
+        Err(_) => FileMatch::Unknown,
+    }
+}
+
+/// Compare the id in the object file `file` to `id`.
+fn verify<R: Read + Seek>(file: &mut R, id: &SharedLibraryId) -> FileMatch {
+    let found = match *id {
+        SharedLibraryId::GnuBuildId(ref build_id) => {
+            elf_build_id(file).map(|found| found.as_deref() == Some(&build_id[..]))
+        }
+        SharedLibraryId::Uuid(uuid) => {
+            macho_uuids(file).map(|found| !found.is_empty() && found.contains(&uuid))
+        }
+        SharedLibraryId::PeSignature(timestamp, size_of_image) => {
+            pe_signature(file).map(|found| found == (timestamp, size_of_image))
+        }
+        // PDB signatures are the ids of debug files.
+        SharedLibraryId::PdbSignature(..) => None,
+    };
+    match found {
+        Some(true) => FileMatch::Match,
+        Some(false) => FileMatch::Mismatch,
+        None => FileMatch::Unknown,
+    }
+}
+
+pub(crate) fn read<const N: usize>(data: &[u8], offset: usize) -> Option<[u8; N]> {
+    data.get(offset..offset.checked_add(N)?)?.try_into().ok()
+}
+
+pub(crate) fn u16_at(data: &[u8], offset: usize, little_endian: bool) -> Option<u16> {
+    read(data, offset).map(|bytes| match little_endian {
+        true => u16::from_le_bytes(bytes),
+        false => u16::from_be_bytes(bytes),
+    })
+}
+
+pub(crate) fn u32_at(data: &[u8], offset: usize, little_endian: bool) -> Option<u32> {
+    read(data, offset).map(|bytes| match little_endian {
+        true => u32::from_le_bytes(bytes),
+        false => u32::from_be_bytes(bytes),
+    })
+}
+
+pub(crate) fn u64_at(data: &[u8], offset: usize, little_endian: bool) -> Option<u64> {
+    read(data, offset).map(|bytes| match little_endian {
+        true => u64::from_le_bytes(bytes),
+        false => u64::from_be_bytes(bytes),
+    })
+}
+
+pub(crate) fn read_at<R: Read + Seek>(file: &mut R, offset: u64, len: usize) -> Option<Vec<u8>> {
+    if len > MAX_READ {
+        return None;
+    }
+    let mut data = vec![0; len];
+    file.seek(SeekFrom::Start(offset)).ok()?;
+    file.read_exact(&mut data).ok()?;
+    Some(data)
+}
+
+/// Get the build id of an ELF file, or `None` if it isn't one.
+fn elf_build_id<R: Read + Seek>(file: &mut R) -> Option<Option<Vec<u8>>> {
+    let ident = read_at(file, 0, 64)?;
+    if ident[..4] != ELF_MAGIC {
+        return None;
+    }
+    let is_64 = ident[4] == 2;
+    let little_endian = ident[5] == 1;
+    let word_at = |data: &[u8], offset| match is_64 {
+        true => u64_at(data, offset, little_endian),
+        false => u32_at(data, offset, little_endian).map(u64::from),
+    };
+
+    let (phoff, phentsize, phnum) = match is_64 {
+        true => (
+            word_at(&ident, 32)?,
+            u16_at(&ident, 54, little_endian)?,
+            u16_at(&ident, 56, little_endian)?,
+        ),
+        false => (
+            word_at(&ident, 28)?,
+            u16_at(&ident, 42, little_endian)?,
+            u16_at(&ident, 44, little_endian)?,
+        ),
+    };
+    let headers = read_at(file, phoff, usize::from(phentsize) * usize::from(phnum))?;
+    for header in headers.chunks_exact(usize::from(phentsize).max(1)) {
+        if u32_at(header, 0, little_endian)? != PT_NOTE {
+            continue;
+        }
+        let (offset, size, align) = match is_64 {
+            true => (
+                word_at(header, 8)?,
+                word_at(header, 32)?,
+                word_at(header, 48)?,
+            ),
+            false => (
+                word_at(header, 4)?,
+                word_at(header, 16)?,
+                word_at(header, 28)?,
+            ),
+        };
+        let notes = read_at(file, offset, size.try_into().ok()?)?;
+        if let Some(build_id) = gnu_build_id(&notes, align, little_endian) {
+            return Some(Some(build_id));
+        }
+    }
+    Some(None)
+}
+
+/// Find the `NT_GNU_BUILD_ID` note among the ELF `notes` of a `PT_NOTE`
+/// segment with the alignment `align`.
+pub(crate) fn gnu_build_id(notes: &[u8], align: u64, little_endian: bool) -> Option<Vec<u8>> {
+    let u32_at = |offset| u32_at(notes, offset, little_endian);
+    let align = if align == 8 { 8 } else { 4 };
+    let pad = |len: usize| len.checked_add(align - 1).map(|len| len & !(align - 1));
+
+    let mut note = 0;
+    while let (Some(namesz), Some(descsz), Some(kind)) =
+        (u32_at(note), u32_at(note + 4), u32_at(note + 8))
+    {
+        let name = note + 12;
+        let desc = name.checked_add(pad(namesz as usize)?)?;
+        let desc_end = desc.checked_add(descsz as usize)?;
+        if kind == NT_GNU_BUILD_ID && notes.get(name..name + namesz as usize)? == b"GNU\0" {
+            return Some(notes.get(desc..desc_end)?.to_vec());
+        }
+        note = desc.checked_add(pad(descsz as usize)?)?;
+    }
+    None
+}
+
+/// Get the UUIDs of the architecture slices of a Mach-O file, or `None` if
+/// it isn't one.
+///
+/// Only slices of the current architecture's byte order are looked at.
+fn macho_uuids<R: Read + Seek>(file: &mut R) -> Option<Vec<[u8; 16]>> {
+    let header = read_at(file, 0, 8)?;
+    let magic = u32::from_be_bytes(read(&header, 0)?);
+    if magic != FAT_MAGIC && magic != FAT_MAGIC_64 {
+        return thin_macho_uuid(file, 0).map(|uuid| uuid.into_iter().collect());
+    }
+
+    let nfat_arch = u32::from_be_bytes(read(&header, 4)?) as usize;
+    let entry_size = if magic == FAT_MAGIC_64 { 32 } else { 20 };
+    let entries = read_at(file, 8, nfat_arch.checked_mul(entry_size)?)?;
+    let mut uuids = vec![];
+    for entry in entries.chunks_exact(entry_size) {
+        let offset = if magic == FAT_MAGIC_64 {
+            u64::from_be_bytes(read(entry, 8)?)
+        } else {
+            u64::from(u32::from_be_bytes(read(entry, 8)?))
+        };
+        if let Some(Some(uuid)) = thin_macho_uuid(file, offset) {
+            uuids.push(uuid);
+        }
+    }
+    Some(uuids)
+}
+
+/// Get the UUID of the thin Mach-O image at `offset` in `file`, or `None` if
+/// there is none of the current architecture's byte order.
+fn thin_macho_uuid<R: Read + Seek>(file: &mut R, offset: u64) -> Option<Option<[u8; 16]>> {
+    let header = read_at(file, offset, 32)?;
+    let header_size = match u32::from_ne_bytes(read(&header, 0)?) {
+        MH_MAGIC => 28,
+        MH_MAGIC_64 => 32,
+        _ => return None,
+    };
+    let sizeofcmds = u32::from_ne_bytes(read(&header, 20)?) as usize;
+    let image = read_at(file, offset, header_size + sizeofcmds)?;
+    Some(macho_uuid(&image))
+}
+
+/// Get the UUID of a thin Mach-O image of the current architecture.
+pub(crate) fn macho_uuid(data: &[u8]) -> Option<[u8; 16]> {
+    let header_size = match u32::from_ne_bytes(read(data, 0)?) {
+        MH_MAGIC => 28,
+        MH_MAGIC_64 => 32,
+        _ => return None,
+    };
+    let ncmds = u32::from_ne_bytes(read(data, 16)?);
+    let mut offset = header_size;
+    for _ in 0..ncmds {
+        let cmd = u32::from_ne_bytes(read(data, offset)?);
+        let cmdsize = u32::from_ne_bytes(read(data, offset + 4)?) as usize;
+        if cmd == LC_UUID {
+            return read(data, offset + 8);
+        }
+        if cmdsize < 8 {
+            return None;
+        }
+        offset = offset.checked_add(cmdsize)?;
+    }
+    None
+}
+
+/// Get the timestamp and image size of a PE file, or `None` if it isn't one.
+fn pe_signature<R: Read + Seek>(file: &mut R) -> Option<(u32, u32)> {
+    let dos_header = read_at(file, 0, 64)?;
+    if dos_header[..2] != DOS_MAGIC {
+        return None;
+    }
+    let pe_offset = u32_at(&dos_header, 0x3c, true)?;
+    // The signature, the file header and the optional header up to
+    // `SizeOfImage`, which is at the same offset in PE32 and PE32+.
+    let headers = read_at(file, pe_offset.into(), 24 + 60)?;
+    if headers[..4] != PE_MAGIC {
+        return None;
+    }
+    let timestamp = u32_at(&headers, 8, true)?;
+    let size_of_image = u32_at(&headers, 24 + 56, true)?;
+    Some((timestamp, size_of_image))
+}
+
+/// Build a thin 64-bit Mach-O header with an `LC_UUID` command.
+#[cfg(test)]
+pub(crate) fn thin_image(uuid: [u8; 16]) -> Vec<u8> {
+    let mut image = vec![];
+    for word in [MH_MAGIC_64, 0, 0, 0, 1, 24, 0, 0] {
+        image.extend_from_slice(&word.to_ne_bytes());
+    }
+    image.extend_from_slice(&LC_UUID.to_ne_bytes());
+    image.extend_from_slice(&24u32.to_ne_bytes());
+    image.extend_from_slice(&uuid);
+    image
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TargetSharedLibrary;
+
+    use std::io::Cursor;
+
+    /// Build a 64-bit little-endian ELF header with a `PT_NOTE` segment
+    /// holding `notes`.
+    fn elf_image(notes: &[u8]) -> Vec<u8> {
+        let mut image = vec![0; 64 + 56];
+        image[..4].copy_from_slice(&ELF_MAGIC);
+        image[4] = 2;
+        image[5] = 1;
+        image[32..40].copy_from_slice(&64u64.to_le_bytes());
+        image[54..56].copy_from_slice(&56u16.to_le_bytes());
+        image[56..58].copy_from_slice(&1u16.to_le_bytes());
+
+        let header = &mut image[64..];
+        header[..4].copy_from_slice(&PT_NOTE.to_le_bytes());
+        header[8..16].copy_from_slice(&120u64.to_le_bytes());
+        header[32..40].copy_from_slice(&(notes.len() as u64).to_le_bytes());
+        header[48..56].copy_from_slice(&4u64.to_le_bytes());
+        image.extend_from_slice(notes);
+        image
+    }
+
+    fn note(kind: u32, name: &[u8], desc: &[u8]) -> Vec<u8> {
+        let mut note = vec![];
+        for word in [name.len() as u32, desc.len() as u32, kind] {
+            note.extend_from_slice(&word.to_le_bytes());
+        }
+        note.extend_from_slice(name);
+        note.resize((note.len() + 3) & !3, 0);
+        note.extend_from_slice(desc);
+        note.resize((note.len() + 3) & !3, 0);
+        note
+    }
+
+    #[test]
+    fn elf_build_ids() {
+        let build_id = SharedLibraryId::GnuBuildId(vec![0xab; 20]);
+        let mut notes = note(1, b"GNU\0", &[0; 16]);
+        notes.extend(note(NT_GNU_BUILD_ID, b"GNU\0", &[0xab; 20]));
+        let mut image = Cursor::new(elf_image(&notes));
+        assert_eq!(verify(&mut image, &build_id), FileMatch::Match);
+
+        let mut rebuilt = Cursor::new(elf_image(&note(NT_GNU_BUILD_ID, b"GNU\0", &[0xcd; 20])));
+        assert_eq!(verify(&mut rebuilt, &build_id), FileMatch::Mismatch);
+        let mut stripped = Cursor::new(elf_image(&[]));
+        assert_eq!(verify(&mut stripped, &build_id), FileMatch::Mismatch);
+        let mut other = Cursor::new(b"MZ".to_vec());
+        assert_eq!(verify(&mut other, &build_id), FileMatch::Unknown);
+    }
+
+    #[test]
+    fn pe_signatures() {
+        let mut image = vec![0; 0x80 + 24 + 60];
+        image[..2].copy_from_slice(&DOS_MAGIC);
+        image[0x3c..0x40].copy_from_slice(&0x80u32.to_le_bytes());
+        image[0x80..0x84].copy_from_slice(&PE_MAGIC);
+        image[0x88..0x8c].copy_from_slice(&0x5ab3_8b4fu32.to_le_bytes());
+        image[0x80 + 80..].copy_from_slice(&0x1c000u32.to_le_bytes());
+
+        let mut image = Cursor::new(image);
+        let id = SharedLibraryId::PeSignature(0x5ab3_8b4f, 0x1c000);
+        assert_eq!(verify(&mut image, &id), FileMatch::Match);
+        let id = SharedLibraryId::PeSignature(0x5ab3_8b4f, 0x1d000);
+        assert_eq!(verify(&mut image, &id), FileMatch::Mismatch);
+        let id = SharedLibraryId::PdbSignature([0; 16], 1);
+        assert_eq!(verify(&mut image, &id), FileMatch::Unknown);
+    }
+
+    #[test]
+    fn macho_uuids_of_slices() {
+        let image = thin_image([1; 16]);
+
+        let mut fat = vec![];
+        for word in [FAT_MAGIC, 1, 0, 0, 0x1000, image.len() as u32, 12] {
+            fat.extend_from_slice(&word.to_be_bytes());
+        }
+        fat.resize(0x1000, 0);
+        fat.extend_from_slice(&image);
+
+        for file in [image, fat] {
+            let mut file = Cursor::new(file);
+            let id = SharedLibraryId::Uuid([1; 16]);
+            assert_eq!(verify(&mut file, &id), FileMatch::Match);
+            let id = SharedLibraryId::Uuid([2; 16]);
+            assert_eq!(verify(&mut file, &id), FileMatch::Mismatch);
+        }
+    }
+
+    #[test]
+    fn loaded_libraries_match() {
+        TargetSharedLibrary::each(|shlib| {
+            let status = shlib.verify_backing_file();
+            assert_ne!(status, FileMatch::Mismatch, "{:?}", shlib.name());
+            if cfg!(target_os = "linux")
+                && shlib.kind() == Some(crate::SharedLibraryKind::Executable)
+                && shlib.id().is_some()
+            {
+                assert_eq!(status, FileMatch::Match);
+            }
+        });
+    }
+}
@@ -0,0 +1,193 @@
+//! Watching for shared libraries being loaded and unloaded by polling.
+//!
+//! Not every platform tells the process when its libraries change, and the
+//! notifications of those that do come from inside the loader, where little
+//! is safe to do. A `ModuleWatcher` instead compares snapshots of the loaded
+//! libraries. Taking a snapshot is cheap while the loader's change counters
+//! stay the same, see `CachedLibraries`, so it can be polled often.
+
+use crate::{Avma, CachedLibraries, OwnedSharedLibrary, SharedLibrary, Snapshot};
+
+use std::ffi::OsStr;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// A change to the set of loaded shared libraries.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ModuleEvent {
+    /// The library was loaded.
+    Loaded(OwnedSharedLibrary),
+    /// The library was unloaded.
+    Unloaded(OwnedSharedLibrary),
+}
+
+impl ModuleEvent {
+    /// Get the library that was loaded or unloaded.
+    pub fn library(&self) -> &OwnedSharedLibrary {
+        match self {
+            ModuleEvent::Loaded(shlib) | ModuleEvent::Unloaded(shlib) => shlib,
+        }
+    }
+}
+
+/// Reports the shared libraries loaded and unloaded since it last looked.
+#[derive(Clone, Debug)]
+pub struct ModuleWatcher {
+    snapshot: Arc<Snapshot>,
+}
+
+impl ModuleWatcher {
+    /// Start watching from the libraries that are loaded right now, which are
+    /// not reported.
+    pub fn new() -> ModuleWatcher {
+        ModuleWatcher {
+            snapshot: CachedLibraries::get(),
+        }
+    }
+
+    /// Get the libraries that were loaded when the watcher last looked.
+    pub fn libraries(&self) -> &[OwnedSharedLibrary] {
+        self.snapshot.libraries()
+    }
+
+    /// Get the libraries loaded and unloaded since the watcher last looked,
+    /// unloaded ones first.
+    ///
+    /// A library that was unloaded and loaded again in between is only
+    /// reported if it came back at a different address.
+    pub fn poll(&mut self) -> Vec<ModuleEvent> {
+        let snapshot = CachedLibraries::get();
+        if Arc::ptr_eq(&snapshot, &self.snapshot) {
+            return vec![];
+        }
+        let events = diff(self.snapshot.libraries(), snapshot.libraries());
+        self.snapshot = snapshot;
+        events
+    }
+
+    /// Poll a new watcher on a thread every `interval`, calling `f` with each
+    /// event.
+    ///
+    /// The thread stops when the returned `WatcherThread` is dropped.
+    pub fn spawn<F>(interval: Duration, mut f: F) -> WatcherThread
+    where
+        F: FnMut(ModuleEvent) + Send + 'static,
+    {
+        let (stop, stopped) = mpsc::channel::<()>();
+        let mut watcher = ModuleWatcher::new();
+        let thread = thread::spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                for event in watcher.poll() {
+                    f(event);
+                }
+            }
+        });
+        WatcherThread {
+            stop: Some(stop),
+            thread: Some(thread),
+        }
+    }
+
+    /// Poll a new watcher on a thread every `interval`, sending the events to
+    /// the returned receiver.
+    ///
+    /// The thread stops when the returned `WatcherThread` is dropped, not when
+    /// the receiver is.
+    pub fn spawn_channel(interval: Duration) -> (WatcherThread, mpsc::Receiver<ModuleEvent>) {
+        let (sender, receiver) = mpsc::channel();
+        let thread = ModuleWatcher::spawn(interval, move |event| {
+            // Events are dropped while nobody is receiving them.
+            let _ = sender.send(event);
+        });
+        (thread, receiver)
+    }
+}
+
+impl Default for ModuleWatcher {
+    fn default() -> Self {
+        ModuleWatcher::new()
+    }
+}
+
+/// A thread polling a `ModuleWatcher`, which is stopped and joined when this
+/// is dropped.
+#[derive(Debug)]
+pub struct WatcherThread {
+    stop: Option<mpsc::Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Drop for WatcherThread {
+    fn drop(&mut self) {
+        drop(self.stop.take());
+        if let Some(thread) = self.thread.take() {
+            // A panic in the callback has already been reported by the
+            // thread.
+            let _ = thread.join();
+        }
+    }
+}
+
+/// What tells a library apart from the one loaded in its place later on.
+fn key(shlib: &OwnedSharedLibrary) -> (Avma, &OsStr) {
+    (shlib.actual_load_addr(), shlib.name())
+}
+
+fn diff(old: &[OwnedSharedLibrary], new: &[OwnedSharedLibrary]) -> Vec<ModuleEvent> {
+    let unloaded = old
+        .iter()
+        .filter(|shlib| !new.iter().any(|other| key(other) == key(shlib)))
+        .map(|shlib| ModuleEvent::Unloaded(shlib.clone()));
+    let loaded = new
+        .iter()
+        .filter(|shlib| !old.iter().any(|other| key(other) == key(shlib)))
+        .map(|shlib| ModuleEvent::Loaded(shlib.clone()));
+    unloaded.chain(loaded).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn diffs() {
+        let snapshot = Snapshot::take();
+        let all = snapshot.libraries();
+        assert!(diff(all, all).is_empty());
+        if all.len() < 2 {
+            return;
+        }
+
+        let (first, rest) = all.split_first().unwrap();
+        let last = &all[all.len() - 1];
+        let events = diff(rest, &all[..all.len() - 1]);
+        assert_eq!(
+            events,
+            [
+                ModuleEvent::Unloaded(last.clone()),
+                ModuleEvent::Loaded(first.clone()),
+            ]
+        );
+        assert_eq!(events[1].library(), first);
+    }
+
+    #[test]
+    fn nothing_changes() {
+        let mut watcher = ModuleWatcher::new();
+        assert_eq!(watcher.libraries().is_empty(), !crate::TARGET_SUPPORTED);
+        let before = watcher.libraries().to_vec();
+        let events = watcher.poll();
+        // Other tests may load libraries in the meantime.
+        if events.is_empty() {
+            assert_eq!(watcher.libraries(), &before[..]);
+        }
+
+        let (thread, events) = ModuleWatcher::spawn_channel(Duration::from_millis(1));
+        thread::sleep(Duration::from_millis(10));
+        drop(thread);
+        // The thread is gone, and with it the sender, so receiving ends.
+        events.iter().for_each(drop);
+    }
+}
@@ -0,0 +1,197 @@
+//! Checking the Authenticode signature of a module's file.
+//!
+//! The signature isn't mapped into memory with the rest of the image, so
+//! this works on the file the module was loaded from. `WinVerifyTrust` says
+//! whether the signature is valid and trusted, and the signer's certificate
+//! is found in the PKCS #7 message embedded in the file.
+
+use std::ffi::{c_void, OsStr};
+use std::mem;
+use std::os::windows::ffi::OsStrExt;
+use std::ptr;
+
+use windows_sys::Win32::Foundation::TRUST_E_NOSIGNATURE;
+use windows_sys::Win32::Security::Cryptography::{
+    CertCloseStore, CertFindCertificateInStore, CertFreeCertificateContext, CertGetNameStringW,
+    CryptMsgClose, CryptMsgGetParam, CryptQueryObject, CERT_FIND_SUBJECT_CERT, CERT_INFO,
+    CERT_NAME_SIMPLE_DISPLAY_TYPE, CERT_QUERY_CONTENT_FLAG_PKCS7_SIGNED_EMBED,
+    CERT_QUERY_FORMAT_FLAG_BINARY, CERT_QUERY_OBJECT_FILE, CMSG_SIGNER_INFO,
+    CMSG_SIGNER_INFO_PARAM, HCERTSTORE, PKCS_7_ASN_ENCODING, X509_ASN_ENCODING,
+};
+use windows_sys::Win32::Security::WinTrust::{
+    WinVerifyTrust, WINTRUST_ACTION_GENERIC_VERIFY_V2, WINTRUST_DATA, WINTRUST_FILE_INFO,
+    WTD_CACHE_ONLY_URL_RETRIEVAL, WTD_CHOICE_FILE, WTD_REVOKE_NONE, WTD_STATEACTION_CLOSE,
+    WTD_STATEACTION_VERIFY, WTD_UI_NONE,
+};
+
+/// The result of checking the Authenticode signature of a module's file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AuthenticodeSignature {
+    status: i32,
+    signer: Option<String>,
+}
+
+impl AuthenticodeSignature {
+    /// Returns `true` if the file has a valid signature from a trusted
+    /// publisher.
+    pub fn is_trusted(&self) -> bool {
+        self.status == 0
+    }
+
+    /// Returns `true` if the file has an embedded signature, whether or not
+    /// it is trusted.
+    ///
+    /// Many system files are instead signed through a catalog, which this
+    /// does not consult.
+    pub fn is_signed(&self) -> bool {
+        self.status != TRUST_E_NOSIGNATURE
+    }
+
+    /// Get the result of `WinVerifyTrust`, which is zero for a trusted
+    /// signature and otherwise an `HRESULT` such as `TRUST_E_NOSIGNATURE`.
+    pub fn status(&self) -> i32 {
+        self.status
+    }
+
+    /// Get the subject name of the signer's certificate, e.g. `Microsoft
+    /// Windows`.
+    pub fn signer(&self) -> Option<&str> {
+        self.signer.as_deref()
+    }
+}
+
+/// Check the signature of the file at `path`.
+///
+/// Revocation isn't checked, so that this doesn't go to the network.
+pub(crate) fn verify(path: &OsStr) -> AuthenticodeSignature {
+    let path: Vec<u16> = path.encode_wide().chain(Some(0)).collect();
+    let status = unsafe { win_verify_trust(&path) };
+    let signer = if status == TRUST_E_NOSIGNATURE {
+        None
+    } else {
+        unsafe { signer(&path) }
+    };
+    AuthenticodeSignature { status, signer }
+}
+
+unsafe fn win_verify_trust(path: &[u16]) -> i32 {
+    let mut file_info = WINTRUST_FILE_INFO {
+        cbStruct: mem::size_of::<WINTRUST_FILE_INFO>() as u32,
+        pcwszFilePath: path.as_ptr(),
+        hFile: ptr::null_mut(),
+        pgKnownSubject: ptr::null_mut(),
+    };
+    let mut data: WINTRUST_DATA = mem::zeroed();
+    data.cbStruct = mem::size_of::<WINTRUST_DATA>() as u32;
+    data.dwUIChoice = WTD_UI_NONE;
+    data.fdwRevocationChecks = WTD_REVOKE_NONE;
+    data.dwUnionChoice = WTD_CHOICE_FILE;
+    data.Anonymous.pFile = &mut file_info;
+    data.dwStateAction = WTD_STATEACTION_VERIFY;
+    data.dwProvFlags = WTD_CACHE_ONLY_URL_RETRIEVAL;
+
+    let mut action = WINTRUST_ACTION_GENERIC_VERIFY_V2;
+    let status = WinVerifyTrust(
+        ptr::null_mut(),
+        &mut action,
+        &mut data as *mut WINTRUST_DATA as *mut _,
+    );
+
+    data.dwStateAction = WTD_STATEACTION_CLOSE;
+    WinVerifyTrust(
+        ptr::null_mut(),
+        &mut action,
+        &mut data as *mut WINTRUST_DATA as *mut _,
+    );
+    status
+}
+
+/// Get the subject of the certificate that signed the file at `path`.
+unsafe fn signer(path: &[u16]) -> Option<String> {
+    let mut encoding = 0;
+    let mut content_type = 0;
+    let mut format_type = 0;
+    let mut store: HCERTSTORE = ptr::null_mut();
+    let mut msg: *mut c_void = ptr::null_mut();
+    if CryptQueryObject(
+        CERT_QUERY_OBJECT_FILE,
+        path.as_ptr() as *const _,
+        CERT_QUERY_CONTENT_FLAG_PKCS7_SIGNED_EMBED,
+        CERT_QUERY_FORMAT_FLAG_BINARY,
+        0,
+        &mut encoding,
+        &mut content_type,
+        &mut format_type,
+        &mut store,
+        &mut msg,
+        ptr::null_mut(),
+    ) == 0
+    {
+        return None;
+    }
+
+    let name = signer_name(store, msg);
+    CertCloseStore(store, 0);
+    CryptMsgClose(msg);
+    name
+}
+
+unsafe fn signer_name(store: HCERTSTORE, msg: *mut c_void) -> Option<String> {
+    let mut size = 0;
+    if CryptMsgGetParam(msg, CMSG_SIGNER_INFO_PARAM, 0, ptr::null_mut(), &mut size) == 0 {
+        return None;
+    }
+    // Keep the signer info suitably aligned.
+    let mut buffer = vec![0u64; (size as usize).div_ceil(8)];
+    if CryptMsgGetParam(
+        msg,
+        CMSG_SIGNER_INFO_PARAM,
+        0,
+        buffer.as_mut_ptr() as *mut _,
+        &mut size,
+    ) == 0
+    {
+        return None;
+    }
+    let signer_info = &*(buffer.as_ptr() as *const CMSG_SIGNER_INFO);
+
+    let mut cert_info: CERT_INFO = mem::zeroed();
+    cert_info.Issuer = signer_info.Issuer;
+    cert_info.SerialNumber = signer_info.SerialNumber;
+    let cert = CertFindCertificateInStore(
+        store,
+        X509_ASN_ENCODING | PKCS_7_ASN_ENCODING,
+        0,
+        CERT_FIND_SUBJECT_CERT,
+        &cert_info as *const CERT_INFO as *const _,
+        ptr::null(),
+    );
+    if cert.is_null() {
+        return None;
+    }
+
+    let len = CertGetNameStringW(
+        cert,
+        CERT_NAME_SIMPLE_DISPLAY_TYPE,
+        0,
+        ptr::null_mut(),
+        ptr::null_mut(),
+        0,
+    );
+    let mut name = vec![0u16; len as usize];
+    let len = CertGetNameStringW(
+        cert,
+        CERT_NAME_SIMPLE_DISPLAY_TYPE,
+        0,
+        ptr::null_mut(),
+        name.as_mut_ptr(),
+        len,
+    );
+    CertFreeCertificateContext(cert);
+
+    // The length includes the terminating NUL, and is 1 for no name.
+    if len <= 1 {
+        return None;
+    }
+    Some(String::from_utf16_lossy(&name[..len as usize - 1]))
+}
@@ -0,0 +1,110 @@
+//! The debug directories of a PE image.
+//!
+//! The debug directory is an array of `IMAGE_DEBUG_DIRECTORY` entries, each
+//! describing a blob of debug data of some type: the CodeView record that
+//! points at the PDB, but also POGO and ILTCG records, the hash of a
+//! reproducible build, an embedded portable PDB and so on. The data of most
+//! entries is mapped with the image, but some are only in the file.
+
+use std::slice;
+
+use windows_sys::Win32::System::Diagnostics::Debug::IMAGE_DEBUG_DIRECTORY;
+
+/// An entry of a module's debug directory.
+#[derive(Clone, Copy)]
+pub struct DebugDirectory<'a> {
+    directory: &'a IMAGE_DEBUG_DIRECTORY,
+    image: &'a [u8],
+}
+
+impl<'a> DebugDirectory<'a> {
+    /// Get the `IMAGE_DEBUG_TYPE_*` type of the entry, e.g.
+    /// `IMAGE_DEBUG_TYPE_CODEVIEW` or `IMAGE_DEBUG_TYPE_POGO`.
+    pub fn debug_type(&self) -> u32 {
+        self.directory.Type
+    }
+
+    /// Get the timestamp of the entry, which usually matches the image's.
+    pub fn timestamp(&self) -> u32 {
+        self.directory.TimeDateStamp
+    }
+
+    /// Get the major version of the format of the data.
+    pub fn major_version(&self) -> u16 {
+        self.directory.MajorVersion
+    }
+
+    /// Get the minor version of the format of the data.
+    pub fn minor_version(&self) -> u16 {
+        self.directory.MinorVersion
+    }
+
+    /// Get the size of the data in bytes.
+    pub fn size(&self) -> u32 {
+        self.directory.SizeOfData
+    }
+
+    /// Get the RVA of the data, or zero if it isn't mapped with the image.
+    pub fn address_of_raw_data(&self) -> u32 {
+        self.directory.AddressOfRawData
+    }
+
+    /// Get the offset of the data in the module's file.
+    pub fn pointer_to_raw_data(&self) -> u32 {
+        self.directory.PointerToRawData
+    }
+
+    /// Get the data, if it is mapped with the image.
+    pub fn data(&self) -> Option<&'a [u8]> {
+        let start = self.directory.AddressOfRawData as usize;
+        if start == 0 {
+            return None;
+        }
+        self.image
+            .get(start..start.checked_add(self.directory.SizeOfData as usize)?)
+    }
+}
+
+impl<'a> std::fmt::Debug for DebugDirectory<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DebugDirectory")
+            .field("debug_type", &self.debug_type())
+            .field("size", &self.size())
+            .field("address_of_raw_data", &self.address_of_raw_data())
+            .finish()
+    }
+}
+
+/// An iterator over the entries of a module's debug directory.
+#[derive(Clone)]
+pub struct DebugDirectoryIter<'a> {
+    directories: slice::Iter<'a, IMAGE_DEBUG_DIRECTORY>,
+    image: &'a [u8],
+}
+
+impl<'a> DebugDirectoryIter<'a> {
+    pub(crate) fn new(directories: &'a [IMAGE_DEBUG_DIRECTORY], image: &'a [u8]) -> Self {
+        DebugDirectoryIter {
+            directories: directories.iter(),
+            image,
+        }
+    }
+}
+
+impl<'a> Iterator for DebugDirectoryIter<'a> {
+    type Item = DebugDirectory<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let directory = self.directories.next()?;
+        Some(DebugDirectory {
+            directory,
+            image: self.image,
+        })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.directories.size_hint()
+    }
+}
+
+impl<'a> ExactSizeIterator for DebugDirectoryIter<'a> {}
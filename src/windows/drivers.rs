@@ -0,0 +1,80 @@
+//! Enumerating the kernel drivers loaded in the system.
+//!
+//! Kernel modules can't be read from user mode, so all that is known about
+//! them is their base address and the path they were loaded from.
+
+use super::{paths, read_path};
+use crate::{Avma, IterationControl};
+
+use std::ffi::{c_void, OsStr, OsString};
+use std::io;
+use std::mem;
+use std::os::windows::ffi::OsStringExt;
+use std::ptr;
+
+use windows_sys::Win32::System::ProcessStatus::{EnumDeviceDrivers, GetDeviceDriverFileNameW};
+
+/// A kernel driver loaded in the system.
+#[derive(Clone, Debug)]
+pub struct Driver {
+    base: Avma,
+    path: OsString,
+}
+
+impl Driver {
+    /// Get the address the driver is loaded at in kernel space.
+    ///
+    /// Without `SeDebugPrivilege` this is zero on recent versions of Windows.
+    pub fn base(&self) -> Avma {
+        self.base
+    }
+
+    /// Get the path of the driver, e.g. `C:\Windows\system32\ntoskrnl.exe`.
+    pub fn path(&self) -> &OsStr {
+        &self.path
+    }
+}
+
+/// Iterate over the kernel drivers loaded in the system, starting with the
+/// kernel itself.
+pub fn each_driver<F, C>(mut f: F) -> io::Result<()>
+where
+    F: FnMut(&Driver) -> C,
+    C: Into<IterationControl>,
+{
+    let bases = unsafe { driver_bases()? };
+    for base in bases {
+        let path = read_path(|buffer| unsafe {
+            GetDeviceDriverFileNameW(base, buffer.as_mut_ptr(), buffer.len() as u32)
+        })
+        .unwrap_or_default();
+        let driver = Driver {
+            base: Avma(base as usize),
+            path: OsString::from_wide(&paths::win32_path(&path)),
+        };
+        match f(&driver).into() {
+            IterationControl::Break => break,
+            IterationControl::Continue => continue,
+        }
+    }
+    Ok(())
+}
+
+unsafe fn driver_bases() -> io::Result<Vec<*mut c_void>> {
+    let mut size = 0;
+    if EnumDeviceDrivers(ptr::null_mut(), 0, &mut size) == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    loop {
+        // Leave room for drivers loaded in the meantime.
+        let mut bases = vec![ptr::null_mut(); size as usize / mem::size_of::<*mut c_void>() + 16];
+        let capacity = (bases.len() * mem::size_of::<*mut c_void>()) as u32;
+        if EnumDeviceDrivers(bases.as_mut_ptr(), capacity, &mut size) == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        if size <= capacity {
+            bases.truncate(size as usize / mem::size_of::<*mut c_void>());
+            return Ok(bases);
+        }
+    }
+}
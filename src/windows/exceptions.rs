@@ -0,0 +1,148 @@
+//! Walking the exception directory of a PE image.
+//!
+//! On x64 and ARM the exception directory, usually the `.pdata` section, is
+//! an array of `RUNTIME_FUNCTION` entries sorted by address, one per function
+//! that needs unwinding. On x64 each entry has the function's start and end
+//! and the RVA of its `UNWIND_INFO`; on ARM and ARM64 there is no end, and
+//! the second word is either the RVA of an `.xdata` record or, if its low
+//! bits are set, the unwind data packed into the word itself.
+
+use crate::verify::u32_at;
+
+/// An entry of the exception directory, describing how to unwind out of a
+/// function.
+///
+/// Addresses are RVAs, i.e. relative to the base of the module.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct RuntimeFunction {
+    begin_address: u32,
+    end_address: Option<u32>,
+    unwind_data: u32,
+}
+
+impl RuntimeFunction {
+    /// Get the RVA of the start of the function.
+    pub fn begin_address(&self) -> u32 {
+        self.begin_address
+    }
+
+    /// Get the RVA just past the end of the function.
+    ///
+    /// Only x64 entries record this; on ARM the length of the function is
+    /// part of its unwind data.
+    pub fn end_address(&self) -> Option<u32> {
+        self.end_address
+    }
+
+    /// Get the second word of the entry: the RVA of the `UNWIND_INFO` on
+    /// x64, and either the RVA of the `.xdata` record or the packed unwind
+    /// data on ARM.
+    pub fn unwind_data(&self) -> u32 {
+        self.unwind_data
+    }
+}
+
+/// An iterator over the entries of an exception directory.
+#[derive(Clone, Debug)]
+pub struct RuntimeFunctionIter<'a> {
+    data: &'a [u8],
+    entry_size: usize,
+}
+
+impl<'a> RuntimeFunctionIter<'a> {
+    /// An iterator over no entries.
+    pub(crate) fn empty() -> RuntimeFunctionIter<'a> {
+        RuntimeFunctionIter {
+            data: &[],
+            entry_size: 8,
+        }
+    }
+
+    /// Walk the exception directory `data`, whose entries have the x64
+    /// layout if `has_end_address` is set and the ARM layout otherwise.
+    pub(crate) fn new(data: &'a [u8], has_end_address: bool) -> Self {
+        RuntimeFunctionIter {
+            data,
+            entry_size: if has_end_address { 12 } else { 8 },
+        }
+    }
+}
+
+impl<'a> Iterator for RuntimeFunctionIter<'a> {
+    type Item = RuntimeFunction;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.data.len() < self.entry_size {
+            self.data = &[];
+            return None;
+        }
+        let (entry, rest) = self.data.split_at(self.entry_size);
+        self.data = rest;
+
+        let begin_address = u32_at(entry, 0, true)?;
+        let function = if self.entry_size == 12 {
+            RuntimeFunction {
+                begin_address,
+                end_address: u32_at(entry, 4, true),
+                unwind_data: u32_at(entry, 8, true)?,
+            }
+        } else {
+            RuntimeFunction {
+                begin_address,
+                end_address: None,
+                unwind_data: u32_at(entry, 4, true)?,
+            }
+        };
+        Some(function)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.data.len() / self.entry_size;
+        (len, Some(len))
+    }
+}
+
+impl<'a> ExactSizeIterator for RuntimeFunctionIter<'a> {}
+
+#[cfg(test)]
+mod tests {
+    use super::{RuntimeFunction, RuntimeFunctionIter};
+
+    fn words(words: &[u32]) -> Vec<u8> {
+        words.iter().flat_map(|word| word.to_le_bytes()).collect()
+    }
+
+    #[test]
+    fn x64_entries() {
+        // A trailing partial entry is ignored.
+        let data = words(&[0x1000, 0x1040, 0x3000, 0x1040, 0x1100, 0x3008, 0x2000]);
+        let functions = RuntimeFunctionIter::new(&data, true);
+        assert_eq!(functions.len(), 2);
+        assert_eq!(
+            functions.collect::<Vec<_>>(),
+            [
+                RuntimeFunction {
+                    begin_address: 0x1000,
+                    end_address: Some(0x1040),
+                    unwind_data: 0x3000,
+                },
+                RuntimeFunction {
+                    begin_address: 0x1040,
+                    end_address: Some(0x1100),
+                    unwind_data: 0x3008,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn arm_entries() {
+        let data = words(&[0x1000, 0x0020_0011, 0x1040, 0x3000]);
+        let functions: Vec<_> = RuntimeFunctionIter::new(&data, false).collect();
+        assert_eq!(functions.len(), 2);
+        assert_eq!(functions[0].end_address(), None);
+        assert_eq!(functions[0].unwind_data(), 0x0020_0011);
+        assert_eq!(functions[1].begin_address(), 0x1040);
+        assert_eq!(RuntimeFunctionIter::empty().count(), 0);
+    }
+}
@@ -0,0 +1,209 @@
+//! Walking the export directory of a PE image.
+//!
+//! The export directory has a table of function RVAs indexed by ordinal
+//! (minus the ordinal base), and a separate table of names, each paired with
+//! the index of the function it names. An RVA that points back into the
+//! export directory is a forwarder string such as `NTDLL.RtlAllocateHeap`
+//! rather than code.
+
+use crate::verify::{u16_at, u32_at};
+use crate::Avma;
+
+use std::mem;
+use std::ops::Range;
+use std::ptr;
+
+use windows_sys::Win32::System::SystemServices::IMAGE_EXPORT_DIRECTORY;
+
+// Bounds the number of exports in case of a corrupt directory.
+const MAX_EXPORTS: u32 = 1 << 20;
+
+/// A function or variable exported by a PE image.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ExportedSymbol {
+    name: Option<String>,
+    ordinal: u32,
+    address: Option<Avma>,
+    forwarder: Option<String>,
+}
+
+impl ExportedSymbol {
+    /// Get the name of the export, if it is exported by name and not only by
+    /// ordinal.
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// Get the ordinal of the export.
+    pub fn ordinal(&self) -> u32 {
+        self.ordinal
+    }
+
+    /// Get the address of the export.
+    ///
+    /// This is `None` for forwarded exports.
+    pub fn address(&self) -> Option<Avma> {
+        self.address
+    }
+
+    /// If this export is forwarded to another module, get where to, e.g.
+    /// `NTDLL.RtlAllocateHeap` or `NTDLL.#12`.
+    pub fn forwarder(&self) -> Option<&str> {
+        self.forwarder.as_deref()
+    }
+}
+
+fn read_cstr(image: &[u8], offset: usize) -> Option<String> {
+    let data = image.get(offset..)?;
+    let len = data.iter().position(|b| *b == 0)?;
+    Some(String::from_utf8_lossy(&data[..len]).into_owned())
+}
+
+/// An iterator over the exports of a PE image, in ordinal order.
+#[derive(Debug)]
+pub struct ExportIter<'a> {
+    image: &'a [u8],
+    base: usize,
+    directory_range: Range<usize>,
+    ordinal_base: u32,
+    functions: usize,
+    count: u32,
+    index: u32,
+    // The name RVA of each function, by index.
+    names: Vec<Option<u32>>,
+}
+
+impl<'a> ExportIter<'a> {
+    /// An iterator over no exports.
+    pub(crate) fn empty() -> ExportIter<'a> {
+        ExportIter {
+            image: &[],
+            base: 0,
+            directory_range: 0..0,
+            ordinal_base: 0,
+            functions: 0,
+            count: 0,
+            index: 0,
+            names: vec![],
+        }
+    }
+
+    /// Walk the export directory covering `directory_range` of `image`,
+    /// which is mapped at `base`.
+    pub(crate) fn new(image: &'a [u8], base: usize, directory_range: Range<usize>) -> Self {
+        let directory = match image.get(directory_range.start..) {
+            Some(data) if data.len() >= mem::size_of::<IMAGE_EXPORT_DIRECTORY>() => unsafe {
+                ptr::read_unaligned(data.as_ptr() as *const IMAGE_EXPORT_DIRECTORY)
+            },
+            _ => return ExportIter::empty(),
+        };
+
+        let count = directory.NumberOfFunctions.min(MAX_EXPORTS);
+        let mut names = vec![None; count as usize];
+        for index in 0..directory.NumberOfNames.min(MAX_EXPORTS) as usize {
+            let name = u32_at(image, directory.AddressOfNames as usize + index * 4, true);
+            let ordinal = u16_at(
+                image,
+                directory.AddressOfNameOrdinals as usize + index * 2,
+                true,
+            );
+            if let (Some(name), Some(ordinal)) = (name, ordinal) {
+                if let Some(slot) = names.get_mut(ordinal as usize) {
+                    *slot = Some(name);
+                }
+            }
+        }
+
+        ExportIter {
+            image,
+            base,
+            directory_range,
+            ordinal_base: directory.Base,
+            functions: directory.AddressOfFunctions as usize,
+            count,
+            index: 0,
+            names,
+        }
+    }
+}
+
+impl<'a> Iterator for ExportIter<'a> {
+    type Item = ExportedSymbol;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.index < self.count {
+            let index = self.index;
+            self.index += 1;
+
+            let rva = match u32_at(self.image, self.functions + index as usize * 4, true) {
+                Some(rva) => rva as usize,
+                None => break,
+            };
+            // Unused ordinals have no function.
+            if rva == 0 {
+                continue;
+            }
+
+            let name =
+                self.names[index as usize].and_then(|name| read_cstr(self.image, name as usize));
+            let (address, forwarder) = if self.directory_range.contains(&rva) {
+                (None, read_cstr(self.image, rva))
+            } else {
+                (Some(Avma(self.base.wrapping_add(rva))), None)
+            };
+            return Some(ExportedSymbol {
+                name,
+                ordinal: self.ordinal_base.wrapping_add(index),
+                address,
+                forwarder,
+            });
+        }
+
+        self.index = self.count;
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ExportIter;
+    use crate::Avma;
+
+    #[test]
+    fn walk_exports() {
+        let mut image = vec![0u8; 0x200];
+        let put = |image: &mut Vec<u8>, offset: usize, value: u32| {
+            image[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+        };
+
+        // The directory at 0x40, with ordinal base 5 and three functions, the
+        // second of which is unused and the third forwarded.
+        put(&mut image, 0x40 + 16, 5); // Base
+        put(&mut image, 0x40 + 20, 3); // NumberOfFunctions
+        put(&mut image, 0x40 + 24, 1); // NumberOfNames
+        put(&mut image, 0x40 + 28, 0x80); // AddressOfFunctions
+        put(&mut image, 0x40 + 32, 0x90); // AddressOfNames
+        put(&mut image, 0x40 + 36, 0x98); // AddressOfNameOrdinals
+
+        put(&mut image, 0x80, 0x1000);
+        put(&mut image, 0x88, 0xb0);
+        put(&mut image, 0x90, 0xa0);
+        image[0xa0..0xa4].copy_from_slice(b"foo\0");
+        image[0xb0..0xbb].copy_from_slice(b"NTDLL.bar\0\0");
+
+        let exports: Vec<_> = ExportIter::new(&image, 0x10000, 0x40..0xc0).collect();
+        assert_eq!(exports.len(), 2);
+        assert_eq!(exports[0].name(), Some("foo"));
+        assert_eq!(exports[0].ordinal(), 5);
+        assert_eq!(exports[0].address(), Some(Avma(0x11000)));
+        assert_eq!(exports[1].name(), None);
+        assert_eq!(exports[1].ordinal(), 7);
+        assert_eq!(exports[1].address(), None);
+        assert_eq!(exports[1].forwarder(), Some("NTDLL.bar"));
+    }
+
+    #[test]
+    fn truncated_directory() {
+        assert_eq!(ExportIter::new(&[0; 16], 0, 0..16).count(), 0);
+    }
+}
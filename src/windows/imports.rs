@@ -0,0 +1,241 @@
+//! Walking the import directory of a PE image.
+//!
+//! The import directory is a zero-terminated array of descriptors, one per
+//! imported DLL. Each points at the DLL's name and at its import name table,
+//! a zero-terminated array of thunks that either hold an ordinal or the RVA
+//! of a hint and a name. The import address table that the loader fills in
+//! has the same layout, but by the time we look at it the thunks have been
+//! overwritten with the imported addresses.
+
+use crate::verify::{u16_at, u32_at};
+
+use std::str;
+
+// The size of an `IMAGE_IMPORT_DESCRIPTOR`.
+const DESCRIPTOR_SIZE: usize = 20;
+
+// Bounds the number of entries in case of a corrupt directory.
+const MAX_IMPORTS: usize = 1 << 16;
+
+fn read_str(image: &[u8], offset: usize) -> Option<&str> {
+    let data = image.get(offset..)?;
+    let len = data.iter().position(|b| *b == 0)?;
+    str::from_utf8(&data[..len]).ok()
+}
+
+fn read_thunk(image: &[u8], offset: usize, is_64: bool) -> Option<u64> {
+    if is_64 {
+        let low = u32_at(image, offset, true)?;
+        let high = u32_at(image, offset.checked_add(4)?, true)?;
+        Some(u64::from(high) << 32 | u64::from(low))
+    } else {
+        u32_at(image, offset, true).map(u64::from)
+    }
+}
+
+/// A function imported from another module.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImportedFunction<'a> {
+    /// Imported by name, with a hint of where it is in the other module's
+    /// export name table.
+    Name {
+        /// The index the exporting module's name table is first searched at.
+        hint: u16,
+        /// The name of the function.
+        name: &'a str,
+    },
+    /// Imported by ordinal only.
+    Ordinal(u16),
+}
+
+/// A module that a PE image imports functions from.
+#[derive(Clone, Copy, Debug)]
+pub struct ImportedModule<'a> {
+    image: &'a [u8],
+    name: &'a str,
+    name_table: usize,
+    is_64: bool,
+}
+
+impl<'a> ImportedModule<'a> {
+    /// Get the name of the imported module, e.g. `KERNEL32.dll`.
+    pub fn name(&self) -> &'a str {
+        self.name
+    }
+
+    /// Iterate over the functions imported from this module.
+    ///
+    /// This is empty if the image was linked without an import name table,
+    /// as the names in the import address table have been replaced with the
+    /// imported addresses.
+    pub fn functions(&self) -> ImportedFunctionIter<'a> {
+        ImportedFunctionIter {
+            image: self.image,
+            offset: self.name_table,
+            is_64: self.is_64,
+            remaining: if self.name_table == 0 { 0 } else { MAX_IMPORTS },
+        }
+    }
+}
+
+/// An iterator over the functions imported from a module.
+#[derive(Clone, Debug)]
+pub struct ImportedFunctionIter<'a> {
+    image: &'a [u8],
+    offset: usize,
+    is_64: bool,
+    remaining: usize,
+}
+
+impl<'a> Iterator for ImportedFunctionIter<'a> {
+    type Item = ImportedFunction<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.remaining > 0 {
+            self.remaining -= 1;
+            let thunk = match read_thunk(self.image, self.offset, self.is_64) {
+                Some(thunk) if thunk != 0 => thunk,
+                _ => break,
+            };
+            self.offset += if self.is_64 { 8 } else { 4 };
+
+            let ordinal_flag = if self.is_64 { 1 << 63 } else { 1 << 31 };
+            if thunk & ordinal_flag != 0 {
+                return Some(ImportedFunction::Ordinal(thunk as u16));
+            }
+            let rva = (thunk & 0x7fff_ffff) as usize;
+            let hint = match u16_at(self.image, rva, true) {
+                Some(hint) => hint,
+                None => continue,
+            };
+            if let Some(name) = read_str(self.image, rva + 2) {
+                return Some(ImportedFunction::Name { hint, name });
+            }
+        }
+
+        self.remaining = 0;
+        None
+    }
+}
+
+/// An iterator over the modules a PE image imports from.
+#[derive(Clone, Debug)]
+pub struct ImportIter<'a> {
+    image: &'a [u8],
+    offset: usize,
+    is_64: bool,
+    remaining: usize,
+}
+
+impl<'a> ImportIter<'a> {
+    /// An iterator over no imports.
+    pub(crate) fn empty() -> ImportIter<'a> {
+        ImportIter {
+            image: &[],
+            offset: 0,
+            is_64: false,
+            remaining: 0,
+        }
+    }
+
+    /// Walk the import directory at `offset` of `image`.
+    pub(crate) fn new(image: &'a [u8], offset: usize, is_64: bool) -> Self {
+        ImportIter {
+            image,
+            offset,
+            is_64,
+            remaining: MAX_IMPORTS,
+        }
+    }
+}
+
+impl<'a> Iterator for ImportIter<'a> {
+    type Item = ImportedModule<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.remaining > 0 {
+            self.remaining -= 1;
+            let descriptor = self.offset;
+            self.offset += DESCRIPTOR_SIZE;
+
+            // `OriginalFirstThunk` and `Name`; the directory ends with an
+            // all-zero descriptor.
+            let (name_table, name) = match (
+                u32_at(self.image, descriptor, true),
+                u32_at(self.image, descriptor + 12, true),
+            ) {
+                (Some(name_table), Some(name)) if name != 0 => (name_table, name),
+                _ => break,
+            };
+            if let Some(name) = read_str(self.image, name as usize) {
+                return Some(ImportedModule {
+                    image: self.image,
+                    name,
+                    name_table: name_table as usize,
+                    is_64: self.is_64,
+                });
+            }
+        }
+
+        self.remaining = 0;
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ImportIter, ImportedFunction};
+
+    fn put(image: &mut [u8], offset: usize, value: u32) {
+        image[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+    }
+
+    #[test]
+    fn walk_imports() {
+        let mut image = vec![0u8; 0x200];
+
+        // Two descriptors at 0x40, followed by the terminating one.
+        put(&mut image, 0x40, 0x100); // OriginalFirstThunk
+        put(&mut image, 0x40 + 12, 0x80); // Name
+        put(&mut image, 0x54 + 12, 0x90); // Name, without a name table
+        image[0x80..0x8d].copy_from_slice(b"KERNEL32.dll\0");
+        image[0x90..0x9b].copy_from_slice(b"USER32.dll\0");
+
+        // A name and an ordinal.
+        image[0x100..0x108].copy_from_slice(&0x120u64.to_le_bytes());
+        image[0x108..0x110].copy_from_slice(&(1u64 << 63 | 7).to_le_bytes());
+        image[0x120..0x122].copy_from_slice(&3u16.to_le_bytes());
+        image[0x122..0x12c].copy_from_slice(b"ExitThread");
+
+        let modules: Vec<_> = ImportIter::new(&image, 0x40, true).collect();
+        assert_eq!(modules.len(), 2);
+        assert_eq!(modules[0].name(), "KERNEL32.dll");
+        assert_eq!(
+            modules[0].functions().collect::<Vec<_>>(),
+            [
+                ImportedFunction::Name {
+                    hint: 3,
+                    name: "ExitThread"
+                },
+                ImportedFunction::Ordinal(7),
+            ]
+        );
+        assert_eq!(modules[1].name(), "USER32.dll");
+        assert_eq!(modules[1].functions().count(), 0);
+    }
+
+    #[test]
+    fn pe32_thunks() {
+        let mut image = vec![0u8; 0x100];
+        put(&mut image, 0, 0x40);
+        put(&mut image, 12, 0x60);
+        image[0x60..0x62].copy_from_slice(b"a\0");
+        put(&mut image, 0x40, 1 << 31 | 2);
+
+        let module = ImportIter::new(&image, 0, false).next().unwrap();
+        assert_eq!(
+            module.functions().collect::<Vec<_>>(),
+            [ImportedFunction::Ordinal(2)]
+        );
+    }
+}
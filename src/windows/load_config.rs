@@ -0,0 +1,155 @@
+//! Reading the exploit mitigations a PE image opts into.
+//!
+//! Most of these are recorded in the load config directory, whose layout
+//! differs between PE32 and PE32+ and which has grown over time: its first
+//! field is its size, and fields past that size are absent. CET
+//! compatibility is instead recorded in an extended DLL characteristics
+//! debug directory.
+
+use super::DllCharacteristics;
+use crate::verify::u32_at;
+
+use windows_sys::Win32::System::Diagnostics::Debug::IMAGE_DLLCHARACTERISTICS_NO_SEH;
+use windows_sys::Win32::System::SystemServices::IMAGE_GUARD_CF_INSTRUMENTED;
+
+const IMAGE_DLLCHARACTERISTICS_EX_CET_COMPAT: u32 = 0x01;
+
+// Offsets of fields in `IMAGE_LOAD_CONFIG_DIRECTORY32` and
+// `IMAGE_LOAD_CONFIG_DIRECTORY64`.
+const SE_HANDLER_COUNT_32: usize = 68;
+const GUARD_FLAGS_32: usize = 88;
+const GUARD_FLAGS_64: usize = 144;
+
+/// The exploit mitigations a module was built with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Mitigations {
+    dll_characteristics: DllCharacteristics,
+    dll_characteristics_ex: u32,
+    guard_flags: u32,
+    safe_seh: Option<bool>,
+}
+
+impl Mitigations {
+    /// Get the DLL characteristics of the optional header, which cover ASLR
+    /// and DEP.
+    pub fn dll_characteristics(&self) -> DllCharacteristics {
+        self.dll_characteristics
+    }
+
+    /// Get the raw `IMAGE_GUARD_*` flags of the load config directory, or
+    /// zero if there are none.
+    pub fn guard_flags(&self) -> u32 {
+        self.guard_flags
+    }
+
+    /// Returns `true` if the module was built with Control Flow Guard
+    /// instrumentation.
+    pub fn has_cfg(&self) -> bool {
+        self.dll_characteristics.is_guard_cf()
+            && self.guard_flags & IMAGE_GUARD_CF_INSTRUMENTED != 0
+    }
+
+    /// Returns `true` if the module is compatible with CET shadow stacks.
+    pub fn is_cet_compatible(&self) -> bool {
+        self.dll_characteristics_ex & IMAGE_DLLCHARACTERISTICS_EX_CET_COMPAT != 0
+    }
+
+    /// Returns whether the module only has registered exception handlers,
+    /// either through a SafeSEH handler table or by having no handlers at
+    /// all.
+    ///
+    /// SafeSEH only exists for x86; other architectures use table-based
+    /// exception handling and get `None`.
+    pub fn has_safe_seh(&self) -> Option<bool> {
+        self.safe_seh
+    }
+}
+
+/// Read the mitigations from the load config directory in `load_config`
+/// (empty if there is none) and the extended DLL characteristics.
+pub(crate) fn parse(
+    load_config: &[u8],
+    is_64: bool,
+    is_x86: bool,
+    dll_characteristics: DllCharacteristics,
+    dll_characteristics_ex: u32,
+) -> Mitigations {
+    // Only fields within the directory's own idea of its size exist.
+    let size = u32_at(load_config, 0, true).map_or(0, |size| size as usize);
+    let load_config = &load_config[..size.min(load_config.len())];
+
+    let guard_flags = if is_64 {
+        GUARD_FLAGS_64
+    } else {
+        GUARD_FLAGS_32
+    };
+    let guard_flags = u32_at(load_config, guard_flags, true).unwrap_or(0);
+
+    let safe_seh = if is_x86 {
+        let no_seh = dll_characteristics.bits() & IMAGE_DLLCHARACTERISTICS_NO_SEH != 0;
+        let handlers = u32_at(load_config, SE_HANDLER_COUNT_32, true).unwrap_or(0);
+        Some(no_seh || handlers != 0)
+    } else {
+        None
+    };
+
+    Mitigations {
+        dll_characteristics,
+        dll_characteristics_ex,
+        guard_flags,
+        safe_seh,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use windows_sys::Win32::System::Diagnostics::Debug::IMAGE_DLLCHARACTERISTICS_GUARD_CF;
+
+    fn load_config(size: usize, fields: &[(usize, u32)]) -> Vec<u8> {
+        let mut data = vec![0; size];
+        data[..4].copy_from_slice(&(size as u32).to_le_bytes());
+        for &(offset, value) in fields {
+            data[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+        }
+        data
+    }
+
+    #[test]
+    fn cfg_and_cet() {
+        let data = load_config(0x100, &[(GUARD_FLAGS_64, IMAGE_GUARD_CF_INSTRUMENTED)]);
+        let characteristics = DllCharacteristics(IMAGE_DLLCHARACTERISTICS_GUARD_CF);
+        let mitigations = parse(&data, true, false, characteristics, 1);
+        assert!(mitigations.has_cfg());
+        assert!(mitigations.is_cet_compatible());
+        assert_eq!(mitigations.has_safe_seh(), None);
+
+        let mitigations = parse(&[], true, false, characteristics, 0);
+        assert!(!mitigations.has_cfg());
+        assert!(!mitigations.is_cet_compatible());
+    }
+
+    #[test]
+    fn safe_seh() {
+        let none = DllCharacteristics(0);
+        let data = load_config(0x48, &[(SE_HANDLER_COUNT_32, 3)]);
+        assert_eq!(
+            parse(&data, false, true, none, 0).has_safe_seh(),
+            Some(true)
+        );
+
+        // The handler count is past the end of this older directory.
+        let mut data = load_config(0x48, &[(SE_HANDLER_COUNT_32, 3)]);
+        data[..4].copy_from_slice(&0x40u32.to_le_bytes());
+        assert_eq!(
+            parse(&data, false, true, none, 0).has_safe_seh(),
+            Some(false)
+        );
+
+        let no_seh = DllCharacteristics(IMAGE_DLLCHARACTERISTICS_NO_SEH);
+        assert_eq!(
+            parse(&[], false, true, no_seh, 0).has_safe_seh(),
+            Some(true)
+        );
+    }
+}
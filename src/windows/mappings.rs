@@ -0,0 +1,222 @@
+//! Finding images mapped with `LOAD_LIBRARY_AS_DATAFILE` or
+//! `LOAD_LIBRARY_AS_IMAGE_RESOURCE`.
+//!
+//! `LoadLibraryExW` with these flags maps a file without loading it: it is
+//! not in the loader's module list, nothing in it is executable, and it is
+//! not relocated or initialized. The `HMODULE` returned for such a mapping is
+//! its base address with one of the low bits set. Since the loader doesn't
+//! track these mappings, they are found by walking the address space.
+//!
+//! The same walk finds the executable memory outside of modules, for
+//! `executable_regions`.
+
+use super::{paths, psapi_modules, read_path};
+use crate::{Avma, ExecutableRegion, ExecutableSource, IterationControl};
+
+use std::ffi::{c_void, OsStr, OsString};
+use std::mem;
+use std::os::windows::ffi::OsStringExt;
+use std::ptr;
+
+use windows_sys::Win32::Foundation::HMODULE;
+use windows_sys::Win32::System::Memory::{
+    VirtualQuery, MEMORY_BASIC_INFORMATION, MEM_COMMIT, MEM_IMAGE, MEM_MAPPED, PAGE_EXECUTE,
+    PAGE_EXECUTE_READ, PAGE_EXECUTE_READWRITE, PAGE_EXECUTE_WRITECOPY, PAGE_GUARD, PAGE_NOACCESS,
+};
+use windows_sys::Win32::System::ProcessStatus::GetMappedFileNameW;
+use windows_sys::Win32::System::SystemServices::IMAGE_DOS_SIGNATURE;
+use windows_sys::Win32::System::Threading::GetCurrentProcess;
+
+// The tag bits of an `HMODULE`, as tested by `LDR_IS_DATAFILE` and
+// `LDR_IS_IMAGEMAPPING`.
+const LDR_DATAFILE_TAG: usize = 1;
+const LDR_IMAGEMAPPING_TAG: usize = 2;
+
+/// How an image that isn't loaded as a module is mapped.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum MappingKind {
+    /// Mapped with `LOAD_LIBRARY_AS_DATAFILE`: the raw contents of the file,
+    /// which aren't laid out like a loaded image.
+    Datafile,
+    /// Mapped with `LOAD_LIBRARY_AS_IMAGE_RESOURCE`: laid out like a loaded
+    /// image, but not executable.
+    ImageResource,
+}
+
+impl MappingKind {
+    /// Get the kind of mapping an `HMODULE` returned by `LoadLibraryExW`
+    /// refers to, or `None` if it is a loaded module.
+    pub fn of_handle(module: HMODULE) -> Option<MappingKind> {
+        let module = module as usize;
+        if module & LDR_DATAFILE_TAG != 0 {
+            Some(MappingKind::Datafile)
+        } else if module & LDR_IMAGEMAPPING_TAG != 0 {
+            Some(MappingKind::ImageResource)
+        } else {
+            None
+        }
+    }
+}
+
+/// An image mapped into the current process without being loaded.
+#[derive(Clone, Debug)]
+pub struct ResourceMapping {
+    base: Avma,
+    size: usize,
+    kind: MappingKind,
+    path: OsString,
+}
+
+impl ResourceMapping {
+    /// Get the address the mapping starts at.
+    pub fn base(&self) -> Avma {
+        self.base
+    }
+
+    /// Get the size of the mapping in bytes.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Get how the image is mapped.
+    pub fn kind(&self) -> MappingKind {
+        self.kind
+    }
+
+    /// Get the path of the mapped file.
+    pub fn path(&self) -> &OsStr {
+        &self.path
+    }
+}
+
+/// Returns `true` if the start of the region described by `info` can be
+/// read and holds a DOS header.
+unsafe fn has_dos_header(info: &MEMORY_BASIC_INFORMATION) -> bool {
+    if info.Protect & (PAGE_NOACCESS | PAGE_GUARD) != 0 || info.Protect == 0 {
+        return false;
+    }
+    let signature = ptr::read_unaligned(info.BaseAddress as *const u16);
+    signature == IMAGE_DOS_SIGNATURE
+}
+
+/// Find the images that are mapped in the current process with
+/// `LOAD_LIBRARY_AS_DATAFILE` or `LOAD_LIBRARY_AS_IMAGE_RESOURCE`, so that
+/// addresses in them can be told apart from addresses in loaded modules.
+///
+/// Other mappings of image files, e.g. with `MapViewOfFile`, show up as
+/// `MappingKind::Datafile` too. Mappings can come and go while the address
+/// space is walked, so this is a snapshot at best.
+pub fn each_resource_mapping<F, C>(mut f: F)
+where
+    F: FnMut(&ResourceMapping) -> C,
+    C: Into<IterationControl>,
+{
+    for mapping in resource_mappings() {
+        match f(&mapping).into() {
+            IterationControl::Break => break,
+            IterationControl::Continue => continue,
+        }
+    }
+}
+
+fn resource_mappings() -> Vec<ResourceMapping> {
+    let proc = unsafe { GetCurrentProcess() };
+    let modules: Vec<usize> = unsafe { psapi_modules(proc) }
+        .into_iter()
+        .filter_map(Result::ok)
+        .map(|(module_info, _)| module_info.lpBaseOfDll as usize)
+        .collect();
+
+    let mut mappings: Vec<ResourceMapping> = vec![];
+    let mut address = 0usize;
+    loop {
+        let mut info: MEMORY_BASIC_INFORMATION = unsafe { mem::zeroed() };
+        let size = mem::size_of::<MEMORY_BASIC_INFORMATION>();
+        if unsafe { VirtualQuery(address as *const c_void, &mut info, size) } != size {
+            break;
+        }
+        let base = info.BaseAddress as usize;
+        let allocation_base = info.AllocationBase as usize;
+        address = match base.checked_add(info.RegionSize) {
+            Some(next) if info.RegionSize != 0 => next,
+            _ => break,
+        };
+
+        // Later regions of a mapping only add to its size.
+        if let Some(mapping) = mappings.last_mut() {
+            if mapping.base.0 == allocation_base && base != allocation_base {
+                mapping.size = address - allocation_base;
+                continue;
+            }
+        }
+
+        let kind = match info.Type {
+            MEM_MAPPED => MappingKind::Datafile,
+            MEM_IMAGE => MappingKind::ImageResource,
+            _ => continue,
+        };
+        if base != allocation_base
+            || info.State != MEM_COMMIT
+            || modules.contains(&base)
+            || !unsafe { has_dos_header(&info) }
+        {
+            continue;
+        }
+
+        mappings.push(ResourceMapping {
+            base: Avma(base),
+            size: info.RegionSize,
+            kind,
+            path: mapped_file_name(base).unwrap_or_default(),
+        });
+    }
+    mappings
+}
+
+/// Get the path of the file mapped at `base`, if any.
+fn mapped_file_name(base: usize) -> Option<OsString> {
+    let proc = unsafe { GetCurrentProcess() };
+    let path = read_path(|buffer| unsafe {
+        GetMappedFileNameW(
+            proc,
+            base as *const c_void,
+            buffer.as_mut_ptr(),
+            buffer.len() as u32,
+        )
+    })?;
+    Some(OsString::from_wide(&paths::win32_path(&path)))
+}
+
+/// Get every committed executable region of the current process.
+pub(crate) fn executable_mappings() -> Vec<ExecutableRegion> {
+    const EXECUTE: u32 =
+        PAGE_EXECUTE | PAGE_EXECUTE_READ | PAGE_EXECUTE_READWRITE | PAGE_EXECUTE_WRITECOPY;
+
+    let mut regions = vec![];
+    let mut address = 0usize;
+    loop {
+        let mut info: MEMORY_BASIC_INFORMATION = unsafe { mem::zeroed() };
+        let size = mem::size_of::<MEMORY_BASIC_INFORMATION>();
+        if unsafe { VirtualQuery(address as *const c_void, &mut info, size) } != size {
+            break;
+        }
+        let base = info.BaseAddress as usize;
+        address = match base.checked_add(info.RegionSize) {
+            Some(next) if info.RegionSize != 0 => next,
+            _ => break,
+        };
+        if info.State != MEM_COMMIT || info.Protect & EXECUTE == 0 || info.Protect & PAGE_GUARD != 0
+        {
+            continue;
+        }
+
+        let source = match info.Type {
+            MEM_IMAGE | MEM_MAPPED => mapped_file_name(base)
+                .map(ExecutableSource::File)
+                .unwrap_or(ExecutableSource::Anonymous),
+            _ => ExecutableSource::Anonymous,
+        };
+        regions.push(ExecutableRegion::new(Avma(base)..Avma(address), source));
+    }
+    regions
+}
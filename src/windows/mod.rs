@@ -1,38 +1,223 @@
 //! Windows-specific implementation of the `SharedLibrary` trait.
 
+use crate::diagnostics::{self, SkipReason};
+use crate::verify::u32_at;
 use crate::Segment as SegmentTrait;
 use crate::SharedLibrary as SharedLibraryTrait;
-use crate::{Bias, IterationControl, SharedLibraryId, Svma};
+use crate::{
+    Avma, Bias, IterationControl, OwnedSharedLibrary, Permissions, SharedLibraryId,
+    SharedLibraryKind, Svma,
+};
 
-use std::ffi::{CStr, OsStr, OsString};
+use std::borrow::Cow;
+use std::cell::{OnceCell, RefCell};
+use std::ffi::{c_void, CStr, OsStr, OsString};
 use std::fmt;
+use std::fs;
+use std::io;
 use std::marker::PhantomData;
 use std::mem;
-use std::os::windows::ffi::OsStringExt;
+use std::ops::Range;
+use std::os::raw::c_char;
+use std::os::windows::ffi::{OsStrExt, OsStringExt};
+use std::panic;
 use std::ptr;
 use std::slice;
-use std::usize;
-
-use winapi::ctypes::c_char;
-use winapi::shared::minwindef::{HMODULE, MAX_PATH};
-use winapi::um::libloaderapi::{FreeLibrary, LoadLibraryExW, LOAD_LIBRARY_AS_DATAFILE};
-use winapi::um::memoryapi::VirtualQuery;
-use winapi::um::processthreadsapi::GetCurrentProcess;
-use winapi::um::psapi::{
-    EnumProcessModules, GetModuleFileNameExW, GetModuleInformation, MODULEINFO,
+use std::thread::LocalKey;
+
+use windows_sys::Win32::Foundation::{
+    CloseHandle, FreeLibrary, GetLastError, ERROR_BAD_LENGTH, HANDLE, HMODULE,
+    INVALID_HANDLE_VALUE, MAX_PATH, NTSTATUS, UNICODE_STRING,
+};
+use windows_sys::Win32::System::Diagnostics::Debug::{
+    IMAGE_DATA_DIRECTORY, IMAGE_DEBUG_DIRECTORY, IMAGE_DEBUG_TYPE_CODEVIEW,
+    IMAGE_DIRECTORY_ENTRY_BASERELOC, IMAGE_DIRECTORY_ENTRY_DEBUG, IMAGE_DIRECTORY_ENTRY_EXCEPTION,
+    IMAGE_DIRECTORY_ENTRY_EXPORT, IMAGE_DIRECTORY_ENTRY_IAT, IMAGE_DIRECTORY_ENTRY_IMPORT,
+    IMAGE_DIRECTORY_ENTRY_LOAD_CONFIG, IMAGE_DIRECTORY_ENTRY_RESOURCE,
+    IMAGE_DIRECTORY_ENTRY_SECURITY, IMAGE_DLLCHARACTERISTICS_DYNAMIC_BASE,
+    IMAGE_DLLCHARACTERISTICS_GUARD_CF, IMAGE_DLLCHARACTERISTICS_HIGH_ENTROPY_VA,
+    IMAGE_DLLCHARACTERISTICS_NX_COMPAT, IMAGE_FILE_DLL, IMAGE_FILE_HEADER, IMAGE_NT_HEADERS32,
+    IMAGE_NT_HEADERS64, IMAGE_NT_OPTIONAL_HDR32_MAGIC, IMAGE_NT_OPTIONAL_HDR64_MAGIC,
+    IMAGE_SCN_CNT_CODE, IMAGE_SCN_CNT_INITIALIZED_DATA, IMAGE_SCN_CNT_UNINITIALIZED_DATA,
+    IMAGE_SCN_MEM_EXECUTE, IMAGE_SCN_MEM_READ, IMAGE_SCN_MEM_WRITE, IMAGE_SECTION_HEADER,
+};
+use windows_sys::Win32::System::Diagnostics::ToolHelp::{
+    CreateToolhelp32Snapshot, Module32FirstW, Module32NextW, MODULEENTRY32W, TH32CS_SNAPMODULE,
+    TH32CS_SNAPMODULE32,
+};
+use windows_sys::Win32::System::LibraryLoader::{
+    GetModuleHandleA, GetModuleHandleExW, GetProcAddress, LoadLibraryExW,
+    GET_MODULE_HANDLE_EX_FLAG_FROM_ADDRESS, GET_MODULE_HANDLE_EX_FLAG_UNCHANGED_REFCOUNT,
+    LOAD_LIBRARY_AS_DATAFILE,
+};
+use windows_sys::Win32::System::Memory::{
+    VirtualQuery, MEMORY_BASIC_INFORMATION, MEM_COMMIT, MEM_IMAGE,
+};
+use windows_sys::Win32::System::ProcessStatus::{
+    EnumProcessModulesEx, GetModuleFileNameExW, GetModuleInformation, LIST_MODULES_ALL, MODULEINFO,
+};
+use windows_sys::Win32::System::SystemInformation::{
+    IMAGE_FILE_MACHINE_AMD64, IMAGE_FILE_MACHINE_ARM64, IMAGE_FILE_MACHINE_ARMNT,
+    IMAGE_FILE_MACHINE_I386,
 };
-use winapi::um::winnt::{
-    IMAGE_DEBUG_DIRECTORY, IMAGE_DEBUG_TYPE_CODEVIEW, IMAGE_DIRECTORY_ENTRY_DEBUG,
-    IMAGE_DOS_HEADER, IMAGE_DOS_SIGNATURE, IMAGE_NT_HEADERS, IMAGE_NT_SIGNATURE,
-    IMAGE_SCN_CNT_CODE, IMAGE_SECTION_HEADER, MEMORY_BASIC_INFORMATION, MEM_COMMIT,
+use windows_sys::Win32::System::SystemServices::{
+    IMAGE_DEBUG_TYPE_EX_DLLCHARACTERISTICS, IMAGE_DEBUG_TYPE_REPRO, IMAGE_DOS_HEADER,
+    IMAGE_DOS_SIGNATURE, IMAGE_NT_SIGNATURE,
 };
+use windows_sys::Win32::System::Threading::GetCurrentProcess;
+
+#[cfg(feature = "authenticode")]
+mod authenticode;
+mod debug_directory;
+#[cfg(feature = "drivers")]
+mod drivers;
+mod exceptions;
+mod exports;
+mod imports;
+mod load_config;
+mod mappings;
+mod observer;
+mod patches;
+mod paths;
+mod remote;
+#[cfg(feature = "pdb")]
+mod symbol_files;
+mod version;
+
+#[cfg(feature = "authenticode")]
+pub use self::authenticode::AuthenticodeSignature;
+pub use self::debug_directory::{DebugDirectory, DebugDirectoryIter};
+#[cfg(feature = "drivers")]
+pub use self::drivers::{each_driver, Driver};
+pub use self::exceptions::{RuntimeFunction, RuntimeFunctionIter};
+pub use self::exports::{ExportIter, ExportedSymbol};
+pub use self::imports::{ImportIter, ImportedFunction, ImportedFunctionIter, ImportedModule};
+pub use self::load_config::Mitigations;
+pub(crate) use self::mappings::executable_mappings;
+pub use self::mappings::{each_resource_mapping, MappingKind, ResourceMapping};
+pub(crate) use self::observer::change_count;
+pub use self::observer::{register_image_observer, ImageEvent};
+pub use self::patches::CodePatch;
+pub use self::remote::RemoteProcess;
+pub use self::version::VersionInfo;
 
 // This is 'RSDS'.
 const CV_SIGNATURE: u32 = 0x5344_5352;
 
+// This is 'NB10'.
+const NB10_SIGNATURE: u32 = 0x3031_424e;
+
+// CodeView records for portable PDBs have this minor version, which is
+// 'PM' in ASCII.
+const PORTABLE_PDB_MINOR_VERSION: u16 = 0x504d;
+
+// Not in windows-sys.
+const IMAGE_FILE_MACHINE_ARM64EC: u16 = 0xa641;
+
+// How many module handles `psapi_modules` makes room for on the stack.
+const STACK_MODULE_HANDLES: usize = 1024;
+
+// The longest path Windows supports, in UTF-16 code units.
+const MAX_LONG_PATH: usize = 32_767;
+
+/// Get the full path of `module`, however long it is.
+unsafe fn module_file_name(proc: HANDLE, module: HMODULE) -> Option<Vec<u16>> {
+    read_path(|buffer| GetModuleFileNameExW(proc, module, buffer.as_mut_ptr(), buffer.len() as u32))
+}
+
+thread_local! {
+    // Buffers that are reused by every enumeration on the same thread, so
+    // that taking frequent snapshots doesn't allocate them every time.
+    static MODULE_HANDLES: RefCell<Vec<HMODULE>> = const { RefCell::new(Vec::new()) };
+    static PATH_BUFFER: RefCell<Vec<u16>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Call `f` with the thread's buffer from `key`, or with a new one if it is
+/// already in use or the thread is exiting.
+fn with_buffer<T, R, F>(key: &'static LocalKey<RefCell<Vec<T>>>, f: F) -> R
+where
+    F: FnOnce(&mut Vec<T>) -> R,
+{
+    let mut buffer = key
+        .try_with(|buffer| mem::take(&mut *buffer.borrow_mut()))
+        .unwrap_or_default();
+    let result = f(&mut buffer);
+    let _ = key.try_with(|cached| *cached.borrow_mut() = buffer);
+    result
+}
+
+/// Get a path from `read`, which fills in the given buffer and returns the
+/// length of the path, growing the buffer until the path fits.
+fn read_path<F>(mut read: F) -> Option<Vec<u16>>
+where
+    F: FnMut(&mut [u16]) -> u32,
+{
+    with_buffer(&PATH_BUFFER, |path| {
+        if path.len() < MAX_PATH as usize + 1 {
+            path.resize(MAX_PATH as usize + 1, 0);
+        }
+        loop {
+            let len = read(path) as usize;
+            if len == 0 {
+                return None;
+            }
+
+            // The path is silently truncated if the buffer is too small, so
+            // only trust it if there was room to spare.
+            if len < path.len() - 1 {
+                return Some(path[..len].to_vec());
+            }
+            if path.len() > MAX_LONG_PATH {
+                return None;
+            }
+            let new_len = (path.len() * 2).min(MAX_LONG_PATH + 1);
+            path.resize(new_len, 0);
+        }
+    })
+}
+
+/// Convert `path` into a NUL-terminated path that is not subject to the
+/// `MAX_PATH` limit when passed to Win32 APIs.
+fn extended_length_path(path: &[u16]) -> Vec<u16> {
+    let wide = |s: &str| s.encode_utf16().collect::<Vec<_>>();
+    let mut result = if path.len() < MAX_PATH as usize || path.starts_with(&wide(r"\\?\")) {
+        path.to_vec()
+    } else if path.starts_with(&wide(r"\\")) {
+        // `\\server\share\...` becomes `\\?\UNC\server\share\...`.
+        let mut result = wide(r"\\?\UNC\");
+        result.extend_from_slice(&path[2..]);
+        result
+    } else {
+        let mut result = wide(r"\\?\");
+        result.extend_from_slice(path);
+        result
+    };
+    result.push(0);
+    result
+}
+
 /// An unsupported segment
 pub struct Segment<'a> {
     section: &'a IMAGE_SECTION_HEADER,
+    section_alignment: u32,
+}
+
+impl<'a> Segment<'a> {
+    /// Get the raw `IMAGE_SCN_*` characteristics of this section.
+    pub fn characteristics(&self) -> u32 {
+        self.section.Characteristics
+    }
+
+    /// Get the size of the section in memory as stated in its header, which
+    /// some packers and old linkers leave at zero.
+    pub fn virtual_size(&self) -> u32 {
+        unsafe { self.section.Misc.VirtualSize }
+    }
+
+    /// Get the size of the section's initialized data in the file.
+    pub fn size_of_raw_data(&self) -> u32 {
+        self.section.SizeOfRawData
+    }
 }
 
 impl<'a> fmt::Debug for Segment<'a> {
@@ -58,20 +243,75 @@ impl<'a> SegmentTrait for Segment<'a> {
         (self.section.Characteristics & IMAGE_SCN_CNT_CODE) != 0
     }
 
+    fn is_data(&self) -> bool {
+        let characteristics = self.section.Characteristics;
+        characteristics & (IMAGE_SCN_CNT_INITIALIZED_DATA | IMAGE_SCN_CNT_UNINITIALIZED_DATA) != 0
+            && characteristics & IMAGE_SCN_MEM_WRITE != 0
+            && characteristics & IMAGE_SCN_MEM_EXECUTE == 0
+    }
+
+    fn is_readonly_data(&self) -> bool {
+        let characteristics = self.section.Characteristics;
+        characteristics & IMAGE_SCN_CNT_INITIALIZED_DATA != 0
+            && characteristics & (IMAGE_SCN_MEM_WRITE | IMAGE_SCN_MEM_EXECUTE) == 0
+    }
+
+    fn is_bss(&self) -> bool {
+        // The linker may merge `.bss` into the end of `.data`, in which case
+        // the section is larger in memory than in the file.
+        self.section.Characteristics & IMAGE_SCN_CNT_UNINITIALIZED_DATA != 0
+            || self.virtual_size() > self.size_of_raw_data()
+    }
+
+    #[inline]
+    fn permissions(&self) -> Option<Permissions> {
+        let characteristics = self.section.Characteristics;
+        Some(Permissions::new(
+            characteristics & IMAGE_SCN_MEM_READ != 0,
+            characteristics & IMAGE_SCN_MEM_WRITE != 0,
+            characteristics & IMAGE_SCN_MEM_EXECUTE != 0,
+        ))
+    }
+
+    #[inline]
+    fn file_range(&self) -> Option<Range<u64>> {
+        let start = u64::from(self.section.PointerToRawData);
+        Some(start..start + u64::from(self.size_of_raw_data()))
+    }
+
+    #[inline]
+    fn raw_flags(&self) -> Option<u64> {
+        Some(u64::from(self.characteristics()))
+    }
+
     #[inline]
     fn stated_virtual_memory_address(&self) -> Svma {
         Svma(self.section.VirtualAddress as usize)
     }
 
+    /// Falls back to the size of the section in the file, rounded up to the
+    /// section alignment, for sections without a `VirtualSize`.
     #[inline]
     fn len(&self) -> usize {
-        *unsafe { self.section.Misc.VirtualSize() } as usize
+        match self.virtual_size() {
+            0 => {
+                let raw_size = self.size_of_raw_data() as usize;
+                match self.section_alignment as usize {
+                    0 => raw_size,
+                    alignment => raw_size
+                        .checked_add(alignment - 1)
+                        .map_or(raw_size, |size| size / alignment * alignment),
+                }
+            }
+            virtual_size => virtual_size as usize,
+        }
     }
 }
 
 /// An iterator over PE sections.
 pub struct SegmentIter<'a> {
     sections: std::slice::Iter<'a, IMAGE_SECTION_HEADER>,
+    section_alignment: u32,
 }
 
 impl<'a> fmt::Debug for SegmentIter<'a> {
@@ -84,8 +324,147 @@ impl<'a> Iterator for SegmentIter<'a> {
     type Item = Segment<'a>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.sections.next().map(|section| Segment { section })
+        let section_alignment = self.section_alignment;
+        self.sections.next().map(|section| Segment {
+            section,
+            section_alignment,
+        })
+    }
+}
+
+/// The NT headers of an image, which come in two flavors that differ in
+/// the layout of the optional header.
+#[derive(Clone, Copy)]
+enum NtHeaders<'a> {
+    /// PE32, used by 32-bit images.
+    Nt32(&'a IMAGE_NT_HEADERS32),
+    /// PE32+, used by 64-bit images.
+    Nt64(&'a IMAGE_NT_HEADERS64),
+}
+
+impl<'a> NtHeaders<'a> {
+    /// Interpret `ptr` as NT headers, checking their signature and magic.
+    ///
+    /// `ptr` must point to at least `mem::size_of::<IMAGE_NT_HEADERS64>()`
+    /// readable bytes.
+    unsafe fn from_ptr(ptr: *const u8) -> Option<NtHeaders<'a>> {
+        let nt_headers = &*(ptr as *const IMAGE_NT_HEADERS32);
+        if nt_headers.Signature != IMAGE_NT_SIGNATURE {
+            return None;
+        }
+        match nt_headers.OptionalHeader.Magic {
+            IMAGE_NT_OPTIONAL_HDR32_MAGIC => Some(NtHeaders::Nt32(nt_headers)),
+            IMAGE_NT_OPTIONAL_HDR64_MAGIC => {
+                Some(NtHeaders::Nt64(&*(ptr as *const IMAGE_NT_HEADERS64)))
+            }
+            _ => None,
+        }
+    }
+
+    fn file_header(&self) -> &'a IMAGE_FILE_HEADER {
+        match *self {
+            NtHeaders::Nt32(nt_headers) => &nt_headers.FileHeader,
+            NtHeaders::Nt64(nt_headers) => &nt_headers.FileHeader,
+        }
+    }
+
+    fn size_of_image(&self) -> u32 {
+        match *self {
+            NtHeaders::Nt32(nt_headers) => nt_headers.OptionalHeader.SizeOfImage,
+            NtHeaders::Nt64(nt_headers) => nt_headers.OptionalHeader.SizeOfImage,
+        }
+    }
+
+    fn address_of_entry_point(&self) -> u32 {
+        match *self {
+            NtHeaders::Nt32(nt_headers) => nt_headers.OptionalHeader.AddressOfEntryPoint,
+            NtHeaders::Nt64(nt_headers) => nt_headers.OptionalHeader.AddressOfEntryPoint,
+        }
+    }
+
+    fn section_alignment(&self) -> u32 {
+        match *self {
+            NtHeaders::Nt32(nt_headers) => nt_headers.OptionalHeader.SectionAlignment,
+            NtHeaders::Nt64(nt_headers) => nt_headers.OptionalHeader.SectionAlignment,
+        }
+    }
+
+    fn image_base(&self) -> u64 {
+        match *self {
+            NtHeaders::Nt32(nt_headers) => u64::from(nt_headers.OptionalHeader.ImageBase),
+            NtHeaders::Nt64(nt_headers) => nt_headers.OptionalHeader.ImageBase,
+        }
+    }
+
+    fn checksum(&self) -> u32 {
+        match *self {
+            NtHeaders::Nt32(nt_headers) => nt_headers.OptionalHeader.CheckSum,
+            NtHeaders::Nt64(nt_headers) => nt_headers.OptionalHeader.CheckSum,
+        }
+    }
+
+    fn dll_characteristics(&self) -> u16 {
+        match *self {
+            NtHeaders::Nt32(nt_headers) => nt_headers.OptionalHeader.DllCharacteristics,
+            NtHeaders::Nt64(nt_headers) => nt_headers.OptionalHeader.DllCharacteristics,
+        }
+    }
+
+    /// Get the data directory entry `index`, if the image has one.
+    fn data_directory(&self, index: u16) -> Option<IMAGE_DATA_DIRECTORY> {
+        let (count, directories) = match *self {
+            NtHeaders::Nt32(nt_headers) => (
+                nt_headers.OptionalHeader.NumberOfRvaAndSizes,
+                &nt_headers.OptionalHeader.DataDirectory,
+            ),
+            NtHeaders::Nt64(nt_headers) => (
+                nt_headers.OptionalHeader.NumberOfRvaAndSizes,
+                &nt_headers.OptionalHeader.DataDirectory,
+            ),
+        };
+        if u32::from(index) >= count {
+            return None;
+        }
+        directories.get(index as usize).copied()
+    }
+
+    /// Get a pointer to the section table, which follows the optional
+    /// header.
+    fn section_table(&self) -> *const IMAGE_SECTION_HEADER {
+        let optional_header = match *self {
+            NtHeaders::Nt32(nt_headers) => &nt_headers.OptionalHeader as *const _ as *const u8,
+            NtHeaders::Nt64(nt_headers) => &nt_headers.OptionalHeader as *const _ as *const u8,
+        };
+        optional_header.wrapping_add(self.file_header().SizeOfOptionalHeader as usize)
+            as *const IMAGE_SECTION_HEADER
+    }
+}
+
+/// Compute the checksum of the PE file `file` the way `CheckSumMappedFile`
+/// does: a 16-bit ones' complement sum of the whole file, skipping the
+/// `CheckSum` field itself, plus the file's length.
+fn pe_checksum(file: &[u8]) -> Option<u32> {
+    let e_lfanew = u32_at(file, 0x3c, true)? as usize;
+    // Past the signature and file header, at the same offset in PE32 and
+    // PE32+ optional headers.
+    let checksum_offset = e_lfanew.checked_add(4 + 20 + 64)?;
+    if checksum_offset + 4 > file.len() {
+        return None;
     }
+
+    let mut sum: u64 = 0;
+    for (index, word) in file.chunks(2).enumerate() {
+        let offset = index * 2;
+        if offset == checksum_offset || offset == checksum_offset + 2 {
+            continue;
+        }
+        let low = word[0];
+        let high = word.get(1).copied().unwrap_or(0);
+        sum += u64::from(u16::from_le_bytes([low, high]));
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    sum = (sum & 0xffff) + (sum >> 16);
+    Some((sum as u32).wrapping_add(file.len() as u32))
 }
 
 #[repr(C)]
@@ -98,10 +477,36 @@ struct CodeViewRecord70 {
     pdb_filename: c_char,
 }
 
+// The record for PDB 2.0 files, used by old toolchains.
+#[repr(C)]
+struct CodeViewRecord20 {
+    signature: u32,
+    offset: u32,
+    pdb_signature: u32,
+    pdb_age: u32,
+    // As for `CodeViewRecord70`.
+    pdb_filename: c_char,
+}
+
+#[derive(Clone, Copy)]
+enum CodeView<'a> {
+    Pdb70(&'a CodeViewRecord70),
+    Pdb20(&'a CodeViewRecord20),
+}
+
 /// A shared library on Windows.
 pub struct SharedLibrary<'a> {
     module_info: MODULEINFO,
-    module_name: OsString,
+    // The path as the loader reports it, which is only turned into an
+    // `OsString` if the name is asked for.
+    module_path: Vec<u16>,
+    module_name: OnceCell<OsString>,
+    // For modules of another process, where `module_info` points at a local
+    // copy of the image rather than the image itself.
+    remote_base: Option<usize>,
+    // The ids are only parsed out of the headers once.
+    id: OnceCell<Option<SharedLibraryId>>,
+    debug_id: OnceCell<Option<SharedLibraryId>>,
     phantom: PhantomData<&'a ()>,
 }
 
@@ -118,14 +523,63 @@ impl<'a> fmt::Debug for SharedLibrary<'a> {
 }
 
 impl<'a> SharedLibrary<'a> {
-    fn new(module_info: MODULEINFO, module_name: OsString) -> SharedLibrary<'a> {
+    fn new(module_info: MODULEINFO, module_path: Vec<u16>) -> SharedLibrary<'a> {
         SharedLibrary {
             module_info,
-            module_name,
+            module_path,
+            module_name: OnceCell::new(),
+            remote_base: None,
+            id: OnceCell::new(),
+            debug_id: OnceCell::new(),
             phantom: PhantomData,
         }
     }
 
+    /// Create a `SharedLibrary` for a module of the current process from its
+    /// `HMODULE`, like the one `DllMain` or `GetModuleHandleExW` hand out,
+    /// without enumerating every module.
+    ///
+    /// Returns `None` if `module` isn't a loaded module, which includes the
+    /// tagged handles of images mapped as datafiles, see
+    /// `MappingKind::of_handle`.
+    ///
+    /// # Safety
+    ///
+    /// The module must stay loaded for `'a`, e.g. because the caller holds a
+    /// reference on it or runs in its `DllMain`.
+    pub unsafe fn from_hmodule(module: HMODULE) -> Option<SharedLibrary<'a>> {
+        if module.is_null() || MappingKind::of_handle(module).is_some() {
+            return None;
+        }
+        let (module_info, module_path) = module_list(GetCurrentProcess(), &[module]).pop()?.ok()?;
+        Some(SharedLibrary::new(module_info, module_path))
+    }
+
+    /// Create a `SharedLibrary` for the module of the current process loaded
+    /// at `base`, which is the same as its `HMODULE`.
+    ///
+    /// Returns `None` if no module starts at `base`.
+    ///
+    /// # Safety
+    ///
+    /// The module must stay loaded for `'a`, see `from_hmodule`.
+    pub unsafe fn from_base_address(base: Avma) -> Option<SharedLibrary<'a>> {
+        SharedLibrary::from_hmodule(base.0 as HMODULE)
+    }
+
+    fn module_name(&self) -> &OsString {
+        self.module_name
+            .get_or_init(|| paths::win32_os_string(&self.module_path))
+    }
+
+    /// Get the path of the module as UTF-16, as the loader reports it.
+    ///
+    /// Unlike `name()`, this converts nothing, so NT device paths are
+    /// returned as they are.
+    pub fn name_wide(&self) -> &[u16] {
+        &self.module_path
+    }
+
     #[inline]
     fn module_base(&self) -> *const c_char {
         self.module_info.lpBaseOfDll as *const c_char
@@ -140,25 +594,27 @@ impl<'a> SharedLibrary<'a> {
         }
     }
 
-    fn nt_headers(&self) -> Option<&IMAGE_NT_HEADERS> {
-        self.dos_header().and_then(|dos_header| {
-            let nt_headers: &IMAGE_NT_HEADERS =
-                unsafe { &*(self.module_base().offset(dos_header.e_lfanew as isize) as *const _) };
-            if nt_headers.Signature == IMAGE_NT_SIGNATURE {
-                Some(nt_headers)
-            } else {
-                None
-            }
+    fn nt_headers(&self) -> Option<NtHeaders<'_>> {
+        self.dos_header().and_then(|dos_header| unsafe {
+            NtHeaders::from_ptr(self.module_base().offset(dos_header.e_lfanew as isize) as *const u8)
+        })
+    }
+
+    fn sections(&self) -> &'a [IMAGE_SECTION_HEADER] {
+        self.nt_headers().map_or(&[], |nt_headers| unsafe {
+            slice::from_raw_parts(
+                nt_headers.section_table(),
+                nt_headers.file_header().NumberOfSections as usize,
+            )
         })
     }
 
-    fn debug_directories(&self) -> &[IMAGE_DEBUG_DIRECTORY] {
+    fn debug_directory_table(&self) -> &[IMAGE_DEBUG_DIRECTORY] {
         self.nt_headers().map_or(&[], |nt_headers| {
-            if nt_headers.OptionalHeader.NumberOfRvaAndSizes <= IMAGE_DIRECTORY_ENTRY_DEBUG as u32 {
-                return &[];
-            }
-            let data_dir =
-                nt_headers.OptionalHeader.DataDirectory[IMAGE_DIRECTORY_ENTRY_DEBUG as usize];
+            let data_dir = match nt_headers.data_directory(IMAGE_DIRECTORY_ENTRY_DEBUG) {
+                Some(data_dir) => data_dir,
+                None => return &[],
+            };
             if data_dir.VirtualAddress == 0 {
                 return &[];
             }
@@ -176,25 +632,495 @@ impl<'a> SharedLibrary<'a> {
         })
     }
 
-    fn codeview_record70(&self) -> Option<&CodeViewRecord70> {
-        self.debug_directories().iter().find_map(|debug_directory| {
-            if debug_directory.Type != IMAGE_DEBUG_TYPE_CODEVIEW {
-                return None;
+    /// Find the CodeView record pointing at this module's PDB.
+    ///
+    /// Mixed-mode .NET modules can have an additional record for a portable
+    /// PDB, which is only used if there is no other record.
+    fn codeview(&self) -> Option<CodeView<'_>> {
+        let mut portable = None;
+        for debug_directory in self.debug_directory_table() {
+            if debug_directory.Type != IMAGE_DEBUG_TYPE_CODEVIEW
+                || debug_directory.AddressOfRawData == 0
+            {
+                continue;
             }
 
-            let debug_info: &CodeViewRecord70 = unsafe {
-                &*(self
-                    .module_base()
+            let size = debug_directory.SizeOfData as usize;
+            let data = unsafe {
+                self.module_base()
                     .offset(debug_directory.AddressOfRawData as isize)
-                    as *const _)
             };
-            if debug_info.signature == CV_SIGNATURE {
-                Some(debug_info)
+            if size < mem::size_of::<u32>() {
+                continue;
+            }
+            let record = match unsafe { ptr::read_unaligned(data as *const u32) } {
+                CV_SIGNATURE if size >= mem::size_of::<CodeViewRecord70>() => {
+                    CodeView::Pdb70(unsafe { &*(data as *const CodeViewRecord70) })
+                }
+                NB10_SIGNATURE if size >= mem::size_of::<CodeViewRecord20>() => {
+                    CodeView::Pdb20(unsafe { &*(data as *const CodeViewRecord20) })
+                }
+                _ => continue,
+            };
+
+            if debug_directory.MinorVersion == PORTABLE_PDB_MINOR_VERSION {
+                portable = portable.or(Some(record));
             } else {
-                None
+                return Some(record);
+            }
+        }
+        portable
+    }
+
+    /// The whole mapped image of this module.
+    fn image(&self) -> &[u8] {
+        unsafe {
+            slice::from_raw_parts(
+                self.module_base() as *const u8,
+                self.module_info.SizeOfImage as usize,
+            )
+        }
+    }
+
+    /// Get the architecture this module was built for.
+    pub fn machine(&self) -> Option<Machine> {
+        self.nt_headers()
+            .map(|nt_headers| Machine::from(nt_headers.file_header().Machine))
+    }
+
+    /// Get the raw `IMAGE_FILE_*` characteristics of the file header.
+    pub fn characteristics(&self) -> Option<u16> {
+        self.nt_headers()
+            .map(|nt_headers| nt_headers.file_header().Characteristics)
+    }
+
+    /// Get the DLL characteristics of the optional header.
+    pub fn dll_characteristics(&self) -> Option<DllCharacteristics> {
+        self.nt_headers()
+            .map(|nt_headers| DllCharacteristics(nt_headers.dll_characteristics()))
+    }
+
+    /// Iterate over the functions and variables exported by this module, in
+    /// ordinal order.
+    ///
+    /// Always empty for modules of another process, whose export directory
+    /// isn't copied.
+    pub fn exports(&self) -> ExportIter<'_> {
+        if self.remote_base.is_some() {
+            return ExportIter::empty();
+        }
+        let export_dir = match self
+            .nt_headers()
+            .and_then(|nt_headers| nt_headers.data_directory(IMAGE_DIRECTORY_ENTRY_EXPORT))
+        {
+            Some(export_dir) if export_dir.VirtualAddress != 0 => export_dir,
+            _ => return ExportIter::empty(),
+        };
+        let start = export_dir.VirtualAddress as usize;
+        ExportIter::new(
+            self.image(),
+            self.module_base() as usize,
+            start..start.saturating_add(export_dir.Size as usize),
+        )
+    }
+
+    /// Get the address range of this module's exception directory, the table
+    /// of `RUNTIME_FUNCTION`s that is used to unwind on x64 and ARM.
+    pub fn exception_directory(&self) -> Option<Range<Avma>> {
+        let exception_dir = self
+            .nt_headers()?
+            .data_directory(IMAGE_DIRECTORY_ENTRY_EXCEPTION)?;
+        if exception_dir.VirtualAddress == 0 || exception_dir.Size == 0 {
+            return None;
+        }
+        let start = self
+            .virtual_memory_bias()
+            .0
+            .wrapping_add(exception_dir.VirtualAddress as usize);
+        Some(Avma(start)..Avma(start.wrapping_add(exception_dir.Size as usize)))
+    }
+
+    /// Iterate over the entries of this module's exception directory.
+    ///
+    /// This is empty for x86 modules, which use frame-based exception
+    /// handling instead, and for modules of another process, whose exception
+    /// directory isn't copied.
+    pub fn runtime_functions(&self) -> RuntimeFunctionIter<'_> {
+        if self.remote_base.is_some() {
+            return RuntimeFunctionIter::empty();
+        }
+        let nt_headers = match self.nt_headers() {
+            Some(nt_headers) => nt_headers,
+            None => return RuntimeFunctionIter::empty(),
+        };
+        let has_end_address = match Machine::from(nt_headers.file_header().Machine) {
+            Machine::X64 => true,
+            Machine::Arm | Machine::Arm64 | Machine::Arm64EC => false,
+            _ => return RuntimeFunctionIter::empty(),
+        };
+        let exception_dir = match nt_headers.data_directory(IMAGE_DIRECTORY_ENTRY_EXCEPTION) {
+            Some(exception_dir) if exception_dir.VirtualAddress != 0 => exception_dir,
+            _ => return RuntimeFunctionIter::empty(),
+        };
+        let start = exception_dir.VirtualAddress as usize;
+        let data = self
+            .image()
+            .get(start..start.saturating_add(exception_dir.Size as usize))
+            .unwrap_or(&[]);
+        RuntimeFunctionIter::new(data, has_end_address)
+    }
+
+    /// Iterate over the entries of this module's debug directory.
+    ///
+    /// For modules of another process, only the data of entries that is
+    /// mapped with the image is available.
+    pub fn debug_directories(&self) -> DebugDirectoryIter<'_> {
+        DebugDirectoryIter::new(self.debug_directory_table(), self.image())
+    }
+
+    /// Returns `true` if this module was linked reproducibly, e.g. with
+    /// `/Brepro`.
+    ///
+    /// The `TimeDateStamp` of such a module, and so the timestamp in its
+    /// `SharedLibraryId::PeSignature`, is derived from a hash of its contents
+    /// rather than being the time it was linked.
+    pub fn is_reproducible_build(&self) -> bool {
+        self.debug_directories()
+            .any(|directory| directory.debug_type() == IMAGE_DEBUG_TYPE_REPRO)
+    }
+
+    /// Get the hash that a reproducible build's timestamp and PDB signature
+    /// are derived from, if the linker recorded it.
+    pub fn repro_hash(&self) -> Option<&[u8]> {
+        let data = self
+            .debug_directories()
+            .find(|directory| directory.debug_type() == IMAGE_DEBUG_TYPE_REPRO)?
+            .data()?;
+        // The data is the length of the hash followed by the hash.
+        let len = u32_at(data, 0, true)? as usize;
+        match data.get(4..4usize.checked_add(len)?) {
+            Some(hash) if !hash.is_empty() => Some(hash),
+            _ => None,
+        }
+    }
+
+    /// Get the address of this module's entry point, i.e. `DllMain` for a DLL.
+    ///
+    /// Returns `None` for modules without an entry point, such as
+    /// resource-only DLLs.
+    pub fn entry_point(&self) -> Option<Avma> {
+        let rva = self.nt_headers()?.address_of_entry_point();
+        if rva == 0 {
+            return None;
+        }
+        Some(Avma(
+            self.virtual_memory_bias().0.wrapping_add(rva as usize),
+        ))
+    }
+
+    /// Get the address this module was linked to be loaded at, its
+    /// `ImageBase`.
+    ///
+    /// Note that segment SVMAs are relative to the image rather than to this
+    /// address, so the bias is always the actual base of the module.
+    pub fn image_base(&self) -> Option<u64> {
+        Some(self.nt_headers()?.image_base())
+    }
+
+    /// Returns whether the loader relocated this module away from its
+    /// preferred `ImageBase`, as it does for modules using ASLR.
+    pub fn was_rebased(&self) -> Option<bool> {
+        let image_base = self.image_base()?;
+        Some(image_base != self.virtual_memory_bias().0 as u64)
+    }
+
+    /// Get the timestamp and image size that identify this module on symbol
+    /// servers.
+    ///
+    /// This is what `id()` returns, unless the module has a GNU build id.
+    pub fn pe_signature(&self) -> Option<SharedLibraryId> {
+        self.nt_headers().map(|nt_headers| {
+            SharedLibraryId::PeSignature(
+                nt_headers.file_header().TimeDateStamp,
+                nt_headers.size_of_image(),
+            )
+        })
+    }
+
+    /// Get the signature of the PDB from the module's CodeView record, which
+    /// is what `debug_id()` returns.
+    fn pdb_signature(&self) -> Option<SharedLibraryId> {
+        self.codeview().map(|codeview| match codeview {
+            CodeView::Pdb70(record) => {
+                SharedLibraryId::PdbSignature(record.pdb_signature, record.pdb_age)
+            }
+            // PDB 2.0 files are identified by a timestamp rather than a
+            // GUID, which goes where the first field of the GUID would be.
+            CodeView::Pdb20(record) => {
+                let mut signature = [0; 16];
+                signature[..4].copy_from_slice(&record.pdb_signature.to_le_bytes());
+                SharedLibraryId::PdbSignature(signature, record.pdb_age)
+            }
+        })
+    }
+
+    /// Get the GNU build id of a module linked by GNU ld with `--build-id`,
+    /// as MinGW toolchains do.
+    ///
+    /// ld has nowhere else to put it, so it stores the first 16 bytes of the
+    /// build id as the GUID of a CodeView record in a `.buildid` section.
+    fn gnu_build_id(&self) -> Option<&[u8; 16]> {
+        let record = match self.codeview()? {
+            CodeView::Pdb70(record) => record,
+            CodeView::Pdb20(_) => return None,
+        };
+        let rva =
+            (record as *const CodeViewRecord70 as usize).wrapping_sub(self.module_base() as usize);
+        let in_buildid = self.segments().any(|segment| {
+            segment.name() == ".buildid"
+                && segment.stated_virtual_memory_address().0 <= rva
+                && rva - segment.stated_virtual_memory_address().0 < segment.len()
+        });
+        if in_buildid {
+            Some(&record.pdb_signature)
+        } else {
+            None
+        }
+    }
+
+    /// Get the exploit mitigations this module was built with, from its
+    /// load config directory and headers.
+    pub fn mitigations(&self) -> Option<Mitigations> {
+        let nt_headers = self.nt_headers()?;
+        let image = self.image();
+
+        let load_config = nt_headers
+            .data_directory(IMAGE_DIRECTORY_ENTRY_LOAD_CONFIG)
+            .and_then(|load_config| {
+                let start = load_config.VirtualAddress as usize;
+                if start == 0 {
+                    return None;
+                }
+                image.get(start..start.checked_add(load_config.Size as usize)?)
+            })
+            .unwrap_or(&[]);
+
+        let dll_characteristics_ex = self
+            .debug_directories()
+            .find(|directory| directory.debug_type() == IMAGE_DEBUG_TYPE_EX_DLLCHARACTERISTICS)
+            .and_then(|directory| u32_at(directory.data()?, 0, true))
+            .unwrap_or(0);
+
+        Some(load_config::parse(
+            load_config,
+            matches!(nt_headers, NtHeaders::Nt64(_)),
+            Machine::from(nt_headers.file_header().Machine) == Machine::X86,
+            DllCharacteristics(nt_headers.dll_characteristics()),
+            dll_characteristics_ex,
+        ))
+    }
+
+    /// Get the checksum recorded in the optional header.
+    ///
+    /// This is zero for most images other than drivers and system DLLs,
+    /// which the loader requires to have a valid checksum.
+    pub fn checksum(&self) -> Option<u32> {
+        self.nt_headers().map(|nt_headers| nt_headers.checksum())
+    }
+
+    /// Recompute the checksum over this module's file on disk, and check
+    /// that it matches the one in the loaded image's header.
+    ///
+    /// Returns `Ok(false)` if the file has been modified or its checksum was
+    /// never set.
+    pub fn verify_checksum(&self) -> io::Result<bool> {
+        let expected = self.checksum().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidData, "module has no NT headers")
+        })?;
+        let file = fs::read(self.module_name())?;
+        Ok(pe_checksum(&file) == Some(expected))
+    }
+
+    /// Compare the code of this module against its file on disk, and find
+    /// the ranges that were modified after it was loaded, e.g. by hooks.
+    ///
+    /// The file's base relocations are applied before comparing, and the
+    /// import address table is skipped. Relocations other than absolute
+    /// addresses aren't applied, so the code they cover is reported as
+    /// modified, as is code the loader itself rewrites, such as import call
+    /// optimization.
+    ///
+    /// Fails for modules of another process, whose code isn't copied.
+    pub fn find_code_patches(&self) -> io::Result<Vec<CodePatch>> {
+        if self.remote_base.is_some() {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "the code of modules of another process isn't available",
+            ));
+        }
+        let nt_headers = self.nt_headers().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidData, "module has no NT headers")
+        })?;
+        let directory = |index| {
+            let directory: IMAGE_DATA_DIRECTORY = nt_headers.data_directory(index)?;
+            if directory.VirtualAddress == 0 {
+                return None;
             }
+            let start = directory.VirtualAddress as usize;
+            Some(start..start.saturating_add(directory.Size as usize))
+        };
+
+        let file = fs::read(self.module_name())?;
+        patches::find(
+            self.image(),
+            self.module_base() as usize,
+            self.sections(),
+            directory(IMAGE_DIRECTORY_ENTRY_BASERELOC),
+            directory(IMAGE_DIRECTORY_ENTRY_IAT).unwrap_or(0..0),
+            &file,
+        )
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                "module's file doesn't match its image",
+            )
         })
     }
+
+    /// Returns `true` if this module's file has an embedded Authenticode
+    /// signature.
+    ///
+    /// This only looks at the headers, and says nothing about whether the
+    /// signature is valid. Files signed through a catalog, as many system
+    /// files are, have no embedded signature.
+    pub fn has_authenticode_signature(&self) -> bool {
+        self.nt_headers()
+            .and_then(|nt_headers| nt_headers.data_directory(IMAGE_DIRECTORY_ENTRY_SECURITY))
+            .is_some_and(|security_dir| security_dir.VirtualAddress != 0 && security_dir.Size != 0)
+    }
+
+    /// Verify the Authenticode signature of this module's file with
+    /// `WinVerifyTrust`, and find who signed it.
+    ///
+    /// This reads the file from disk, so it is much slower than anything
+    /// else here, and the file may have been replaced since the module was
+    /// loaded.
+    #[cfg(feature = "authenticode")]
+    pub fn authenticode_signature(&self) -> AuthenticodeSignature {
+        authenticode::verify(self.module_name())
+    }
+
+    /// Find and open the PDB named by this module's CodeView record.
+    ///
+    /// The PDB is looked for at the path the linker recorded, next to the
+    /// module, and in each of `symbol_paths`, both directly and in the
+    /// `name.pdb\<signature><age>\name.pdb` layout of symbol stores and
+    /// symbol server caches. Only a PDB whose signature and age match the
+    /// module's is opened.
+    #[cfg(feature = "pdb")]
+    pub fn open_pdb<P: AsRef<std::path::Path>>(
+        &self,
+        symbol_paths: &[P],
+    ) -> pdb::Result<pdb::PDB<'static, std::fs::File>> {
+        let signature = match self.codeview() {
+            Some(CodeView::Pdb70(record)) => Some((
+                symbol_files::PdbSignature::Guid(record.pdb_signature),
+                record.pdb_age,
+            )),
+            Some(CodeView::Pdb20(record)) => Some((
+                symbol_files::PdbSignature::Timestamp(record.pdb_signature),
+                record.pdb_age,
+            )),
+            None => None,
+        };
+        let no_pdb = || {
+            pdb::Error::IoError(io::Error::new(
+                io::ErrorKind::NotFound,
+                "the module doesn't name a PDB",
+            ))
+        };
+        let (signature, age) = signature.ok_or_else(no_pdb)?;
+        let recorded_path = self.debug_name().ok_or_else(no_pdb)?;
+        let symbol_paths: Vec<_> = symbol_paths.iter().map(AsRef::as_ref).collect();
+        symbol_files::open(
+            recorded_path,
+            signature,
+            age,
+            self.name().as_ref(),
+            &symbol_paths,
+        )
+    }
+
+    /// Keep this module loaded until the returned guard is dropped, so that
+    /// addresses in it stay valid after the callback returns.
+    ///
+    /// This takes a reference on the module with `GetModuleHandleExW`, which
+    /// `FreeLibrary` drops again, rather than pinning it for the lifetime of
+    /// the process. Returns `None` if the module was already unloaded, and
+    /// for modules of another process.
+    pub fn pin(&self) -> Option<PinnedLibrary> {
+        if self.remote_base.is_some() {
+            return None;
+        }
+        let mut module: HMODULE = ptr::null_mut();
+        let ok = unsafe {
+            GetModuleHandleExW(
+                GET_MODULE_HANDLE_EX_FLAG_FROM_ADDRESS,
+                self.module_base() as *const u16,
+                &mut module,
+            )
+        };
+        if ok == 0 {
+            return None;
+        }
+        let pinned = PinnedLibrary { module };
+        // Something else may have been loaded at the same address since.
+        if module != self.module_info.lpBaseOfDll {
+            return None;
+        }
+        Some(pinned)
+    }
+
+    /// Get the version information of this module from its `VERSIONINFO`
+    /// resource.
+    ///
+    /// Always `None` for modules of another process, whose resources aren't
+    /// copied.
+    pub fn version_info(&self) -> Option<VersionInfo> {
+        if self.remote_base.is_some() {
+            return None;
+        }
+        let resource_dir = self
+            .nt_headers()?
+            .data_directory(IMAGE_DIRECTORY_ENTRY_RESOURCE)?;
+        if resource_dir.VirtualAddress == 0 {
+            return None;
+        }
+        version::parse(self.image(), resource_dir.VirtualAddress as usize)
+    }
+
+    /// Iterate over the modules this module imports functions from.
+    ///
+    /// Only modules in the import directory are listed; delay-loaded imports
+    /// are not. Always empty for modules of another process, whose import
+    /// directory isn't copied.
+    pub fn imports(&self) -> ImportIter<'_> {
+        if self.remote_base.is_some() {
+            return ImportIter::empty();
+        }
+        let nt_headers = match self.nt_headers() {
+            Some(nt_headers) => nt_headers,
+            None => return ImportIter::empty(),
+        };
+        match nt_headers.data_directory(IMAGE_DIRECTORY_ENTRY_IMPORT) {
+            Some(import_dir) if import_dir.VirtualAddress != 0 => ImportIter::new(
+                self.image(),
+                import_dir.VirtualAddress as usize,
+                matches!(nt_headers, NtHeaders::Nt64(_)),
+            ),
+            _ => ImportIter::empty(),
+        }
+    }
 }
 
 impl<'a> SharedLibraryTrait for SharedLibrary<'a> {
@@ -203,13 +1129,17 @@ impl<'a> SharedLibraryTrait for SharedLibrary<'a> {
 
     #[inline]
     fn name(&self) -> &OsStr {
-        &self.module_name
+        self.module_name()
     }
 
     #[inline]
     fn debug_name(&self) -> Option<&OsStr> {
-        self.codeview_record70().and_then(|codeview| {
-            let cstr = unsafe { CStr::from_ptr(&codeview.pdb_filename as *const _) };
+        self.codeview().and_then(|codeview| {
+            let pdb_filename = match codeview {
+                CodeView::Pdb70(record) => &record.pdb_filename,
+                CodeView::Pdb20(record) => &record.pdb_filename,
+            };
+            let cstr = unsafe { CStr::from_ptr(pdb_filename as *const _) };
             if let Ok(s) = cstr.to_str() {
                 Some(OsStr::new(s))
             } else {
@@ -218,128 +1148,751 @@ impl<'a> SharedLibraryTrait for SharedLibrary<'a> {
         })
     }
 
-    fn id(&self) -> Option<SharedLibraryId> {
+    /// Only the PDB named by the image's CodeView record is the debug file,
+    /// so this is `None` for images without one.
+    fn debug_file(&self) -> Option<Cow<'_, OsStr>> {
+        self.debug_name().map(Cow::Borrowed)
+    }
+
+    fn kind(&self) -> Option<SharedLibraryKind> {
         self.nt_headers().map(|nt_headers| {
-            SharedLibraryId::PeSignature(
-                nt_headers.FileHeader.TimeDateStamp,
-                nt_headers.OptionalHeader.SizeOfImage,
-            )
+            if nt_headers.file_header().Characteristics & IMAGE_FILE_DLL != 0 {
+                SharedLibraryKind::Library
+            } else {
+                SharedLibraryKind::Executable
+            }
         })
     }
 
-    #[inline]
+    fn id(&self) -> Option<SharedLibraryId> {
+        self.id
+            .get_or_init(|| match self.gnu_build_id() {
+                Some(build_id) => Some(SharedLibraryId::GnuBuildId(build_id.to_vec())),
+                None => self.pe_signature(),
+            })
+            .clone()
+    }
+
     fn debug_id(&self) -> Option<SharedLibraryId> {
-        self.codeview_record70()
-            .map(|codeview| SharedLibraryId::PdbSignature(codeview.pdb_signature, codeview.pdb_age))
+        self.debug_id.get_or_init(|| self.pdb_signature()).clone()
+    }
+
+    fn dependencies(&self) -> Vec<&OsStr> {
+        self.imports()
+            .map(|module| OsStr::new(module.name()))
+            .collect()
     }
 
     fn segments(&self) -> Self::SegmentIter {
-        let sections = self.nt_headers().map(|nt_headers| unsafe {
-            let base =
-                (nt_headers as *const _ as *const u8).add(mem::size_of::<IMAGE_NT_HEADERS>());
-            slice::from_raw_parts(
-                base as *const IMAGE_SECTION_HEADER,
-                nt_headers.FileHeader.NumberOfSections as usize,
-            )
-        });
         SegmentIter {
-            sections: sections.unwrap_or(&[][..]).iter(),
+            sections: self.sections().iter(),
+            section_alignment: self
+                .nt_headers()
+                .map_or(0, |nt_headers| nt_headers.section_alignment()),
         }
     }
 
     #[inline]
     fn virtual_memory_bias(&self) -> Bias {
-        Bias(self.module_base() as usize)
+        Bias(
+            self.remote_base
+                .unwrap_or_else(|| self.module_base() as usize),
+        )
     }
 
-    fn each<F, C>(mut f: F)
+    fn each<F, C>(f: F)
     where
         F: FnMut(&Self) -> C,
         C: Into<IterationControl>,
     {
-        let proc = unsafe { GetCurrentProcess() };
-        let mut modules_size = 0;
-        unsafe {
-            if EnumProcessModules(proc, ptr::null_mut(), 0, &mut modules_size) == 0 {
-                return;
-            }
-        }
-        let module_count = modules_size / mem::size_of::<HMODULE>() as u32;
-        let mut modules = vec![unsafe { mem::zeroed() }; module_count as usize];
-        unsafe {
-            if EnumProcessModules(proc, modules.as_mut_ptr(), modules_size, &mut modules_size) == 0
-            {
-                return;
-            }
-        }
+        SharedLibrary::each_with_backend(Backend::default(), f)
+    }
+}
 
-        modules.truncate(modules_size as usize / mem::size_of::<HMODULE>());
+/// The architecture a module was built for, from the `Machine` field of its
+/// file header.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Machine {
+    /// `IMAGE_FILE_MACHINE_I386`: x86.
+    X86,
+    /// `IMAGE_FILE_MACHINE_AMD64`: x64.
+    X64,
+    /// `IMAGE_FILE_MACHINE_ARMNT`: 32-bit ARM in Thumb-2 mode.
+    Arm,
+    /// `IMAGE_FILE_MACHINE_ARM64`: ARM64.
+    Arm64,
+    /// `IMAGE_FILE_MACHINE_ARM64EC`: ARM64 code that interoperates with x64.
+    Arm64EC,
+    /// Any other machine type.
+    Other(u16),
+}
 
-        for module in modules {
-            unsafe {
-                let mut module_path = vec![0u16; MAX_PATH + 1];
-                let module_path_len = GetModuleFileNameExW(
-                    proc,
-                    module,
-                    module_path.as_mut_ptr(),
-                    MAX_PATH as u32 + 1,
-                ) as usize;
-                if module_path_len == 0 {
-                    continue;
-                }
+impl From<u16> for Machine {
+    fn from(machine: u16) -> Machine {
+        match machine {
+            IMAGE_FILE_MACHINE_I386 => Machine::X86,
+            IMAGE_FILE_MACHINE_AMD64 => Machine::X64,
+            IMAGE_FILE_MACHINE_ARMNT => Machine::Arm,
+            IMAGE_FILE_MACHINE_ARM64 => Machine::Arm64,
+            IMAGE_FILE_MACHINE_ARM64EC => Machine::Arm64EC,
+            other => Machine::Other(other),
+        }
+    }
+}
 
-                let mut module_info = mem::zeroed();
-                if GetModuleInformation(
-                    proc,
-                    module,
-                    &mut module_info,
-                    mem::size_of::<MODULEINFO>() as u32,
-                ) == 0
-                {
-                    continue;
-                }
+/// The `DllCharacteristics` of a module's optional header, which say which
+/// exploit mitigations it opts into.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct DllCharacteristics(u16);
+
+impl DllCharacteristics {
+    /// Get the raw `IMAGE_DLLCHARACTERISTICS_*` flags.
+    pub fn bits(&self) -> u16 {
+        self.0
+    }
+
+    /// Returns `true` if the module can be relocated, i.e. supports ASLR.
+    pub fn is_dynamic_base(&self) -> bool {
+        self.0 & IMAGE_DLLCHARACTERISTICS_DYNAMIC_BASE != 0
+    }
+
+    /// Returns `true` if the module is compatible with DEP.
+    pub fn is_nx_compat(&self) -> bool {
+        self.0 & IMAGE_DLLCHARACTERISTICS_NX_COMPAT != 0
+    }
+
+    /// Returns `true` if the module supports high-entropy 64-bit ASLR.
+    pub fn is_high_entropy_va(&self) -> bool {
+        self.0 & IMAGE_DLLCHARACTERISTICS_HIGH_ENTROPY_VA != 0
+    }
+
+    /// Returns `true` if the module was built with Control Flow Guard.
+    pub fn is_guard_cf(&self) -> bool {
+        self.0 & IMAGE_DLLCHARACTERISTICS_GUARD_CF != 0
+    }
+}
+
+/// How to find the modules loaded in the process.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Backend {
+    /// Use `EnumProcessModules` from psapi.
+    #[default]
+    Psapi,
+    /// Use a `CreateToolhelp32Snapshot` module snapshot.
+    ///
+    /// This also covers modules that psapi misses in WOW64 processes, and
+    /// works in some sandboxes where psapi is not allowed.
+    Toolhelp,
+    /// Walk the loader's own module list with `LdrEnumerateLoadedModules`.
+    ///
+    /// This lists modules in load order without going through psapi or
+    /// another process handle, but holds the loader lock while doing so.
+    Ldr,
+}
+
+impl Backend {
+    /// List the modules of the current process.
+    unsafe fn modules(self) -> ModuleList {
+        let proc = GetCurrentProcess();
+        match self {
+            Backend::Psapi => psapi_modules(proc),
+            Backend::Toolhelp => toolhelp_modules(proc),
+            Backend::Ldr => ldr_modules(),
+        }
+    }
+}
+
+/// The step at which looking at a module failed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ModuleErrorKind {
+    /// `GetModuleFileNameExW` failed.
+    FileName,
+    /// `GetModuleInformation` failed.
+    Information,
+    /// `VirtualQuery` failed on the module's base address.
+    VirtualQuery,
+    /// The module was unloaded after it was listed.
+    NotMapped,
+}
+
+impl From<ModuleErrorKind> for SkipReason {
+    fn from(kind: ModuleErrorKind) -> SkipReason {
+        match kind {
+            ModuleErrorKind::FileName => SkipReason::FileName,
+            ModuleErrorKind::Information => SkipReason::Information,
+            ModuleErrorKind::VirtualQuery => SkipReason::VirtualQuery,
+            ModuleErrorKind::NotMapped => SkipReason::NotMapped,
+        }
+    }
+}
+
+/// Why a module that was listed by the loader isn't passed to the callback
+/// of `each`.
+#[derive(Debug)]
+pub struct ModuleError {
+    base: usize,
+    path: Option<OsString>,
+    kind: ModuleErrorKind,
+    error: io::Error,
+}
+
+impl ModuleError {
+    fn new(base: usize, path: Option<OsString>, kind: ModuleErrorKind, error: io::Error) -> Self {
+        ModuleError {
+            base,
+            path,
+            kind,
+            error,
+        }
+    }
+
+    /// Get the base address of the module, which is also its `HMODULE`.
+    pub fn base(&self) -> usize {
+        self.base
+    }
+
+    /// Get the path of the module, if it was found before the failure.
+    pub fn path(&self) -> Option<&OsStr> {
+        self.path.as_deref()
+    }
+
+    /// Get the step that failed.
+    pub fn kind(&self) -> ModuleErrorKind {
+        self.kind
+    }
+
+    /// Get the underlying OS error.
+    pub fn io_error(&self) -> &io::Error {
+        &self.error
+    }
+}
+
+impl fmt::Display for ModuleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "module at {:#x}", self.base)?;
+        if let Some(path) = &self.path {
+            write!(f, " ({})", path.to_string_lossy())?;
+        }
+        write!(f, ": {:?}: {}", self.kind, self.error)
+    }
+}
+
+impl std::error::Error for ModuleError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+/// Keeps a module loaded for as long as it is held.
+///
+/// See [`SharedLibrary::pin`](struct.SharedLibrary.html#method.pin).
+#[derive(Debug)]
+pub struct PinnedLibrary {
+    module: HMODULE,
+}
+
+// The handle is only ever passed to `FreeLibrary`, which can be called from
+// any thread.
+unsafe impl Send for PinnedLibrary {}
+unsafe impl Sync for PinnedLibrary {}
+
+impl Drop for PinnedLibrary {
+    fn drop(&mut self) {
+        unsafe {
+            FreeLibrary(self.module);
+        }
+    }
+}
+
+/// A module as listed by the loader, before anything is read from its image.
+///
+/// See `SharedLibrary::each_entry`.
+#[derive(Clone)]
+pub struct ModuleEntry {
+    module_info: MODULEINFO,
+    path: Vec<u16>,
+    name: OnceCell<OsString>,
+}
+
+impl ModuleEntry {
+    /// Get the base address of the module, which is also its `HMODULE`.
+    pub fn base(&self) -> Avma {
+        Avma(self.module_info.lpBaseOfDll as usize)
+    }
+
+    /// Get the size of the module's image in bytes.
+    pub fn size(&self) -> usize {
+        self.module_info.SizeOfImage as usize
+    }
+
+    /// Get the path of the module.
+    pub fn name(&self) -> &OsStr {
+        self.name.get_or_init(|| paths::win32_os_string(&self.path))
+    }
+
+    /// Get the path of the module as UTF-16, as the loader reports it.
+    pub fn name_wide(&self) -> &[u16] {
+        &self.path
+    }
+
+    /// Returns `true` if `address` is inside the module's image.
+    pub fn contains_avma(&self, address: Avma) -> bool {
+        address.0.wrapping_sub(self.base().0) < self.size()
+    }
+
+    /// Pin the module and call `f` with it, like `each` would.
+    ///
+    /// Returns `None` without calling `f` if the module was unloaded since it
+    /// was listed.
+    pub fn with_library<F, R>(&self, f: F) -> Option<R>
+    where
+        F: FnOnce(&SharedLibrary) -> R,
+    {
+        unsafe {
+            let handle_lock = LoadLibraryExW(
+                extended_length_path(&self.path).as_ptr(),
+                ptr::null_mut(),
+                LOAD_LIBRARY_AS_DATAFILE,
+            );
+            if handle_lock.is_null() {
+                return None;
+            }
+            // A module loaded again after being unloaded may be somewhere
+            // else now, and one that isn't loaded at all is only mapped as a
+            // datafile.
+            let result = if handle_lock == self.module_info.lpBaseOfDll {
+                let shlib = SharedLibrary::new(self.module_info, self.path.clone());
+                panic::catch_unwind(panic::AssertUnwindSafe(|| f(&shlib))).map(Some)
+            } else {
+                Ok(None)
+            };
+            FreeLibrary(handle_lock);
+            result.unwrap_or_else(|panicked| panic::resume_unwind(panicked))
+        }
+    }
+}
+
+impl fmt::Debug for ModuleEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ModuleEntry")
+            .field("base", &self.base())
+            .field("size", &self.size())
+            .field("name", &self.name())
+            .finish()
+    }
+}
+
+type ModuleList = Vec<Result<(MODULEINFO, Vec<u16>), ModuleError>>;
+
+/// List the modules of a process with psapi.
+///
+/// This asks for both 32-bit and 64-bit modules, so that a 64-bit caller sees
+/// all modules of a WOW64 process. 32-bit callers only ever get 32-bit
+/// modules.
+unsafe fn psapi_modules(proc: HANDLE) -> ModuleList {
+    // Almost every process has fewer modules than this, so a single call
+    // with a buffer on the stack is usually all it takes.
+    let mut stack_modules = [ptr::null_mut(); STACK_MODULE_HANDLES];
+    let capacity = mem::size_of_val(&stack_modules) as u32;
+    let mut modules_size = 0;
+    if EnumProcessModulesEx(
+        proc,
+        stack_modules.as_mut_ptr(),
+        capacity,
+        &mut modules_size,
+        LIST_MODULES_ALL,
+    ) == 0
+    {
+        return vec![];
+    }
+    if modules_size <= capacity {
+        let count = modules_size as usize / mem::size_of::<HMODULE>();
+        return module_list(proc, &stack_modules[..count]);
+    }
+
+    with_buffer(&MODULE_HANDLES, |modules| loop {
+        // Leave room for modules loaded in the meantime.
+        let count = modules_size as usize / mem::size_of::<HMODULE>() + 16;
+        modules.clear();
+        modules.resize(count, ptr::null_mut());
+        let capacity = mem::size_of_val(&modules[..]) as u32;
+        if EnumProcessModulesEx(
+            proc,
+            modules.as_mut_ptr(),
+            capacity,
+            &mut modules_size,
+            LIST_MODULES_ALL,
+        ) == 0
+        {
+            return vec![];
+        }
+        if modules_size <= capacity {
+            modules.truncate(modules_size as usize / mem::size_of::<HMODULE>());
+            return module_list(proc, modules);
+        }
+    })
+}
+
+/// Look up the path and `MODULEINFO` of each of `modules`.
+unsafe fn module_list(proc: HANDLE, modules: &[HMODULE]) -> ModuleList {
+    modules
+        .iter()
+        .map(|&module| {
+            let module_path = module_file_name(proc, module).ok_or_else(|| {
+                ModuleError::new(
+                    module as usize,
+                    None,
+                    ModuleErrorKind::FileName,
+                    io::Error::last_os_error(),
+                )
+            })?;
+
+            let mut module_info = mem::zeroed();
+            if GetModuleInformation(
+                proc,
+                module,
+                &mut module_info,
+                mem::size_of::<MODULEINFO>() as u32,
+            ) == 0
+            {
+                return Err(ModuleError::new(
+                    module as usize,
+                    Some(OsString::from_wide(&module_path)),
+                    ModuleErrorKind::Information,
+                    io::Error::last_os_error(),
+                ));
+            }
+            Ok((module_info, module_path))
+        })
+        .collect()
+}
+
+/// List the modules of the current process with a Toolhelp snapshot.
+unsafe fn toolhelp_modules(proc: HANDLE) -> ModuleList {
+    // Taking the snapshot fails with `ERROR_BAD_LENGTH` if the module list
+    // changes while it is being copied, so try a few times.
+    let mut snapshot = INVALID_HANDLE_VALUE;
+    for _ in 0..8 {
+        snapshot = CreateToolhelp32Snapshot(TH32CS_SNAPMODULE | TH32CS_SNAPMODULE32, 0);
+        if snapshot != INVALID_HANDLE_VALUE || GetLastError() != ERROR_BAD_LENGTH {
+            break;
+        }
+    }
+    if snapshot == INVALID_HANDLE_VALUE {
+        return vec![];
+    }
+
+    let mut modules = vec![];
+    let mut entry: MODULEENTRY32W = mem::zeroed();
+    entry.dwSize = mem::size_of::<MODULEENTRY32W>() as u32;
+    let mut ok = Module32FirstW(snapshot, &mut entry);
+    while ok != 0 {
+        // `szExePath` is limited to `MAX_PATH`, so prefer asking for the
+        // full path.
+        let module_path = module_file_name(proc, entry.hModule).unwrap_or_else(|| {
+            let len = entry
+                .szExePath
+                .iter()
+                .position(|c| *c == 0)
+                .unwrap_or(entry.szExePath.len());
+            entry.szExePath[..len].to_vec()
+        });
+        let module_info = MODULEINFO {
+            lpBaseOfDll: entry.modBaseAddr as *mut _,
+            SizeOfImage: entry.modBaseSize,
+            EntryPoint: ptr::null_mut(),
+        };
+        modules.push(Ok((module_info, module_path)));
+        ok = Module32NextW(snapshot, &mut entry);
+    }
+
+    CloseHandle(snapshot);
+    modules
+}
+
+/// `LDR_DATA_TABLE_ENTRY`, up to the fields that are used.
+#[repr(C)]
+struct LdrDataTableEntry {
+    // `InLoadOrderLinks`, `InMemoryOrderLinks` and
+    // `InInitializationOrderLinks`.
+    links: [*mut c_void; 6],
+    dll_base: *mut c_void,
+    entry_point: *mut c_void,
+    size_of_image: u32,
+    full_dll_name: UNICODE_STRING,
+}
+
+type LdrEnumerateCallback =
+    unsafe extern "system" fn(entry: *const LdrDataTableEntry, context: *mut c_void, stop: *mut u8);
 
+type LdrEnumerateLoadedModules = unsafe extern "system" fn(
+    reserved: u8,
+    callback: LdrEnumerateCallback,
+    context: *mut c_void,
+) -> NTSTATUS;
+
+/// List the modules of the current process from the loader's module list.
+///
+/// `LdrEnumerateLoadedModules` is exported by ntdll but not declared in any
+/// SDK header, so it is looked up at runtime.
+unsafe fn ldr_modules() -> ModuleList {
+    unsafe extern "system" fn push_module(
+        entry: *const LdrDataTableEntry,
+        context: *mut c_void,
+        _stop: *mut u8,
+    ) {
+        let modules = &mut *(context as *mut ModuleList);
+        let entry = match entry.as_ref() {
+            Some(entry) => entry,
+            None => return,
+        };
+        let name = &entry.full_dll_name;
+        let module_path = if name.Buffer.is_null() {
+            vec![]
+        } else {
+            slice::from_raw_parts(name.Buffer, name.Length as usize / 2).to_vec()
+        };
+        let module_info = MODULEINFO {
+            lpBaseOfDll: entry.dll_base,
+            SizeOfImage: entry.size_of_image,
+            EntryPoint: entry.entry_point,
+        };
+        modules.push(Ok((module_info, module_path)));
+    }
+
+    let ntdll = GetModuleHandleA(b"ntdll.dll\0".as_ptr());
+    if ntdll.is_null() {
+        return vec![];
+    }
+    let enumerate = match GetProcAddress(ntdll, b"LdrEnumerateLoadedModules\0".as_ptr()) {
+        Some(enumerate) => enumerate,
+        None => return vec![],
+    };
+    let enumerate: LdrEnumerateLoadedModules = mem::transmute(enumerate);
+
+    let mut modules: ModuleList = vec![];
+    if enumerate(
+        0,
+        push_module,
+        &mut modules as *mut ModuleList as *mut c_void,
+    ) < 0
+    {
+        return vec![];
+    }
+    modules
+}
+
+impl<'a> SharedLibrary<'a> {
+    /// Iterate over the modules of the current process, finding them with
+    /// the given `Backend`.
+    pub fn each_with_backend<F, C>(backend: Backend, mut f: F)
+    where
+        F: FnMut(&Self) -> C,
+        C: Into<IterationControl>,
+    {
+        SharedLibrary::each_impl(backend, true, |result| match result {
+            Ok(shlib) => f(shlib).into(),
+            Err(_) => IterationControl::Continue,
+        })
+    }
+
+    /// Iterate over the modules of the current process like `each`, but also
+    /// call `f` with an error for every module that was listed but couldn't
+    /// be looked at, rather than skipping it.
+    pub fn each_with_errors<F, C>(f: F)
+    where
+        F: FnMut(Result<&Self, ModuleError>) -> C,
+        C: Into<IterationControl>,
+    {
+        SharedLibrary::each_impl(Backend::default(), true, f)
+    }
+
+    /// Iterate over the modules of the current process without pinning them.
+    ///
+    /// `each` keeps every module loaded while it is being looked at by
+    /// loading it again with `LoadLibraryExW`, which takes the loader lock.
+    /// That can deadlock when called from `DllMain` or a crash handler, so
+    /// this instead only checks with `VirtualQuery` that the module's image
+    /// is still mapped before handing it to `f`.
+    ///
+    /// Nothing stops another thread from unloading a module while `f` looks
+    /// at it, so this should only be used where taking the loader lock is
+    /// not an option, ideally while other threads are suspended.
+    pub fn each_unpinned<F, C>(mut f: F)
+    where
+        F: FnMut(&Self) -> C,
+        C: Into<IterationControl>,
+    {
+        SharedLibrary::each_impl(Backend::default(), false, |result| match result {
+            Ok(shlib) => f(shlib).into(),
+            Err(_) => IterationControl::Continue,
+        })
+    }
+
+    /// Iterate over the modules of the current process, only looking at what
+    /// the loader lists for them.
+    ///
+    /// Unlike `each`, this neither pins the modules nor reads their images,
+    /// which is most of the cost of `each` for callers that only need names,
+    /// bases and sizes. `ModuleEntry::with_library` pins a module and gives
+    /// access to the rest on demand.
+    pub fn each_entry<F, C>(backend: Backend, mut f: F)
+    where
+        F: FnMut(&ModuleEntry) -> C,
+        C: Into<IterationControl>,
+    {
+        let modules = unsafe { backend.modules() };
+
+        for (module_info, path) in modules.into_iter().filter_map(Result::ok) {
+            let entry = ModuleEntry {
+                module_info,
+                path,
+                name: OnceCell::new(),
+            };
+            match f(&entry).into() {
+                IterationControl::Break => break,
+                IterationControl::Continue => continue,
+            }
+        }
+    }
+
+    fn each_impl<F, C>(backend: Backend, pin: bool, mut f: F)
+    where
+        F: FnMut(Result<&Self, ModuleError>) -> C,
+        C: Into<IterationControl>,
+    {
+        diagnostics::enumerated();
+        let listing = diagnostics::start();
+        let modules = unsafe { backend.modules() };
+        diagnostics::finish(listing, "list");
+
+        let visiting = diagnostics::start();
+        let mut f = |result: Result<&Self, ModuleError>| {
+            match &result {
+                Ok(_) => diagnostics::visited(),
+                Err(error) => diagnostics::skipped(error.base, error.path(), error.kind.into()),
+            }
+            f(result)
+        };
+        for module in modules {
+            let (module_info, module_path) = match module {
+                Ok(module) => module,
+                Err(error) => match f(Err(error)).into() {
+                    IterationControl::Break => break,
+                    IterationControl::Continue => continue,
+                },
+            };
+
+            unsafe {
                 // to prevent something else from unloading the module while
                 // we're poking around in memory we load it a second time.  This
                 // will effectively just increment the refcount since it has been
                 // loaded before.
-                let handle_lock = LoadLibraryExW(
-                    module_path.as_ptr(),
-                    ptr::null_mut(),
-                    LOAD_LIBRARY_AS_DATAFILE,
-                );
+                let handle_lock = if pin {
+                    LoadLibraryExW(
+                        extended_length_path(&module_path).as_ptr(),
+                        ptr::null_mut(),
+                        LOAD_LIBRARY_AS_DATAFILE,
+                    )
+                } else {
+                    ptr::null_mut()
+                };
 
-                let mut vmem_info = mem::zeroed();
-                let mut should_break = false;
-                if VirtualQuery(
-                    module_info.lpBaseOfDll,
-                    &mut vmem_info,
-                    mem::size_of::<MEMORY_BASIC_INFORMATION>(),
-                ) == mem::size_of::<MEMORY_BASIC_INFORMATION>()
-                {
-                    let module_path = OsString::from_wide(&module_path[..module_path_len]);
-                    if vmem_info.State == MEM_COMMIT {
+                let mut vmem_info: MEMORY_BASIC_INFORMATION = mem::zeroed();
+                let base = module_info.lpBaseOfDll as usize;
+                // Catch panics so the pin is released before they carry on
+                // unwinding, like Linux does for `dl_iterate_phdr`.
+                let control = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+                    let error_path = || Some(paths::win32_os_string(&module_path));
+                    if VirtualQuery(
+                        module_info.lpBaseOfDll,
+                        &mut vmem_info,
+                        mem::size_of::<MEMORY_BASIC_INFORMATION>(),
+                    ) != mem::size_of::<MEMORY_BASIC_INFORMATION>()
+                    {
+                        let error = io::Error::last_os_error();
+                        f(Err(ModuleError::new(
+                            base,
+                            error_path(),
+                            ModuleErrorKind::VirtualQuery,
+                            error,
+                        )))
+                        .into()
+                    } else if vmem_info.State != MEM_COMMIT
+                        || (!pin && vmem_info.Type != MEM_IMAGE)
+                        || MappingKind::of_handle(handle_lock).is_some()
+                    {
+                        // Without the pin the module may have been unloaded since
+                        // it was listed, in which case its memory is either gone or
+                        // reused for something other than an image. With the pin,
+                        // a module that was unloaded in the meantime is mapped
+                        // again as a datafile instead of being kept loaded.
+                        f(Err(ModuleError::new(
+                            base,
+                            error_path(),
+                            ModuleErrorKind::NotMapped,
+                            io::Error::new(io::ErrorKind::NotFound, "module is no longer mapped"),
+                        )))
+                        .into()
+                    } else {
                         let shlib = SharedLibrary::new(module_info, module_path);
-                        match f(&shlib).into() {
-                            IterationControl::Break => should_break = true,
-                            IterationControl::Continue => {}
-                        }
+                        f(Ok(&shlib)).into()
                     }
+                }));
+                if !handle_lock.is_null() {
+                    FreeLibrary(handle_lock);
                 }
 
-                FreeLibrary(handle_lock);
-
-                if should_break {
-                    break;
+                match control {
+                    Ok(IterationControl::Break) => break,
+                    Ok(IterationControl::Continue) => continue,
+                    Err(panicked) => panic::resume_unwind(panicked),
                 }
             }
         }
+        diagnostics::finish(visiting, "visit");
+    }
+}
+
+/// Look up the loaded module `name` with `GetModuleHandleExW`, which doesn't
+/// load anything, into `module`.
+fn module_handle(name: &OsStr, flags: u32, module: &mut HMODULE) -> bool {
+    let name: Vec<u16> = name.encode_wide().collect();
+    // An empty name would find the executable instead, and a NUL would cut
+    // the name short.
+    if name.is_empty() || name.contains(&0) {
+        return false;
     }
+    let name: Vec<u16> = name.into_iter().chain(Some(0)).collect();
+    unsafe { GetModuleHandleExW(flags, name.as_ptr(), module) != 0 }
+}
+
+/// Ask the loader whether `name` is loaded, the way `GetModuleHandleW` would
+/// look for it, without taking a reference on it.
+pub(crate) fn probe_loaded(name: &OsStr) -> Option<bool> {
+    let mut module = ptr::null_mut();
+    Some(module_handle(
+        name,
+        GET_MODULE_HANDLE_EX_FLAG_UNCHANGED_REFCOUNT,
+        &mut module,
+    ))
+}
+
+/// Find the loaded module that `GetModuleHandleW` would return for `name`.
+pub(crate) fn find_loaded(name: &OsStr) -> Option<OwnedSharedLibrary> {
+    let mut module = ptr::null_mut();
+    if !module_handle(name, 0, &mut module) {
+        return None;
+    }
+    // The reference keeps the module loaded while it is copied.
+    let _pinned = PinnedLibrary { module };
+    let shlib = unsafe { SharedLibrary::from_hmodule(module) }?;
+    Some(OwnedSharedLibrary::from_shared_library(&shlib))
 }
 
 #[cfg(test)]
 mod tests {
-    use super::super::{IterationControl, Segment, SharedLibrary};
+    use super::super::{IterationControl, Permissions, Segment, SharedLibrary};
     use crate::windows;
 
     #[test]
@@ -387,6 +1940,1006 @@ mod tests {
         });
     }
 
+    #[test]
+    fn get_kind() {
+        use crate::SharedLibraryKind;
+
+        let mut executables = 0;
+        windows::SharedLibrary::each(|shlib| {
+            if shlib.kind() == Some(SharedLibraryKind::Executable) {
+                executables += 1;
+            }
+        });
+        assert_eq!(executables, 1);
+    }
+
+    /// Build a minimal 32-bit image with a single code section, as a 64-bit
+    /// process would see in a WOW64 process, optionally with a CodeView
+    /// record.
+    fn pe32_image(codeview: Option<(&[u8], u16)>) -> Vec<u64> {
+        use windows_sys::Win32::System::Diagnostics::Debug::IMAGE_DEBUG_TYPE_CODEVIEW;
+
+        match codeview {
+            Some((record, minor_version)) => {
+                pe32_image_with_debug(&[(IMAGE_DEBUG_TYPE_CODEVIEW, record, minor_version)])
+            }
+            None => pe32_image_with_debug(&[]),
+        }
+    }
+
+    /// Build a minimal 32-bit image with the given `(type, data, minor
+    /// version)` debug directories.
+    fn pe32_image_with_debug(debug: &[(u32, &[u8], u16)]) -> Vec<u64> {
+        use std::mem;
+        use windows_sys::Win32::System::Diagnostics::Debug::{
+            IMAGE_DEBUG_DIRECTORY, IMAGE_DIRECTORY_ENTRY_DEBUG,
+            IMAGE_DLLCHARACTERISTICS_DYNAMIC_BASE, IMAGE_DLLCHARACTERISTICS_NX_COMPAT,
+            IMAGE_NT_HEADERS32, IMAGE_NT_OPTIONAL_HDR32_MAGIC, IMAGE_SCN_CNT_CODE,
+            IMAGE_SCN_MEM_EXECUTE, IMAGE_SCN_MEM_READ, IMAGE_SECTION_HEADER,
+        };
+        use windows_sys::Win32::System::SystemInformation::IMAGE_FILE_MACHINE_I386;
+        use windows_sys::Win32::System::SystemServices::{
+            IMAGE_DOS_HEADER, IMAGE_DOS_SIGNATURE, IMAGE_NT_SIGNATURE,
+        };
+
+        const DEBUG_DIRECTORY: usize = 0x600;
+        const DEBUG_DATA: usize = 0x700;
+
+        let mut image = vec![0u64; 512];
+        let size = image.len() * 8;
+        let base = image.as_mut_ptr() as *mut u8;
+        let nt_offset = mem::size_of::<IMAGE_DOS_HEADER>();
+        unsafe {
+            let dos_header = &mut *(base as *mut IMAGE_DOS_HEADER);
+            dos_header.e_magic = IMAGE_DOS_SIGNATURE;
+            dos_header.e_lfanew = nt_offset as i32;
+
+            let nt_headers = &mut *(base.add(nt_offset) as *mut IMAGE_NT_HEADERS32);
+            nt_headers.Signature = IMAGE_NT_SIGNATURE;
+            nt_headers.FileHeader.Machine = IMAGE_FILE_MACHINE_I386;
+            nt_headers.FileHeader.NumberOfSections = 1;
+            nt_headers.FileHeader.TimeDateStamp = 0x1234_5678;
+            nt_headers.FileHeader.SizeOfOptionalHeader =
+                mem::size_of_val(&nt_headers.OptionalHeader) as u16;
+            nt_headers.OptionalHeader.Magic = IMAGE_NT_OPTIONAL_HDR32_MAGIC;
+            nt_headers.OptionalHeader.SizeOfImage = size as u32;
+            nt_headers.OptionalHeader.DllCharacteristics =
+                IMAGE_DLLCHARACTERISTICS_DYNAMIC_BASE | IMAGE_DLLCHARACTERISTICS_NX_COMPAT;
+            nt_headers.OptionalHeader.NumberOfRvaAndSizes = 16;
+
+            let section = &mut *(base.add(nt_offset + mem::size_of::<IMAGE_NT_HEADERS32>())
+                as *mut IMAGE_SECTION_HEADER);
+            section.Name = *b".text\0\0\0";
+            section.VirtualAddress = 0x1000;
+            section.Misc.VirtualSize = 0x100;
+            section.Characteristics =
+                IMAGE_SCN_CNT_CODE | IMAGE_SCN_MEM_EXECUTE | IMAGE_SCN_MEM_READ;
+
+            if !debug.is_empty() {
+                let data_dir = &mut nt_headers.OptionalHeader.DataDirectory
+                    [IMAGE_DIRECTORY_ENTRY_DEBUG as usize];
+                data_dir.VirtualAddress = DEBUG_DIRECTORY as u32;
+                data_dir.Size = (debug.len() * mem::size_of::<IMAGE_DEBUG_DIRECTORY>()) as u32;
+            }
+            let mut data_offset = DEBUG_DATA;
+            for (index, &(debug_type, data, minor_version)) in debug.iter().enumerate() {
+                let debug_directory =
+                    &mut *(base.add(DEBUG_DIRECTORY) as *mut IMAGE_DEBUG_DIRECTORY).add(index);
+                debug_directory.Type = debug_type;
+                debug_directory.MinorVersion = minor_version;
+                if !data.is_empty() {
+                    debug_directory.AddressOfRawData = data_offset as u32;
+                    debug_directory.SizeOfData = data.len() as u32;
+                    std::ptr::copy_nonoverlapping(data.as_ptr(), base.add(data_offset), data.len());
+                    data_offset += (data.len() + 7) & !7;
+                }
+            }
+        }
+        image
+    }
+
+    fn image_library(image: &mut [u64]) -> windows::SharedLibrary<'_> {
+        use windows_sys::Win32::System::ProcessStatus::MODULEINFO;
+
+        let module_info = MODULEINFO {
+            lpBaseOfDll: image.as_mut_ptr() as *mut _,
+            SizeOfImage: (image.len() * 8) as u32,
+            EntryPoint: std::ptr::null_mut(),
+        };
+        windows::SharedLibrary::new(module_info, "test.dll".encode_utf16().collect())
+    }
+
+    #[test]
+    fn pe32_headers() {
+        let mut image = pe32_image(None);
+        let shlib = image_library(&mut image);
+        assert_eq!(
+            shlib.id(),
+            Some(crate::SharedLibraryId::PeSignature(0x1234_5678, 4096))
+        );
+        let segments: Vec<_> = shlib.segments().collect();
+        assert_eq!(segments.len(), 1);
+        assert_eq!(segments[0].name(), ".text");
+        assert!(segments[0].is_code());
+        assert_eq!(segments[0].len(), 0x100);
+        assert_eq!(shlib.debug_id(), None);
+    }
+
+    #[test]
+    fn section_without_virtual_size() {
+        use std::mem;
+        use windows_sys::Win32::System::Diagnostics::Debug::{
+            IMAGE_NT_HEADERS32, IMAGE_SECTION_HEADER,
+        };
+        use windows_sys::Win32::System::SystemServices::IMAGE_DOS_HEADER;
+
+        let mut image = pe32_image(None);
+        let base = image.as_mut_ptr() as *mut u8;
+        let nt_offset = mem::size_of::<IMAGE_DOS_HEADER>();
+        unsafe {
+            let nt_headers = &mut *(base.add(nt_offset) as *mut IMAGE_NT_HEADERS32);
+            nt_headers.OptionalHeader.SectionAlignment = 0x1000;
+            let section = &mut *(base.add(nt_offset + mem::size_of::<IMAGE_NT_HEADERS32>())
+                as *mut IMAGE_SECTION_HEADER);
+            section.Misc.VirtualSize = 0;
+            section.SizeOfRawData = 0x234;
+        }
+
+        let shlib = image_library(&mut image);
+        let text = shlib.segments().next().unwrap();
+        assert_eq!(text.virtual_size(), 0);
+        assert_eq!(text.size_of_raw_data(), 0x234);
+        assert_eq!(text.len(), 0x1000);
+        assert!(text.contains_svma(crate::Svma(0x1fff)));
+    }
+
+    #[test]
+    fn no_sections() {
+        use std::mem;
+        use windows_sys::Win32::System::SystemServices::IMAGE_DOS_HEADER;
+
+        let mut image = pe32_image(None);
+        // `FileHeader.NumberOfSections`, after the signature and `Machine`.
+        let number_of_sections = mem::size_of::<IMAGE_DOS_HEADER>() + 6;
+        unsafe {
+            *(image.as_mut_ptr() as *mut u8).add(number_of_sections) = 0;
+        }
+        let shlib = image_library(&mut image);
+        assert_eq!(shlib.segments().count(), 0);
+        assert_eq!(shlib.actual_load_addr().0, shlib.virtual_memory_bias().0);
+        assert_eq!(shlib.stated_load_addr().0, 0);
+        assert_eq!(shlib.len(), 0);
+        assert!(shlib.id().is_some());
+    }
+
+    #[test]
+    fn pe32_plus_headers() {
+        use std::mem;
+        use windows_sys::Win32::System::Diagnostics::Debug::{
+            IMAGE_DIRECTORY_ENTRY_DEBUG, IMAGE_NT_HEADERS64, IMAGE_NT_OPTIONAL_HDR64_MAGIC,
+        };
+        use windows_sys::Win32::System::SystemInformation::IMAGE_FILE_MACHINE_AMD64;
+        use windows_sys::Win32::System::SystemServices::{
+            IMAGE_DOS_HEADER, IMAGE_DOS_SIGNATURE, IMAGE_NT_SIGNATURE,
+        };
+
+        let mut image = vec![0u64; 512];
+        let base = image.as_mut_ptr() as *mut u8;
+        let nt_offset = mem::size_of::<IMAGE_DOS_HEADER>();
+        unsafe {
+            let dos_header = &mut *(base as *mut IMAGE_DOS_HEADER);
+            dos_header.e_magic = IMAGE_DOS_SIGNATURE;
+            dos_header.e_lfanew = nt_offset as i32;
+
+            let nt_headers = &mut *(base.add(nt_offset) as *mut IMAGE_NT_HEADERS64);
+            nt_headers.Signature = IMAGE_NT_SIGNATURE;
+            nt_headers.FileHeader.Machine = IMAGE_FILE_MACHINE_AMD64;
+            nt_headers.FileHeader.TimeDateStamp = 0x8765_4321;
+            nt_headers.FileHeader.SizeOfOptionalHeader =
+                mem::size_of_val(&nt_headers.OptionalHeader) as u16;
+            nt_headers.OptionalHeader.Magic = IMAGE_NT_OPTIONAL_HDR64_MAGIC;
+            nt_headers.OptionalHeader.SizeOfImage = 4096;
+            nt_headers.OptionalHeader.CheckSum = 0xabcd;
+            // Entries past `NumberOfRvaAndSizes` must be ignored.
+            nt_headers.OptionalHeader.NumberOfRvaAndSizes = 2;
+            nt_headers.OptionalHeader.DataDirectory[IMAGE_DIRECTORY_ENTRY_DEBUG as usize]
+                .VirtualAddress = 0x600;
+            nt_headers.OptionalHeader.DataDirectory[IMAGE_DIRECTORY_ENTRY_DEBUG as usize].Size = 28;
+        }
+
+        let shlib = image_library(&mut image);
+        assert_eq!(shlib.machine(), Some(windows::Machine::X64));
+        assert_eq!(
+            shlib.id(),
+            Some(crate::SharedLibraryId::PeSignature(0x8765_4321, 4096))
+        );
+        assert_eq!(shlib.checksum(), Some(0xabcd));
+        assert_eq!(shlib.segments().count(), 0);
+        assert_eq!(shlib.debug_id(), None);
+    }
+
+    #[test]
+    fn repro() {
+        use windows_sys::Win32::System::SystemServices::IMAGE_DEBUG_TYPE_REPRO;
+
+        let mut image = pe32_image(None);
+        let shlib = image_library(&mut image);
+        assert!(!shlib.is_reproducible_build());
+        assert_eq!(shlib.repro_hash(), None);
+
+        let mut data = 32u32.to_le_bytes().to_vec();
+        data.extend_from_slice(&[0x5a; 32]);
+        let mut image = pe32_image_with_debug(&[(IMAGE_DEBUG_TYPE_REPRO, &data, 0)]);
+        let shlib = image_library(&mut image);
+        assert!(shlib.is_reproducible_build());
+        assert_eq!(shlib.repro_hash(), Some(&[0x5a; 32][..]));
+
+        // `/Brepro` without a hash.
+        let mut image = pe32_image_with_debug(&[(IMAGE_DEBUG_TYPE_REPRO, &[], 0)]);
+        let shlib = image_library(&mut image);
+        assert!(shlib.is_reproducible_build());
+        assert_eq!(shlib.repro_hash(), None);
+    }
+
+    #[test]
+    fn debug_directories() {
+        use windows_sys::Win32::System::Diagnostics::Debug::IMAGE_DEBUG_TYPE_CODEVIEW;
+        use windows_sys::Win32::System::SystemServices::{
+            IMAGE_DEBUG_TYPE_POGO, IMAGE_DEBUG_TYPE_REPRO,
+        };
+
+        let mut image = pe32_image_with_debug(&[
+            (IMAGE_DEBUG_TYPE_CODEVIEW, b"RSDS", 0),
+            (IMAGE_DEBUG_TYPE_POGO, b"PGU\0\x00\x10\0\0", 0),
+            (IMAGE_DEBUG_TYPE_REPRO, &[], 0),
+        ]);
+        let shlib = image_library(&mut image);
+        let directories: Vec<_> = shlib.debug_directories().collect();
+        assert_eq!(directories.len(), 3);
+        assert_eq!(directories[0].debug_type(), IMAGE_DEBUG_TYPE_CODEVIEW);
+        assert_eq!(directories[0].data(), Some(&b"RSDS"[..]));
+        assert_eq!(directories[1].debug_type(), IMAGE_DEBUG_TYPE_POGO);
+        assert_eq!(directories[1].size(), 8);
+        assert_eq!(
+            directories[1].data().map(|data| &data[..4]),
+            Some(&b"PGU\0"[..])
+        );
+        assert_eq!(directories[2].data(), None);
+
+        let mut image = pe32_image(None);
+        assert_eq!(image_library(&mut image).debug_directories().len(), 0);
+    }
+
+    #[test]
+    fn nb10_codeview() {
+        let mut record = vec![];
+        record.extend_from_slice(b"NB10");
+        record.extend_from_slice(&0u32.to_le_bytes());
+        record.extend_from_slice(&0x3b1c_0f2au32.to_le_bytes());
+        record.extend_from_slice(&7u32.to_le_bytes());
+        record.extend_from_slice(b"old.pdb\0");
+
+        let mut image = pe32_image(Some((&record, 0)));
+        let shlib = image_library(&mut image);
+        let mut signature = [0; 16];
+        signature[..4].copy_from_slice(&[0x2a, 0x0f, 0x1c, 0x3b]);
+        assert_eq!(
+            shlib.debug_id(),
+            Some(crate::SharedLibraryId::PdbSignature(signature, 7))
+        );
+        assert_eq!(shlib.debug_name(), Some(std::ffi::OsStr::new("old.pdb")));
+    }
+
+    #[test]
+    fn gnu_build_id() {
+        let mut record = vec![];
+        record.extend_from_slice(b"RSDS");
+        record.extend_from_slice(&[0x42; 16]);
+        record.extend_from_slice(&1u32.to_le_bytes());
+        record.push(0);
+
+        let mut image = pe32_image(Some((&record, 0)));
+        assert_eq!(
+            image_library(&mut image).id(),
+            Some(crate::SharedLibraryId::PeSignature(0x1234_5678, 4096))
+        );
+
+        // Move the only section over the CodeView record, as ld does.
+        unsafe {
+            use windows_sys::Win32::System::Diagnostics::Debug::{
+                IMAGE_NT_HEADERS32, IMAGE_SECTION_HEADER,
+            };
+            use windows_sys::Win32::System::SystemServices::IMAGE_DOS_HEADER;
+            let offset =
+                std::mem::size_of::<IMAGE_DOS_HEADER>() + std::mem::size_of::<IMAGE_NT_HEADERS32>();
+            let section =
+                &mut *((image.as_mut_ptr() as *mut u8).add(offset) as *mut IMAGE_SECTION_HEADER);
+            section.Name = *b".buildid";
+            section.VirtualAddress = 0x700;
+        }
+        let shlib = image_library(&mut image);
+        assert_eq!(
+            shlib.id(),
+            Some(crate::SharedLibraryId::GnuBuildId(vec![0x42; 16]))
+        );
+        assert_eq!(
+            shlib.pe_signature(),
+            Some(crate::SharedLibraryId::PeSignature(0x1234_5678, 4096))
+        );
+        assert_eq!(
+            shlib.debug_id(),
+            Some(crate::SharedLibraryId::PdbSignature([0x42; 16], 1))
+        );
+    }
+
+    #[test]
+    fn portable_pdb_codeview() {
+        let mut record = vec![];
+        record.extend_from_slice(b"RSDS");
+        record.extend_from_slice(&[0xab; 16]);
+        record.extend_from_slice(&1u32.to_le_bytes());
+        record.extend_from_slice(b"managed.pdb\0");
+
+        // A lone portable PDB record is still used.
+        let mut image = pe32_image(Some((&record, 0x504d)));
+        let shlib = image_library(&mut image);
+        assert_eq!(
+            shlib.debug_id(),
+            Some(crate::SharedLibraryId::PdbSignature([0xab; 16], 1))
+        );
+        assert_eq!(
+            shlib.debug_name(),
+            Some(std::ffi::OsStr::new("managed.pdb"))
+        );
+    }
+
+    #[test]
+    fn kernel32_exports() {
+        use windows_sys::Win32::System::LibraryLoader::{GetModuleHandleA, GetProcAddress};
+
+        let kernel32 = unsafe { GetModuleHandleA(b"kernel32.dll\0".as_ptr() as *const _) };
+        let get_proc_address =
+            unsafe { GetProcAddress(kernel32, b"GetProcAddress\0".as_ptr() as *const _) };
+
+        let mut found = None;
+        windows::SharedLibrary::each(|shlib| {
+            if shlib.virtual_memory_bias().0 == kernel32 as usize {
+                found = shlib
+                    .exports()
+                    .find(|export| export.name() == Some("GetProcAddress"));
+                IterationControl::Break
+            } else {
+                IterationControl::Continue
+            }
+        });
+
+        let export = found.expect("kernel32 should export GetProcAddress");
+        assert_eq!(
+            export.address(),
+            Some(crate::Avma(get_proc_address.unwrap() as usize))
+        );
+    }
+
+    #[test]
+    fn dependencies() {
+        windows::SharedLibrary::each(|shlib| {
+            if !shlib.name().to_string_lossy().ends_with(".exe") {
+                return IterationControl::Continue;
+            }
+            let dependencies = shlib.dependencies();
+            assert!(dependencies
+                .iter()
+                .any(|name| name.eq_ignore_ascii_case("kernel32.dll")));
+
+            let kernel32 = shlib
+                .imports()
+                .find(|module| module.name().eq_ignore_ascii_case("kernel32.dll"))
+                .unwrap();
+            assert!(kernel32.functions().count() > 0);
+            IterationControl::Break
+        });
+    }
+
+    #[test]
+    fn machine_and_characteristics() {
+        let expected = if cfg!(target_arch = "x86") {
+            windows::Machine::X86
+        } else if cfg!(target_arch = "x86_64") {
+            windows::Machine::X64
+        } else {
+            windows::Machine::Arm64
+        };
+        windows::SharedLibrary::each(|shlib| {
+            assert_eq!(shlib.machine(), Some(expected));
+            assert!(shlib.dll_characteristics().is_some());
+            if shlib.name().to_string_lossy().ends_with("ntdll.dll") {
+                let dll_characteristics = shlib.dll_characteristics().unwrap();
+                assert!(dll_characteristics.is_dynamic_base());
+                assert!(dll_characteristics.is_nx_compat());
+            }
+        });
+
+        let mut image = pe32_image(None);
+        let shlib = image_library(&mut image);
+        assert_eq!(shlib.machine(), Some(windows::Machine::X86));
+        let dll_characteristics = shlib.dll_characteristics().unwrap();
+        assert!(dll_characteristics.is_dynamic_base());
+        assert!(!dll_characteristics.is_high_entropy_va());
+    }
+
+    #[test]
+    fn kernel32_version_info() {
+        windows::SharedLibrary::each(|shlib| {
+            if !shlib
+                .name()
+                .to_string_lossy()
+                .to_ascii_lowercase()
+                .ends_with("\\kernel32.dll")
+            {
+                return IterationControl::Continue;
+            }
+            let info = shlib.version_info().unwrap();
+            assert_eq!(info.company_name(), Some("Microsoft Corporation"));
+            assert_eq!(info.file_version().map(|version| version[0]), Some(10));
+            assert!(info.file_version_string().is_some());
+            IterationControl::Break
+        });
+    }
+
+    #[test]
+    fn authenticode() {
+        let mut image = pe32_image(None);
+        assert!(!image_library(&mut image).has_authenticode_signature());
+
+        #[cfg(feature = "authenticode")]
+        windows::SharedLibrary::each(|shlib| {
+            if !shlib.has_authenticode_signature() {
+                return IterationControl::Continue;
+            }
+            let signature = shlib.authenticode_signature();
+            assert!(signature.is_signed());
+            assert!(signature.signer().is_some());
+            IterationControl::Break
+        });
+    }
+
+    #[cfg(feature = "drivers")]
+    #[test]
+    fn drivers() {
+        let mut paths = vec![];
+        windows::each_driver(|driver| {
+            paths.push(driver.path().to_owned());
+        })
+        .unwrap();
+
+        // The kernel is always loaded first.
+        let kernel = paths[0].to_string_lossy().to_lowercase();
+        assert!(kernel.ends_with(".exe"), "{:?}", paths[0]);
+    }
+
+    #[test]
+    fn runtime_functions() {
+        let mut image = pe32_image(None);
+        let shlib = image_library(&mut image);
+        assert_eq!(shlib.exception_directory(), None);
+        assert_eq!(shlib.runtime_functions().count(), 0);
+
+        // The function containing this code has an entry in the executable's
+        // exception directory.
+        #[cfg(target_arch = "x86_64")]
+        {
+            let here = runtime_functions as fn() as usize;
+            let mut found = false;
+            windows::SharedLibrary::each(|shlib| {
+                let bias = shlib.virtual_memory_bias().0;
+                if here < bias || here - bias >= shlib.len() {
+                    return IterationControl::Continue;
+                }
+                let directory = shlib.exception_directory().unwrap();
+                assert_eq!(
+                    shlib.runtime_functions().len() * 12,
+                    directory.end.0 - directory.start.0
+                );
+                let rva = (here - bias) as u32;
+                found = shlib.runtime_functions().any(|function| {
+                    function.begin_address() <= rva && Some(rva) < function.end_address()
+                });
+                IterationControl::Break
+            });
+            assert!(found);
+        }
+    }
+
+    #[test]
+    fn code_patches() {
+        let exe = std::env::current_exe().unwrap();
+        windows::SharedLibrary::each(|shlib| {
+            if shlib.name() != exe.as_os_str() {
+                return IterationControl::Continue;
+            }
+            assert_eq!(shlib.find_code_patches().unwrap(), []);
+            IterationControl::Break
+        });
+    }
+
+    #[test]
+    fn resource_mappings() {
+        use std::os::windows::ffi::OsStrExt;
+        use windows_sys::Win32::Foundation::FreeLibrary;
+        use windows_sys::Win32::System::LibraryLoader::{
+            GetModuleHandleA, LoadLibraryExW, LOAD_LIBRARY_AS_DATAFILE,
+            LOAD_LIBRARY_AS_IMAGE_RESOURCE,
+        };
+
+        // Map a copy, so that the executable itself doesn't show up.
+        let exe = std::env::current_exe().unwrap();
+        let copy = std::env::temp_dir().join("findshlibs-resource-mapping.exe");
+        std::fs::copy(&exe, &copy).unwrap();
+        let path: Vec<u16> = copy.as_os_str().encode_wide().chain(Some(0)).collect();
+
+        for (flags, kind) in [
+            (LOAD_LIBRARY_AS_DATAFILE, windows::MappingKind::Datafile),
+            (
+                LOAD_LIBRARY_AS_IMAGE_RESOURCE,
+                windows::MappingKind::ImageResource,
+            ),
+        ] {
+            let handle = unsafe { LoadLibraryExW(path.as_ptr(), std::ptr::null_mut(), flags) };
+            assert!(!handle.is_null());
+            assert_eq!(windows::MappingKind::of_handle(handle), Some(kind));
+
+            let base = handle as usize & !3;
+            let mut found = None;
+            windows::each_resource_mapping(|mapping| {
+                if mapping.base().0 == base {
+                    found = Some(mapping.clone());
+                    IterationControl::Break
+                } else {
+                    IterationControl::Continue
+                }
+            });
+            let mapping = found.unwrap();
+            assert_eq!(mapping.kind(), kind);
+            assert!(mapping.size() > 0);
+            let file_name = copy.file_name().unwrap().to_string_lossy().to_lowercase();
+            assert!(mapping
+                .path()
+                .to_string_lossy()
+                .to_lowercase()
+                .ends_with(&file_name));
+
+            // Nothing mapped this way is reported as a module.
+            windows::SharedLibrary::each(|shlib| {
+                assert_ne!(shlib.virtual_memory_bias().0, base);
+            });
+            unsafe { FreeLibrary(handle) };
+        }
+        assert_eq!(
+            windows::MappingKind::of_handle(unsafe { GetModuleHandleA(std::ptr::null()) }),
+            None
+        );
+        std::fs::remove_file(&copy).unwrap();
+    }
+
+    #[test]
+    fn checksum() {
+        let mut file = vec![0u8; 0x101];
+        file[0x3c] = 0x40;
+        // The checksum field, which is skipped.
+        file[0x40 + 88..0x40 + 92].copy_from_slice(&[0xff; 4]);
+        file[0x10] = 0x34;
+        file[0x11] = 0x12;
+        file[0x100] = 0x01;
+        assert_eq!(
+            super::pe_checksum(&file),
+            Some(0x40 + 0x1234 + 0x01 + 0x101)
+        );
+        assert_eq!(super::pe_checksum(&file[..0x80]), None);
+
+        windows::SharedLibrary::each(|shlib| {
+            if !shlib
+                .name()
+                .to_string_lossy()
+                .to_ascii_lowercase()
+                .ends_with("\\ntdll.dll")
+            {
+                return IterationControl::Continue;
+            }
+            assert_ne!(shlib.checksum(), Some(0));
+            assert!(shlib.verify_checksum().unwrap());
+            IterationControl::Break
+        });
+    }
+
+    #[test]
+    fn segment_permissions() {
+        windows::SharedLibrary::each(|shlib| {
+            for seg in shlib.segments() {
+                let permissions = seg.permissions().unwrap();
+                if seg.is_code() {
+                    assert!(permissions.is_executable());
+                }
+                if seg.name() == ".rdata" {
+                    assert!(permissions.is_readable() && !permissions.is_writable());
+                }
+            }
+        });
+
+        let mut image = pe32_image(None);
+        let shlib = image_library(&mut image);
+        let text = shlib.segments().next().unwrap();
+        assert_eq!(
+            text.permissions(),
+            Some(Permissions::new(true, false, true))
+        );
+    }
+
+    #[test]
+    fn image_observer() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+        use windows_sys::Win32::Foundation::FreeLibrary;
+        use windows_sys::Win32::System::LibraryLoader::LoadLibraryA;
+
+        let added = Arc::new(AtomicUsize::new(0));
+        let version_added = Arc::new(AtomicUsize::new(0));
+        let observer_added = added.clone();
+        let observer_version_added = version_added.clone();
+        windows::register_image_observer(move |event, shlib| {
+            if event != windows::ImageEvent::Added || shlib.id().is_none() {
+                return;
+            }
+            observer_added.fetch_add(1, Ordering::SeqCst);
+            if shlib
+                .name()
+                .to_string_lossy()
+                .to_ascii_lowercase()
+                .ends_with("\\version.dll")
+            {
+                observer_version_added.fetch_add(1, Ordering::SeqCst);
+            }
+        });
+
+        let mut count = 0;
+        windows::SharedLibrary::each(|_| count += 1);
+        assert!(added.load(Ordering::SeqCst) >= count);
+
+        let before = version_added.load(Ordering::SeqCst);
+        let version = unsafe { LoadLibraryA(b"version.dll\0".as_ptr() as *const _) };
+        assert!(!version.is_null());
+        let loaded_now = version_added.load(Ordering::SeqCst) > before;
+        unsafe { FreeLibrary(version) };
+        // Unless something else had already loaded it, loading it again must
+        // have been observed.
+        assert!(loaded_now || before > 0);
+    }
+
+    #[test]
+    fn mitigations() {
+        windows::SharedLibrary::each(|shlib| {
+            if !shlib
+                .name()
+                .to_string_lossy()
+                .to_ascii_lowercase()
+                .ends_with("\\kernel32.dll")
+            {
+                return IterationControl::Continue;
+            }
+            let mitigations = shlib.mitigations().unwrap();
+            assert!(mitigations.has_cfg());
+            assert!(mitigations.dll_characteristics().is_nx_compat());
+            assert_eq!(
+                mitigations.has_safe_seh().is_some(),
+                cfg!(target_arch = "x86")
+            );
+            IterationControl::Break
+        });
+
+        let mut image = pe32_image(None);
+        let mitigations = image_library(&mut image).mitigations().unwrap();
+        assert!(!mitigations.has_cfg());
+        assert_eq!(mitigations.has_safe_seh(), Some(false));
+    }
+
+    #[test]
+    fn each_with_errors() {
+        let mut count = 0;
+        windows::SharedLibrary::each(|_| count += 1);
+
+        let mut ok = 0;
+        windows::SharedLibrary::each_with_errors(|result| match result {
+            Ok(_) => ok += 1,
+            Err(error) => panic!("{}", error),
+        });
+        assert_eq!(ok, count);
+    }
+
+    #[test]
+    fn entry_point() {
+        windows::SharedLibrary::each(|shlib| {
+            if !shlib.module_info.EntryPoint.is_null() {
+                assert_eq!(
+                    shlib.entry_point(),
+                    Some(crate::Avma(shlib.module_info.EntryPoint as usize))
+                );
+            }
+        });
+
+        // A resource-only DLL has no entry point.
+        let mut image = pe32_image(None);
+        assert_eq!(image_library(&mut image).entry_point(), None);
+
+        unsafe {
+            use std::mem;
+            use windows_sys::Win32::System::Diagnostics::Debug::IMAGE_NT_HEADERS32;
+            use windows_sys::Win32::System::SystemServices::IMAGE_DOS_HEADER;
+
+            let base = image.as_mut_ptr() as *mut u8;
+            let nt_headers =
+                &mut *(base.add(mem::size_of::<IMAGE_DOS_HEADER>()) as *mut IMAGE_NT_HEADERS32);
+            nt_headers.OptionalHeader.AddressOfEntryPoint = 0x1010;
+        }
+        let shlib = image_library(&mut image);
+        assert_eq!(
+            shlib.entry_point(),
+            Some(crate::Avma(shlib.virtual_memory_bias().0 + 0x1010))
+        );
+    }
+
+    #[test]
+    fn image_base() {
+        let mut image = pe32_image(None);
+        let shlib = image_library(&mut image);
+        assert_eq!(shlib.image_base(), Some(0));
+        assert_eq!(shlib.was_rebased(), Some(true));
+
+        windows::SharedLibrary::each(|shlib| {
+            assert_ne!(shlib.image_base(), Some(0));
+            assert!(shlib.was_rebased().is_some());
+        });
+    }
+
+    #[test]
+    fn remote_process() {
+        use windows_sys::Win32::System::Threading::GetCurrentProcessId;
+
+        let mut local = vec![];
+        windows::SharedLibrary::each(|shlib| {
+            local.push((shlib.name().to_owned(), shlib.id(), shlib.debug_id()));
+        });
+
+        let process = windows::RemoteProcess::for_pid(unsafe { GetCurrentProcessId() }).unwrap();
+        let mut remote = vec![];
+        process
+            .each(|shlib| {
+                remote.push((shlib.name().to_owned(), shlib.id(), shlib.debug_id()));
+            })
+            .unwrap();
+
+        assert_eq!(local, remote);
+        assert_eq!(
+            process.executable_path().unwrap(),
+            std::env::current_exe().unwrap().into_os_string()
+        );
+    }
+
+    #[test]
+    fn unpinned() {
+        let mut pinned = vec![];
+        windows::SharedLibrary::each(|shlib| {
+            pinned.push(shlib.name().to_owned());
+        });
+        let mut unpinned = vec![];
+        windows::SharedLibrary::each_unpinned(|shlib| {
+            unpinned.push(shlib.name().to_owned());
+        });
+        assert_eq!(pinned, unpinned);
+    }
+
+    #[test]
+    fn toolhelp_backend() {
+        let mut psapi = vec![];
+        windows::SharedLibrary::each_with_backend(windows::Backend::Psapi, |shlib| {
+            psapi.push((shlib.name().to_owned(), shlib.id()));
+        });
+        let mut toolhelp = vec![];
+        windows::SharedLibrary::each_with_backend(windows::Backend::Toolhelp, |shlib| {
+            toolhelp.push((shlib.name().to_owned(), shlib.id()));
+        });
+
+        assert!(!psapi.is_empty());
+        for module in &psapi {
+            assert!(toolhelp.contains(module), "{:?} missing", module);
+        }
+    }
+
+    #[test]
+    fn ldr_backend() {
+        let mut psapi = vec![];
+        windows::SharedLibrary::each_with_backend(windows::Backend::Psapi, |shlib| {
+            psapi.push((shlib.name().to_owned(), shlib.id(), shlib.entry_point()));
+        });
+        let mut ldr = vec![];
+        windows::SharedLibrary::each_with_backend(windows::Backend::Ldr, |shlib| {
+            ldr.push((shlib.name().to_owned(), shlib.id(), shlib.entry_point()));
+        });
+        assert_eq!(psapi, ldr);
+    }
+
+    #[test]
+    fn name_wide() {
+        use std::ffi::OsString;
+        use std::os::windows::ffi::OsStringExt;
+
+        windows::SharedLibrary::each(|shlib| {
+            assert_eq!(OsString::from_wide(shlib.name_wide()), shlib.name());
+        });
+    }
+
+    #[test]
+    fn from_hmodule() {
+        use windows_sys::Win32::System::LibraryLoader::GetModuleHandleA;
+
+        let mut libraries = vec![];
+        windows::SharedLibrary::each(|shlib| {
+            libraries.push((
+                shlib.name().to_owned(),
+                shlib.id(),
+                shlib.actual_load_addr(),
+            ));
+        });
+        for (name, id, base) in &libraries {
+            let shlib = unsafe { windows::SharedLibrary::from_base_address(*base) }.unwrap();
+            assert_eq!(shlib.name(), name);
+            assert_eq!(&shlib.id(), id);
+        }
+
+        let exe =
+            unsafe { windows::SharedLibrary::from_hmodule(GetModuleHandleA(std::ptr::null())) };
+        assert_eq!(exe.unwrap().name(), libraries[0].0);
+        let inside = crate::Avma(libraries[0].2 .0 + 0x10);
+        assert!(unsafe { windows::SharedLibrary::from_base_address(inside) }.is_none());
+        assert!(unsafe { windows::SharedLibrary::from_hmodule(std::ptr::null_mut()) }.is_none());
+    }
+
+    #[test]
+    fn module_entries() {
+        let mut libraries = vec![];
+        windows::SharedLibrary::each(|shlib| {
+            libraries.push((shlib.name().to_owned(), shlib.virtual_memory_bias().0));
+        });
+        let mut entries = vec![];
+        windows::SharedLibrary::each_entry(windows::Backend::Psapi, |entry| {
+            assert!(entry.contains_avma(entry.base()));
+            let len = entry.with_library(|shlib| shlib.len()).unwrap();
+            assert!(len <= entry.size(), "{:?}", entry);
+            entries.push((entry.name().to_owned(), entry.base().0));
+        });
+        assert_eq!(entries, libraries);
+    }
+
+    #[test]
+    fn data_segments() {
+        static mut ZEROED: [u8; 0x10000] = [0; 0x10000];
+
+        let zeroed = crate::Avma(std::ptr::addr_of!(ZEROED) as usize);
+        let mut checked = 0;
+        windows::SharedLibrary::each(|shlib| {
+            for segment in shlib.segments() {
+                if segment.contains_avma(shlib, zeroed) {
+                    assert!(segment.is_data() && segment.is_bss(), "{:?}", segment);
+                    assert!(!segment.is_readonly_data() && !segment.is_code());
+                    assert_eq!(segment.raw_type(), None);
+                    assert_eq!(
+                        segment.raw_flags(),
+                        Some(u64::from(segment.characteristics()))
+                    );
+                    checked += 1;
+                }
+            }
+        });
+        assert_eq!(checked, 1);
+    }
+
+    #[test]
+    #[cfg(feature = "pdb")]
+    fn open_pdb() {
+        // Tests are linked with their PDBs next to them.
+        let exe = std::env::current_exe().unwrap();
+        let mut opened = false;
+        windows::SharedLibrary::each(|shlib| {
+            if shlib.name() == exe.as_os_str() {
+                let no_symbol_paths: &[&std::path::Path] = &[];
+                let mut pdb = shlib.open_pdb(no_symbol_paths).unwrap();
+                assert!(pdb.pdb_information().is_ok());
+                opened = true;
+            }
+        });
+        assert!(opened);
+    }
+
+    #[test]
+    fn pin() {
+        let mut pins = vec![];
+        windows::SharedLibrary::each(|shlib| {
+            pins.push(shlib.pin().unwrap());
+        });
+        std::thread::spawn(move || drop(pins)).join().unwrap();
+    }
+
+    #[test]
+    fn panic_in_callbacks() {
+        use std::panic;
+
+        // The pin taken for each module is released when the callback panics,
+        // and the panic carries on with its payload.
+        let caught = panic::catch_unwind(|| {
+            windows::SharedLibrary::each::<_, ()>(|_| panic!("uh oh"));
+        })
+        .unwrap_err();
+        assert_eq!(*caught.downcast_ref::<&'static str>().unwrap(), "uh oh");
+
+        windows::SharedLibrary::each_entry(windows::Backend::Psapi, |entry| {
+            let caught = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+                entry.with_library::<_, ()>(|_| panic!("uh oh"));
+            }));
+            assert!(caught.is_err());
+            assert!(entry.with_library(|_| ()).is_some());
+            IterationControl::Break
+        });
+    }
+
+    #[test]
+    fn read_path() {
+        let fill = |path: &str| {
+            let path: Vec<u16> = path.encode_utf16().collect();
+            move |buffer: &mut [u16]| {
+                let len = path.len().min(buffer.len());
+                buffer[..len].copy_from_slice(&path[..len]);
+                len as u32
+            }
+        };
+
+        // The buffer grows for long paths, and stays usable for short ones.
+        let long = format!(r"C:\{}.dll", "a".repeat(1000));
+        let read = windows::read_path(fill(&long)).unwrap();
+        assert_eq!(String::from_utf16(&read).unwrap(), long);
+        let read = windows::read_path(fill(r"C:\a.dll")).unwrap();
+        assert_eq!(String::from_utf16(&read).unwrap(), r"C:\a.dll");
+        assert_eq!(windows::read_path(|_| 0), None);
+    }
+
+    #[test]
+    fn extended_length_path() {
+        let wide = |s: &str| s.encode_utf16().collect::<Vec<_>>();
+        let nul = |s: &str| {
+            let mut s = wide(s);
+            s.push(0);
+            s
+        };
+
+        let short = r"C:\Windows\System32\kernel32.dll";
+        assert_eq!(windows::extended_length_path(&wide(short)), nul(short));
+
+        let long = format!(r"C:\{}\foo.dll", "a".repeat(300));
+        assert_eq!(
+            windows::extended_length_path(&wide(&long)),
+            nul(&format!(r"\\?\{}", long))
+        );
+
+        let unc = format!(r"\\server\share\{}\foo.dll", "a".repeat(300));
+        assert_eq!(
+            windows::extended_length_path(&wide(&unc)),
+            nul(&format!(r"\\?\UNC\{}", &unc[2..]))
+        );
+
+        let prefixed = format!(r"\\?\C:\{}\foo.dll", "a".repeat(300));
+        assert_eq!(
+            windows::extended_length_path(&wide(&prefixed)),
+            nul(&prefixed)
+        );
+    }
+
     #[test]
     fn get_id() {
         windows::SharedLibrary::each(|shlib| {
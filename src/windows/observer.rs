@@ -0,0 +1,164 @@
+//! Observing modules as they are loaded and unloaded.
+//!
+//! This uses `LdrRegisterDllNotification`, which ntdll exports but doesn't
+//! declare in any SDK header, so it is looked up at runtime.
+
+use super::SharedLibrary;
+use crate::SharedLibrary as SharedLibraryTrait;
+
+use std::ffi::c_void;
+use std::panic;
+use std::ptr;
+use std::slice;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Once};
+
+use windows_sys::Win32::Foundation::{NTSTATUS, UNICODE_STRING};
+use windows_sys::Win32::System::LibraryLoader::{GetModuleHandleA, GetProcAddress};
+use windows_sys::Win32::System::ProcessStatus::MODULEINFO;
+
+const LDR_DLL_NOTIFICATION_REASON_LOADED: u32 = 1;
+const LDR_DLL_NOTIFICATION_REASON_UNLOADED: u32 = 2;
+
+/// `LDR_DLL_LOADED_NOTIFICATION_DATA`, which has the same layout as
+/// `LDR_DLL_UNLOADED_NOTIFICATION_DATA`.
+#[repr(C)]
+struct LdrDllNotificationData {
+    flags: u32,
+    full_dll_name: *const UNICODE_STRING,
+    base_dll_name: *const UNICODE_STRING,
+    dll_base: *mut c_void,
+    size_of_image: u32,
+}
+
+type LdrDllNotificationFunction = unsafe extern "system" fn(
+    reason: u32,
+    data: *const LdrDllNotificationData,
+    context: *mut c_void,
+);
+
+type LdrRegisterDllNotification = unsafe extern "system" fn(
+    flags: u32,
+    notification_function: LdrDllNotificationFunction,
+    context: *mut c_void,
+    cookie: *mut *mut c_void,
+) -> NTSTATUS;
+
+/// The kind of change reported to an image observer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImageEvent {
+    /// The module was just loaded.
+    Added,
+    /// The module is being unloaded.
+    Removed,
+}
+
+type ImageObserver = Arc<dyn Fn(ImageEvent, &SharedLibrary) + Send + Sync>;
+
+static IMAGE_OBSERVERS: Mutex<Vec<ImageObserver>> = Mutex::new(Vec::new());
+
+static REGISTER_DLL_NOTIFICATION: Once = Once::new();
+
+/// Register `f` to be called whenever the loader maps or unmaps a module.
+///
+/// `f` is called with `ImageEvent::Added` for every module that is already
+/// loaded before this function returns, and then again for every module
+/// loaded afterwards. A module loaded concurrently with the registration may
+/// be reported as added twice.
+///
+/// Observers stay registered for the lifetime of the process. `f` runs while
+/// the loader lock is held and must not load or unload modules itself, or
+/// wait on threads that might. Panics in `f` are caught and discarded, since
+/// they can't unwind through the loader.
+pub fn register_image_observer<F>(f: F)
+where
+    F: Fn(ImageEvent, &SharedLibrary) + Send + Sync + 'static,
+{
+    let observer: ImageObserver = Arc::new(f);
+    IMAGE_OBSERVERS.lock().unwrap().push(observer.clone());
+
+    register_dll_notification();
+
+    // Unlike dyld, the loader doesn't replay the modules that are already
+    // loaded.
+    SharedLibrary::each(|shlib| observer(ImageEvent::Added, shlib));
+}
+
+/// Make sure `on_dll_notification` is registered, returning whether it is.
+fn register_dll_notification() -> bool {
+    REGISTER_DLL_NOTIFICATION.call_once(|| unsafe {
+        let ntdll = GetModuleHandleA(b"ntdll.dll\0".as_ptr());
+        if ntdll.is_null() {
+            return;
+        }
+        let register = match GetProcAddress(ntdll, b"LdrRegisterDllNotification\0".as_ptr()) {
+            Some(register) => register,
+            None => return,
+        };
+        let register: LdrRegisterDllNotification = std::mem::transmute(register);
+        let mut cookie = ptr::null_mut();
+        if register(0, on_dll_notification, ptr::null_mut(), &mut cookie) >= 0 {
+            DLL_NOTIFICATION_REGISTERED.store(true, Ordering::Release);
+        }
+    });
+    DLL_NOTIFICATION_REGISTERED.load(Ordering::Acquire)
+}
+
+static DLL_NOTIFICATION_REGISTERED: AtomicBool = AtomicBool::new(false);
+
+static MODULE_CHANGES: AtomicU64 = AtomicU64::new(0);
+
+/// Get the number of times the loader mapped or unmapped a module since this
+/// was first called.
+///
+/// Returns `None` if DLL notifications aren't available.
+pub(crate) fn change_count() -> Option<u64> {
+    if register_dll_notification() {
+        Some(MODULE_CHANGES.load(Ordering::Acquire))
+    } else {
+        None
+    }
+}
+
+unsafe fn unicode_string(string: *const UNICODE_STRING) -> Vec<u16> {
+    match string.as_ref() {
+        Some(string) if !string.Buffer.is_null() => {
+            slice::from_raw_parts(string.Buffer, string.Length as usize / 2).to_vec()
+        }
+        _ => Vec::new(),
+    }
+}
+
+unsafe extern "system" fn on_dll_notification(
+    reason: u32,
+    data: *const LdrDllNotificationData,
+    _context: *mut c_void,
+) {
+    MODULE_CHANGES.fetch_add(1, Ordering::AcqRel);
+    let event = match reason {
+        LDR_DLL_NOTIFICATION_REASON_LOADED => ImageEvent::Added,
+        LDR_DLL_NOTIFICATION_REASON_UNLOADED => ImageEvent::Removed,
+        _ => return,
+    };
+    let data = match data.as_ref() {
+        Some(data) => data,
+        None => return,
+    };
+
+    let module_info = MODULEINFO {
+        lpBaseOfDll: data.dll_base,
+        SizeOfImage: data.size_of_image,
+        EntryPoint: ptr::null_mut(),
+    };
+    let shlib = SharedLibrary::new(module_info, unicode_string(data.full_dll_name));
+
+    // Don't hold the lock while calling out so observers can register more
+    // observers.
+    let observers = match IMAGE_OBSERVERS.lock() {
+        Ok(observers) => observers.clone(),
+        Err(_) => return,
+    };
+    for observer in observers {
+        let _ = panic::catch_unwind(panic::AssertUnwindSafe(|| observer(event, &shlib)));
+    }
+}
@@ -0,0 +1,247 @@
+//! Finding code that was modified after a module was loaded.
+//!
+//! The executable sections of the loaded image are compared against the same
+//! sections of the module's file. Before comparing, the file's base
+//! relocations are applied to its contents for wherever the module was
+//! actually loaded, and the import address table, which the loader fills in,
+//! is skipped.
+
+use crate::verify::{u16_at, u32_at, u64_at};
+use crate::Avma;
+
+use std::convert::TryInto;
+use std::ops::Range;
+use std::str;
+
+use windows_sys::Win32::System::Diagnostics::Debug::{IMAGE_SCN_MEM_EXECUTE, IMAGE_SECTION_HEADER};
+use windows_sys::Win32::System::SystemServices::{IMAGE_REL_BASED_DIR64, IMAGE_REL_BASED_HIGHLOW};
+
+/// A range of a module's code that differs from its file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CodePatch {
+    section: String,
+    range: Range<Avma>,
+}
+
+impl CodePatch {
+    /// Get the name of the section the modified code is in, e.g. `.text`.
+    pub fn section_name(&self) -> &str {
+        &self.section
+    }
+
+    /// Get the addresses of the modified bytes.
+    pub fn range(&self) -> Range<Avma> {
+        self.range.clone()
+    }
+}
+
+/// Read the preferred `ImageBase` from the headers of the PE file `file`.
+fn file_image_base(file: &[u8]) -> Option<u64> {
+    let e_lfanew = u32_at(file, 0x3c, true)? as usize;
+    // Past the signature and file header.
+    let optional_header = e_lfanew.checked_add(4 + 20)?;
+    match u16_at(file, optional_header, true)? {
+        0x10b => u32_at(file, optional_header + 28, true).map(u64::from),
+        0x20b => u64_at(file, optional_header + 24, true),
+        _ => None,
+    }
+}
+
+/// Get the offset in the file of the data at `rva`.
+fn rva_to_offset(sections: &[IMAGE_SECTION_HEADER], rva: usize) -> Option<usize> {
+    sections.iter().find_map(|section| {
+        let start = section.VirtualAddress as usize;
+        let offset = rva.checked_sub(start)?;
+        if offset < section.SizeOfRawData as usize {
+            (section.PointerToRawData as usize).checked_add(offset)
+        } else {
+            None
+        }
+    })
+}
+
+/// Apply the base relocation blocks in `relocs` to `data`, the contents of
+/// the section at `section_rva`, for a module loaded `delta` bytes away from
+/// its preferred base.
+///
+/// Only absolute addresses are relocated, which covers x86, x64 and ARM64.
+fn relocate(data: &mut [u8], section_rva: usize, mut relocs: &[u8], delta: u64) {
+    while let (Some(page), Some(block_size)) = (u32_at(relocs, 0, true), u32_at(relocs, 4, true)) {
+        let block_size = block_size as usize;
+        if block_size < 8 || block_size > relocs.len() {
+            break;
+        }
+        for entry in relocs[8..block_size].chunks_exact(2) {
+            let entry = u16::from_le_bytes([entry[0], entry[1]]);
+            let rva = page as usize + (entry & 0xfff) as usize;
+            let size = match u32::from(entry >> 12) {
+                IMAGE_REL_BASED_HIGHLOW => 4,
+                IMAGE_REL_BASED_DIR64 => 8,
+                // Padding, or a kind of relocation that isn't supported.
+                _ => continue,
+            };
+            let offset = match rva.checked_sub(section_rva) {
+                Some(offset) if offset + size <= data.len() => offset,
+                _ => continue,
+            };
+            let target = &mut data[offset..offset + size];
+            if size == 4 {
+                let value = u32::from_le_bytes(target[..].try_into().unwrap());
+                target.copy_from_slice(&value.wrapping_add(delta as u32).to_le_bytes());
+            } else {
+                let value = u64::from_le_bytes(target[..].try_into().unwrap());
+                target.copy_from_slice(&value.wrapping_add(delta).to_le_bytes());
+            }
+        }
+        relocs = &relocs[block_size..];
+    }
+}
+
+/// Find the runs of bytes that differ between `memory` and `expected`,
+/// ignoring those in `skip`.
+fn diff(memory: &[u8], expected: &[u8], skip: &Range<usize>) -> Vec<Range<usize>> {
+    let mut ranges: Vec<Range<usize>> = vec![];
+    for (offset, (actual, expected)) in memory.iter().zip(expected).enumerate() {
+        if actual == expected || skip.contains(&offset) {
+            continue;
+        }
+        match ranges.last_mut() {
+            Some(range) if range.end == offset => range.end += 1,
+            _ => ranges.push(offset..offset + 1),
+        }
+    }
+    ranges
+}
+
+/// Compare the executable sections of `image`, loaded at `base`, against
+/// `file`.
+///
+/// `relocs` and `iat` are the RVA ranges of the base relocation directory and
+/// of the import address table. Returns `None` if `file` doesn't look like
+/// the file the image was loaded from.
+pub(crate) fn find(
+    image: &[u8],
+    base: usize,
+    sections: &[IMAGE_SECTION_HEADER],
+    relocs: Option<Range<usize>>,
+    iat: Range<usize>,
+    file: &[u8],
+) -> Option<Vec<CodePatch>> {
+    let delta = (base as u64).wrapping_sub(file_image_base(file)?);
+    let relocs = relocs
+        .and_then(|relocs| {
+            let offset = rva_to_offset(sections, relocs.start)?;
+            file.get(offset..offset.checked_add(relocs.len())?)
+        })
+        .unwrap_or(&[]);
+
+    let mut patches = vec![];
+    for section in sections {
+        if section.Characteristics & IMAGE_SCN_MEM_EXECUTE == 0 {
+            continue;
+        }
+        let rva = section.VirtualAddress as usize;
+        let virtual_size = match unsafe { section.Misc.VirtualSize } as usize {
+            0 => section.SizeOfRawData as usize,
+            virtual_size => virtual_size,
+        };
+        let memory = image.get(rva..rva.checked_add(virtual_size)?)?;
+
+        // Past the end of the raw data, the section is zero-filled.
+        let mut expected = vec![0; virtual_size];
+        let raw_size = virtual_size.min(section.SizeOfRawData as usize);
+        let raw_start = section.PointerToRawData as usize;
+        expected[..raw_size].copy_from_slice(file.get(raw_start..raw_start + raw_size)?);
+        relocate(&mut expected, rva, relocs, delta);
+
+        let skip = iat.start.saturating_sub(rva)..iat.end.saturating_sub(rva);
+        let name = section.Name.split(|b| *b == 0).next().unwrap_or(&[]);
+        let name = str::from_utf8(name).unwrap_or("");
+        for range in diff(memory, &expected, &skip) {
+            patches.push(CodePatch {
+                section: name.to_owned(),
+                range: Avma(base + rva + range.start)..Avma(base + rva + range.end),
+            });
+        }
+    }
+    Some(patches)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::mem;
+
+    fn section(
+        name: &[u8],
+        rva: u32,
+        size: u32,
+        raw: u32,
+        executable: bool,
+    ) -> IMAGE_SECTION_HEADER {
+        let mut section: IMAGE_SECTION_HEADER = unsafe { mem::zeroed() };
+        section.Name[..name.len()].copy_from_slice(name);
+        section.VirtualAddress = rva;
+        section.Misc.VirtualSize = size;
+        section.SizeOfRawData = size;
+        section.PointerToRawData = raw;
+        if executable {
+            section.Characteristics = IMAGE_SCN_MEM_EXECUTE;
+        }
+        section
+    }
+
+    #[test]
+    fn relocated_and_patched() {
+        let sections = [
+            section(b".text", 0x1000, 0x20, 0x200, true),
+            section(b".reloc", 0x2000, 0x10, 0x300, false),
+        ];
+
+        let mut file = vec![0u8; 0x400];
+        file[0x3c] = 0x40;
+        file[0x58..0x5a].copy_from_slice(&0x10bu16.to_le_bytes());
+        file[0x74..0x78].copy_from_slice(&0x1000_0000u32.to_le_bytes());
+        for (i, b) in file[0x200..0x220].iter_mut().enumerate() {
+            *b = 0x90 + i as u8;
+        }
+        // An absolute address at .text+4, and its relocation.
+        file[0x204..0x208].copy_from_slice(&0x1000_1010u32.to_le_bytes());
+        file[0x300..0x304].copy_from_slice(&0x1000u32.to_le_bytes());
+        file[0x304..0x308].copy_from_slice(&12u32.to_le_bytes());
+        file[0x308..0x30a].copy_from_slice(&(3u16 << 12 | 4).to_le_bytes());
+
+        let base = 0x5000_0000;
+        let mut image = vec![0u8; 0x3000];
+        image[0x1000..0x1020].copy_from_slice(&file[0x200..0x220]);
+        image[0x1004..0x1008].copy_from_slice(&0x5000_1010u32.to_le_bytes());
+
+        let find = |image: &[u8]| {
+            find(
+                image,
+                base,
+                &sections,
+                Some(0x2000..0x200c),
+                0x1018..0x1020,
+                &file,
+            )
+            .unwrap()
+        };
+        assert_eq!(find(&image), []);
+
+        // A hook, and a change to the import address table.
+        image[0x1010..0x1012].copy_from_slice(&[0xcc, 0xcc]);
+        image[0x1018] = 0;
+        let patches = find(&image);
+        assert_eq!(patches.len(), 1);
+        assert_eq!(patches[0].section_name(), ".text");
+        assert_eq!(patches[0].range(), Avma(base + 0x1010)..Avma(base + 0x1012));
+
+        assert_eq!(super::find(&image, base, &sections, None, 0..0, &[]), None);
+    }
+
+    #[test]
+    fn diff_runs() {
+        assert_eq!(diff(b"abXXeYgH", b"abcdefgh", &(5..6)), vec![2..4, 7..8]);
+    }
+}
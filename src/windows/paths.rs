@@ -0,0 +1,149 @@
+//! Turning NT paths into the Win32 paths users and symbol servers expect.
+//!
+//! Some APIs report paths in the NT namespace rather than with a drive
+//! letter: `GetMappedFileNameW` returns `\Device\HarddiskVolume3\...`, the
+//! kernel reports drivers as `\SystemRoot\system32\...`, and the loader
+//! keeps `\??\C:\...` for some modules. Drive letters are resolved by asking
+//! `QueryDosDeviceW` which device each one refers to.
+
+use super::read_path;
+
+use std::ffi::OsString;
+use std::os::windows::ffi::OsStringExt;
+
+use windows_sys::Win32::Storage::FileSystem::QueryDosDeviceW;
+use windows_sys::Win32::System::SystemInformation::GetSystemWindowsDirectoryW;
+
+fn wide(s: &str) -> Vec<u16> {
+    s.encode_utf16().collect()
+}
+
+/// If `path` starts with the path component(s) `prefix`, compared ASCII
+/// case-insensitively, get the rest of it, starting with the separator.
+fn strip_prefix<'a>(path: &'a [u16], prefix: &[u16]) -> Option<&'a [u16]> {
+    if path.len() < prefix.len() {
+        return None;
+    }
+    let (head, rest) = path.split_at(prefix.len());
+    let lower = |c: &u16| match *c {
+        c @ 0x41..=0x5a => c + 0x20,
+        c => c,
+    };
+    if !head.iter().map(lower).eq(prefix.iter().map(lower)) {
+        return None;
+    }
+    match rest.first() {
+        None | Some(0x5c) => Some(rest),
+        Some(_) => None,
+    }
+}
+
+/// The devices the drive letters `A:` to `Z:` refer to, e.g.
+/// `("C:", "\Device\HarddiskVolume3")`.
+fn drive_devices() -> Vec<(Vec<u16>, Vec<u16>)> {
+    (b'A'..=b'Z')
+        .filter_map(|letter| {
+            let drive = vec![u16::from(letter), u16::from(b':')];
+            let mut name = drive.clone();
+            name.push(0);
+            let device = read_path(|buffer| unsafe {
+                QueryDosDeviceW(name.as_ptr(), buffer.as_mut_ptr(), buffer.len() as u32)
+            })?;
+            // The result is a list of NUL-terminated targets, of which the
+            // first is the current one.
+            let device = device.split(|c| *c == 0).next()?.to_vec();
+            Some((drive, device))
+        })
+        .collect()
+}
+
+fn system_root() -> Option<Vec<u16>> {
+    read_path(|buffer| unsafe {
+        GetSystemWindowsDirectoryW(buffer.as_mut_ptr(), buffer.len() as u32)
+    })
+}
+
+/// Convert `path` into a Win32 path like `win32_path`, as an `OsString`.
+pub(crate) fn win32_os_string(path: &[u16]) -> OsString {
+    OsString::from_wide(&win32_path(path))
+}
+
+/// Convert `path` from the NT namespace into a Win32 path if it is in it,
+/// or return it unchanged otherwise.
+pub(crate) fn win32_path(path: &[u16]) -> Vec<u16> {
+    // Win32 paths, including `\\server\share` and `\\?\` ones, don't need
+    // any resolution.
+    if path.first() != Some(&0x5c) || path.get(1) == Some(&0x5c) {
+        return path.to_vec();
+    }
+    to_win32_path(path, drive_devices, system_root)
+}
+
+fn to_win32_path<D, R>(path: &[u16], drive_devices: D, system_root: R) -> Vec<u16>
+where
+    D: FnOnce() -> Vec<(Vec<u16>, Vec<u16>)>,
+    R: FnOnce() -> Option<Vec<u16>>,
+{
+    let join = |head: &[u16], rest: &[u16]| {
+        let mut result = head.to_vec();
+        result.extend_from_slice(rest);
+        result
+    };
+
+    if let Some(rest) = strip_prefix(path, &wide(r"\??")) {
+        return rest.get(1..).map_or_else(|| path.to_vec(), <[u16]>::to_vec);
+    }
+    if let Some(rest) = strip_prefix(path, &wide(r"\Device\Mup")) {
+        return join(&wide(r"\"), rest);
+    }
+    if let Some(rest) = strip_prefix(path, &wide(r"\SystemRoot")) {
+        return match system_root() {
+            Some(root) => join(&root, rest),
+            None => path.to_vec(),
+        };
+    }
+    for (drive, device) in drive_devices() {
+        if let Some(rest) = strip_prefix(path, &device) {
+            return join(&drive, rest);
+        }
+    }
+    path.to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{to_win32_path, wide};
+
+    fn convert(path: &str) -> String {
+        let devices = || {
+            vec![
+                (wide("C:"), wide(r"\Device\HarddiskVolume1")),
+                (wide("D:"), wide(r"\Device\HarddiskVolume10")),
+            ]
+        };
+        let root = || Some(wide(r"C:\Windows"));
+        String::from_utf16(&to_win32_path(&wide(path), devices, root)).unwrap()
+    }
+
+    #[test]
+    fn nt_paths() {
+        assert_eq!(
+            convert(r"\Device\HarddiskVolume1\Windows\System32\ntdll.dll"),
+            r"C:\Windows\System32\ntdll.dll"
+        );
+        assert_eq!(convert(r"\device\harddiskvolume10\foo.dll"), r"D:\foo.dll");
+        assert_eq!(
+            convert(r"\Device\HarddiskVolume2\foo.dll"),
+            r"\Device\HarddiskVolume2\foo.dll"
+        );
+        assert_eq!(
+            convert(r"\SystemRoot\system32\ntoskrnl.exe"),
+            r"C:\Windows\system32\ntoskrnl.exe"
+        );
+        assert_eq!(convert(r"\??\C:\foo.dll"), r"C:\foo.dll");
+        assert_eq!(
+            convert(r"\Device\Mup\server\share\foo.dll"),
+            r"\\server\share\foo.dll"
+        );
+    }
+}
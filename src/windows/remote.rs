@@ -0,0 +1,277 @@
+//! Enumerating the modules of another process.
+//!
+//! The module list comes from psapi, which works on any process handle with
+//! `PROCESS_QUERY_INFORMATION` and `PROCESS_VM_READ` access. The headers, load
+//! config and debug information of each module are then copied out of the
+//! process with `ReadProcessMemory` into a local buffer laid out like the
+//! mapped image, so that the same PE and CodeView parsing can be used on them.
+
+use super::{psapi_modules, NtHeaders, SharedLibrary, MAX_LONG_PATH};
+use crate::IterationControl;
+
+use std::convert::TryFrom;
+use std::ffi::OsString;
+use std::io;
+use std::mem;
+use std::os::windows::ffi::OsStringExt;
+use std::ptr;
+use std::slice;
+
+use windows_sys::Win32::Foundation::{CloseHandle, FALSE, HANDLE};
+use windows_sys::Win32::System::Diagnostics::Debug::{
+    ReadProcessMemory, IMAGE_DEBUG_DIRECTORY, IMAGE_DIRECTORY_ENTRY_DEBUG,
+    IMAGE_DIRECTORY_ENTRY_LOAD_CONFIG, IMAGE_NT_HEADERS64, IMAGE_SECTION_HEADER,
+};
+use windows_sys::Win32::System::ProcessStatus::MODULEINFO;
+use windows_sys::Win32::System::SystemServices::{IMAGE_DOS_HEADER, IMAGE_DOS_SIGNATURE};
+use windows_sys::Win32::System::Threading::{
+    OpenProcess, QueryFullProcessImageNameW, PROCESS_QUERY_INFORMATION, PROCESS_VM_READ,
+};
+
+// The most that is read of a single module's headers.
+const MAX_HEADERS_SIZE: usize = 64 * 1024;
+
+/// Another process whose modules can be enumerated.
+///
+/// ```no_run
+/// # fn main() -> std::io::Result<()> {
+/// use findshlibs::windows::RemoteProcess;
+/// use findshlibs::SharedLibrary;
+///
+/// let process = RemoteProcess::for_pid(1234)?;
+/// process.each(|shlib| {
+///     println!("{:?} {:?}", shlib.name(), shlib.debug_id());
+/// })?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct RemoteProcess {
+    handle: HANDLE,
+    owned: bool,
+}
+
+impl RemoteProcess {
+    /// Use an existing handle to a process.
+    ///
+    /// The handle needs `PROCESS_QUERY_INFORMATION` and `PROCESS_VM_READ`
+    /// access, and is not closed when the `RemoteProcess` is dropped.
+    pub fn from_handle(handle: HANDLE) -> RemoteProcess {
+        RemoteProcess {
+            handle,
+            owned: false,
+        }
+    }
+
+    /// Open the process with the given id.
+    pub fn for_pid(pid: u32) -> io::Result<RemoteProcess> {
+        let handle =
+            unsafe { OpenProcess(PROCESS_QUERY_INFORMATION | PROCESS_VM_READ, FALSE, pid) };
+        if handle.is_null() {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(RemoteProcess {
+            handle,
+            owned: true,
+        })
+    }
+
+    /// Get the underlying process handle.
+    pub fn handle(&self) -> HANDLE {
+        self.handle
+    }
+
+    /// Get the full path of the process's executable.
+    pub fn executable_path(&self) -> io::Result<OsString> {
+        let mut path = vec![0u16; MAX_LONG_PATH + 1];
+        let mut len = path.len() as u32;
+        if unsafe { QueryFullProcessImageNameW(self.handle, 0, path.as_mut_ptr(), &mut len) } == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        path.truncate(len as usize);
+        Ok(OsString::from_wide(&path))
+    }
+
+    /// Iterate over the modules loaded in the process.
+    ///
+    /// The `SharedLibrary`s passed to `f` only have the headers, load config
+    /// and debug information of the module available, so names, ids, debug
+    /// ids, segments and mitigations work as for the current process, but
+    /// anything that needs other parts of the image does not.
+    pub fn each<F, C>(&self, mut f: F) -> io::Result<()>
+    where
+        F: FnMut(&SharedLibrary) -> C,
+        C: Into<IterationControl>,
+    {
+        let modules = unsafe { psapi_modules(self.handle) };
+        if modules.is_empty() {
+            return Err(io::Error::last_os_error());
+        }
+
+        for (module_info, module_path) in modules.into_iter().filter_map(Result::ok) {
+            // The module may have been unloaded since it was listed, so skip
+            // anything that can't be read.
+            let image = match self.copy_image(&module_info) {
+                Some(image) => image,
+                None => continue,
+            };
+
+            let local_info = MODULEINFO {
+                lpBaseOfDll: image.as_ptr() as *mut _,
+                ..module_info
+            };
+            let mut shlib = SharedLibrary::new(local_info, module_path);
+            shlib.remote_base = Some(module_info.lpBaseOfDll as usize);
+
+            match f(&shlib).into() {
+                IterationControl::Break => break,
+                IterationControl::Continue => continue,
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Copy `buf.len()` bytes at `address` out of the process.
+    fn read(&self, address: usize, buf: &mut [u8]) -> bool {
+        let mut read = 0;
+        let ok = unsafe {
+            ReadProcessMemory(
+                self.handle,
+                address as *const _,
+                buf.as_mut_ptr() as *mut _,
+                buf.len(),
+                &mut read,
+            )
+        };
+        ok != 0 && read == buf.len()
+    }
+
+    /// Copy `len` bytes at `rva` of the image at `base` into the same place
+    /// in `image`.
+    fn read_rva(&self, base: usize, image: &mut [u8], rva: usize, len: usize) -> bool {
+        match image.get_mut(rva..rva.saturating_add(len)) {
+            Some(dest) => self.read(base.wrapping_add(rva), dest),
+            None => false,
+        }
+    }
+
+    /// Make a sparse local copy of a module's image, with only the headers,
+    /// load config, debug directories and debug data filled in.
+    ///
+    /// The copy is kept in a `u64` buffer so that it is suitably aligned for
+    /// the header structures.
+    fn copy_image(&self, module_info: &MODULEINFO) -> Option<Vec<u64>> {
+        let base = module_info.lpBaseOfDll as usize;
+        let size = module_info.SizeOfImage as usize;
+
+        // Zeroed allocations this large are lazily committed, so only the
+        // parts that are copied below take up memory.
+        let mut buffer = vec![0u64; size.div_ceil(8)];
+        let image = unsafe { slice::from_raw_parts_mut(buffer.as_mut_ptr() as *mut u8, size) };
+
+        let headers_size = size.min(MAX_HEADERS_SIZE);
+        if !self.read(base, &mut image[..headers_size]) {
+            // The headers usually fit in a single page even if the rest of
+            // the first 64K isn't mapped.
+            let page = headers_size.min(4096);
+            if !self.read(base, &mut image[..page]) {
+                return None;
+            }
+        }
+
+        let nt_headers = checked_nt_headers(image)?;
+        let load_config = nt_headers.data_directory(IMAGE_DIRECTORY_ENTRY_LOAD_CONFIG);
+        let debug_dir = nt_headers.data_directory(IMAGE_DIRECTORY_ENTRY_DEBUG);
+
+        if let Some(load_config) = load_config {
+            if load_config.VirtualAddress != 0 {
+                self.read_rva(
+                    base,
+                    image,
+                    load_config.VirtualAddress as usize,
+                    load_config.Size as usize,
+                );
+            }
+        }
+
+        let debug_dir = match debug_dir {
+            Some(debug_dir) => debug_dir,
+            None => return Some(buffer),
+        };
+        if debug_dir.VirtualAddress == 0
+            || !self.read_rva(
+                base,
+                image,
+                debug_dir.VirtualAddress as usize,
+                debug_dir.Size as usize,
+            )
+        {
+            return Some(buffer);
+        }
+
+        let directories: Vec<IMAGE_DEBUG_DIRECTORY> = {
+            let start = debug_dir.VirtualAddress as usize;
+            let count = debug_dir.Size as usize / mem::size_of::<IMAGE_DEBUG_DIRECTORY>();
+            (0..count)
+                .map(|index| unsafe {
+                    ptr::read_unaligned(
+                        image[start + index * mem::size_of::<IMAGE_DEBUG_DIRECTORY>()..].as_ptr()
+                            as *const IMAGE_DEBUG_DIRECTORY,
+                    )
+                })
+                .collect()
+        };
+        for directory in directories {
+            if directory.AddressOfRawData != 0 {
+                self.read_rva(
+                    base,
+                    image,
+                    directory.AddressOfRawData as usize,
+                    directory.SizeOfData as usize,
+                );
+            }
+        }
+
+        Some(buffer)
+    }
+}
+
+/// Get the NT headers of a copied image, making sure that they and the
+/// section table lie within it.
+///
+/// The image may be of either bitness, regardless of our own.
+fn checked_nt_headers(image: &[u8]) -> Option<NtHeaders<'_>> {
+    if image.len() < mem::size_of::<IMAGE_DOS_HEADER>() {
+        return None;
+    }
+    let dos_header = unsafe { &*(image.as_ptr() as *const IMAGE_DOS_HEADER) };
+    if dos_header.e_magic != IMAGE_DOS_SIGNATURE {
+        return None;
+    }
+
+    let offset = usize::try_from(dos_header.e_lfanew).ok()?;
+    let end = offset.checked_add(mem::size_of::<IMAGE_NT_HEADERS64>())?;
+    if end > image.len() || offset % mem::align_of::<IMAGE_NT_HEADERS64>() != 0 {
+        return None;
+    }
+    let nt_headers = unsafe { NtHeaders::from_ptr(image[offset..].as_ptr()) }?;
+
+    let sections_start = nt_headers.section_table() as usize - image.as_ptr() as usize;
+    let sections_len =
+        nt_headers.file_header().NumberOfSections as usize * mem::size_of::<IMAGE_SECTION_HEADER>();
+    if sections_start + sections_len > image.len() {
+        return None;
+    }
+    Some(nt_headers)
+}
+
+impl Drop for RemoteProcess {
+    fn drop(&mut self) {
+        if self.owned {
+            unsafe {
+                CloseHandle(self.handle);
+            }
+        }
+    }
+}
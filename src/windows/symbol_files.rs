@@ -0,0 +1,160 @@
+//! Finding and opening the PDB of a module.
+//!
+//! The linker records the path it wrote the PDB to in the module's CodeView
+//! record, along with the PDB's signature and age. The PDB is looked for at
+//! that path, next to the module, and in symbol stores laid out the way
+//! `symstore` and symbol server caches lay them out, where `foo.pdb` is kept
+//! as `foo.pdb\<signature><age>\foo.pdb`.
+
+use std::ffi::OsStr;
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use pdb::PDB;
+
+/// What a PDB is identified by, besides its age.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum PdbSignature {
+    /// The GUID of a PDB 7.0 file, as it is laid out in the CodeView record.
+    Guid([u8; 16]),
+    /// The timestamp of a PDB 2.0 file.
+    Timestamp(u32),
+}
+
+impl PdbSignature {
+    /// Get the name of the directory a symbol store keeps the PDB with this
+    /// signature and `age` in.
+    fn store_key(self, age: u32) -> String {
+        match self {
+            PdbSignature::Guid(guid) => {
+                let mut key = format!(
+                    "{:08X}{:04X}{:04X}",
+                    u32::from_le_bytes([guid[0], guid[1], guid[2], guid[3]]),
+                    u16::from_le_bytes([guid[4], guid[5]]),
+                    u16::from_le_bytes([guid[6], guid[7]]),
+                );
+                for byte in &guid[8..] {
+                    key.push_str(&format!("{:02X}", byte));
+                }
+                key.push_str(&format!("{:x}", age));
+                key
+            }
+            PdbSignature::Timestamp(timestamp) => format!("{:08X}{:x}", timestamp, age),
+        }
+    }
+}
+
+/// Returns `true` if `pdb` is the PDB with `signature` and `age`.
+fn matches(pdb: &mut PDB<'static, File>, signature: PdbSignature, age: u32) -> pdb::Result<bool> {
+    let info = pdb.pdb_information()?;
+    let signature_matches = match signature {
+        PdbSignature::Guid(guid) => info.guid.to_bytes_le() == guid,
+        PdbSignature::Timestamp(timestamp) => info.signature == timestamp,
+    };
+    if !signature_matches {
+        return Ok(false);
+    }
+    // The age in the PDB stream is bumped whenever the PDB is written to, so
+    // the one the linker put in the DBI stream is what the module refers to.
+    match pdb.debug_information()?.age() {
+        Some(dbi_age) => Ok(dbi_age == age),
+        None => Ok(info.age >= age),
+    }
+}
+
+/// Get the paths the PDB named `recorded_path` may be found at, in the order
+/// they are tried.
+fn candidates(
+    recorded_path: &Path,
+    signature: PdbSignature,
+    age: u32,
+    module_path: &Path,
+    symbol_paths: &[&Path],
+) -> Vec<PathBuf> {
+    let mut candidates = vec![recorded_path.to_owned()];
+    // The recorded path is a Windows path, so its file name is taken apart
+    // like one whether or not it is absolute.
+    let file_name = match recorded_path.file_name() {
+        Some(file_name) => file_name,
+        None => return candidates,
+    };
+    if let Some(directory) = module_path.parent() {
+        candidates.push(directory.join(file_name));
+    }
+    let key = signature.store_key(age);
+    for symbol_path in symbol_paths {
+        candidates.push(symbol_path.join(file_name).join(&key).join(file_name));
+        candidates.push(symbol_path.join(file_name));
+    }
+    candidates
+}
+
+/// Open the PDB with `signature` and `age` that the linker wrote to
+/// `recorded_path`, for the module at `module_path`.
+pub(crate) fn open(
+    recorded_path: &OsStr,
+    signature: PdbSignature,
+    age: u32,
+    module_path: &Path,
+    symbol_paths: &[&Path],
+) -> pdb::Result<PDB<'static, File>> {
+    let recorded_path = Path::new(recorded_path);
+    for candidate in candidates(recorded_path, signature, age, module_path, symbol_paths) {
+        let file = match File::open(&candidate) {
+            Ok(file) => file,
+            Err(_) => continue,
+        };
+        // A file that isn't a PDB, or isn't this one, is passed over like a
+        // missing one.
+        let mut pdb = match PDB::open(file) {
+            Ok(pdb) => pdb,
+            Err(_) => continue,
+        };
+        if let Ok(true) = matches(&mut pdb, signature, age) {
+            return Ok(pdb);
+        }
+    }
+    Err(pdb::Error::IoError(io::Error::new(
+        io::ErrorKind::NotFound,
+        "no matching PDB was found",
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn store_keys() {
+        let guid = [
+            0x78, 0x56, 0x34, 0x12, 0x34, 0x12, 0x78, 0x56, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06,
+            0x07, 0x08,
+        ];
+        assert_eq!(
+            PdbSignature::Guid(guid).store_key(0x1a),
+            "123456781234567801020304050607081a"
+        );
+        assert_eq!(
+            PdbSignature::Timestamp(0x5ab3_8b4f).store_key(2),
+            "5AB38B4F2"
+        );
+
+        let candidates = candidates(
+            Path::new(r"C:\build\foo.pdb"),
+            PdbSignature::Timestamp(0x5ab3_8b4f),
+            2,
+            Path::new(r"D:\app\foo.dll"),
+            &[Path::new(r"E:\symbols")],
+        );
+        assert_eq!(
+            candidates,
+            [
+                PathBuf::from(r"C:\build\foo.pdb"),
+                PathBuf::from(r"D:\app\foo.pdb"),
+                PathBuf::from(r"E:\symbols\foo.pdb\5AB38B4F2\foo.pdb"),
+                PathBuf::from(r"E:\symbols\foo.pdb"),
+            ]
+        );
+    }
+}
@@ -0,0 +1,345 @@
+//! Reading the version resource of a PE image.
+//!
+//! The resource directory is a three-level tree of type, name and language,
+//! whose leaves point at the resource data. The version resource is a tree of
+//! `VS_VERSIONINFO` blocks: the root holds a `VS_FIXEDFILEINFO` with numeric
+//! versions, and under its `StringFileInfo` child are string tables of
+//! `CompanyName`, `FileVersion` and so on, one table per language.
+
+use crate::verify::{u16_at, u32_at};
+
+// The resource type of version information.
+const RT_VERSION: u32 = 16;
+
+// Set in a resource directory entry for subdirectories, and for named
+// rather than numbered entries.
+const RESOURCE_SUBDIRECTORY: u32 = 0x8000_0000;
+const RESOURCE_NAME_IS_STRING: u32 = 0x8000_0000;
+
+const VS_FFI_SIGNATURE: u32 = 0xfeef_04bd;
+
+/// The version information of a module, from its `VERSIONINFO` resource.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct VersionInfo {
+    file_version: Option<[u16; 4]>,
+    product_version: Option<[u16; 4]>,
+    strings: Vec<(String, String)>,
+}
+
+impl VersionInfo {
+    /// Get the numeric file version, e.g. `[10, 0, 19041, 1]`.
+    pub fn file_version(&self) -> Option<[u16; 4]> {
+        self.file_version
+    }
+
+    /// Get the numeric product version.
+    pub fn product_version(&self) -> Option<[u16; 4]> {
+        self.product_version
+    }
+
+    /// Get the value of the string `key` of the first string table, e.g.
+    /// `FileVersion`, `ProductVersion` or `CompanyName`.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.strings
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Get the `FileVersion` string, which often has more detail than the
+    /// numeric version, such as the build branch.
+    pub fn file_version_string(&self) -> Option<&str> {
+        self.get("FileVersion")
+    }
+
+    /// Get the `ProductVersion` string.
+    pub fn product_version_string(&self) -> Option<&str> {
+        self.get("ProductVersion")
+    }
+
+    /// Get the `CompanyName` string.
+    pub fn company_name(&self) -> Option<&str> {
+        self.get("CompanyName")
+    }
+
+    /// Iterate over the `(key, value)` pairs of the first string table.
+    pub fn strings(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.strings
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+    }
+}
+
+fn align4(offset: usize) -> usize {
+    offset.saturating_add(3) & !3
+}
+
+/// Find the entry of the resource directory at `directory` with the given
+/// id, or the first entry if `id` is `None`, and get its `OffsetToData`.
+///
+/// Offsets are relative to the start of the resource directory, `root`.
+fn find_entry(image: &[u8], root: usize, directory: usize, id: Option<u32>) -> Option<u32> {
+    let directory = root.checked_add(directory)?;
+    let named = u16_at(image, directory + 12, true)? as usize;
+    let numbered = u16_at(image, directory + 14, true)? as usize;
+    for index in 0..named + numbered {
+        let entry = directory + 16 + index * 8;
+        let name = u32_at(image, entry, true)?;
+        let data = u32_at(image, entry + 4, true)?;
+        match id {
+            None => return Some(data),
+            Some(id) if name & RESOURCE_NAME_IS_STRING == 0 && name == id => return Some(data),
+            Some(_) => {}
+        }
+    }
+    None
+}
+
+/// Find the data of the version resource, in whichever language comes
+/// first.
+fn version_resource(image: &[u8], root: usize) -> Option<&[u8]> {
+    let subdirectory = |data: u32| {
+        if data & RESOURCE_SUBDIRECTORY != 0 {
+            Some((data & !RESOURCE_SUBDIRECTORY) as usize)
+        } else {
+            None
+        }
+    };
+
+    let names = subdirectory(find_entry(image, root, 0, Some(RT_VERSION))?)?;
+    let languages = subdirectory(find_entry(image, root, names, None)?)?;
+    let data_entry = find_entry(image, root, languages, None)?;
+    if data_entry & RESOURCE_SUBDIRECTORY != 0 {
+        return None;
+    }
+
+    // An `IMAGE_RESOURCE_DATA_ENTRY`, whose data is at an RVA rather than an
+    // offset in the directory.
+    let data_entry = root.checked_add(data_entry as usize)?;
+    let rva = u32_at(image, data_entry, true)? as usize;
+    let size = u32_at(image, data_entry + 4, true)? as usize;
+    image.get(rva..rva.checked_add(size)?)
+}
+
+/// A block of the version resource.
+struct Block<'a> {
+    key: String,
+    is_text: bool,
+    value: &'a [u8],
+    children: &'a [u8],
+}
+
+impl<'a> Block<'a> {
+    /// Parse the block at the start of `data`, returning it and its length.
+    fn parse(data: &'a [u8]) -> Option<(Block<'a>, usize)> {
+        let length = u16_at(data, 0, true)? as usize;
+        let value_length = u16_at(data, 2, true)? as usize;
+        let is_text = u16_at(data, 4, true)? == 1;
+        let data = data.get(..length)?;
+
+        let (key, key_end) = read_utf16(data, 6)?;
+        let value_start = align4(key_end).min(length);
+        // Text values are measured in UTF-16 code units.
+        let value_length = if is_text {
+            value_length * 2
+        } else {
+            value_length
+        };
+        let value_end = value_start.saturating_add(value_length).min(length);
+        let children_start = align4(value_end).min(length);
+
+        let block = Block {
+            key,
+            is_text,
+            value: &data[value_start..value_end],
+            children: &data[children_start..],
+        };
+        Some((block, length))
+    }
+
+    fn children(&self) -> impl Iterator<Item = Block<'a>> {
+        let mut data = self.children;
+        std::iter::from_fn(move || {
+            let (block, length) = Block::parse(data)?;
+            if length == 0 {
+                return None;
+            }
+            data = data.get(align4(length)..).unwrap_or(&[]);
+            Some(block)
+        })
+    }
+
+    fn text(&self) -> Option<String> {
+        if !self.is_text {
+            return None;
+        }
+        read_utf16(self.value, 0).map(|(text, _)| text)
+    }
+}
+
+/// Read a NUL-terminated (or slice-terminated) UTF-16 string at `offset`,
+/// returning it and the offset just past it.
+fn read_utf16(data: &[u8], offset: usize) -> Option<(String, usize)> {
+    let data = data.get(offset..)?;
+    let mut units = vec![];
+    let mut end = offset;
+    for unit in data.chunks_exact(2) {
+        end += 2;
+        match u16::from_le_bytes([unit[0], unit[1]]) {
+            0 => break,
+            unit => units.push(unit),
+        }
+    }
+    Some((String::from_utf16_lossy(&units), end))
+}
+
+fn fixed_version(ms: u32, ls: u32) -> [u16; 4] {
+    [(ms >> 16) as u16, ms as u16, (ls >> 16) as u16, ls as u16]
+}
+
+/// Parse a `VS_VERSIONINFO` resource.
+fn parse_version_info(data: &[u8]) -> Option<VersionInfo> {
+    let (root, _) = Block::parse(data)?;
+    if root.key != "VS_VERSION_INFO" {
+        return None;
+    }
+
+    let mut info = VersionInfo::default();
+    if u32_at(root.value, 0, true) == Some(VS_FFI_SIGNATURE) {
+        let field = |index: usize| u32_at(root.value, index * 4, true);
+        if let (Some(file_ms), Some(file_ls), Some(product_ms), Some(product_ls)) =
+            (field(2), field(3), field(4), field(5))
+        {
+            info.file_version = Some(fixed_version(file_ms, file_ls));
+            info.product_version = Some(fixed_version(product_ms, product_ls));
+        }
+    }
+
+    let table = root
+        .children()
+        .find(|child| child.key == "StringFileInfo")
+        .and_then(|string_file_info| string_file_info.children().next());
+    if let Some(table) = table {
+        info.strings = table
+            .children()
+            .map(|string| {
+                let value = string.text().unwrap_or_default();
+                (string.key, value)
+            })
+            .collect();
+    }
+    Some(info)
+}
+
+/// Read the version resource of `image`, whose resource directory is at
+/// `root`.
+pub(crate) fn parse(image: &[u8], root: usize) -> Option<VersionInfo> {
+    parse_version_info(version_resource(image, root)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Build a version resource block, with `value` as raw bytes and the
+    /// value length of a text value given in code units.
+    fn block(key: &str, value: &[u8], is_text: bool, children: &[Vec<u8>]) -> Vec<u8> {
+        let mut data = vec![0; 6];
+        for unit in key.encode_utf16().chain(Some(0)) {
+            data.extend_from_slice(&unit.to_le_bytes());
+        }
+        data.resize(align4(data.len()), 0);
+        data.extend_from_slice(value);
+        let value_length = if is_text {
+            value.len() / 2
+        } else {
+            value.len()
+        };
+        for child in children {
+            data.resize(align4(data.len()), 0);
+            data.extend_from_slice(child);
+        }
+
+        let length = data.len() as u16;
+        data[0..2].copy_from_slice(&length.to_le_bytes());
+        data[2..4].copy_from_slice(&(value_length as u16).to_le_bytes());
+        data[4..6].copy_from_slice(&(is_text as u16).to_le_bytes());
+        data
+    }
+
+    fn string(key: &str, value: &str) -> Vec<u8> {
+        let value: Vec<u8> = value
+            .encode_utf16()
+            .chain(Some(0))
+            .flat_map(|unit| unit.to_le_bytes())
+            .collect();
+        block(key, &value, true, &[])
+    }
+
+    fn version_info() -> Vec<u8> {
+        let mut fixed = vec![];
+        for field in &[
+            VS_FFI_SIGNATURE,
+            0x1_0000,
+            0xa_0000,
+            0x4a61_0001,
+            0xa_0000,
+            0x4a61_0000,
+        ] {
+            fixed.extend_from_slice(&field.to_le_bytes());
+        }
+        fixed.resize(52, 0);
+
+        let table = block(
+            "040904b0",
+            &[],
+            true,
+            &[
+                string("CompanyName", "Example Corp"),
+                string("FileVersion", "10.0.19041.1 (branch)"),
+            ],
+        );
+        let string_file_info = block("StringFileInfo", &[], true, &[table]);
+        block("VS_VERSION_INFO", &fixed, false, &[string_file_info])
+    }
+
+    #[test]
+    fn fixed_and_strings() {
+        let info = parse_version_info(&version_info()).unwrap();
+        assert_eq!(info.file_version(), Some([10, 0, 19041, 1]));
+        assert_eq!(info.product_version(), Some([10, 0, 19041, 0]));
+        assert_eq!(info.company_name(), Some("Example Corp"));
+        assert_eq!(info.file_version_string(), Some("10.0.19041.1 (branch)"));
+        assert_eq!(info.product_version_string(), None);
+        assert_eq!(info.strings().count(), 2);
+    }
+
+    #[test]
+    fn resource_directory() {
+        let data = version_info();
+        let mut image = vec![0u8; 0x100 + data.len()];
+        let put = |image: &mut Vec<u8>, offset: usize, value: u32| {
+            image[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+        };
+
+        // Type, name and language directories at 0x10, 0x30 and 0x50 of the
+        // resource directory at 0x10, each with a single numbered entry.
+        for (directory, id, data) in &[
+            (0x00, RT_VERSION, RESOURCE_SUBDIRECTORY | 0x20),
+            (0x20, 1, RESOURCE_SUBDIRECTORY | 0x40),
+            (0x40, 0x409, 0x60),
+        ] {
+            let directory = 0x10 + directory;
+            image[directory + 14] = 1;
+            put(&mut image, directory + 16, *id);
+            put(&mut image, directory + 20, *data);
+        }
+        put(&mut image, 0x70, 0x100);
+        put(&mut image, 0x74, data.len() as u32);
+        image[0x100..].copy_from_slice(&data);
+
+        let info = parse(&image, 0x10).unwrap();
+        assert_eq!(info.company_name(), Some("Example Corp"));
+        assert_eq!(parse(&image[..0x80], 0x10), None);
+    }
+}